@@ -0,0 +1,94 @@
+//! `#[derive(EmmV)]` for structs of fixed-size fields, re-exported by
+//! `rb-okvs` with its `derive` feature.
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{format_ident, quote};
+use syn::{parse_macro_input, Data, DeriveInput, Error, Fields, Index};
+
+/// Implements `EmmV` for a struct whose fields all implement `EmmV`,
+/// encoding the fields in declaration order. Its `len` is the sum of the
+/// field lengths, so variable-size fields such as `Vec` or `String` are
+/// rejected at compile time for lacking an `EmmV` implementation.
+#[proc_macro_derive(EmmV)]
+pub fn derive_emm_v(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+fn expand(input: DeriveInput) -> syn::Result<TokenStream2> {
+    let fields = match &input.data {
+        Data::Struct(data) => &data.fields,
+        _ => {
+            return Err(Error::new_spanned(
+                &input.ident,
+                "EmmV can only be derived for structs",
+            ))
+        }
+    };
+
+    if fields.is_empty() {
+        return Err(Error::new_spanned(
+            &input.ident,
+            "EmmV needs at least one field",
+        ));
+    }
+
+    let types: Vec<_> = fields.iter().map(|f| &f.ty).collect();
+    let members: Vec<TokenStream2> = match fields {
+        Fields::Named(named) => named
+            .named
+            .iter()
+            .map(|f| {
+                let ident = &f.ident;
+                quote!(#ident)
+            })
+            .collect(),
+        _ => (0..fields.len())
+            .map(|i| {
+                let index = Index::from(i);
+                quote!(#index)
+            })
+            .collect(),
+    };
+    let emm_v = quote!(::rb_okvs::types::EmmV);
+    let vars: Vec<_> = (0..fields.len()).map(|i| format_ident!("f{}", i)).collect();
+    let construct = match fields {
+        Fields::Named(_) => quote!(Self { #(#members: #vars),* }),
+        _ => quote!(Self(#(#vars),*)),
+    };
+
+    let decode = quote! {
+        let mut offset = 0;
+        #(
+            let #vars = {
+                let len = <#types as #emm_v>::len();
+                offset += len;
+                <#types as #emm_v>::decode(&b[offset - len..offset])
+            };
+        )*
+        #construct
+    };
+
+    let ident = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics #emm_v for #ident #ty_generics #where_clause {
+            fn len() -> usize {
+                0 #(+ <#types as #emm_v>::len())*
+            }
+
+            fn encode(&self) -> ::std::vec::Vec<u8> {
+                let mut bytes = ::std::vec::Vec::with_capacity(<Self as #emm_v>::len());
+                #(bytes.extend_from_slice(&#emm_v::encode(&self.#members));)*
+                bytes
+            }
+
+            fn decode(b: &[u8]) -> Self {
+                #decode
+            }
+        }
+    })
+}
@@ -0,0 +1,172 @@
+//! Measures encode and decode throughput over a grid of parameters and
+//! writes one CSV row per point, to pick parameters for a machine.
+//!
+//! ```text
+//! cargo run --release --example bench -- \
+//!     [--log-n 10..24] [--epsilon 0.1,0.2] [--value-size 8,32] [--threads 1,4] [--out bench.csv]
+//! ```
+//!
+//! `n` sweeps powers of two over the inclusive `--log-n` range. Encodes run
+//! [`RbOkvs::par_encode`] and decodes [`Okvs::decode_batch`] on that many
+//! threads. The rate is pairs per encoding value, `n / columns`.
+
+use std::env;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::ops::RangeInclusive;
+use std::process;
+use std::str::FromStr;
+use std::time::Instant;
+
+use rb_okvs::error::{Error, Result};
+use rb_okvs::executor::StdThreads;
+use rb_okvs::okvs::RbOkvs;
+use rb_okvs::types::{Okvs, OkvsKey, OkvsValue, Pair};
+
+const USAGE: &str = "usage: bench [--log-n <min>..<max>] [--epsilon <e,..>] \
+                     [--value-size <bytes,..>] [--threads <t,..>] [--out <file.csv>]";
+const HEADER: &str = "n,epsilon,value_size,threads,columns,encode_mb_per_s,decode_ops_per_s,rate";
+
+/// Dispatches on a runtime value size to the `OkvsValue` of that size.
+macro_rules! with_size {
+    ($size:expr, $f:ident($($arg:expr),*)) => {
+        match $size {
+            8 => $f::<8>($($arg),*),
+            16 => $f::<16>($($arg),*),
+            32 => $f::<32>($($arg),*),
+            64 => $f::<64>($($arg),*),
+            128 => $f::<128>($($arg),*),
+            size => Err(Error::Config(format!("unsupported value size {size}"))),
+        }
+    };
+}
+
+struct Sweep {
+    log_n:       RangeInclusive<u32>,
+    epsilons:    Vec<f64>,
+    value_sizes: Vec<usize>,
+    threads:     Vec<usize>,
+}
+
+struct Row {
+    columns:          usize,
+    encode_mb_per_s:  f64,
+    decode_ops_per_s: f64,
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    if let Err(e) = run(&args) {
+        eprintln!("bench: {e}\n{USAGE}");
+        process::exit(1);
+    }
+}
+
+fn run(args: &[&str]) -> Result<()> {
+    let sweep = Sweep {
+        log_n: match flag(args, "--log-n") {
+            Some(range) => parse_range(range)?,
+            None => 10..=24,
+        },
+        epsilons: list(args, "--epsilon", "0.1")?,
+        value_sizes: list(args, "--value-size", "8")?,
+        threads: list(args, "--threads", "1")?,
+    };
+    let mut out: Box<dyn Write> = match flag(args, "--out") {
+        Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+        None => Box::new(io::stdout().lock()),
+    };
+
+    writeln!(out, "{HEADER}")?;
+    for log_n in sweep.log_n.clone() {
+        let n = 1usize << log_n;
+        for &epsilon in &sweep.epsilons {
+            for &value_size in &sweep.value_sizes {
+                for &threads in &sweep.threads {
+                    // A failed encode, e.g. at too small an epsilon, skips
+                    // its point rather than the sweep
+                    match with_size!(value_size, measure(n, epsilon, threads)) {
+                        Ok(row) => writeln!(
+                            out,
+                            "{n},{epsilon},{value_size},{threads},{},{:.2},{:.0},{:.4}",
+                            row.columns,
+                            row.encode_mb_per_s,
+                            row.decode_ops_per_s,
+                            n as f64 / row.columns as f64,
+                        )?,
+                        Err(e) => eprintln!(
+                            "bench: n={n} epsilon={epsilon} value_size={value_size} \
+                             threads={threads}: {e}"
+                        ),
+                    }
+                    out.flush()?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn measure<const N: usize>(n: usize, epsilon: f64, threads: usize) -> Result<Row> {
+    let okvs = RbOkvs::builder().epsilon(epsilon).build(n)?;
+    let pairs: Vec<Pair<OkvsKey, OkvsValue<N>>> = (0..n as u64)
+        .map(|i| {
+            let mut value = [0u8; N];
+            value[..8].copy_from_slice(&i.wrapping_mul(0x9e37_79b9_7f4a_7c15).to_le_bytes());
+            (OkvsKey(i.to_le_bytes()), OkvsValue(value))
+        })
+        .collect();
+    let keys: Vec<OkvsKey> = pairs.iter().map(|(k, _)| k.clone()).collect();
+    let executor = StdThreads::new(threads);
+
+    let now = Instant::now();
+    let encoding = okvs.par_encode(&executor, &pairs)?;
+    let encode_secs = now.elapsed().as_secs_f64();
+
+    let now = Instant::now();
+    let decoded = okvs.decode_batch(&executor, &encoding, &keys)?;
+    let decode_secs = now.elapsed().as_secs_f64();
+    if decoded.iter().zip(&pairs).any(|(d, (_, v))| d != v) {
+        return Err(Error::Message(
+            "decoded values differ from the input".into(),
+        ));
+    }
+
+    Ok(Row {
+        columns: okvs.columns(),
+        encode_mb_per_s: (n * N) as f64 / 1e6 / encode_secs.max(f64::EPSILON),
+        decode_ops_per_s: n as f64 / decode_secs.max(f64::EPSILON),
+    })
+}
+
+fn flag<'a>(args: &[&'a str], name: &str) -> Option<&'a str> {
+    args.windows(2).find(|w| w[0] == name).map(|w| w[1])
+}
+
+/// The comma-separated values of flag `name`, or of `default` if absent.
+fn list<T: FromStr>(args: &[&str], name: &str, default: &str) -> Result<Vec<T>> {
+    flag(args, name)
+        .unwrap_or(default)
+        .split(',')
+        .map(|item| {
+            item.trim()
+                .parse()
+                .map_err(|_| Error::Config(format!("bad {name} value {item:?}")))
+        })
+        .collect()
+}
+
+/// `min..max`, inclusive, with both ends at most 40.
+fn parse_range(range: &str) -> Result<RangeInclusive<u32>> {
+    let bad = || Error::Config(format!("bad --log-n range {range:?}"));
+    let (min, max) = range.split_once("..").ok_or_else(bad)?;
+    let (min, max): (u32, u32) = (
+        min.parse().map_err(|_| bad())?,
+        max.parse().map_err(|_| bad())?,
+    );
+    if min > max || max > 40 {
+        return Err(bad());
+    }
+    Ok(min..=max)
+}
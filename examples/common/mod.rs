@@ -0,0 +1,89 @@
+//! The demo multi-map shared by the EMM examples: key `i` maps to the
+//! multiples of `i` below 100. `setup` writes the server's encoding to
+//! `<dir>/encoding.bin`, the client keys, sealed under a passphrase, to
+//! `<dir>/state.bin`, and the fingerprint of the OKVS the client's tokens
+//! must carry to `<dir>/fingerprint.bin`.
+
+// Each example uses only part of this module
+#![allow(dead_code)]
+
+use std::fs;
+use std::path::Path;
+
+use rb_okvs::cipher::AesGcmCipher;
+use rb_okvs::emm::{okvs_v_size, ClientState, EmmPair, VhEmm, VhEmmClient};
+use rb_okvs::error::{Error, Result};
+use rb_okvs::okvs::RbOkvs;
+use rb_okvs::types::{EmmV, Encoding, OkvsValue};
+
+pub const OKVS_V_SIZE: usize = okvs_v_size::<AesGcmCipher>(8);
+
+pub struct Value(pub u64);
+
+impl EmmV for Value {
+    fn len() -> usize {
+        8
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        self.0.to_le_bytes().into()
+    }
+
+    fn decode(b: &[u8]) -> Self {
+        Self(u64::from_le_bytes(b.try_into().unwrap()))
+    }
+}
+
+/// The values of key `i` in the demo multi-map, empty past its keys.
+pub fn expected(i: u64) -> Vec<u64> {
+    match i {
+        1..=99 => (i..100).step_by(i as usize).collect(),
+        _ => vec![],
+    }
+}
+
+pub fn setup(dir: &Path, passphrase: &str) -> Result<()> {
+    let pairs: Vec<EmmPair<u64, Vec<Value>>> = (1..100u64)
+        .map(|i| (i, expected(i).into_iter().map(Value).collect()))
+        .collect();
+    // A length entry per key
+    let kv_count = pairs.iter().map(|(_, v)| v.len() + 1).sum::<usize>();
+
+    let state = ClientState::new_random();
+    let okvs = RbOkvs::new(kv_count);
+    let fingerprint = okvs.fingerprint();
+    let emm = VhEmm::<RbOkvs, 8, OKVS_V_SIZE>::new(okvs).setup(pairs, &state)?;
+
+    // kv_count (u64 LE) || encoding
+    let mut bytes = (kv_count as u64).to_le_bytes().to_vec();
+    for value in &emm {
+        bytes.extend_from_slice(&value.0);
+    }
+    fs::create_dir_all(dir)?;
+    fs::write(dir.join("encoding.bin"), bytes)?;
+    fs::write(dir.join("state.bin"), state.seal(passphrase.as_bytes()))?;
+    fs::write(dir.join("fingerprint.bin"), fingerprint)?;
+    println!("{kv_count} entries written to {}", dir.display());
+    Ok(())
+}
+
+pub fn load_encoding(dir: &Path) -> Result<(RbOkvs, Encoding<OkvsValue<OKVS_V_SIZE>>)> {
+    let bytes = fs::read(dir.join("encoding.bin"))?;
+    if bytes.len() < 8 || (bytes.len() - 8) % OKVS_V_SIZE != 0 {
+        return Err(Error::Message("malformed encoding file".into()));
+    }
+    let kv_count = u64::from_le_bytes(bytes[..8].try_into().unwrap()) as usize;
+    let emm = bytes[8..]
+        .chunks_exact(OKVS_V_SIZE)
+        .map(|chunk| OkvsValue(chunk.try_into().unwrap()))
+        .collect();
+    Ok((RbOkvs::new(kv_count), emm))
+}
+
+pub fn load_client(dir: &Path, passphrase: &str) -> Result<VhEmmClient> {
+    let state = ClientState::unseal(&fs::read(dir.join("state.bin"))?, passphrase.as_bytes())?;
+    let fingerprint = fs::read(dir.join("fingerprint.bin"))?
+        .try_into()
+        .map_err(|_| Error::Message("malformed fingerprint file".into()))?;
+    Ok(VhEmmClient::new(state, fingerprint))
+}
@@ -0,0 +1,80 @@
+//! Sets up the demo EMM and queries an `emm-server` over HTTP.
+//!
+//! ```text
+//! cargo run --example emm-client --features http -- setup <dir> <passphrase>
+//! cargo run --example emm-client --features http -- query <dir> <passphrase> 127.0.0.1:8080 <key>
+//! cargo run --example emm-client --features http -- check <dir> <passphrase> 127.0.0.1:8080
+//! ```
+//!
+//! `check` queries every key of the demo multi-map and a few missing ones,
+//! exiting with an error at the first wrong answer, so a server and client
+//! pair doubles as an end-to-end test.
+
+mod common;
+
+use std::env;
+use std::path::Path;
+
+use common::{expected, load_client, setup, Value, OKVS_V_SIZE};
+use rb_okvs::emm::VhEmmClient;
+use rb_okvs::error::{Error, Result};
+use rb_okvs::http::HttpEmmServer;
+use rb_okvs::server::query;
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let args: Vec<String> = env::args().skip(1).collect();
+    match args.iter().map(String::as_str).collect::<Vec<_>>()[..] {
+        ["setup", dir, passphrase] => setup(Path::new(dir), passphrase),
+        ["query", dir, passphrase, addr, key] => {
+            let key: u64 = key
+                .parse()
+                .map_err(|_| Error::Message(format!("key {key}")))?;
+            let (client, server) = connect(dir, passphrase, addr).await?;
+            match lookup(&client, &server, key).await? {
+                Some(values) => println!("{key}: {values:?}"),
+                None => println!("{key}: not found"),
+            }
+            Ok(())
+        }
+        ["check", dir, passphrase, addr] => {
+            let (client, server) = connect(dir, passphrase, addr).await?;
+            for key in 0..110u64 {
+                let values = lookup(&client, &server, key).await?.unwrap_or_default();
+                if values != expected(key) {
+                    return Err(Error::Message(format!("{key}: got {values:?}")));
+                }
+            }
+            println!("110 keys checked");
+            Ok(())
+        }
+        _ => {
+            eprintln!(
+                "usage: emm-client setup <dir> <passphrase> \
+                 | query <dir> <passphrase> <addr> <key> | check <dir> <passphrase> <addr>"
+            );
+            std::process::exit(2);
+        }
+    }
+}
+
+async fn connect(
+    dir: &str,
+    passphrase: &str,
+    addr: &str,
+) -> Result<(VhEmmClient, HttpEmmServer<OKVS_V_SIZE>)> {
+    let client = load_client(Path::new(dir), passphrase)?;
+    Ok((client, HttpEmmServer::connect(addr).await?))
+}
+
+async fn lookup(
+    client: &VhEmmClient,
+    server: &HttpEmmServer<OKVS_V_SIZE>,
+    key: u64,
+) -> Result<Option<Vec<u64>>> {
+    match query::<_, _, _, _, Value, _>(client, server, &key).await {
+        Ok(values) => Ok(Some(values.iter().map(|v| v.0).collect())),
+        Err(Error::NotFound) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
@@ -0,0 +1,35 @@
+//! Serves a volume-hiding EMM over HTTP, answering `POST /query`.
+//!
+//! ```text
+//! cargo run --example emm-client --features http -- setup <dir> <passphrase>
+//! cargo run --example emm-server --features http -- <dir> 127.0.0.1:8080
+//! ```
+//!
+//! The server only reads `<dir>/encoding.bin`.
+
+mod common;
+
+use std::env;
+use std::path::Path;
+use std::sync::Arc;
+
+use common::{load_encoding, OKVS_V_SIZE};
+use rb_okvs::emm::VhEmmServer;
+use rb_okvs::error::Result;
+use rb_okvs::http::serve_http;
+use rb_okvs::okvs::RbOkvs;
+use tokio::net::TcpListener;
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let [dir, addr] = &args[..] else {
+        eprintln!("usage: emm-server <dir> <addr>");
+        std::process::exit(2);
+    };
+    let (okvs, emm) = load_encoding(Path::new(dir))?;
+    let server = Arc::new(VhEmmServer::<RbOkvs, 8, OKVS_V_SIZE>::new(okvs, emm));
+    let listener = TcpListener::bind(addr).await?;
+    println!("serving {} on http://{}", dir, listener.local_addr()?);
+    serve_http(listener, server).await
+}
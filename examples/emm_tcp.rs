@@ -0,0 +1,62 @@
+//! Serves a volume-hiding EMM over TCP.
+//!
+//! ```text
+//! cargo run --example emm_tcp --features tokio -- setup <dir> <passphrase>
+//! cargo run --example emm_tcp --features tokio -- serve <dir> 127.0.0.1:7878
+//! cargo run --example emm_tcp --features tokio -- query <dir> <passphrase> 127.0.0.1:7878 <key>
+//! ```
+//!
+//! `setup` encodes the demo multi-map of `common`. The server only reads the
+//! encoding.
+
+mod common;
+
+use std::env;
+use std::path::Path;
+use std::sync::Arc;
+
+use common::{load_client, load_encoding, setup, Value, OKVS_V_SIZE};
+use rb_okvs::emm::VhEmmServer;
+use rb_okvs::error::{Error, Result};
+use rb_okvs::okvs::RbOkvs;
+use rb_okvs::server::query;
+use rb_okvs::tcp::{serve, RemoteEmmServer};
+use tokio::net::TcpListener;
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let args: Vec<String> = env::args().skip(1).collect();
+    match args.iter().map(String::as_str).collect::<Vec<_>>()[..] {
+        ["setup", dir, passphrase] => setup(Path::new(dir), passphrase),
+        ["serve", dir, addr] => {
+            let (okvs, emm) = load_encoding(Path::new(dir))?;
+            let server = Arc::new(VhEmmServer::<RbOkvs, 8, OKVS_V_SIZE>::new(okvs, emm));
+            let listener = TcpListener::bind(addr).await?;
+            println!("serving {} on {}", dir, listener.local_addr()?);
+            serve(listener, server).await
+        }
+        ["query", dir, passphrase, addr, key] => {
+            let key: u64 = key
+                .parse()
+                .map_err(|_| Error::Message(format!("key {key}")))?;
+            let client = load_client(Path::new(dir), passphrase)?;
+            let server = RemoteEmmServer::<OKVS_V_SIZE>::connect(addr).await?;
+            match query::<_, _, _, _, Value, _>(&client, &server, &key).await {
+                Ok(values) => {
+                    let values: Vec<u64> = values.iter().map(|v| v.0).collect();
+                    println!("{key}: {values:?}");
+                }
+                Err(Error::NotFound) => println!("{key}: not found"),
+                Err(e) => return Err(e),
+            }
+            Ok(())
+        }
+        _ => {
+            eprintln!(
+                "usage: emm_tcp setup <dir> <passphrase> | serve <dir> <addr> \
+                 | query <dir> <passphrase> <addr> <key>"
+            );
+            std::process::exit(2);
+        }
+    }
+}
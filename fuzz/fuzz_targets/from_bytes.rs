@@ -0,0 +1,24 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use rb_okvs::circuit_psi::CircuitPsiRequest;
+use rb_okvs::emm::{ClientState, QueryResponse, QueryToken};
+use rb_okvs::opprf::{OpprfRequest, OpprfResponse};
+use rb_okvs::psi::{PsiCaResponse, PsiEncoding, PsiEvaluation, PsiRequest, PsiResponse};
+use rb_okvs::set_okvs::PackedEncoding;
+
+// Parsers reject malformed input with an error, never a panic.
+fuzz_target!(|data: &[u8]| {
+    let _ = PackedEncoding::from_bytes(data);
+    let _ = ClientState::from_bytes(data);
+    let _ = QueryToken::from_bytes(data);
+    let _ = QueryResponse::<16>::from_bytes(data);
+    let _ = PsiRequest::from_bytes(data);
+    let _ = PsiResponse::from_bytes(data);
+    let _ = PsiEncoding::from_bytes(data);
+    let _ = PsiEvaluation::from_bytes(data);
+    let _ = PsiCaResponse::from_bytes(data);
+    let _ = OpprfRequest::from_bytes(data);
+    let _ = OpprfResponse::<16>::from_bytes(data);
+    let _ = CircuitPsiRequest::from_bytes(data);
+});
@@ -0,0 +1,41 @@
+#![no_main]
+
+use arbitrary::Arbitrary;
+use libfuzzer_sys::fuzz_target;
+use rb_okvs::okvs::RbOkvs;
+use rb_okvs::types::{Okvs, OkvsKey, OkvsValue};
+
+#[derive(Arbitrary, Debug)]
+struct Input {
+    kv_count:   u16,
+    band_width: Option<u16>,
+    epsilon:    Option<f64>,
+    encoding:   Vec<[u8; 2]>,
+}
+
+// Arbitrary parameters either fail to build or give an instance that encodes
+// its keys, and decoding a short encoding fails rather than panics.
+fuzz_target!(|input: Input| {
+    let mut builder = RbOkvs::builder();
+    if let Some(band_width) = input.band_width {
+        builder = builder.band_width(band_width as usize);
+    }
+    if let Some(epsilon) = input.epsilon {
+        builder = builder.epsilon(epsilon);
+    }
+    let Ok(okvs) = builder.build(input.kv_count as usize) else {
+        return;
+    };
+
+    let pairs: Vec<_> = (0..input.kv_count as u64)
+        .map(|i| (OkvsKey(i.to_le_bytes()), OkvsValue((i as u16).to_le_bytes())))
+        .collect();
+    if let Ok(encoding) = okvs.encode(pairs.clone()) {
+        for (k, v) in &pairs {
+            assert_eq!(okvs.decode(&encoding, k).unwrap(), *v);
+        }
+    }
+
+    let encoding: Vec<_> = input.encoding.into_iter().map(OkvsValue).collect();
+    let _ = okvs.decode(&encoding, &OkvsKey([0u8; 8]));
+});
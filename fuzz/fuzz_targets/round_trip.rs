@@ -0,0 +1,24 @@
+#![no_main]
+
+use std::collections::HashSet;
+
+use libfuzzer_sys::fuzz_target;
+use rb_okvs::okvs::RbOkvs;
+use rb_okvs::types::{Okvs, OkvsKey, OkvsValue};
+
+// Every key of a successful encoding decodes to its value.
+fuzz_target!(|pairs: Vec<([u8; 8], [u8; 4])>| {
+    let mut seen = HashSet::new();
+    let pairs: Vec<_> = pairs
+        .into_iter()
+        .filter(|(k, _)| seen.insert(*k))
+        .map(|(k, v)| (OkvsKey(k), OkvsValue(v)))
+        .collect();
+
+    let okvs = RbOkvs::new(pairs.len());
+    if let Ok(encoding) = okvs.encode(pairs.clone()) {
+        for (k, v) in &pairs {
+            assert_eq!(okvs.decode(&encoding, k).unwrap(), *v);
+        }
+    }
+});
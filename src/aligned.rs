@@ -0,0 +1,110 @@
+use std::ops::Deref;
+use std::slice;
+
+use crate::error::{Error, Result};
+use crate::hasher::KeyHasher;
+use crate::okvs::RbOkvs;
+use crate::types::{OkvsK, OkvsValue};
+
+/// Alignment and granularity of [`AlignedEncoding`] buffers, the width of
+/// an AVX2 register.
+pub const ALIGNMENT: usize = 32;
+
+#[derive(Clone, Copy)]
+#[repr(C, align(32))]
+struct Block([u8; ALIGNMENT]);
+
+/// An encoding in a buffer aligned to [`ALIGNMENT`] bytes and zero-padded
+/// to a whole number of blocks, so SIMD decoders can load it without
+/// unaligned or partial reads. Together with
+/// [`pad_columns`](crate::okvs::RbOkvsBuilder::pad_columns) no block of a
+/// 64-column chunk straddles the end of the encoding.
+#[derive(Clone)]
+pub struct AlignedEncoding<const N: usize> {
+    blocks: Vec<Block>,
+    len:    usize,
+}
+
+impl<const N: usize> AlignedEncoding<N> {
+    pub fn new(encoding: &[OkvsValue<N>]) -> Self {
+        let bytes = encoding.len() * N;
+        let mut blocks = vec![Block([0; ALIGNMENT]); bytes.div_ceil(ALIGNMENT)];
+        for (i, value) in encoding.iter().enumerate() {
+            for (j, byte) in value.0.iter().enumerate() {
+                let offset = i * N + j;
+                blocks[offset / ALIGNMENT].0[offset % ALIGNMENT] = *byte;
+            }
+        }
+        Self {
+            blocks,
+            len: encoding.len(),
+        }
+    }
+
+    /// The whole buffer, padding included: a multiple of [`ALIGNMENT`]
+    /// bytes starting at an aligned address.
+    pub fn as_bytes(&self) -> &[u8] {
+        // SAFETY: `Block` is a plain byte array without padding
+        unsafe { slice::from_raw_parts(self.blocks.as_ptr().cast(), self.blocks.len() * ALIGNMENT) }
+    }
+
+    /// Decodes `key` like [`Okvs::decode`](crate::okvs::Okvs::decode).
+    pub fn decode<const W: usize, H: KeyHasher>(
+        &self,
+        okvs: &RbOkvs<W, H>,
+        key: &impl OkvsK,
+    ) -> Result<OkvsValue<N>> {
+        if self.len < okvs.columns() {
+            return Err(Error::EncodingTooShort {
+                required: okvs.columns(),
+                actual: self.len,
+            });
+        }
+        let (start, band) = okvs.locate(key);
+        okvs.decode_with(start, &band, self)
+    }
+}
+
+impl<const N: usize> Deref for AlignedEncoding<N> {
+    type Target = [OkvsValue<N>];
+
+    fn deref(&self) -> &[OkvsValue<N>] {
+        // SAFETY: `OkvsValue<N>` is a transparent `[u8; N]`, so it has no
+        // alignment requirement, and the blocks hold at least `len * N`
+        // bytes written by `new`
+        unsafe { slice::from_raw_parts(self.blocks.as_ptr().cast(), self.len) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{Okvs, OkvsKey, Pair};
+
+    #[test]
+    fn test_aligned_encoding() {
+        let pairs: Vec<Pair<OkvsKey, OkvsValue<20>>> = (0..1000u64)
+            .map(|i| (OkvsKey(i.to_le_bytes()), OkvsValue([i as u8; 20])))
+            .collect();
+        let rb_okvs = RbOkvs::builder()
+            .pad_columns(true)
+            .build(pairs.len())
+            .unwrap();
+        let encoding = rb_okvs.encode_ref(&pairs).unwrap();
+        let aligned = AlignedEncoding::new(&encoding);
+
+        assert_eq!(aligned.as_bytes().as_ptr() as usize % ALIGNMENT, 0);
+        assert_eq!(aligned.as_bytes().len() % ALIGNMENT, 0);
+        assert_eq!(aligned.len(), 1152);
+        assert_eq!(&aligned[..], &encoding[..]);
+        for (k, v) in &pairs {
+            assert_eq!(aligned.decode(&rb_okvs, k).unwrap(), *v);
+        }
+
+        let short = AlignedEncoding::new(&encoding[1..]);
+        assert!(matches!(
+            short.decode(&rb_okvs, &pairs[0].0),
+            Err(Error::EncodingTooShort { .. })
+        ));
+    }
+}
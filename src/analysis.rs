@@ -0,0 +1,120 @@
+//! The banded system an [`RbOkvs`] builds for a key set, with statistics to
+//! check parameter choices against a real key distribution offline.
+
+use crate::band::Band;
+use crate::error::{Error, Result};
+use crate::hasher::KeyHasher;
+use crate::okvs::RbOkvs;
+use crate::solver::gauss_eliminate;
+use crate::types::{OkvsK, OkvsValue};
+
+/// Outcome of [`RbOkvs::analyze`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct Analysis<const W: usize = 4> {
+    pub columns:         usize,
+    pub band_width:      usize,
+    /// Start of every row, sorted.
+    pub start_pos:       Vec<usize>,
+    /// Band of every row, in the order of `start_pos`.
+    pub bands:           Vec<Band<W>>,
+    /// Rows per bucket of equal width over the start positions.
+    pub start_histogram: Vec<usize>,
+    /// Most bands covering one column.
+    pub max_overlap:     usize,
+    /// `None` when elimination fails.
+    pub pivot_spread:    Option<PivotSpread>,
+}
+
+/// Distance of pivots from the start of their row after elimination. A
+/// band wide enough for its keys keeps them well below the band width.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PivotSpread {
+    pub max:  usize,
+    pub mean: f64,
+}
+
+impl<const W: usize, H: KeyHasher> RbOkvs<W, H> {
+    /// The system `keys` hash to, with a start histogram of `buckets`
+    /// buckets. Costs a forward elimination.
+    pub fn analyze<K: OkvsK>(&self, keys: &[K], buckets: usize) -> Result<Analysis<W>> {
+        if buckets == 0 {
+            return Err(Error::Config("analysis needs at least one bucket".into()));
+        }
+        let mut rows: Vec<(usize, Band<W>)> = keys.iter().map(|k| self.locate(k)).collect();
+        rows.sort_by_key(|(start, _)| *start);
+        let (start_pos, bands): (Vec<usize>, Vec<Band<W>>) = rows.into_iter().unzip();
+
+        let range = self.columns() - self.band_width();
+        let mut start_histogram = vec![0; buckets];
+        for start in &start_pos {
+            start_histogram[start * buckets / range] += 1;
+        }
+
+        // Bands entering and leaving the cover of each column
+        let mut delta = vec![0isize; self.columns() + 1];
+        for start in &start_pos {
+            delta[*start] += 1;
+            delta[start + self.band_width()] -= 1;
+        }
+        let max_overlap = delta
+            .iter()
+            .scan(0, |depth, d| {
+                *depth += d;
+                Some(*depth as usize)
+            })
+            .max()
+            .unwrap_or(0);
+
+        // Eliminates a copy, so the bands are returned as hashed
+        let mut eliminated = bands.clone();
+        let mut y = vec![OkvsValue([0u8; 1]); bands.len()];
+        let pivot_spread = gauss_eliminate(&mut y, &mut eliminated, &start_pos)
+            .ok()
+            .map(|pivots| {
+                let offsets = pivots.iter().zip(&start_pos).map(|(p, s)| p - s);
+                PivotSpread {
+                    max: offsets.clone().max().unwrap_or(0),
+                    mean: offsets.sum::<usize>() as f64 / pivots.len().max(1) as f64,
+                }
+            });
+
+        Ok(Analysis {
+            columns: self.columns(),
+            band_width: self.band_width(),
+            start_pos,
+            bands,
+            start_histogram,
+            max_overlap,
+            pivot_spread,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::OkvsKey;
+
+    #[test]
+    fn test_analyze() {
+        let keys: Vec<OkvsKey> = (0..1000u64).map(|i| OkvsKey(i.to_le_bytes())).collect();
+        let rb_okvs = RbOkvs::new(keys.len());
+        let analysis = rb_okvs.analyze(&keys, 10).unwrap();
+
+        assert_eq!(analysis.start_histogram.iter().sum::<usize>(), 1000);
+        let mut rows: Vec<_> = keys.iter().map(|k| rb_okvs.locate(k)).collect();
+        rows.sort_by_key(|(start, _)| *start);
+        assert!(rows.into_iter().eq(analysis
+            .start_pos
+            .iter()
+            .copied()
+            .zip(analysis.bands.iter().copied())));
+        // About band_width / (1 + epsilon) rows cover a column on average
+        assert!(analysis.max_overlap > 100 && analysis.max_overlap < 200);
+        let spread = analysis.pivot_spread.unwrap();
+        assert!(spread.mean < spread.max as f64 && spread.max < 128);
+
+        let narrow = RbOkvs::builder().band_width(8).build(1000).unwrap();
+        assert_eq!(narrow.analyze(&keys, 10).unwrap().pivot_spread, None);
+    }
+}
@@ -0,0 +1,183 @@
+//! Empirical check of the obliviousness of an [`Okvs`]: with random values,
+//! an encoding must not reveal which keys it was made for.
+
+use aes_gcm::aead::{rand_core::RngCore, OsRng};
+
+use crate::error::{Error, Result};
+use crate::types::{Okvs, OkvsK, OkvsValue, Pair};
+
+/// Outcome of [`audit`]. A small `p_value` means the encodings of the two
+/// key sets were told apart.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AuditReport {
+    pub trials:             usize,
+    /// Chi-squared statistic over the columns.
+    pub statistic:          f64,
+    pub degrees_of_freedom: usize,
+    pub p_value:            f64,
+}
+
+/// Encodes the keys of `a` and of `b` `trials` times each, under fresh random
+/// values, and tests whether the bits of the encodings follow the same
+/// distribution for both.
+///
+/// Per column, the number of encodings with the low bit set is compared
+/// between the two sets by a two-proportion z-test; the squared scores add
+/// up to a chi-squared statistic with one degree of freedom per column.
+/// Columns constant across both sets carry no information and are skipped.
+pub fn audit<O: Okvs, K: OkvsK + Clone>(
+    okvs: &O,
+    a: &[K],
+    b: &[K],
+    trials: usize,
+) -> Result<AuditReport> {
+    if trials == 0 {
+        return Err(Error::Config("audit needs at least one trial".into()));
+    }
+    let ones_a = count_ones(okvs, a, trials)?;
+    let ones_b = count_ones(okvs, b, trials)?;
+    if ones_a.len() != ones_b.len() {
+        return Err(Error::Config(format!(
+            "encodings of {} and {} columns",
+            ones_a.len(),
+            ones_b.len()
+        )));
+    }
+
+    let n = trials as f64;
+    let mut statistic = 0.0;
+    let mut degrees_of_freedom = 0;
+    for (x, y) in ones_a.iter().zip(&ones_b) {
+        let pooled = (x + y) as f64 / (2.0 * n);
+        let variance = pooled * (1.0 - pooled) * 2.0 / n;
+        if variance == 0.0 {
+            continue;
+        }
+        statistic += (*x as f64 - *y as f64).powi(2) / (n * n) / variance;
+        degrees_of_freedom += 1;
+    }
+    Ok(AuditReport {
+        trials,
+        statistic,
+        degrees_of_freedom,
+        p_value: chi_squared_sf(statistic, degrees_of_freedom),
+    })
+}
+
+/// Per column, the number of `trials` encodings of `keys` whose low bit is
+/// set.
+fn count_ones<O: Okvs, K: OkvsK + Clone>(okvs: &O, keys: &[K], trials: usize) -> Result<Vec<u64>> {
+    let mut ones: Vec<u64> = vec![];
+    for _ in 0..trials {
+        let pairs: Vec<Pair<K, OkvsValue<1>>> = keys
+            .iter()
+            .map(|k| (k.clone(), OkvsValue([OsRng.next_u32() as u8])))
+            .collect();
+        let encoding = okvs.encode_with_rng(&pairs, &mut OsRng)?;
+        ones.resize(encoding.len(), 0);
+        for (count, v) in ones.iter_mut().zip(&encoding) {
+            *count += (v.0[0] & 1) as u64;
+        }
+    }
+    Ok(ones)
+}
+
+/// `P(X >= x)` for `X` chi-squared with `k` degrees of freedom, by the
+/// Wilson–Hilferty normal approximation.
+fn chi_squared_sf(x: f64, k: usize) -> f64 {
+    if k == 0 {
+        return 1.0;
+    }
+    let k = k as f64;
+    let s = 2.0 / (9.0 * k);
+    let z = ((x / k).cbrt() - (1.0 - s)) / s.sqrt();
+    0.5 * erfc(z / std::f64::consts::SQRT_2)
+}
+
+/// Abramowitz and Stegun 7.1.26, absolute error below 1.5e-7.
+fn erfc(x: f64) -> f64 {
+    let t = 1.0 / (1.0 + 0.3275911 * x.abs());
+    let poly = t
+        * (0.254829592
+            + t * (-0.284496736 + t * (1.421413741 + t * (-1.453152027 + t * 1.061405429))));
+    let r = poly * (-x * x).exp();
+    if x >= 0.0 {
+        r
+    } else {
+        2.0 - r
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::okvs::{Fingerprint, RbOkvs};
+    use crate::types::{Encoding, OkvsKey, OkvsV, RandomV};
+
+    fn keys(seed: u64, n: u64) -> Vec<OkvsKey<8>> {
+        (0..n)
+            .map(|i| OkvsKey((seed << 32 | i).to_le_bytes()))
+            .collect()
+    }
+
+    #[test]
+    fn test_audit() {
+        let okvs = RbOkvs::new(500);
+        let report = audit(&okvs, &keys(1, 500), &keys(1, 500), 200).unwrap();
+        assert!(report.p_value > 1e-6, "{report:?}");
+
+        let report = audit(&okvs, &keys(1, 500), &keys(2, 500), 200).unwrap();
+        assert!(report.p_value > 1e-6, "{report:?}");
+
+        assert!(audit(&okvs, &keys(1, 500), &keys(1, 500), 0).is_err());
+    }
+
+    /// Leaves free columns zero whatever the rng, like `encode`.
+    struct ZeroFree(RbOkvs);
+
+    impl Okvs for ZeroFree {
+        fn encode<K: OkvsK, V: OkvsV>(&self, input: Vec<Pair<K, V>>) -> Result<Encoding<V>> {
+            self.0.encode(input)
+        }
+
+        fn encode_ref<K: OkvsK, V: OkvsV>(&self, input: &[Pair<K, V>]) -> Result<Encoding<V>> {
+            self.0.encode_ref(input)
+        }
+
+        fn encode_with_rng<K, V, R>(&self, input: &[Pair<K, V>], _: &mut R) -> Result<Encoding<V>>
+        where
+            K: OkvsK,
+            V: RandomV,
+            R: RngCore + ?Sized,
+        {
+            self.0.encode_ref(input)
+        }
+
+        fn decode<V: OkvsV>(&self, encoding: &Encoding<V>, key: &impl OkvsK) -> Result<V> {
+            self.0.decode(encoding, key)
+        }
+
+        fn fingerprint(&self) -> Fingerprint {
+            self.0.fingerprint()
+        }
+    }
+
+    #[test]
+    fn test_audit_detects_zero_free_columns() {
+        // The smaller set leaves most columns free, so zero
+        let (a, b) = (keys(1, 500), keys(2, 100));
+        let report = audit(&ZeroFree(RbOkvs::new(500)), &a, &b, 200).unwrap();
+        assert!(report.p_value < 1e-6, "{report:?}");
+
+        let report = audit(&RbOkvs::new(500), &a, &b, 200).unwrap();
+        assert!(report.p_value > 1e-6, "{report:?}");
+    }
+
+    #[test]
+    fn test_chi_squared_sf() {
+        assert!((chi_squared_sf(100.0, 100) - 0.481).abs() < 0.01);
+        assert!((chi_squared_sf(124.3, 100) - 0.05).abs() < 0.005);
+        assert!(chi_squared_sf(300.0, 100) < 1e-12);
+        assert_eq!(chi_squared_sf(5.0, 0), 1.0);
+    }
+}
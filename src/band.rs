@@ -0,0 +1,213 @@
+use std::ops::BitXorAssign;
+
+/// A row's random band: bit `i` is the coefficient of column `start + i`.
+///
+/// Backed by `W` little-endian `u64` words, so it holds up to `64 * W` bits.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Band<const W: usize = 4>(pub [u64; W]);
+
+impl<const W: usize> Default for Band<W> {
+    fn default() -> Self {
+        Self([0u64; W])
+    }
+}
+
+impl<const W: usize> BitXorAssign<&Band<W>> for Band<W> {
+    fn bitxor_assign(&mut self, rhs: &Band<W>) {
+        for (a, b) in self.0.iter_mut().zip(rhs.0) {
+            *a ^= b;
+        }
+    }
+}
+
+impl<const W: usize> From<u64> for Band<W> {
+    fn from(v: u64) -> Self {
+        let mut band = Self::default();
+        band.0[0] = v;
+        band
+    }
+}
+
+impl<const W: usize> Band<W> {
+    pub const BITS: usize = 64 * W;
+
+    /// Little-endian bytes, at most `8 * W` of them.
+    pub fn from_le_bytes(bytes: &[u8]) -> Self {
+        assert!(bytes.len() <= 8 * W);
+
+        let mut band = Self::default();
+        for (i, chunk) in bytes.chunks(8).enumerate() {
+            let mut word = [0u8; 8];
+            word[..chunk.len()].copy_from_slice(chunk);
+            band.0[i] = u64::from_le_bytes(word);
+        }
+        band
+    }
+
+    /// The low `bits` bits of little-endian `bytes`, for bands whose width
+    /// is not a multiple of 8.
+    pub fn from_le_bits(bytes: &[u8], bits: usize) -> Self {
+        let mut band = Self::from_le_bytes(&bytes[..bits.div_ceil(8)]);
+        for (i, w) in band.0.iter_mut().enumerate() {
+            let keep = bits.saturating_sub(i * 64);
+            if keep < 64 {
+                *w &= (1u64 << keep) - 1;
+            }
+        }
+        band
+    }
+
+    /// The `8 * W` little-endian bytes of the band, whatever the byte order
+    /// of the host.
+    pub fn to_le_bytes(&self) -> Vec<u8> {
+        self.0.iter().flat_map(|w| w.to_le_bytes()).collect()
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.0.iter().all(|w| *w == 0)
+    }
+
+    pub fn bit(&self, index: usize) -> bool {
+        self.0[index / 64] >> (index % 64) & 1 == 1
+    }
+
+    /// Index of the lowest set bit, or `Self::BITS` if the band is zero.
+    pub fn trailing_zeros(&self) -> usize {
+        for (i, w) in self.0.iter().enumerate() {
+            if *w != 0 {
+                return i * 64 + w.trailing_zeros() as usize;
+            }
+        }
+        Self::BITS
+    }
+
+    /// Index of the highest set bit plus one, or 0 if the band is zero.
+    pub fn bits(&self) -> usize {
+        for (i, w) in self.0.iter().enumerate().rev() {
+            if *w != 0 {
+                return i * 64 + 64 - w.leading_zeros() as usize;
+            }
+        }
+        0
+    }
+
+    /// `self ^= other >> shift`, i.e. XORs in a band starting `shift` columns
+    /// earlier. Bits of `other` below `shift` are dropped.
+    pub fn xor_shr(&mut self, other: &Self, shift: usize) {
+        let words = shift / 64;
+        let bits = shift % 64;
+
+        for i in 0..W.saturating_sub(words) {
+            let lo = other.0[i + words] >> bits;
+            let hi = match (bits, other.0.get(i + words + 1)) {
+                (0, _) | (_, None) => 0,
+                (_, Some(next)) => next << (64 - bits),
+            };
+            self.0[i] ^= lo | hi;
+        }
+    }
+
+    /// `self ^= other << shift`, i.e. XORs in a band starting `shift` columns
+    /// later. Bits shifted past `Self::BITS` are dropped.
+    pub fn xor_shl(&mut self, other: &Self, shift: usize) {
+        let words = shift / 64;
+        let bits = shift % 64;
+
+        for i in words..W {
+            let hi = other.0[i - words] << bits;
+            let lo = match bits {
+                0 => 0,
+                _ if i == words => 0,
+                _ => other.0[i - words - 1] >> (64 - bits),
+            };
+            self.0[i] ^= hi | lo;
+        }
+    }
+
+    /// Calls `f` with the index of every set bit, in increasing order.
+    pub fn for_each_one(&self, mut f: impl FnMut(usize)) {
+        for (i, w) in self.0.iter().enumerate() {
+            let mut w = *w;
+            while w != 0 {
+                f(i * 64 + w.trailing_zeros() as usize);
+                w &= w - 1;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bits() {
+        let band = Band::<4>::from_le_bytes(&[0, 0, 0, 0, 0, 0, 0, 0, 0b110]);
+        assert!(!band.is_zero());
+        assert!(!band.bit(64));
+        assert!(band.bit(65));
+        assert!(band.bit(66));
+        assert_eq!(band.trailing_zeros(), 65);
+        assert_eq!(band.bits(), 67);
+        assert_eq!(Band::<4>::from_le_bits(&[0xff; 9], 67).bits(), 67);
+        assert_eq!(
+            Band::<4>::from_le_bits(&[0xff; 17], 130).0,
+            [!0, !0, 0b11, 0]
+        );
+
+        let zero = Band::<4>::default();
+        assert!(zero.is_zero());
+        assert_eq!(zero.trailing_zeros(), 256);
+        assert_eq!(zero.bits(), 0);
+    }
+
+    #[test]
+    fn test_xor_shr() {
+        let mut a = Band::<4>([0, 0, 0, 0]);
+        let b = Band::<4>([0, 1, 0, 1 << 63]);
+
+        a.xor_shr(&b, 0);
+        assert_eq!(a, b);
+
+        let mut a = Band::<4>::default();
+        a.xor_shr(&b, 1);
+        assert_eq!(a, Band([1 << 63, 0, 0, 1 << 62]));
+
+        let mut a = Band::<4>::default();
+        a.xor_shr(&b, 64);
+        assert_eq!(a, Band([1, 0, 1 << 63, 0]));
+
+        let mut a = Band::<4>::default();
+        a.xor_shr(&b, 255);
+        assert_eq!(a, Band([1, 0, 0, 0]));
+    }
+
+    #[test]
+    fn test_xor_shl() {
+        let b = Band::<4>([1 << 63, 1, 0, 1]);
+
+        let mut a = Band::<4>::default();
+        a.xor_shl(&b, 0);
+        assert_eq!(a, b);
+
+        let mut a = Band::<4>::default();
+        a.xor_shl(&b, 1);
+        assert_eq!(a, Band([0, 0b11, 0, 0b10]));
+
+        let mut a = Band::<4>::default();
+        a.xor_shl(&b, 64);
+        assert_eq!(a, Band([0, 1 << 63, 1, 0]));
+
+        let mut a = Band::<4>::from(1);
+        a.xor_shl(&Band::from(1), 255);
+        assert_eq!(a, Band([1, 0, 0, 1 << 63]));
+    }
+
+    #[test]
+    fn test_for_each_one() {
+        let band = Band::<2>([0b101, 1 << 63]);
+        let mut ones = vec![];
+        band.for_each_one(|i| ones.push(i));
+        assert_eq!(ones, vec![0, 2, 127]);
+    }
+}
@@ -0,0 +1,296 @@
+//! Encodes key-value files into RB-OKVS tables and queries them.
+//!
+//! ```text
+//! rb-okvs encode --input pairs.csv --out table.okvs
+//! rb-okvs decode --table table.okvs --key <key>
+//! rb-okvs info table.okvs
+//! ```
+//!
+//! The input has one `key,value` pair per line, split at the first comma.
+//! Values are stored with a tag of their key, so `decode` reports keys that
+//! were never encoded instead of printing garbage.
+
+use std::collections::HashSet;
+use std::env;
+use std::fs;
+use std::process;
+
+use aes_gcm::aead::rand_core::RngCore;
+use aes_gcm::aead::OsRng;
+use blake2::digest::{Update, VariableOutput};
+use blake2::Blake2bVar;
+use rb_okvs::error::{Error, Result};
+use rb_okvs::okvs::{RbOkvs, FORMAT_VERSION};
+use rb_okvs::types::{Okvs, OkvsKey, OkvsValue, Pair, Seed};
+
+const MAGIC: &[u8; 4] = b"RBOK";
+/// `magic || version (u8) || seed || kv_count (u64 LE) || width (u32 LE)`
+const HEADER_LEN: usize = 4 + 1 + 16 + 8 + 4;
+const TAG_LEN: usize = 8;
+/// Tag and value length prefix.
+const VALUE_OVERHEAD: usize = TAG_LEN + 2;
+/// Widths values are padded to; each is a separate instantiation.
+const WIDTHS: [usize; 8] = [16, 32, 64, 128, 256, 512, 1024, 4096];
+
+const USAGE: &str = "usage: rb-okvs encode --input <pairs.csv> --out <table.okvs>
+       rb-okvs decode --table <table.okvs> --key <key>
+       rb-okvs info <table.okvs>";
+
+/// Dispatches on a runtime value width to the `OkvsValue` of that size.
+macro_rules! with_width {
+    ($width:expr, $f:ident($($arg:expr),*)) => {
+        match $width {
+            16 => $f::<16>($($arg),*),
+            32 => $f::<32>($($arg),*),
+            64 => $f::<64>($($arg),*),
+            128 => $f::<128>($($arg),*),
+            256 => $f::<256>($($arg),*),
+            512 => $f::<512>($($arg),*),
+            1024 => $f::<1024>($($arg),*),
+            4096 => $f::<4096>($($arg),*),
+            width => Err(Error::Message(format!("unsupported value width {width}"))),
+        }
+    };
+}
+
+struct Header {
+    seed:     Seed,
+    kv_count: usize,
+    width:    usize,
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    let result = match args[..] {
+        ["encode", ref flags @ ..] => {
+            flag(flags, "--input").and_then(|input| encode(input, flag(flags, "--out")?))
+        }
+        ["decode", ref flags @ ..] => {
+            flag(flags, "--table").and_then(|table| decode(table, flag(flags, "--key")?))
+        }
+        ["info", table] => info(table),
+        _ => {
+            eprintln!("{USAGE}");
+            process::exit(2);
+        }
+    };
+    if let Err(e) = result {
+        eprintln!("rb-okvs: {e}");
+        process::exit(1);
+    }
+}
+
+fn flag<'a>(flags: &[&'a str], name: &str) -> Result<&'a str> {
+    flags
+        .windows(2)
+        .find(|w| w[0] == name)
+        .map(|w| w[1])
+        .ok_or_else(|| Error::Config(format!("missing {name}\n{USAGE}")))
+}
+
+fn encode(input: &str, out: &str) -> Result<()> {
+    let text = fs::read_to_string(input)?;
+    let pairs = parse_pairs(&text)?;
+    let longest = pairs.iter().map(|(_, v)| v.len()).max().unwrap_or(0);
+    let width = WIDTHS
+        .into_iter()
+        .find(|w| *w >= longest + VALUE_OVERHEAD)
+        .ok_or_else(|| Error::Config(format!("values of {longest} bytes are too long")))?;
+
+    let mut seed = [0u8; 16];
+    OsRng.fill_bytes(&mut seed);
+    let header = Header {
+        seed,
+        kv_count: pairs.len(),
+        width,
+    };
+    let bytes = with_width!(width, encode_table(&header, &pairs))?;
+    fs::write(out, bytes)?;
+    println!("{} pairs encoded into {out}", pairs.len());
+    Ok(())
+}
+
+fn decode(table: &str, key: &str) -> Result<()> {
+    let bytes = fs::read(table)?;
+    let header = read_header(&bytes)?;
+    match with_width!(header.width, decode_table(&header, &bytes, key.as_bytes()))? {
+        Some(value) => {
+            println!("{}", String::from_utf8_lossy(&value));
+            Ok(())
+        }
+        None => Err(Error::NotFound),
+    }
+}
+
+fn info(table: &str) -> Result<()> {
+    let bytes = fs::read(table)?;
+    let header = read_header(&bytes)?;
+    let okvs = okvs(&header)?;
+    check_len(&header, &okvs, &bytes)?;
+    println!("{}", okvs.describe());
+    println!("rows={} value_width={}", header.kv_count, header.width);
+    Ok(())
+}
+
+/// `key,value` lines, skipping blank ones. Keys must be distinct.
+fn parse_pairs(text: &str) -> Result<Vec<(&[u8], &[u8])>> {
+    let mut seen = HashSet::new();
+    let mut pairs = vec![];
+    for (line_no, line) in text.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let Some((key, value)) = line.split_once(',') else {
+            return Err(Error::Message(format!("line {}: no comma", line_no + 1)));
+        };
+        if !seen.insert(key) {
+            return Err(Error::Message(format!(
+                "line {}: duplicate key {key}",
+                line_no + 1
+            )));
+        }
+        pairs.push((key.as_bytes(), value.as_bytes()));
+    }
+    Ok(pairs)
+}
+
+fn encode_table<const N: usize>(header: &Header, pairs: &[(&[u8], &[u8])]) -> Result<Vec<u8>> {
+    let okvs = okvs(header)?;
+    let rows: Vec<Pair<OkvsKey<16>, OkvsValue<N>>> = pairs
+        .iter()
+        .map(|(k, v)| {
+            let mut value = [0u8; N];
+            value[..TAG_LEN].copy_from_slice(&tag(k));
+            value[TAG_LEN..VALUE_OVERHEAD].copy_from_slice(&(v.len() as u16).to_le_bytes());
+            value[VALUE_OVERHEAD..VALUE_OVERHEAD + v.len()].copy_from_slice(v);
+            (okvs_key(k), OkvsValue(value))
+        })
+        .collect();
+    let encoding = okvs.encode_with_rng(&rows, &mut OsRng)?;
+
+    let mut bytes = Vec::with_capacity(HEADER_LEN + encoding.len() * N);
+    bytes.extend_from_slice(MAGIC);
+    bytes.push(FORMAT_VERSION);
+    bytes.extend_from_slice(&header.seed);
+    bytes.extend_from_slice(&(header.kv_count as u64).to_le_bytes());
+    bytes.extend_from_slice(&(header.width as u32).to_le_bytes());
+    for value in &encoding {
+        bytes.extend_from_slice(&value.0);
+    }
+    Ok(bytes)
+}
+
+fn decode_table<const N: usize>(
+    header: &Header,
+    bytes: &[u8],
+    key: &[u8],
+) -> Result<Option<Vec<u8>>> {
+    let okvs = okvs(header)?;
+    check_len(header, &okvs, bytes)?;
+    let encoding: Vec<OkvsValue<N>> = bytes[HEADER_LEN..]
+        .chunks_exact(N)
+        .map(|chunk| OkvsValue(chunk.try_into().unwrap()))
+        .collect();
+
+    let value = okvs.decode(&encoding, &okvs_key(key))?.0;
+    let len = u16::from_le_bytes(value[TAG_LEN..VALUE_OVERHEAD].try_into().unwrap()) as usize;
+    if value[..TAG_LEN] != tag(key) || VALUE_OVERHEAD + len > N {
+        return Ok(None);
+    }
+    Ok(Some(value[VALUE_OVERHEAD..VALUE_OVERHEAD + len].to_vec()))
+}
+
+fn read_header(bytes: &[u8]) -> Result<Header> {
+    if bytes.len() < HEADER_LEN || &bytes[..4] != MAGIC {
+        return Err(Error::Message("not an rb-okvs table".into()));
+    }
+    if bytes[4] != FORMAT_VERSION {
+        return Err(Error::Message(format!(
+            "unsupported table version {}",
+            bytes[4]
+        )));
+    }
+    Ok(Header {
+        seed: bytes[5..21].try_into().unwrap(),
+        kv_count: usize::try_from(u64::from_le_bytes(bytes[21..29].try_into().unwrap()))
+            .map_err(|_| Error::Message("row count does not fit in usize".into()))?,
+        width: u32::from_le_bytes(bytes[29..33].try_into().unwrap()) as usize,
+    })
+}
+
+fn okvs(header: &Header) -> Result<RbOkvs> {
+    RbOkvs::builder().seed(header.seed).build(header.kv_count)
+}
+
+fn check_len(header: &Header, okvs: &RbOkvs, bytes: &[u8]) -> Result<()> {
+    let expected = okvs.columns() * header.width;
+    match bytes.len() - HEADER_LEN == expected {
+        true => Ok(()),
+        false => Err(Error::ParamsMismatch("columns")),
+    }
+}
+
+fn okvs_key(key: &[u8]) -> OkvsKey<16> {
+    OkvsKey(blake2b(&[b"rb-okvs/cli/key", key].concat()))
+}
+
+fn tag(key: &[u8]) -> [u8; TAG_LEN] {
+    blake2b(&[b"rb-okvs/cli/tag", key].concat())
+}
+
+fn blake2b<const N: usize>(data: &[u8]) -> [u8; N] {
+    let mut hasher = Blake2bVar::new(N).unwrap();
+    hasher.update(data);
+    let mut buf = [0u8; N];
+    hasher.finalize_variable(&mut buf).unwrap();
+    buf
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_table_round_trip() {
+        let text: String = (0..300)
+            .map(|i| format!("key{i},value {i}, with comma\n"))
+            .collect();
+        let pairs = parse_pairs(&text).unwrap();
+        let header = Header {
+            seed: [4; 16],
+            kv_count: pairs.len(),
+            width: 32,
+        };
+        let bytes = encode_table::<32>(&header, &pairs).unwrap();
+
+        let header = read_header(&bytes).unwrap();
+        assert_eq!((header.kv_count, header.width), (300, 32));
+        assert_eq!(
+            decode_table::<32>(&header, &bytes, b"key7")
+                .unwrap()
+                .unwrap(),
+            b"value 7, with comma"
+        );
+        assert_eq!(
+            decode_table::<32>(&header, &bytes, b"key300").unwrap(),
+            None
+        );
+        assert!(matches!(
+            decode_table::<32>(&header, &bytes[..bytes.len() - 1], b"key7"),
+            Err(Error::ParamsMismatch("columns"))
+        ));
+        let long = [&bytes[..], &[0]].concat();
+        assert!(matches!(
+            decode_table::<32>(&header, &long, b"key7"),
+            Err(Error::ParamsMismatch("columns"))
+        ));
+    }
+
+    #[test]
+    fn test_parse_pairs() {
+        assert!(parse_pairs("a,1\nb,2\na,3\n").is_err());
+        assert!(parse_pairs("a,1\nb\n").is_err());
+        assert_eq!(parse_pairs("a,1\n\nb,\n").unwrap().len(), 2);
+    }
+}
@@ -0,0 +1,120 @@
+use std::collections::HashMap;
+use std::mem;
+use std::sync::Mutex;
+
+use crate::band::Band;
+use crate::error::{Error, Result};
+use crate::hasher::{Blake2Hasher, KeyHasher};
+use crate::okvs::RbOkvs;
+use crate::types::{Encoding, OkvsK, OkvsV};
+
+type Located<const W: usize> = (usize, Band<W>);
+
+/// Start and band of hot keys of an [`RbOkvs`], so their repeated decodes
+/// skip hashing.
+///
+/// Keys given to [`DecoderCache::with_keys`] stay cached. Other keys are
+/// kept while recently decoded, in two generations of up to `capacity` keys:
+/// when the current one is full it replaces the previous one, and a key
+/// found in the previous one moves back to the current one.
+pub struct DecoderCache<const W: usize = 4, H = Blake2Hasher> {
+    okvs:     RbOkvs<W, H>,
+    capacity: usize,
+    pinned:   HashMap<Vec<u8>, Located<W>>,
+    recent:   Mutex<Generations<W>>,
+}
+
+struct Generations<const W: usize> {
+    current:  HashMap<Vec<u8>, Located<W>>,
+    previous: HashMap<Vec<u8>, Located<W>>,
+}
+
+impl<const W: usize, H: KeyHasher> DecoderCache<W, H> {
+    pub fn new(okvs: RbOkvs<W, H>, capacity: usize) -> Self {
+        Self {
+            okvs,
+            capacity,
+            pinned: HashMap::new(),
+            recent: Mutex::new(Generations {
+                current: HashMap::new(),
+                previous: HashMap::new(),
+            }),
+        }
+    }
+
+    /// A cache with `keys` located up front and never evicted.
+    pub fn with_keys<K: OkvsK>(okvs: RbOkvs<W, H>, keys: &[K], capacity: usize) -> Self {
+        let mut cache = Self::new(okvs, capacity);
+        cache.pinned = keys
+            .iter()
+            .map(|k| (k.to_bytes(), cache.okvs.locate(k)))
+            .collect();
+        cache
+    }
+
+    pub fn okvs(&self) -> &RbOkvs<W, H> {
+        &self.okvs
+    }
+
+    /// Same as [`Okvs::decode`](crate::types::Okvs::decode) on the cached
+    /// instance.
+    pub fn decode<V: OkvsV>(&self, encoding: &Encoding<V>, key: &impl OkvsK) -> Result<V> {
+        if encoding.len() < self.okvs.columns() {
+            return Err(Error::EncodingTooShort {
+                required: self.okvs.columns(),
+                actual: encoding.len(),
+            });
+        }
+        let (start, band) = self.locate(key);
+        self.okvs.decode_with(start, &band, encoding)
+    }
+
+    fn locate<K: OkvsK>(&self, key: &K) -> Located<W> {
+        let bytes = key.to_bytes();
+        if let Some(located) = self.pinned.get(&bytes) {
+            return *located;
+        }
+
+        let mut recent = self.recent.lock().unwrap();
+        if let Some(located) = recent.current.get(&bytes) {
+            return *located;
+        }
+        let located = match recent.previous.remove(&bytes) {
+            Some(located) => located,
+            None => self.okvs.locate(key),
+        };
+        if self.capacity > 0 {
+            if recent.current.len() >= self.capacity {
+                recent.previous = mem::take(&mut recent.current);
+            }
+            recent.current.insert(bytes, located);
+        }
+        located
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{Okvs, OkvsKey, OkvsValue, Pair};
+
+    #[test]
+    fn test_decoder_cache() {
+        let pairs: Vec<Pair<OkvsKey, OkvsValue<8>>> = (0..1000u64)
+            .map(|i| (OkvsKey(i.to_le_bytes()), OkvsValue((i * 3).to_le_bytes())))
+            .collect();
+        let okvs = RbOkvs::new(pairs.len());
+        let encoding = okvs.encode_ref(&pairs).unwrap();
+
+        let hot: Vec<OkvsKey> = pairs[..10].iter().map(|(k, _)| k.clone()).collect();
+        let cache = DecoderCache::with_keys(okvs, &hot, 16);
+        for _ in 0..2 {
+            for (k, v) in &pairs {
+                assert_eq!(cache.decode(&encoding, k).unwrap(), *v);
+            }
+        }
+        assert_eq!(cache.pinned.len(), 10);
+        let recent = cache.recent.lock().unwrap();
+        assert!(recent.current.len() <= 16 && recent.previous.len() <= 16);
+    }
+}
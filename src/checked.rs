@@ -0,0 +1,401 @@
+use blake2::digest::{Update, VariableOutput};
+use blake2::Blake2bVar;
+
+use crate::error::{Error, Result};
+use crate::hasher::KeyHasher;
+use crate::okvs::{Fingerprint, RbOkvs};
+use crate::types::{Encoding, Okvs, OkvsK, OkvsV, OkvsValue, Seed};
+use crate::utils::to_usize;
+
+pub type Checksum = [u8; 8];
+
+const CHECKSUM_DOMAIN: &[u8] = b"rb-okvs/checksum";
+/// Version of the header layout. The hashing format is covered by the
+/// fingerprint instead.
+const HEADER_VERSION: u8 = 4;
+/// `version || columns || band width || epsilon || value size || seed ||
+/// fingerprint || checksum`
+const HEADER_LEN: usize = 1 + 8 + 4 + 8 + 4 + 16 + 8 + 8;
+/// Bit of the version byte marking a body compressed by
+/// [`CheckedEncoding::to_compressed_bytes`].
+const COMPRESSED: u8 = 0x80;
+
+/// An encoding along with the parameters and fingerprint of the instance
+/// that made it and a checksum of its values, so decoding it with another
+/// instance fails instead of returning garbage.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CheckedEncoding<V> {
+    columns:     usize,
+    band_width:  usize,
+    /// Bits of the `f64`, which has no `Eq`
+    epsilon:     u64,
+    value_size:  usize,
+    seed:        Seed,
+    fingerprint: Fingerprint,
+    checksum:    Checksum,
+    values:      Encoding<V>,
+}
+
+impl<V: OkvsV + AsRef<[u8]>> CheckedEncoding<V> {
+    /// Wraps `values`, an encoding made by `okvs`.
+    pub fn new<const W: usize, H: KeyHasher>(
+        okvs: &RbOkvs<W, H>,
+        values: Encoding<V>,
+    ) -> Result<Self> {
+        if values.len() != okvs.columns() {
+            return Err(Error::ParamsMismatch("columns"));
+        }
+        Ok(Self {
+            columns: okvs.columns(),
+            band_width: okvs.band_width(),
+            epsilon: okvs.epsilon().to_bits(),
+            value_size: values.first().map_or(0, |v| v.as_ref().len()),
+            seed: *okvs.seed(),
+            fingerprint: okvs.fingerprint(),
+            checksum: checksum(&values),
+            values,
+        })
+    }
+
+    pub fn columns(&self) -> usize {
+        self.columns
+    }
+
+    pub fn band_width(&self) -> usize {
+        self.band_width
+    }
+
+    pub fn epsilon(&self) -> f64 {
+        f64::from_bits(self.epsilon)
+    }
+
+    /// Bytes of every value.
+    pub fn value_size(&self) -> usize {
+        self.value_size
+    }
+
+    pub fn seed(&self) -> &Seed {
+        &self.seed
+    }
+
+    /// [`RbOkvs::fingerprint`] of the instance the encoding was made with.
+    pub fn fingerprint(&self) -> &Fingerprint {
+        &self.fingerprint
+    }
+
+    pub fn checksum(&self) -> &Checksum {
+        &self.checksum
+    }
+
+    pub fn values(&self) -> &Encoding<V> {
+        &self.values
+    }
+
+    pub fn into_values(self) -> Encoding<V> {
+        self.values
+    }
+
+    /// The instance the encoding was made with, for parties that only
+    /// decode. Keys are hashed with the default hasher, so encodings made
+    /// with another one fail with [`Error::ParamsMismatch`].
+    pub fn okvs(&self) -> Result<RbOkvs> {
+        let okvs = RbOkvs::from_params(self.columns, self.band_width, self.epsilon(), self.seed)?;
+        self.check_params(&okvs)?;
+        Ok(okvs)
+    }
+
+    /// Fails with [`Error::ParamsMismatch`] unless `okvs` has the parameters
+    /// the encoding was made with.
+    pub fn check_params<const W: usize, H: KeyHasher>(&self, okvs: &RbOkvs<W, H>) -> Result<()> {
+        if self.columns != okvs.columns() {
+            return Err(Error::ParamsMismatch("columns"));
+        }
+        if self.band_width != okvs.band_width() {
+            return Err(Error::ParamsMismatch("band width"));
+        }
+        if self.seed != *okvs.seed() {
+            return Err(Error::ParamsMismatch("seed"));
+        }
+        // Equal parameters left, so the hasher or hashing format differs
+        if self.fingerprint != okvs.fingerprint() {
+            return Err(Error::ParamsMismatch("hasher"));
+        }
+        Ok(())
+    }
+
+    /// Checks the values against the recorded length, value size and
+    /// checksum, `O(columns)`.
+    pub fn verify(&self) -> Result<()> {
+        if self.values.len() != self.columns {
+            return Err(Error::ParamsMismatch("columns"));
+        }
+        if let Some(v) = self
+            .values
+            .iter()
+            .find(|v| v.as_ref().len() != self.value_size)
+        {
+            return Err(Error::ValueSizeMismatch {
+                expected: self.value_size,
+                actual: v.as_ref().len(),
+            });
+        }
+        if checksum(&self.values) != self.checksum {
+            return Err(Error::ChecksumMismatch);
+        }
+        Ok(())
+    }
+
+    /// Decodes `key` once [`CheckedEncoding::check_params`] passes.
+    pub fn decode<const W: usize, H: KeyHasher>(
+        &self,
+        okvs: &RbOkvs<W, H>,
+        key: &impl OkvsK,
+    ) -> Result<V> {
+        self.check_params(okvs)?;
+        okvs.decode(&self.values, key)
+    }
+}
+
+impl<const N: usize> CheckedEncoding<OkvsValue<N>> {
+    /// `version || columns (u64 LE) || band width (u32 LE) || epsilon (f64
+    /// LE) || value size (u32 LE) || seed || fingerprint || checksum ||
+    /// values`
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.header(HEADER_VERSION);
+        for value in &self.values {
+            bytes.extend_from_slice(&value.0);
+        }
+        bytes
+    }
+
+    /// [`to_bytes`](Self::to_bytes) with the values replaced by a bitmap of
+    /// the nonzero ones, one bit per column from the low bit of the first
+    /// byte, followed by those values. Encodings made by
+    /// [`Okvs::encode`] leave free columns zero, so this saves up to their
+    /// share of the values.
+    ///
+    /// The version byte has its high bit set. [`from_bytes`](Self::from_bytes)
+    /// reads both forms.
+    pub fn to_compressed_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.header(HEADER_VERSION | COMPRESSED);
+        let mut bitmap = vec![0u8; self.values.len().div_ceil(8)];
+        for (i, value) in self.values.iter().enumerate() {
+            if !value.is_zero() {
+                bitmap[i / 8] |= 1 << (i % 8);
+            }
+        }
+        bytes.extend_from_slice(&bitmap);
+        for value in self.values.iter().filter(|v| !v.is_zero()) {
+            bytes.extend_from_slice(&value.0);
+        }
+        bytes
+    }
+
+    fn header(&self, version: u8) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(HEADER_LEN + self.values.len() * N);
+        bytes.push(version);
+        bytes.extend_from_slice(&(self.columns as u64).to_le_bytes());
+        bytes.extend_from_slice(&(self.band_width as u32).to_le_bytes());
+        bytes.extend_from_slice(&self.epsilon.to_le_bytes());
+        bytes.extend_from_slice(&(self.value_size as u32).to_le_bytes());
+        bytes.extend_from_slice(&self.seed);
+        bytes.extend_from_slice(&self.fingerprint);
+        bytes.extend_from_slice(&self.checksum);
+        bytes
+    }
+
+    /// Parses and [verifies](CheckedEncoding::verify) an encoding.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() < HEADER_LEN {
+            return Err(Error::Message("truncated encoding header".into()));
+        }
+        if bytes[0] & !COMPRESSED != HEADER_VERSION {
+            return Err(Error::Message(format!(
+                "unsupported encoding version {}",
+                bytes[0]
+            )));
+        }
+        let value_size = u32::from_le_bytes(bytes[21..25].try_into().unwrap()) as usize;
+        if value_size != N {
+            return Err(Error::ValueSizeMismatch {
+                expected: N,
+                actual: value_size,
+            });
+        }
+        let columns = to_usize(u64::from_le_bytes(bytes[1..9].try_into().unwrap()))?;
+        let body = &bytes[HEADER_LEN..];
+        let values = if bytes[0] & COMPRESSED == 0 {
+            parse_values(body)?
+        } else {
+            decompress(body, columns)?
+        };
+        let encoding = Self {
+            columns,
+            band_width: u32::from_le_bytes(bytes[9..13].try_into().unwrap()) as usize,
+            epsilon: u64::from_le_bytes(bytes[13..21].try_into().unwrap()),
+            value_size,
+            seed: bytes[25..41].try_into().unwrap(),
+            fingerprint: bytes[41..49].try_into().unwrap(),
+            checksum: bytes[49..57].try_into().unwrap(),
+            values,
+        };
+        encoding.verify()?;
+        Ok(encoding)
+    }
+}
+
+fn parse_values<const N: usize>(body: &[u8]) -> Result<Encoding<OkvsValue<N>>> {
+    if !body.len().is_multiple_of(N.max(1)) {
+        return Err(Error::Message(format!("{} bytes of values", body.len())));
+    }
+    Ok(body
+        .chunks_exact(N.max(1))
+        .map(|chunk| OkvsValue(chunk.try_into().unwrap()))
+        .collect())
+}
+
+/// The `columns` values of a body of [`CheckedEncoding::to_compressed_bytes`].
+fn decompress<const N: usize>(body: &[u8], columns: usize) -> Result<Encoding<OkvsValue<N>>> {
+    let bitmap_len = columns.div_ceil(8);
+    if body.len() < bitmap_len {
+        return Err(Error::Message("truncated zero bitmap".into()));
+    }
+    let (bitmap, body) = body.split_at(bitmap_len);
+    let mut nonzero = parse_values::<N>(body)?.into_iter();
+    let values = (0..columns)
+        .map(|i| match bitmap[i / 8] >> (i % 8) & 1 {
+            0 => Some(OkvsValue([0u8; N])),
+            _ => nonzero.next(),
+        })
+        .collect::<Option<Vec<_>>>()
+        .ok_or_else(|| Error::Message("fewer values than the zero bitmap sets".into()))?;
+    if nonzero.next().is_some() {
+        return Err(Error::Message(
+            "more values than the zero bitmap sets".into(),
+        ));
+    }
+    Ok(values)
+}
+
+fn checksum<V: AsRef<[u8]>>(values: &[V]) -> Checksum {
+    let mut hasher = Blake2bVar::new(8).unwrap();
+    hasher.update(CHECKSUM_DOMAIN);
+    for value in values {
+        hasher.update(value.as_ref());
+    }
+    let mut checksum = [0u8; 8];
+    hasher.finalize_variable(&mut checksum).unwrap();
+    checksum
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hasher::AesHasher;
+    use crate::types::{OkvsKey, OkvsValue, Pair};
+
+    #[test]
+    fn test_checked_encoding() {
+        let pairs: Vec<Pair<OkvsKey, OkvsValue<8>>> = (0..1000u64)
+            .map(|i| (OkvsKey(i.to_le_bytes()), OkvsValue(i.to_le_bytes())))
+            .collect();
+        let okvs = RbOkvs::new(pairs.len());
+        let encoding = CheckedEncoding::new(&okvs, okvs.encode_ref(&pairs).unwrap()).unwrap();
+        assert_eq!(encoding.value_size(), 8);
+        assert!(encoding.verify().is_ok());
+        for (k, v) in &pairs {
+            assert_eq!(encoding.decode(&okvs, k).unwrap(), *v);
+        }
+
+        let reseeded = RbOkvs::builder().seed([1; 16]).build(1000).unwrap();
+        let key = &pairs[0].0;
+        assert!(matches!(
+            encoding.decode(&reseeded, key),
+            Err(Error::ParamsMismatch("seed"))
+        ));
+        assert!(matches!(
+            encoding.decode(&RbOkvs::new(1001), key),
+            Err(Error::ParamsMismatch("columns"))
+        ));
+        assert!(matches!(
+            encoding.decode(&okvs.clone().with_hasher(AesHasher::default()), key),
+            Err(Error::ParamsMismatch("hasher"))
+        ));
+
+        let mut tampered = encoding.clone();
+        tampered.values[3].0[0] ^= 1;
+        assert!(matches!(tampered.verify(), Err(Error::ChecksumMismatch)));
+        assert!(CheckedEncoding::new(&okvs, vec![OkvsValue([0u8; 8]); 10]).is_err());
+    }
+
+    #[test]
+    fn test_from_bytes() {
+        let pairs: Vec<Pair<OkvsKey, OkvsValue<8>>> = (0..500u64)
+            .map(|i| (OkvsKey(i.to_le_bytes()), OkvsValue((i + 1).to_le_bytes())))
+            .collect();
+        let okvs = RbOkvs::builder()
+            .seed([9; 16])
+            .epsilon(0.2)
+            .build(pairs.len())
+            .unwrap();
+        let encoding = CheckedEncoding::new(&okvs, okvs.encode_ref(&pairs).unwrap()).unwrap();
+        let bytes = encoding.to_bytes();
+
+        // A decode-only party needs nothing but the bytes
+        let received = CheckedEncoding::<OkvsValue<8>>::from_bytes(&bytes).unwrap();
+        assert_eq!(received, encoding);
+        let decoder = received.okvs().unwrap();
+        assert_eq!(decoder.fingerprint(), okvs.fingerprint());
+        assert_eq!(decoder.epsilon(), 0.2);
+        for (k, v) in &pairs {
+            assert_eq!(received.decode(&decoder, k).unwrap(), *v);
+        }
+
+        let mut corrupted = bytes.clone();
+        *corrupted.last_mut().unwrap() ^= 1;
+        assert!(matches!(
+            CheckedEncoding::<OkvsValue<8>>::from_bytes(&corrupted),
+            Err(Error::ChecksumMismatch)
+        ));
+        assert!(matches!(
+            CheckedEncoding::<OkvsValue<16>>::from_bytes(&bytes),
+            Err(Error::ValueSizeMismatch { .. })
+        ));
+        assert!(CheckedEncoding::<OkvsValue<8>>::from_bytes(&bytes[..bytes.len() - 8]).is_err());
+
+        // Made under another hasher, which the default one must not decode
+        let aes = okvs.with_hasher(AesHasher::default());
+        let other = CheckedEncoding::new(&aes, aes.encode_ref(&pairs).unwrap()).unwrap();
+        let received = CheckedEncoding::<OkvsValue<8>>::from_bytes(&other.to_bytes()).unwrap();
+        assert!(matches!(
+            received.okvs(),
+            Err(Error::ParamsMismatch("hasher"))
+        ));
+    }
+
+    #[test]
+    fn test_compressed_bytes() {
+        // Far more columns than pairs, so most are free and left zero
+        let pairs: Vec<Pair<OkvsKey, OkvsValue<8>>> = (0..100u64)
+            .map(|i| (OkvsKey(i.to_le_bytes()), OkvsValue((i + 1).to_le_bytes())))
+            .collect();
+        let okvs = RbOkvs::new(1000);
+        let encoding = CheckedEncoding::new(&okvs, okvs.encode_ref(&pairs).unwrap()).unwrap();
+
+        let compressed = encoding.to_compressed_bytes();
+        assert!(compressed.len() < encoding.to_bytes().len() / 4);
+        let received = CheckedEncoding::<OkvsValue<8>>::from_bytes(&compressed).unwrap();
+        assert_eq!(received, encoding);
+        for (k, v) in &pairs {
+            assert_eq!(received.decode(&okvs, k).unwrap(), *v);
+        }
+
+        assert!(
+            CheckedEncoding::<OkvsValue<8>>::from_bytes(&compressed[..compressed.len() - 8])
+                .is_err()
+        );
+        let mut extra = compressed.clone();
+        extra.extend_from_slice(&[1; 8]);
+        assert!(CheckedEncoding::<OkvsValue<8>>::from_bytes(&extra).is_err());
+    }
+}
@@ -0,0 +1,119 @@
+use aes_gcm::{
+    aead::{Aead, AeadCore, KeyInit, OsRng, Payload},
+    Aes256Gcm, Nonce,
+};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+
+/// AEAD encrypting the EMM entries under the client's `ke`. Ciphertexts carry
+/// their random nonce, so an OKVS value holds `OVERHEAD` bytes more than its
+/// plaintext.
+pub trait EmmCipher: Send + Sync {
+    /// Nonce and tag bytes added to a plaintext.
+    const OVERHEAD: usize;
+
+    /// `nonce || ct || tag` under a fresh random nonce, authenticating
+    /// `aad` too. An empty `aad` is the same as none.
+    fn encrypt(key: &[u8; 32], plaintext: &[u8], aad: &[u8]) -> Vec<u8>;
+
+    /// None if the tag doesn't verify, e.g. for a key that was never inserted
+    /// or an `aad` other than the one encrypted with.
+    fn decrypt(key: &[u8; 32], ciphertext: &[u8], aad: &[u8]) -> Option<Vec<u8>>;
+}
+
+/// AES-256-GCM with a 12-byte nonce, the default.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct AesGcmCipher;
+
+/// XChaCha20-Poly1305 with a 24-byte nonce, for targets without AES
+/// instructions and for key lifetimes where random 12-byte nonces may repeat.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct XChaChaCipher;
+
+const AES_GCM_NONCE_LEN: usize = 12;
+const XCHACHA_NONCE_LEN: usize = 24;
+const TAG_LEN: usize = 16;
+
+impl EmmCipher for AesGcmCipher {
+    const OVERHEAD: usize = AES_GCM_NONCE_LEN + TAG_LEN;
+
+    fn encrypt(key: &[u8; 32], plaintext: &[u8], aad: &[u8]) -> Vec<u8> {
+        let cipher = Aes256Gcm::new(key.into());
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let mut v = nonce.to_vec();
+        let payload = Payload {
+            msg: plaintext,
+            aad,
+        };
+        v.extend(cipher.encrypt(&nonce, payload).unwrap());
+        v
+    }
+
+    fn decrypt(key: &[u8; 32], ciphertext: &[u8], aad: &[u8]) -> Option<Vec<u8>> {
+        if ciphertext.len() < Self::OVERHEAD {
+            return None;
+        }
+        let cipher = Aes256Gcm::new(key.into());
+        let (nonce, ct) = ciphertext.split_at(AES_GCM_NONCE_LEN);
+        let payload = Payload { msg: ct, aad };
+        cipher.decrypt(Nonce::from_slice(nonce), payload).ok()
+    }
+}
+
+impl EmmCipher for XChaChaCipher {
+    const OVERHEAD: usize = XCHACHA_NONCE_LEN + TAG_LEN;
+
+    fn encrypt(key: &[u8; 32], plaintext: &[u8], aad: &[u8]) -> Vec<u8> {
+        let cipher = XChaCha20Poly1305::new(key.into());
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let mut v = nonce.to_vec();
+        let payload = Payload {
+            msg: plaintext,
+            aad,
+        };
+        v.extend(cipher.encrypt(&nonce, payload).unwrap());
+        v
+    }
+
+    fn decrypt(key: &[u8; 32], ciphertext: &[u8], aad: &[u8]) -> Option<Vec<u8>> {
+        if ciphertext.len() < Self::OVERHEAD {
+            return None;
+        }
+        let cipher = XChaCha20Poly1305::new(key.into());
+        let (nonce, ct) = ciphertext.split_at(XCHACHA_NONCE_LEN);
+        let payload = Payload { msg: ct, aad };
+        cipher.decrypt(XNonce::from_slice(nonce), payload).ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check<C: EmmCipher>() {
+        let ct = C::encrypt(&[1u8; 32], b"value", b"");
+        assert_eq!(ct.len(), 5 + C::OVERHEAD);
+        assert_eq!(C::decrypt(&[1u8; 32], &ct, b"").unwrap(), b"value");
+        // Random nonces
+        assert_ne!(ct, C::encrypt(&[1u8; 32], b"value", b""));
+
+        assert!(C::decrypt(&[2u8; 32], &ct, b"").is_none());
+        let mut tampered = ct.clone();
+        tampered[C::OVERHEAD] ^= 1;
+        assert!(C::decrypt(&[1u8; 32], &tampered, b"").is_none());
+        assert!(C::decrypt(&[1u8; 32], &ct[..C::OVERHEAD - 1], b"").is_none());
+
+        let bound = C::encrypt(&[1u8; 32], b"value", b"entry 3");
+        assert_eq!(
+            C::decrypt(&[1u8; 32], &bound, b"entry 3").unwrap(),
+            b"value"
+        );
+        assert!(C::decrypt(&[1u8; 32], &bound, b"entry 4").is_none());
+        assert!(C::decrypt(&[1u8; 32], &bound, b"").is_none());
+    }
+
+    #[test]
+    fn test_ciphers() {
+        check::<AesGcmCipher>();
+        check::<XChaChaCipher>();
+    }
+}
@@ -0,0 +1,303 @@
+use aes_gcm::aead::{rand_core::RngCore, OsRng};
+#[cfg(feature = "serde")]
+use serde::Deserialize;
+
+use crate::error::{Error, Result};
+use crate::opprf::{OpprfReceiver, OpprfRequest, OpprfResponse, OpprfSender};
+#[cfg(feature = "serde")]
+use crate::utils::serialize_to_bytes;
+use crate::utils::{blake2b, reduce, to_usize};
+
+/// Bytes of a share.
+pub const SHARE_LEN: usize = 16;
+pub type Share = [u8; SHARE_LEN];
+/// Leading byte of serialized [`CircuitPsiRequest`]s.
+pub const MESSAGE_VERSION: u8 = 1;
+/// Candidate bins of an item.
+const CUCKOO_HASHES: usize = 3;
+/// Bins per receiver item. Cuckoo hashing with three hash functions rarely
+/// fails below a load of 0.9.
+const BINS_PER_ITEM: f64 = 1.5;
+const MIN_BINS: usize = 16;
+const MAX_EVICTIONS: usize = 1000;
+const CUCKOO_DOMAIN: &[u8] = b"rb-okvs/circuit-psi/bin";
+
+/// Receives the share of each bin, e.g. to feed it into a 2PC engine. Both
+/// parties put one share per bin, and a bin holds an item of the
+/// intersection exactly when their shares are equal.
+pub trait ShareSink {
+    fn put(&mut self, bin: usize, share: &Share) -> Result<()>;
+}
+
+impl ShareSink for Vec<(usize, Share)> {
+    fn put(&mut self, bin: usize, share: &Share) -> Result<()> {
+        self.push((bin, *share));
+        Ok(())
+    }
+}
+
+/// Sender of the circuit-PSI: neither party learns the intersection, which
+/// is left to a 2PC engine as shares of equality tests.
+///
+/// The receiver places its items into bins by cuckoo hashing, at most one
+/// per bin, and the sender places each of its items into all of its
+/// candidate bins. Per bin, the sender draws a random share and programs it
+/// into an [`OpprfSender`] at every item of the bin. The receiver evaluates
+/// the OPPRF at the item of each bin: it gets the sender's share of that bin
+/// if the sender holds the item, and a random value otherwise. The engine
+/// then computes `share_s == share_r` per bin without revealing it, plus
+/// whatever function of the intersection the parties agreed on.
+pub struct CircuitPsiSender {
+    set: Vec<Vec<u8>>,
+}
+
+/// Receiver of the circuit-PSI, see [`CircuitPsiSender`].
+pub struct CircuitPsiReceiver {
+    bins:  Vec<Option<usize>>,
+    opprf: OpprfReceiver,
+}
+
+/// The number of bins and an OPPRF request for the receiver's bins.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+#[cfg_attr(feature = "serde", serde(try_from = "Vec<u8>"))]
+pub struct CircuitPsiRequest {
+    pub bins:  usize,
+    pub opprf: OpprfRequest,
+}
+
+#[cfg(feature = "serde")]
+serialize_to_bytes!(CircuitPsiRequest);
+
+impl CircuitPsiSender {
+    /// Items must be distinct.
+    pub fn new<T: AsRef<[u8]>>(set: &[T]) -> Self {
+        Self {
+            set: set.iter().map(|x| x.as_ref().to_vec()).collect(),
+        }
+    }
+
+    /// Puts the sender's share of every bin into `sink`, and returns the
+    /// OPPRF response for the receiver.
+    pub fn respond<S: ShareSink>(
+        &self,
+        request: &CircuitPsiRequest,
+        sink: &mut S,
+    ) -> Result<OpprfResponse<SHARE_LEN>> {
+        if request.bins != request.opprf.blinded.len() {
+            return Err(Error::Message(format!(
+                "{} points for {} bins",
+                request.opprf.blinded.len(),
+                request.bins
+            )));
+        }
+
+        let mut shares = vec![[0u8; SHARE_LEN]; request.bins];
+        for (bin, share) in shares.iter_mut().enumerate() {
+            OsRng.fill_bytes(share);
+            sink.put(bin, share)?;
+        }
+
+        let mut pairs = vec![];
+        for x in &self.set {
+            let mut bins = candidate_bins(x, request.bins).to_vec();
+            bins.sort_unstable();
+            bins.dedup();
+            for bin in bins {
+                pairs.push((bin_point(bin, x), shares[bin]));
+            }
+        }
+        OpprfSender::new(&pairs).program(&request.opprf)
+    }
+}
+
+impl CircuitPsiReceiver {
+    /// Items must be distinct. Fails in the rare case cuckoo hashing can't
+    /// place every item.
+    pub fn new<T: AsRef<[u8]>>(set: &[T]) -> Result<Self> {
+        let set: Vec<Vec<u8>> = set.iter().map(|y| y.as_ref().to_vec()).collect();
+        let bins = cuckoo(&set, bin_count(set.len()))?;
+
+        // Empty bins evaluate at a random point, indistinguishable to the sender
+        let points: Vec<Vec<u8>> = bins
+            .iter()
+            .enumerate()
+            .map(|(bin, item)| match item {
+                Some(i) => bin_point(bin, &set[*i]),
+                None => {
+                    let mut point = vec![0u8; 32];
+                    OsRng.fill_bytes(&mut point);
+                    point
+                }
+            })
+            .collect();
+        Ok(Self {
+            opprf: OpprfReceiver::new(&points),
+            bins,
+        })
+    }
+
+    pub fn request(&self) -> CircuitPsiRequest {
+        CircuitPsiRequest {
+            bins: self.bins.len(),
+            opprf: self.opprf.request(),
+        }
+    }
+
+    /// Index into the receiver's set of the item in each bin, so inputs
+    /// associated with the items can be handed to the engine along with the
+    /// shares.
+    pub fn bins(&self) -> &[Option<usize>] {
+        &self.bins
+    }
+
+    /// Puts the receiver's share of every bin into `sink`.
+    pub fn finish<S: ShareSink>(
+        &self,
+        response: &OpprfResponse<SHARE_LEN>,
+        sink: &mut S,
+    ) -> Result<()> {
+        for (bin, share) in self.opprf.evaluate(response)?.iter().enumerate() {
+            sink.put(bin, share)?;
+        }
+        Ok(())
+    }
+}
+
+impl CircuitPsiRequest {
+    /// `version || bins (u64 LE) || OPPRF request`
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        let mut bytes = vec![MESSAGE_VERSION];
+        bytes.extend_from_slice(&(self.bins as u64).to_le_bytes());
+        bytes.extend(self.opprf.to_bytes()?);
+        Ok(bytes)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        match bytes {
+            [MESSAGE_VERSION, rest @ ..] if rest.len() >= 8 => Ok(Self {
+                bins: to_usize(u64::from_le_bytes(rest[..8].try_into().unwrap()))?,
+                opprf: OpprfRequest::from_bytes(&rest[8..])?,
+            }),
+            [MESSAGE_VERSION, ..] => Err(Error::Message("truncated request".into())),
+            [version, ..] => Err(Error::Message(format!("unsupported version {version}"))),
+            [] => Err(Error::Message("empty message".into())),
+        }
+    }
+}
+
+impl TryFrom<CircuitPsiRequest> for Vec<u8> {
+    type Error = Error;
+
+    fn try_from(request: CircuitPsiRequest) -> Result<Self> {
+        request.to_bytes()
+    }
+}
+
+impl TryFrom<Vec<u8>> for CircuitPsiRequest {
+    type Error = Error;
+
+    fn try_from(bytes: Vec<u8>) -> Result<Self> {
+        Self::from_bytes(&bytes)
+    }
+}
+
+fn bin_count(items: usize) -> usize {
+    ((items as f64 * BINS_PER_ITEM).ceil() as usize).max(MIN_BINS)
+}
+
+fn candidate_bins(item: &[u8], bins: usize) -> [usize; CUCKOO_HASHES] {
+    let mut candidates = [0; CUCKOO_HASHES];
+    for (i, bin) in candidates.iter_mut().enumerate() {
+        let h = blake2b::<8>(&[CUCKOO_DOMAIN, &[i as u8], item].concat());
+        *bin = reduce(u64::from_le_bytes(h), bins);
+    }
+    candidates
+}
+
+/// OPPRF point of `item` in `bin`, so an item programmed in several bins
+/// gets distinct points.
+fn bin_point(bin: usize, item: &[u8]) -> Vec<u8> {
+    [&(bin as u64).to_le_bytes(), item].concat()
+}
+
+/// Places every item into one of its candidate bins, evicting along a random
+/// walk when all are taken.
+fn cuckoo(set: &[Vec<u8>], bins: usize) -> Result<Vec<Option<usize>>> {
+    let mut table: Vec<Option<usize>> = vec![None; bins];
+    for item in 0..set.len() {
+        let mut current = item;
+        let mut placed = false;
+        for _ in 0..MAX_EVICTIONS {
+            let candidates = candidate_bins(&set[current], bins);
+            if let Some(&bin) = candidates.iter().find(|&&bin| table[bin].is_none()) {
+                table[bin] = Some(current);
+                placed = true;
+                break;
+            }
+            let bin = candidates[OsRng.next_u32() as usize % CUCKOO_HASHES];
+            current = table[bin].replace(current).unwrap();
+        }
+        if !placed {
+            return Err(Error::Config(format!(
+                "cuckoo hashing of {} items into {bins} bins failed",
+                set.len()
+            )));
+        }
+    }
+    Ok(table)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_circuit_psi() {
+        let sender_set: Vec<Vec<u8>> = (0..300u32).map(|i| i.to_le_bytes().to_vec()).collect();
+        let receiver_set: Vec<Vec<u8>> = (200..400u32).map(|i| i.to_le_bytes().to_vec()).collect();
+
+        let sender = CircuitPsiSender::new(&sender_set);
+        let receiver = CircuitPsiReceiver::new(&receiver_set).unwrap();
+        let request =
+            CircuitPsiRequest::from_bytes(&receiver.request().to_bytes().unwrap()).unwrap();
+        assert_eq!(request.bins, 300);
+
+        let mut sender_shares = vec![];
+        let response = sender.respond(&request, &mut sender_shares).unwrap();
+        let mut receiver_shares = vec![];
+        receiver.finish(&response, &mut receiver_shares).unwrap();
+        assert_eq!(sender_shares.len(), request.bins);
+        assert_eq!(receiver_shares.len(), request.bins);
+
+        // What the engine would compute
+        let mut intersection: Vec<u32> = sender_shares
+            .iter()
+            .zip(&receiver_shares)
+            .filter(|((_, s), (_, r))| s == r)
+            .map(|((bin, _), _)| {
+                let item = receiver.bins()[*bin].unwrap();
+                u32::from_le_bytes(receiver_set[item].clone().try_into().unwrap())
+            })
+            .collect();
+        intersection.sort_unstable();
+        assert_eq!(intersection, (200..300).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_cuckoo() {
+        let set: Vec<Vec<u8>> = (0..1000u32).map(|i| i.to_le_bytes().to_vec()).collect();
+        let bins = cuckoo(&set, bin_count(set.len())).unwrap();
+        let mut placed: Vec<usize> = bins.iter().flatten().copied().collect();
+        placed.sort_unstable();
+        assert_eq!(placed, (0..1000).collect::<Vec<_>>());
+        for (bin, item) in bins.iter().enumerate() {
+            if let Some(item) = item {
+                assert!(candidate_bins(&set[*item], bins.len()).contains(&bin));
+            }
+        }
+
+        // More items than bins
+        assert!(cuckoo(&set, 999).is_err());
+    }
+}
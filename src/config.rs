@@ -0,0 +1,189 @@
+#[cfg(feature = "serde")]
+use serde::Deserialize;
+
+use crate::cache::DecoderCache;
+use crate::emm::{ClientState, Padding, TokenFormat, VhEmm, VhEmmClient};
+use crate::error::{Error, Result};
+#[cfg(feature = "rayon")]
+use crate::executor::RayonPool;
+use crate::executor::StdThreads;
+use crate::okvs::{Fingerprint, RbOkvs, RbOkvsBuilder};
+use crate::types::{Okvs, Seed};
+
+/// Tuning knobs for an [`RbOkvs`], loadable from any serde format.
+///
+/// Missing fields fall back to the crate defaults.
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+#[cfg_attr(feature = "serde", serde(default, deny_unknown_fields))]
+pub struct RbOkvsConfig {
+    pub epsilon:     Option<f64>,
+    pub band_width:  Option<usize>,
+    /// Worker threads for the parallel APIs, defaults to one per core.
+    pub threads:     Option<usize>,
+    /// Check every encoding decodes its input, see [`RbOkvsBuilder::verify`].
+    pub verify:      Option<bool>,
+    /// See [`RbOkvsBuilder::seed`].
+    pub seed:        Option<Seed>,
+    /// See [`RbOkvsBuilder::pad_columns`].
+    pub pad_columns: Option<bool>,
+}
+
+/// Tuning knobs for a [`VhEmm`], its clients and its server.
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+#[cfg_attr(feature = "serde", serde(default, deny_unknown_fields))]
+pub struct EmmConfig {
+    pub okvs:           RbOkvsConfig,
+    pub padding:        Option<Padding>,
+    /// Setup and clients must agree, see [`VhEmm::token_format`].
+    pub token_format:   Option<TokenFormat>,
+    /// Keys per generation of the server's [`DecoderCache`], none by
+    /// default.
+    pub cache_capacity: Option<usize>,
+}
+
+impl RbOkvsConfig {
+    /// Validates the configuration and turns it into a builder.
+    pub fn to_builder(&self) -> Result<RbOkvsBuilder> {
+        let mut builder = RbOkvs::builder();
+        if let Some(epsilon) = self.epsilon {
+            builder = builder.epsilon(epsilon);
+        }
+        if let Some(band_width) = self.band_width {
+            builder = builder.band_width(band_width);
+        }
+        if let Some(verify) = self.verify {
+            builder = builder.verify(verify);
+        }
+        if let Some(seed) = self.seed {
+            builder = builder.seed(seed);
+        }
+        if let Some(pad) = self.pad_columns {
+            builder = builder.pad_columns(pad);
+        }
+        if self.threads == Some(0) {
+            return Err(Error::Config("threads must be positive".into()));
+        }
+        builder.validate()?;
+        Ok(builder)
+    }
+
+    pub fn build(&self, kv_count: usize) -> Result<RbOkvs> {
+        self.to_builder()?.build(kv_count)
+    }
+
+    pub fn executor(&self) -> StdThreads {
+        self.threads.map(StdThreads::new).unwrap_or_default()
+    }
+
+    /// A dedicated rayon pool of `threads` workers, one per core by default.
+    /// To share an existing pool instead, wrap it in [`RayonPool::new`].
+    #[cfg(feature = "rayon")]
+    pub fn rayon_executor(&self) -> Result<RayonPool> {
+        RayonPool::with_threads(self.executor().threads())
+    }
+}
+
+impl EmmConfig {
+    pub fn validate(&self) -> Result<()> {
+        self.okvs.to_builder()?;
+        if self.cache_capacity == Some(0) {
+            return Err(Error::Config("cache capacity must be positive".into()));
+        }
+        Ok(())
+    }
+
+    /// Validates the configuration and applies it to a [`VhEmm`] over
+    /// `okvs`, e.g. one from [`RbOkvsConfig::build`].
+    pub fn to_vh_emm<T: Okvs, const OKVS_K_SIZE: usize, const OKVS_V_SIZE: usize>(
+        &self,
+        okvs: T,
+    ) -> Result<VhEmm<T, OKVS_K_SIZE, OKVS_V_SIZE>> {
+        self.validate()?;
+        let mut emm = VhEmm::new(okvs);
+        if let Some(padding) = self.padding {
+            emm = emm.padding(padding);
+        }
+        if let Some(token_format) = self.token_format {
+            emm = emm.token_format(token_format);
+        }
+        Ok(emm)
+    }
+
+    /// A client issuing the tokens of [`to_vh_emm`](Self::to_vh_emm), for
+    /// the OKVS of fingerprint `fingerprint`.
+    pub fn to_client(&self, state: ClientState, fingerprint: Fingerprint) -> VhEmmClient {
+        let client = VhEmmClient::new(state, fingerprint);
+        match self.token_format {
+            Some(token_format) => client.token_format(token_format),
+            None => client,
+        }
+    }
+
+    /// A cache over `okvs` holding `cache_capacity` keys, if set.
+    pub fn decoder_cache(&self, okvs: RbOkvs) -> Option<DecoderCache> {
+        self.cache_capacity
+            .map(|capacity| DecoderCache::new(okvs, capacity))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_config() {
+        let config = RbOkvsConfig::default();
+        assert!(config.to_builder().is_ok());
+        assert!(EmmConfig::default().validate().is_ok());
+    }
+
+    #[test]
+    fn test_invalid_config() {
+        let config = RbOkvsConfig {
+            epsilon: Some(-1.0),
+            ..Default::default()
+        };
+        assert!(config.to_builder().is_err());
+
+        let config = RbOkvsConfig {
+            threads: Some(0),
+            ..Default::default()
+        };
+        assert!(config.to_builder().is_err());
+
+        let config = EmmConfig {
+            cache_capacity: Some(0),
+            ..Default::default()
+        };
+        assert!(config.to_vh_emm::<_, 8, 64>(RbOkvs::new(10)).is_err());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_deserialize() {
+        let config: EmmConfig =
+            serde_json::from_str(r#"{"okvs": {"epsilon": 0.05, "band_width": 64, "threads": 2}}"#)
+                .unwrap();
+        assert_eq!(config.okvs.epsilon, Some(0.05));
+        assert_eq!(config.okvs.executor().threads(), 2);
+
+        assert!(config.okvs.build(1000).is_ok());
+
+        let config: EmmConfig = serde_json::from_str(
+            r#"{"okvs": {"seed": [7, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0], "pad_columns": true},
+                "padding": {"fixed": 4}, "token_format": "hex", "cache_capacity": 64}"#,
+        )
+        .unwrap();
+        assert_eq!(config.padding, Some(Padding::Fixed(4)));
+        assert_eq!(config.token_format, Some(TokenFormat::Hex));
+        let okvs = config.okvs.build(1000).unwrap();
+        assert_eq!((okvs.seed()[0], okvs.columns() % 64), (7, 0));
+        assert!(config.decoder_cache(okvs.clone()).is_some());
+        let emm = config.to_vh_emm::<_, 8, 100>(okvs).unwrap();
+        assert_eq!(emm.value_size::<u64>(), 100);
+
+        assert!(serde_json::from_str::<RbOkvsConfig>(r#"{"epsilom": 0.05}"#).is_err());
+    }
+}
@@ -0,0 +1,393 @@
+use std::collections::hash_map::{Entry, HashMap};
+
+use crate::emm::{ClientState, EmmPair, QueryToken, VhEmm, VhEmmClient, VhEmmServer};
+use crate::error::{Error, Result};
+use crate::okvs::{RbOkvs, RbOkvsBuilder};
+use crate::types::{EmmK, EmmV};
+
+/// Smallest OKVS a level is encoded into, narrow encodings fail too often.
+/// Levels are padded with random rows up to their size.
+const MIN_LEVEL_ROWS: usize = 1024;
+
+/// Context of the level keys in forward-private mode, followed by the epoch.
+const EPOCH_INFO: &[u8] = b"rb-okvs/dyn-emm/epoch";
+
+/// Pairs of a level and the rows it is padded to, `None` for a free slot.
+type LevelPairs<K, V> = Option<(Vec<EmmPair<K, Vec<V>>>, usize)>;
+
+/// Updatable volume-hiding EMM.
+///
+/// Inserts are kept in hierarchical levels like a binary counter: level `i`
+/// holds the pairs of `2^i` inserts, and an insert merges every full level
+/// below the first empty one into it. Each level is a separate [`VhEmm`]
+/// encoding under the same keys, so an insert re-encodes `O(log n)` levels
+/// amortized instead of the whole dataset. [`DynVhEmm::rebuild`] collapses
+/// all levels into the top one.
+///
+/// The data owner keeps the plaintext pairs of every level to merge them.
+/// With [`DynVhEmm::forward_private`], updates stay unlinkable to earlier
+/// queries; for cheaper updates that reveal list lengths, see the
+/// [`FpEmmClient`](crate::fp_emm::FpEmmClient).
+pub struct DynVhEmm<K, V, const OKVS_K_SIZE: usize, const OKVS_V_SIZE: usize> {
+    builder:         RbOkvsBuilder,
+    state:           ClientState,
+    forward_private: bool,
+    /// Levels encoded so far, the counter level keys are derived from.
+    epoch:           u64,
+    levels:          Vec<Option<Level<K, V, OKVS_K_SIZE, OKVS_V_SIZE>>>,
+}
+
+struct Level<K, V, const OKVS_K_SIZE: usize, const OKVS_V_SIZE: usize> {
+    pairs:  Vec<EmmPair<K, Vec<V>>>,
+    /// Rows the level is padded to, kept when deletes shrink `pairs`.
+    rows:   usize,
+    /// Tokens for this level: its keys and its OKVS fingerprint.
+    client: VhEmmClient,
+    server: VhEmmServer<RbOkvs, OKVS_K_SIZE, OKVS_V_SIZE>,
+}
+
+impl<K, V, const OKVS_K_SIZE: usize, const OKVS_V_SIZE: usize>
+    DynVhEmm<K, V, OKVS_K_SIZE, OKVS_V_SIZE>
+where
+    K: EmmK + Clone,
+    V: EmmV + Clone,
+{
+    pub fn new(builder: RbOkvsBuilder, state: ClientState) -> Self {
+        Self {
+            builder,
+            state,
+            forward_private: false,
+            epoch: 0,
+            levels: vec![],
+        }
+    }
+
+    /// Encodes each level under fresh keys derived from the client keys and
+    /// a counter, so tokens issued before an insert or delete match none of
+    /// the levels it encodes. Queries then take one PRF evaluation per level.
+    pub fn forward_private(mut self, forward_private: bool) -> Self {
+        self.forward_private = forward_private;
+        self
+    }
+
+    /// Appends `values` to the list of `key`.
+    pub fn insert(&mut self, key: K, values: Vec<V>) -> Result<()> {
+        let mut pairs = vec![(key, values)];
+        for i in 0.. {
+            if i == self.levels.len() {
+                self.levels.push(None);
+            }
+            match self.levels[i].take() {
+                // Older pairs first, so values keep their insertion order
+                Some(level) => pairs = merge(level.pairs, pairs),
+                None => {
+                    let rows = level_rows(&pairs);
+                    self.levels[i] = Some(self.encode_level(pairs, rows)?);
+                    break;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Merges all levels into a single encoding.
+    pub fn rebuild(&mut self) -> Result<()> {
+        let mut pairs = vec![];
+        for level in self.levels.iter_mut().rev() {
+            if let Some(level) = level.take() {
+                pairs = merge(pairs, level.pairs);
+            }
+        }
+        let top = self.levels.len().saturating_sub(1);
+        self.levels.truncate(top);
+        if !pairs.is_empty() {
+            let rows = level_rows(&pairs);
+            let level = self.encode_level(pairs, rows)?;
+            self.levels.push(Some(level));
+        }
+        Ok(())
+    }
+
+    /// Removes every value of `key`.
+    ///
+    /// Every level is re-encoded at the size it had, an emptied one
+    /// included, so the server can't tell which levels held the key or how
+    /// many entries were removed.
+    pub fn delete(&mut self, key: &K) -> Result<bool> {
+        let key = key.to_bytes();
+        let mut levels = self.level_pairs();
+        let mut deleted = false;
+        for (pairs, _) in levels.iter_mut().flatten() {
+            let len = pairs.len();
+            pairs.retain(|(k, _)| k.to_bytes() != key);
+            deleted |= pairs.len() != len;
+        }
+        if deleted {
+            self.reencode(levels)?;
+        }
+        Ok(deleted)
+    }
+
+    /// Removes the value at `index` of the list [`DynVhEmm::query`] returns
+    /// for `key`, re-encoding every level like [`DynVhEmm::delete`].
+    pub fn delete_value(&mut self, key: &K, mut index: usize) -> Result<bool> {
+        let key = key.to_bytes();
+        let mut levels = self.level_pairs();
+        for (pairs, _) in levels.iter_mut().rev().flatten() {
+            let Some(pos) = pairs.iter().position(|(k, _)| k.to_bytes() == key) else {
+                continue;
+            };
+            let len = pairs[pos].1.len();
+            if index >= len {
+                index -= len;
+                continue;
+            }
+
+            pairs[pos].1.remove(index);
+            if pairs[pos].1.is_empty() {
+                pairs.remove(pos);
+            }
+            self.reencode(levels)?;
+            return Ok(true);
+        }
+        Ok(false)
+    }
+
+    /// Number of levels, i.e. encodings a query touches. Levels emptied by
+    /// deletes are kept, so deletes never lower it.
+    pub fn level_count(&self) -> usize {
+        self.levels.iter().flatten().count()
+    }
+
+    /// Looks up the values of `key` in every level, in insertion order.
+    pub fn query(&self, key: &K) -> Result<Vec<V>> {
+        let mut values = vec![];
+        for level in self.levels.iter().rev().flatten() {
+            let token: QueryToken = level.client.gen_token(key);
+            let v_len = match level
+                .client
+                .resolve_len(&token, level.server.answer_len(&token)?)
+            {
+                Ok(v_len) => v_len,
+                // Levels without the key have no length entry for it
+                Err(Error::NotFound) => continue,
+                Err(e) => return Err(e),
+            };
+            let response = level.server.answer(&token, v_len)?;
+            values.extend(level.client.resolve::<V, OKVS_V_SIZE>(&token, response)?);
+        }
+        Ok(values)
+    }
+
+    /// The pairs and size of every level, to edit before [`Self::reencode`].
+    fn level_pairs(&self) -> Vec<LevelPairs<K, V>> {
+        self.levels
+            .iter()
+            .map(|level| {
+                level
+                    .as_ref()
+                    .map(|level| (level.pairs.clone(), level.rows))
+            })
+            .collect()
+    }
+
+    /// Replaces every level with a fresh encoding of its pairs at its size.
+    /// Nothing is replaced unless all levels encode.
+    fn reencode(&mut self, levels: Vec<LevelPairs<K, V>>) -> Result<()> {
+        self.levels = levels
+            .into_iter()
+            .map(|level| {
+                level
+                    .map(|(pairs, rows)| self.encode_level(pairs, rows))
+                    .transpose()
+            })
+            .collect::<Result<_>>()?;
+        Ok(())
+    }
+
+    /// Encodes `pairs` padded to `rows` rows, which must be at least
+    /// [`level_rows`] of them.
+    fn encode_level(
+        &mut self,
+        pairs: Vec<EmmPair<K, Vec<V>>>,
+        rows: usize,
+    ) -> Result<Level<K, V, OKVS_K_SIZE, OKVS_V_SIZE>> {
+        let okvs = self.builder.build(rows)?;
+        let state = self.level_state();
+
+        let emm = VhEmm::<RbOkvs, OKVS_K_SIZE, OKVS_V_SIZE>::new(okvs.clone()).encode_with(
+            pairs.clone(),
+            &state,
+            rows,
+        )?;
+        Ok(Level {
+            pairs,
+            rows,
+            client: VhEmmClient::new(state, okvs.fingerprint()),
+            server: VhEmmServer::new(okvs, emm),
+        })
+    }
+
+    /// Keys of the next level encoded: the client keys, or in forward-private
+    /// mode keys of a fresh epoch.
+    fn level_state(&mut self) -> ClientState {
+        self.epoch += 1;
+        match self.forward_private {
+            true => self
+                .state
+                .derive(&[EPOCH_INFO, &self.epoch.to_le_bytes()].concat()),
+            false => self.state.clone(),
+        }
+    }
+}
+
+/// Rows of a level holding `pairs`: a length entry and at least one entry
+/// per key, and no fewer than [`MIN_LEVEL_ROWS`].
+fn level_rows<K, V>(pairs: &[EmmPair<K, Vec<V>>]) -> usize {
+    let rows: usize = pairs.iter().map(|(_, v)| v.len().max(1) + 1).sum();
+    rows.max(MIN_LEVEL_ROWS)
+}
+
+/// Concatenates the lists of keys present in both, `older` values first, as
+/// an encoding can't hold the same key twice.
+fn merge<K: EmmK, V>(
+    older: Vec<EmmPair<K, Vec<V>>>,
+    newer: Vec<EmmPair<K, Vec<V>>>,
+) -> Vec<EmmPair<K, Vec<V>>> {
+    let mut index: HashMap<Vec<u8>, usize> = HashMap::new();
+    let mut merged: Vec<EmmPair<K, Vec<V>>> = Vec::with_capacity(older.len() + newer.len());
+    for (key, values) in older.into_iter().chain(newer) {
+        match index.entry(key.to_bytes()) {
+            Entry::Occupied(e) => merged[*e.get()].1.extend(values),
+            Entry::Vacant(e) => {
+                e.insert(merged.len());
+                merged.push((key, values));
+            }
+        }
+    }
+    merged
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_insert_query() {
+        // 68 = 60 + <u64 as EmmV>::len()
+        let mut emm =
+            DynVhEmm::<u64, u64, 8, 68>::new(RbOkvs::builder(), ClientState::new_random());
+
+        for i in 0..20u64 {
+            emm.insert(i % 5, vec![i]).unwrap();
+        }
+        // 20 = 0b10100
+        assert_eq!(emm.level_count(), 2);
+
+        for k in 0..5u64 {
+            let values: Vec<u64> = emm.query(&k).unwrap();
+            assert_eq!(values, vec![k, k + 5, k + 10, k + 15]);
+        }
+        assert!(emm.query(&5u64).unwrap().is_empty());
+
+        emm.rebuild().unwrap();
+        assert_eq!(emm.level_count(), 1);
+        let values: Vec<u64> = emm.query(&3u64).unwrap();
+        assert_eq!(values, vec![3, 8, 13, 18]);
+
+        emm.insert(3u64, vec![100u64]).unwrap();
+        assert_eq!(emm.level_count(), 2);
+        let values: Vec<u64> = emm.query(&3u64).unwrap();
+        assert_eq!(values, vec![3, 8, 13, 18, 100]);
+
+        // Merged into the same level as the older list
+        emm.insert(0u64, vec![]).unwrap();
+        assert_eq!(emm.level_count(), 2);
+        let values: Vec<u64> = emm.query(&3u64).unwrap();
+        assert_eq!(values, vec![3, 8, 13, 18, 100]);
+        assert_eq!(emm.query(&0u64).unwrap().len(), 4);
+    }
+
+    #[test]
+    fn test_delete() {
+        // 68 = 60 + <u64 as EmmV>::len()
+        let mut emm =
+            DynVhEmm::<u64, u64, 8, 68>::new(RbOkvs::builder(), ClientState::new_random());
+
+        for i in 0..6u64 {
+            emm.insert(i % 2, vec![i]).unwrap();
+        }
+        // 6 = 0b110
+        assert_eq!(emm.level_count(), 2);
+
+        // Index 2 lives in the newer level
+        assert!(emm.delete_value(&0u64, 2).unwrap());
+        let values: Vec<u64> = emm.query(&0u64).unwrap();
+        assert_eq!(values, vec![0, 2]);
+        assert!(!emm.delete_value(&0u64, 2).unwrap());
+
+        // Every level changes and keeps its size, so the server can't tell
+        // where the key was
+        let before: Vec<_> = emm
+            .levels
+            .iter()
+            .flatten()
+            .map(|level| level.server.encoding().clone())
+            .collect();
+        assert!(emm.delete(&1u64).unwrap());
+        assert!(emm.query(&1u64).unwrap().is_empty());
+        for (level, before) in emm.levels.iter().flatten().zip(&before) {
+            assert_eq!(level.server.encoding().len(), before.len());
+            assert_ne!(level.server.encoding(), before);
+        }
+        assert!(!emm.delete(&1u64).unwrap());
+        // The newer level only held key 1, and stays as padding
+        assert_eq!(emm.level_count(), 2);
+
+        let values: Vec<u64> = emm.query(&0u64).unwrap();
+        assert_eq!(values, vec![0, 2]);
+    }
+
+    #[test]
+    fn test_forward_private() {
+        // 68 = 60 + <u64 as EmmV>::len()
+        let mut emm =
+            DynVhEmm::<u64, u64, 8, 68>::new(RbOkvs::builder(), ClientState::new_random())
+                .forward_private(true);
+        let h = |emm: &DynVhEmm<u64, u64, 8, 68>, i: usize| {
+            emm.levels[i]
+                .as_ref()
+                .unwrap()
+                .client
+                .gen_token(&0u64)
+                .h
+                .clone()
+        };
+
+        for i in 0..3u64 {
+            emm.insert(i % 2, vec![i]).unwrap();
+        }
+        let before = h(&emm, 1);
+
+        // Every level encoded later is under keys no earlier token used
+        emm.insert(0u64, vec![3]).unwrap();
+        assert_eq!(emm.level_count(), 1);
+        let merged = h(&emm, 2);
+        assert_ne!(merged, before);
+        let values: Vec<u64> = emm.query(&0u64).unwrap();
+        assert_eq!(values, vec![0, 2, 3]);
+
+        assert!(emm.delete_value(&0u64, 1).unwrap());
+        assert_ne!(h(&emm, 2), merged);
+        let values: Vec<u64> = emm.query(&0u64).unwrap();
+        assert_eq!(values, vec![0, 3]);
+
+        // Without the mode every level shares the client keys
+        let mut emm =
+            DynVhEmm::<u64, u64, 8, 68>::new(RbOkvs::builder(), ClientState::new_random());
+        for i in 0..3u64 {
+            emm.insert(i % 2, vec![i]).unwrap();
+        }
+        assert_eq!(h(&emm, 0), h(&emm, 1));
+    }
+}
@@ -1,27 +1,218 @@
+use std::marker::PhantomData;
+
 use aes_gcm::{
-    aead::{Aead, KeyInit, OsRng},
-    Aes256Gcm, Key as AesKey,
+    aead::{rand_core::RngCore, KeyInit, OsRng},
+    Aes256Gcm,
 };
-use sha256::digest;
+use argon2::Argon2;
+use hkdf::Hkdf;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+#[cfg(feature = "zeroize")]
+use zeroize::{Zeroize, ZeroizeOnDrop, Zeroizing};
 
+use crate::cipher::{AesGcmCipher, EmmCipher};
 use crate::error::{Error, Result};
+use crate::executor::{Executor, Sequential};
+use crate::okvs::Fingerprint;
+use crate::prf::{EmmPrf, HmacSha256};
 use crate::types::{EmmK, EmmV, Encoding, Okvs, OkvsKey, OkvsValue, Pair};
-use crate::utils::hash;
+#[cfg(feature = "serde")]
+use crate::utils::serialize_to_bytes;
+use crate::utils::{hash, to_u32, to_usize};
 
 type KF = [u8; 32];
 type KE = [u8; 32];
 pub type EmmPair<K, V> = (K, V);
-pub const H_LEN: usize = 64;
+/// Bytes of a binary token, see [`TokenFormat`].
+pub const H_LEN: usize = 32;
+/// Nonce and tag bytes of AES-256-GCM, the default [`EmmCipher`].
+pub const NONCE_LEN: usize = 12;
+pub const TAG_LEN: usize = 16;
+/// Leading byte of serialized [`QueryToken`]s and [`QueryResponse`]s.
+pub const MESSAGE_VERSION: u8 = 2;
+/// HKDF info strings of the keys derived from a master secret.
+const KF_INFO: &[u8] = b"rb-okvs/emm/kf";
+const KE_INFO: &[u8] = b"rb-okvs/emm/ke";
+/// Leading byte of [`ClientState::to_bytes`] and [`ClientState::seal`].
+const STATE_VERSION: u8 = 1;
+pub(crate) const STATE_LEN: usize = 1 + 32 + 32;
+const SALT_LEN: usize = 16;
+
+/// Keys, tokens and plaintexts, wiped on drop with the `zeroize` feature.
+#[cfg(feature = "zeroize")]
+pub(crate) type Secret<T = Vec<u8>> = Zeroizing<T>;
+#[cfg(not(feature = "zeroize"))]
+pub(crate) type Secret<T = Vec<u8>> = T;
+
+#[cfg(feature = "zeroize")]
+pub(crate) fn secret<T: Zeroize>(v: T) -> Secret<T> {
+    Zeroizing::new(v)
+}
+
+#[cfg(not(feature = "zeroize"))]
+pub(crate) fn secret<T>(v: T) -> Secret<T> {
+    v
+}
 
-#[derive(Default)]
+/// OKVS value size for entries of `value_len` bytes with binary tokens,
+/// e.g. `VhEmm::<RbOkvs, 8, { okvs_v_size::<AesGcmCipher>(8) }>`.
+pub const fn okvs_v_size<C: EmmCipher>(value_len: usize) -> usize {
+    C::OVERHEAD + H_LEN + value_len
+}
+
+#[derive(Clone)]
 pub struct ClientState {
     pub kf: KF,
     pub ke: KE,
 }
 
+#[cfg(feature = "zeroize")]
+impl Drop for ClientState {
+    fn drop(&mut self) {
+        self.kf.zeroize();
+        self.ke.zeroize();
+    }
+}
+
+#[cfg(feature = "zeroize")]
+impl ZeroizeOnDrop for ClientState {}
+
+/// How [`VhEmm::setup`] pads value lists, so responses don't leak volumes.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum Padding {
+    /// Every list stores exactly its values.
+    #[default]
+    None,
+    /// Every list is padded to this many entries.
+    Fixed(usize),
+    /// Every list is padded to the next power of two.
+    PowerOfTwo,
+}
+
+/// How PRF outputs are encoded into tokens. Each entry stores its token, so
+/// an OKVS value takes `C::OVERHEAD + token_len() + V::len()` bytes for an
+/// [`EmmCipher`] `C`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum TokenFormat {
+    /// The raw PRF output.
+    #[default]
+    Binary,
+    /// Lowercase hex digits of the PRF output, twice as long, for tokens
+    /// that pass through text-only channels unescaped.
+    Hex,
+}
+
 // Volume-Hiding Encrypted Multi-Maps
-pub struct VhEmm<T: Okvs, const OKVS_K_SIZE: usize, const OKVS_V_SIZE: usize> {
-    okvs: T,
+pub struct VhEmm<
+    T: Okvs,
+    const OKVS_K_SIZE: usize,
+    const OKVS_V_SIZE: usize,
+    P: EmmPrf = HmacSha256,
+    C: EmmCipher = AesGcmCipher,
+> {
+    okvs:         T,
+    padding:      Padding,
+    prf:          P,
+    token_format: TokenFormat,
+    cipher:       PhantomData<C>,
+}
+
+/// Client role of the EMM: holds the keys, issues tokens and decrypts
+/// responses.
+pub struct VhEmmClient<P: EmmPrf = HmacSha256, C: EmmCipher = AesGcmCipher> {
+    state:        ClientState,
+    fingerprint:  Fingerprint,
+    prf:          P,
+    token_format: TokenFormat,
+    cipher:       PhantomData<C>,
+}
+
+/// Server role of the EMM: holds the encoding and answers tokens without
+/// learning the queried key.
+pub struct VhEmmServer<T: Okvs, const OKVS_K_SIZE: usize, const OKVS_V_SIZE: usize> {
+    okvs:        T,
+    fingerprint: Fingerprint,
+    emm:         Encoding<OkvsValue<OKVS_V_SIZE>>,
+}
+
+/// PRF output of a queried key, the only thing the server learns about it,
+/// and the fingerprint of the OKVS it is meant for.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(into = "Vec<u8>", try_from = "Vec<u8>"))]
+pub struct QueryToken {
+    pub fingerprint: Fingerprint,
+    pub h:           Vec<u8>,
+}
+
+/// Tokens are PRF outputs, which let anyone holding the encoding find the
+/// entries of their key.
+#[cfg(feature = "zeroize")]
+impl Drop for QueryToken {
+    fn drop(&mut self) {
+        self.h.zeroize();
+    }
+}
+
+#[cfg(feature = "zeroize")]
+impl ZeroizeOnDrop for QueryToken {}
+
+/// The encrypted entries a server decoded for a [`QueryToken`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+#[cfg_attr(feature = "serde", serde(try_from = "Vec<u8>"))]
+pub struct QueryResponse<const OKVS_V_SIZE: usize> {
+    pub values: Vec<OkvsValue<OKVS_V_SIZE>>,
+}
+
+#[cfg(feature = "serde")]
+serialize_to_bytes!(QueryResponse<OKVS_V_SIZE>, OKVS_V_SIZE);
+
+impl TokenFormat {
+    pub fn token_len(&self) -> usize {
+        match self {
+            TokenFormat::Binary => H_LEN,
+            TokenFormat::Hex => 2 * H_LEN,
+        }
+    }
+}
+
+impl Padding {
+    /// Entries stored for a list of `len` values, an empty list still takes
+    /// one. This is the length a query fetches.
+    pub fn padded_len(&self, len: usize) -> Result<usize> {
+        match *self {
+            Padding::None => Ok(len.max(1)),
+            Padding::Fixed(n) if len > n => Err(Error::Config(format!(
+                "{len} values exceed the padding of {n}"
+            ))),
+            Padding::Fixed(n) => Ok(n.max(1)),
+            Padding::PowerOfTwo => Ok(len.max(1).next_power_of_two()),
+        }
+    }
+
+    /// OKVS rows a list of `len` values takes, its length entry included.
+    /// Size the OKVS for the sum of these.
+    pub fn rows(&self, len: usize) -> Result<usize> {
+        Ok(self.padded_len(len)? + 1)
+    }
+}
+
+/// All-zero keys, never to be used outside tests.
+#[cfg(test)]
+impl Default for ClientState {
+    fn default() -> Self {
+        Self {
+            kf: [0u8; 32],
+            ke: [0u8; 32],
+        }
+    }
 }
 
 impl ClientState {
@@ -31,33 +222,208 @@ impl ClientState {
             ke: Aes256Gcm::generate_key(OsRng).into(),
         }
     }
+
+    /// Derives the PRF and encryption keys from one secret with HKDF-SHA256.
+    /// The secret should carry at least 32 bytes of entropy.
+    pub fn from_master_secret(secret: &[u8]) -> Self {
+        let hkdf = Hkdf::<Sha256>::new(None, secret);
+        let mut state = Self {
+            kf: [0u8; 32],
+            ke: [0u8; 32],
+        };
+        hkdf.expand(KF_INFO, &mut state.kf).unwrap();
+        hkdf.expand(KE_INFO, &mut state.ke).unwrap();
+        state
+    }
+
+    /// Independent keys for `context`, derived from both keys like
+    /// [`ClientState::from_master_secret`].
+    pub(crate) fn derive(&self, context: &[u8]) -> Self {
+        let mut secret = secret(self.kf.to_vec());
+        secret.extend_from_slice(&self.ke);
+        secret.extend_from_slice(context);
+        Self::from_master_secret(&secret)
+    }
+
+    /// `version || kf || ke`, in the clear.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(STATE_LEN);
+        bytes.push(STATE_VERSION);
+        bytes.extend_from_slice(&self.kf);
+        bytes.extend_from_slice(&self.ke);
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        match bytes {
+            [STATE_VERSION, keys @ ..] if bytes.len() == STATE_LEN => Ok(Self {
+                kf: keys[..32].try_into().unwrap(),
+                ke: keys[32..].try_into().unwrap(),
+            }),
+            [STATE_VERSION, ..] => Err(Error::Message(format!(
+                "client state of {} bytes",
+                bytes.len()
+            ))),
+            _ => Err(Error::Message("unsupported client state version".into())),
+        }
+    }
+
+    /// Encrypts the state for storage at rest under a key derived from
+    /// `passphrase` with Argon2id: `version || salt || nonce || ct || tag`.
+    pub fn seal(&self, passphrase: &[u8]) -> Vec<u8> {
+        seal_bytes(&secret(self.to_bytes()), passphrase)
+    }
+
+    /// Fails with [`Error::Auth`] for a wrong passphrase or a corrupted blob.
+    pub fn unseal(bytes: &[u8], passphrase: &[u8]) -> Result<Self> {
+        const SEALED_LEN: usize = 1 + SALT_LEN + NONCE_LEN + STATE_LEN + TAG_LEN;
+        if bytes.len() != SEALED_LEN || bytes[0] != STATE_VERSION {
+            return Err(Error::Message("malformed sealed client state".into()));
+        }
+        Self::from_bytes(&unseal_bytes(bytes, passphrase)?)
+    }
 }
 
 impl<T: Okvs, const OKVS_K_SIZE: usize, const OKVS_V_SIZE: usize>
     VhEmm<T, OKVS_K_SIZE, OKVS_V_SIZE>
 {
     pub fn new(okvs: T) -> Self {
-        Self { okvs }
+        Self::with_prf(okvs, HmacSha256)
+    }
+}
+
+impl<T: Okvs, const OKVS_K_SIZE: usize, const OKVS_V_SIZE: usize, P: EmmPrf, C: EmmCipher>
+    VhEmm<T, OKVS_K_SIZE, OKVS_V_SIZE, P, C>
+{
+    /// Derives tokens with `prf` instead of [`HmacSha256`]. Setup and queries
+    /// must use the same PRF, and the same cipher `C`.
+    pub fn with_prf(okvs: T, prf: P) -> Self {
+        Self {
+            okvs,
+            padding: Padding::None,
+            prf,
+            token_format: TokenFormat::Binary,
+            cipher: PhantomData,
+        }
+    }
+
+    /// Clients must use the same format, see [`VhEmmClient::token_format`].
+    pub fn token_format(mut self, token_format: TokenFormat) -> Self {
+        self.token_format = token_format;
+        self
     }
 
+    fn tokens(&self) -> Tokens<'_> {
+        Tokens {
+            prf: &self.prf,
+            format: self.token_format,
+        }
+    }
+
+    pub fn padding(mut self, padding: Padding) -> Self {
+        self.padding = padding;
+        self
+    }
+
+    /// OKVS value size entries of `V` take, which `OKVS_V_SIZE` must equal.
+    pub fn value_size<V: EmmV>(&self) -> usize {
+        C::OVERHEAD + self.token_format.token_len() + V::len()
+    }
+
+    /// Encrypts and encodes `input` under `client_state`, see
+    /// [`ClientState::new_random`] and [`ClientState::from_master_secret`].
     pub fn setup<K: EmmK, V: EmmV>(
         &self,
         input: Vec<EmmPair<K, Vec<V>>>,
-    ) -> Result<(Encoding<OkvsValue<OKVS_V_SIZE>>, ClientState)> {
-        let client_state = ClientState::default();
+        client_state: &ClientState,
+    ) -> Result<Encoding<OkvsValue<OKVS_V_SIZE>>> {
+        self.encode_with(input, client_state, 0)
+    }
+
+    /// Encrypts and encodes `input` under an existing `client_state`, adding
+    /// random rows up to `pad_rows` so that decoding an absent entry never
+    /// lands on a single stored ciphertext.
+    pub(crate) fn encode_with<K: EmmK, V: EmmV>(
+        &self,
+        input: Vec<EmmPair<K, Vec<V>>>,
+        client_state: &ClientState,
+        pad_rows: usize,
+    ) -> Result<Encoding<OkvsValue<OKVS_V_SIZE>>> {
+        if self.value_size::<V>() != OKVS_V_SIZE {
+            return Err(Error::ValueSizeMismatch {
+                expected: OKVS_V_SIZE,
+                actual: self.value_size::<V>(),
+            });
+        }
         let mut new_input: Vec<Pair<OkvsKey<OKVS_K_SIZE>, OkvsValue<OKVS_V_SIZE>>> = vec![];
 
         for (key, value) in input {
-            let h = calc_h(&client_state.kf, &key); // H_LEN = h.len()
+            let h = self.tokens().h(&client_state.kf, &key);
+            let padded_len = self.padding.padded_len(value.len())?;
+            new_input.push((
+                create_len_key::<OKVS_K_SIZE>(&h),
+                encode_len::<C, V, OKVS_V_SIZE>(self.tokens(), client_state, &h, padded_len)?,
+            ));
+            if value.is_empty() {
+                // Header entry, so an empty list doesn't decode like an unknown key
+                let k = create_key::<OKVS_K_SIZE>(&h, 0);
+                let v = encode_marker::<C, V, OKVS_V_SIZE>(
+                    client_state,
+                    self.tokens().empty(&client_state.kf, &h),
+                    &self.tokens().entry_ad(&h, 0),
+                )?;
+                new_input.push((k, v));
+            }
             for (j, v) in value.iter().enumerate() {
-                let k = create_key::<OKVS_K_SIZE>(h.clone(), j);
-                let v = encode_value::<V, OKVS_V_SIZE>(&client_state.ke, h.clone(), v);
+                let k = create_key::<OKVS_K_SIZE>(&h, j);
+                let ad = self.tokens().entry_ad(&h, j);
+                let v = encode_value::<C, V, OKVS_V_SIZE>(&client_state.ke, &h, v, &ad)?;
+                new_input.push((k, v));
+            }
+            for j in value.len().max(1)..padded_len {
+                let k = create_key::<OKVS_K_SIZE>(&h, j);
+                let v = encode_marker::<C, V, OKVS_V_SIZE>(
+                    client_state,
+                    self.tokens().pad(&client_state.kf, &h),
+                    &self.tokens().entry_ad(&h, j),
+                )?;
                 new_input.push((k, v));
             }
         }
 
-        let emm = self.okvs.encode(new_input)?;
-        Ok((emm, client_state))
+        while new_input.len() < pad_rows {
+            let mut k = OkvsKey([0u8; OKVS_K_SIZE]);
+            let mut v = OkvsValue([0u8; OKVS_V_SIZE]);
+            OsRng.fill_bytes(&mut k.0);
+            OsRng.fill_bytes(&mut v.0);
+            new_input.push((k, v));
+        }
+
+        self.okvs.encode_with_rng(&new_input, &mut OsRng)
+    }
+
+    /// Re-encrypts `emm` under `new_state`, e.g. for periodic key rotation.
+    ///
+    /// An OKVS can't be enumerated, so this takes the `keys` whose lists to
+    /// carry over; their values are recovered from `emm` itself. Keys missing
+    /// from `emm` fail with [`Error::NotFound`].
+    pub fn rotate_keys<K: EmmK, V: EmmV>(
+        &self,
+        old_state: &ClientState,
+        new_state: &ClientState,
+        emm: &Encoding<OkvsValue<OKVS_V_SIZE>>,
+        keys: Vec<K>,
+    ) -> Result<Encoding<OkvsValue<OKVS_V_SIZE>>> {
+        let mut input: Vec<EmmPair<K, Vec<V>>> = Vec::with_capacity(keys.len());
+        for key in keys {
+            let h = self.tokens().h(&old_state.kf, &key);
+            let len = self.response_len(h.to_vec(), emm)?;
+            let v_len = self.decode_len(&key, &len, old_state)?;
+            let response = self.response(v_len, h.to_vec(), emm)?;
+            let values = resolve::<C, V, OKVS_V_SIZE>(self.tokens(), old_state, &h, response)?;
+            input.push((key, values));
+        }
+        self.encode_with(input, new_state, 0)
     }
 
     // Just for test
@@ -65,204 +431,970 @@ impl<T: Okvs, const OKVS_K_SIZE: usize, const OKVS_V_SIZE: usize>
     fn query<K: EmmK, V: EmmV>(
         &self,
         key: K,
-        v_len: usize,
         client_state: &ClientState,
         emm: &Encoding<OkvsValue<OKVS_V_SIZE>>,
     ) -> Result<Vec<V>> {
-        let h = calc_h(&client_state.kf, &key);
-        let response = self.response(v_len, h, emm);
+        let h = self.tokens().h(&client_state.kf, &key);
+        let len = self.response_len(h.to_vec(), emm)?;
+        let v_len = self.decode_len(&key, &len, client_state)?;
+        let response = self.response(v_len, h.to_vec(), emm)?;
         self.decode(key, response, client_state)
     }
 
+    // Just for test
+    #[allow(unused)]
+    fn query_batch<K: EmmK, V: EmmV>(
+        &self,
+        keys: &[K],
+        client_state: &ClientState,
+        emm: Encoding<OkvsValue<OKVS_V_SIZE>>,
+    ) -> Result<Vec<Vec<V>>>
+    where
+        T: Clone + Sync,
+        P: Clone,
+    {
+        let client = VhEmmClient::<P, C>::with_prf(
+            client_state.clone(),
+            self.okvs.fingerprint(),
+            self.prf.clone(),
+        )
+        .token_format(self.token_format);
+        let server = VhEmmServer::<T, OKVS_K_SIZE, OKVS_V_SIZE>::new(self.okvs.clone(), emm);
+
+        let tokens = client.gen_tokens(keys);
+        let lens = tokens
+            .iter()
+            .map(|token| client.resolve_len(token, server.answer_len(token)?))
+            .collect::<Result<Vec<_>>>()?;
+        // One round for all keys, each response cut back to its own length
+        let v_len = lens.iter().copied().max().unwrap_or(0);
+        let mut responses = server.answer_batch(&Sequential, &tokens, v_len)?;
+        for (response, len) in responses.iter_mut().zip(lens) {
+            response.values.truncate(len);
+        }
+        client.resolve_batch(&tokens, responses)
+    }
+
+    // server
+    /// The encrypted length entry of `h`, to be passed to
+    /// [`VhEmm::decode_len`].
+    pub fn response_len(
+        &self,
+        h: Vec<u8>,
+        emm: &Encoding<OkvsValue<OKVS_V_SIZE>>,
+    ) -> Result<OkvsValue<OKVS_V_SIZE>> {
+        self.okvs.decode(emm, &create_len_key::<OKVS_K_SIZE>(&h))
+    }
+
     // server
     pub fn response(
         &self,
         v_len: usize,
         h: Vec<u8>,
         emm: &Encoding<OkvsValue<OKVS_V_SIZE>>,
-    ) -> Vec<OkvsValue<OKVS_V_SIZE>> {
-        let mut x = vec![];
-        for i in 0..v_len {
-            let k = create_key::<OKVS_K_SIZE>(h.clone(), i);
-            x.push(self.okvs.decode(emm, &k));
-        }
-        x
+    ) -> Result<Vec<OkvsValue<OKVS_V_SIZE>>> {
+        answer::<T, OKVS_K_SIZE, OKVS_V_SIZE>(&self.okvs, emm, &h, v_len)
+    }
+
+    // client
+    /// Number of entries to request for `key`, fails with [`Error::NotFound`]
+    /// for keys that were never set up.
+    ///
+    /// Every key set up has an authenticated length entry, so a length that
+    /// doesn't authenticate means the key is absent. A server can always
+    /// answer garbage, which makes a key look absent too.
+    pub fn decode_len<K: EmmK>(
+        &self,
+        key: &K,
+        response: &OkvsValue<OKVS_V_SIZE>,
+        client_state: &ClientState,
+    ) -> Result<usize> {
+        let h = self.tokens().h(&client_state.kf, key);
+        resolve_len::<C, OKVS_V_SIZE>(self.tokens(), client_state, &h, response)
     }
 
     // client
+    /// Returns an empty list for keys that were set up with no values, which
+    /// requires `response` to cover at least one entry.
     pub fn decode<K: EmmK, V: EmmV>(
         &self,
         key: K,
         response: Vec<OkvsValue<OKVS_V_SIZE>>,
         client_state: &ClientState,
     ) -> Result<Vec<V>> {
-        let h = calc_h(&client_state.kf, &key);
+        let h = self.tokens().h(&client_state.kf, &key);
+        resolve::<C, V, OKVS_V_SIZE>(self.tokens(), client_state, &h, response)
+    }
+}
+
+impl VhEmmClient {
+    /// Issues tokens for the OKVS of fingerprint `fingerprint`, see
+    /// [`VhEmmServer::fingerprint`].
+    pub fn new(state: ClientState, fingerprint: Fingerprint) -> Self {
+        Self::with_prf(state, fingerprint, HmacSha256)
+    }
+}
+
+impl<P: EmmPrf, C: EmmCipher> VhEmmClient<P, C> {
+    /// See [`VhEmm::with_prf`].
+    pub fn with_prf(state: ClientState, fingerprint: Fingerprint, prf: P) -> Self {
+        Self {
+            state,
+            fingerprint,
+            prf,
+            token_format: TokenFormat::Binary,
+            cipher: PhantomData,
+        }
+    }
+
+    /// See [`VhEmm::token_format`].
+    pub fn token_format(mut self, token_format: TokenFormat) -> Self {
+        self.token_format = token_format;
+        self
+    }
+
+    pub fn gen_token<K: EmmK>(&self, key: &K) -> QueryToken {
+        QueryToken {
+            fingerprint: self.fingerprint,
+            h: self.tokens().h(&self.state.kf, key).to_vec(),
+        }
+    }
+
+    pub fn gen_tokens<K: EmmK>(&self, keys: &[K]) -> Vec<QueryToken> {
+        keys.iter().map(|key| self.gen_token(key)).collect()
+    }
+
+    /// Decrypts the length entry [`VhEmmServer::answer_len`] returned for
+    /// `token`, the `v_len` to query next.
+    pub fn resolve_len<const OKVS_V_SIZE: usize>(
+        &self,
+        token: &QueryToken,
+        response: QueryResponse<OKVS_V_SIZE>,
+    ) -> Result<usize> {
+        match response.values.as_slice() {
+            [value] => resolve_len::<C, OKVS_V_SIZE>(self.tokens(), &self.state, &token.h, value),
+            values => Err(Error::Message(format!(
+                "{} values for a length",
+                values.len()
+            ))),
+        }
+    }
+
+    /// Decrypts the response to `token`, see [`VhEmm::decode`].
+    pub fn resolve<V: EmmV, const OKVS_V_SIZE: usize>(
+        &self,
+        token: &QueryToken,
+        response: QueryResponse<OKVS_V_SIZE>,
+    ) -> Result<Vec<V>> {
+        resolve::<C, V, OKVS_V_SIZE>(self.tokens(), &self.state, &token.h, response.values)
+    }
+
+    /// Resolves the responses to `tokens`, in order.
+    pub fn resolve_batch<V: EmmV, const OKVS_V_SIZE: usize>(
+        &self,
+        tokens: &[QueryToken],
+        responses: Vec<QueryResponse<OKVS_V_SIZE>>,
+    ) -> Result<Vec<Vec<V>>> {
+        if tokens.len() != responses.len() {
+            return Err(Error::Message(format!(
+                "{} responses for {} tokens",
+                responses.len(),
+                tokens.len()
+            )));
+        }
+        tokens
+            .iter()
+            .zip(responses)
+            .map(|(token, response)| self.resolve(token, response))
+            .collect()
+    }
+
+    fn tokens(&self) -> Tokens<'_> {
+        Tokens {
+            prf: &self.prf,
+            format: self.token_format,
+        }
+    }
+}
+
+impl<T: Okvs, const OKVS_K_SIZE: usize, const OKVS_V_SIZE: usize>
+    VhEmmServer<T, OKVS_K_SIZE, OKVS_V_SIZE>
+{
+    pub fn new(okvs: T, emm: Encoding<OkvsValue<OKVS_V_SIZE>>) -> Self {
+        Self {
+            fingerprint: okvs.fingerprint(),
+            okvs,
+            emm,
+        }
+    }
+
+    /// Fingerprint of the OKVS, which clients stamp on their tokens.
+    pub fn fingerprint(&self) -> &Fingerprint {
+        &self.fingerprint
+    }
+
+    /// The encoding tokens are answered from.
+    pub fn encoding(&self) -> &Encoding<OkvsValue<OKVS_V_SIZE>> {
+        &self.emm
+    }
+
+    /// Decodes the length entry stored under `token`.
+    pub fn answer_len(&self, token: &QueryToken) -> Result<QueryResponse<OKVS_V_SIZE>> {
+        self.check_fingerprint(token)?;
+        Ok(QueryResponse {
+            values: vec![self
+                .okvs
+                .decode(&self.emm, &create_len_key::<OKVS_K_SIZE>(&token.h))?],
+        })
+    }
+
+    /// Decodes the first `v_len` entries stored under `token`.
+    pub fn answer(&self, token: &QueryToken, v_len: usize) -> Result<QueryResponse<OKVS_V_SIZE>> {
+        self.check_fingerprint(token)?;
+        Ok(QueryResponse {
+            values: answer::<T, OKVS_K_SIZE, OKVS_V_SIZE>(&self.okvs, &self.emm, &token.h, v_len)?,
+        })
+    }
+
+    /// Answers all `tokens` with a single batched decode on `executor`,
+    /// responses in token order.
+    pub fn answer_batch<E: Executor>(
+        &self,
+        executor: &E,
+        tokens: &[QueryToken],
+        v_len: usize,
+    ) -> Result<Vec<QueryResponse<OKVS_V_SIZE>>>
+    where
+        T: Sync,
+    {
+        for token in tokens {
+            self.check_fingerprint(token)?;
+        }
+        let keys: Vec<OkvsKey<OKVS_K_SIZE>> = tokens
+            .iter()
+            .flat_map(|token| (0..v_len).map(|i| create_key::<OKVS_K_SIZE>(&token.h, i)))
+            .collect();
+        let mut values = self
+            .okvs
+            .decode_batch(executor, &self.emm, &keys)?
+            .into_iter();
+
+        Ok(tokens
+            .iter()
+            .map(|_| QueryResponse {
+                values: values.by_ref().take(v_len).collect(),
+            })
+            .collect())
+    }
+
+    /// A token for another OKVS would decode to garbage, which the client
+    /// can't tell from an absent key.
+    fn check_fingerprint(&self, token: &QueryToken) -> Result<()> {
+        match token.fingerprint == self.fingerprint {
+            true => Ok(()),
+            false => Err(Error::ParamsMismatch("OKVS")),
+        }
+    }
+}
+
+impl QueryToken {
+    /// `version || fingerprint || h`
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(9 + self.h.len());
+        bytes.push(MESSAGE_VERSION);
+        bytes.extend_from_slice(&self.fingerprint);
+        bytes.extend_from_slice(&self.h);
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let rest = check_version(bytes)?;
+        if rest.len() < 8 {
+            return Err(Error::Message("truncated token fingerprint".into()));
+        }
+        let (fingerprint, h) = rest.split_at(8);
+        if h.len() != TokenFormat::Binary.token_len() && h.len() != TokenFormat::Hex.token_len() {
+            return Err(Error::Message(format!("token of {} bytes", h.len())));
+        }
+        Ok(Self {
+            fingerprint: fingerprint.try_into().unwrap(),
+            h: h.to_vec(),
+        })
+    }
+}
 
-        let mut v = vec![];
-        for (i, xi) in response.into_iter().enumerate() {
-            let (dh, y) = decode_value::<V, OKVS_V_SIZE>(&client_state.ke, xi);
-            if dh != h {
-                return Err(Error::Decode(i));
+impl<const OKVS_V_SIZE: usize> QueryResponse<OKVS_V_SIZE> {
+    /// `version || count (u32 LE) || value size (u32 LE) || values`
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        let mut bytes = Vec::with_capacity(9 + self.values.len() * OKVS_V_SIZE);
+        bytes.push(MESSAGE_VERSION);
+        bytes.extend_from_slice(&to_u32(self.values.len())?.to_le_bytes());
+        bytes.extend_from_slice(&(OKVS_V_SIZE as u32).to_le_bytes());
+        for value in &self.values {
+            bytes.extend_from_slice(&value.0);
+        }
+        Ok(bytes)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let rest = check_version(bytes)?;
+        if rest.len() < 8 {
+            return Err(Error::Message("truncated response header".into()));
+        }
+        let count = u32::from_le_bytes(rest[..4].try_into().unwrap()) as usize;
+        let size = u32::from_le_bytes(rest[4..8].try_into().unwrap()) as usize;
+        if size != OKVS_V_SIZE {
+            return Err(Error::Message(format!(
+                "value size {size}, expected {OKVS_V_SIZE}"
+            )));
+        }
+
+        let body = &rest[8..];
+        if count.checked_mul(OKVS_V_SIZE) != Some(body.len()) {
+            return Err(Error::Message(format!(
+                "{} bytes for {count} values",
+                body.len()
+            )));
+        }
+        let values = body
+            .chunks_exact(OKVS_V_SIZE)
+            .map(|chunk| OkvsValue(chunk.try_into().unwrap()))
+            .collect();
+        Ok(Self { values })
+    }
+}
+
+impl From<QueryToken> for Vec<u8> {
+    fn from(token: QueryToken) -> Self {
+        token.to_bytes()
+    }
+}
+
+impl TryFrom<Vec<u8>> for QueryToken {
+    type Error = Error;
+
+    fn try_from(bytes: Vec<u8>) -> Result<Self> {
+        Self::from_bytes(&bytes)
+    }
+}
+
+impl<const OKVS_V_SIZE: usize> TryFrom<QueryResponse<OKVS_V_SIZE>> for Vec<u8> {
+    type Error = Error;
+
+    fn try_from(response: QueryResponse<OKVS_V_SIZE>) -> Result<Self> {
+        response.to_bytes()
+    }
+}
+
+impl<const OKVS_V_SIZE: usize> TryFrom<Vec<u8>> for QueryResponse<OKVS_V_SIZE> {
+    type Error = Error;
+
+    fn try_from(bytes: Vec<u8>) -> Result<Self> {
+        Self::from_bytes(&bytes)
+    }
+}
+
+/// Strips the version byte of a serialized message.
+fn check_version(bytes: &[u8]) -> Result<&[u8]> {
+    match bytes.split_first() {
+        Some((&MESSAGE_VERSION, rest)) => Ok(rest),
+        Some((version, _)) => Err(Error::Message(format!("unsupported version {version}"))),
+        None => Err(Error::Message("empty message".into())),
+    }
+}
+
+fn answer<T: Okvs, const OKVS_K_SIZE: usize, const OKVS_V_SIZE: usize>(
+    okvs: &T,
+    emm: &Encoding<OkvsValue<OKVS_V_SIZE>>,
+    h: &[u8],
+    v_len: usize,
+) -> Result<Vec<OkvsValue<OKVS_V_SIZE>>> {
+    (0..v_len)
+        .map(|i| okvs.decode(emm, &create_key::<OKVS_K_SIZE>(h, i)))
+        .collect()
+}
+
+fn resolve<C: EmmCipher, V: EmmV, const OKVS_V_SIZE: usize>(
+    tokens: Tokens,
+    client_state: &ClientState,
+    h: &[u8],
+    response: Vec<OkvsValue<OKVS_V_SIZE>>,
+) -> Result<Vec<V>> {
+    let mut v = vec![];
+    for (i, xi) in response.into_iter().enumerate() {
+        let ad = tokens.entry_ad(h, i);
+        let plaintext =
+            decrypt::<C, OKVS_V_SIZE>(&client_state.ke, &xi, &ad).ok_or(Error::Auth(i))?;
+        let (token, value) = plaintext.split_at(h.len());
+        if token != h {
+            if i == 0 && token == &tokens.empty(&client_state.kf, h)[..] {
+                return Ok(vec![]);
+            }
+            // Padding only follows the values
+            if token == &tokens.pad(&client_state.kf, h)[..] {
+                return Ok(v);
             }
-            v.push(y);
+            return Err(Error::Decode(i));
         }
-        Ok(v)
+        v.push(V::decode(&value[..V::len()]));
+    }
+    Ok(v)
+}
+
+fn resolve_len<C: EmmCipher, const OKVS_V_SIZE: usize>(
+    tokens: Tokens,
+    client_state: &ClientState,
+    h: &[u8],
+    response: &OkvsValue<OKVS_V_SIZE>,
+) -> Result<usize> {
+    let ad = tokens.len_ad(h);
+    let plaintext =
+        decrypt::<C, OKVS_V_SIZE>(&client_state.ke, response, &ad).ok_or(Error::NotFound)?;
+    let marker = tokens.len_marker(&client_state.kf, h);
+    let split = marker.len() - 8;
+    if plaintext[..split] != marker[..split] {
+        return Err(Error::Decode(0));
     }
+    let len = u64::from_le_bytes(plaintext[split..marker.len()].try_into().unwrap());
+    to_usize(len)
 }
 
-fn calc_h<K: EmmK>(kf: &KF, key: &K) -> Vec<u8> {
-    let mut arr = kf.to_vec();
-    arr.extend_from_slice(&key.to_bytes());
-    digest(arr).into_bytes()
+/// Derives tokens and entry markers with a PRF, and the associated data of
+/// entries.
+#[derive(Clone, Copy)]
+struct Tokens<'a> {
+    prf:    &'a dyn EmmPrf,
+    format: TokenFormat,
 }
 
-fn create_key<const OKVS_K_SIZE: usize>(mut h: Vec<u8>, i: usize) -> OkvsKey<OKVS_K_SIZE> {
-    h.extend_from_slice(&i.to_le_bytes());
+impl Tokens<'_> {
+    fn eval(&self, kf: &KF, input: &[u8]) -> Secret {
+        let out = secret(self.prf.eval(kf, input));
+        secret(match self.format {
+            TokenFormat::Binary => out.to_vec(),
+            TokenFormat::Hex => to_hex(&out[..]),
+        })
+    }
+
+    fn h<K: EmmK>(&self, kf: &KF, key: &K) -> Secret {
+        self.eval(kf, &key.to_bytes())
+    }
 
-    let k = hash(&h, OKVS_K_SIZE);
+    /// Marks the header entry of a key whose value list is empty.
+    fn empty(&self, kf: &KF, h: &[u8]) -> Secret {
+        self.eval(kf, &secret([h, b"empty"].concat()))
+    }
+
+    /// Marks the dummy entries a list is padded with.
+    fn pad(&self, kf: &KF, h: &[u8]) -> Secret {
+        self.eval(kf, &secret([h, b"pad"].concat()))
+    }
 
-    let mut buf = [0u8; OKVS_K_SIZE];
-    buf.copy_from_slice(&k);
-    OkvsKey(buf)
+    /// Marks the length entry of a key.
+    fn len_marker(&self, kf: &KF, h: &[u8]) -> Secret {
+        self.eval(kf, &secret([h, b"len"].concat()))
+    }
+
+    /// Associated data of entry `j` of `h`, `h || j` (u64 LE), so a server
+    /// answering with an entry in another's place fails authentication.
+    fn entry_ad(&self, h: &[u8], j: usize) -> Secret {
+        secret([h, &(j as u64).to_le_bytes()].concat())
+    }
+
+    /// Associated data of the length entry of `h`, `h || "len"`.
+    fn len_ad(&self, h: &[u8]) -> Secret {
+        secret([h, b"len"].concat())
+    }
 }
 
-fn encode_value<V: EmmV, const OKVS_V_SIZE: usize>(
+fn to_hex(bytes: &[u8]) -> Vec<u8> {
+    const DIGITS: &[u8; 16] = b"0123456789abcdef";
+    bytes
+        .iter()
+        .flat_map(|b| [DIGITS[(b >> 4) as usize], DIGITS[(b & 0xf) as usize]])
+        .collect()
+}
+
+/// The length entry stores the number of entries to query, padding included,
+/// in the last 8 bytes of its marker.
+fn encode_len<C: EmmCipher, V: EmmV, const OKVS_V_SIZE: usize>(
+    tokens: Tokens,
+    client_state: &ClientState,
+    h: &[u8],
+    len: usize,
+) -> Result<OkvsValue<OKVS_V_SIZE>> {
+    let mut marker = tokens.len_marker(&client_state.kf, h);
+    let split = marker.len() - 8;
+    marker[split..].copy_from_slice(&(len as u64).to_le_bytes());
+    encode_marker::<C, V, OKVS_V_SIZE>(client_state, marker, &tokens.len_ad(h))
+}
+
+/// Encrypts a marker in place of `h`, with zeros in place of a value.
+fn encode_marker<C: EmmCipher, V: EmmV, const OKVS_V_SIZE: usize>(
+    client_state: &ClientState,
+    mut marker: Secret,
+    aad: &[u8],
+) -> Result<OkvsValue<OKVS_V_SIZE>> {
+    let len = marker.len() + V::len();
+    marker.resize(len, 0);
+    encrypt::<C, OKVS_V_SIZE>(&client_state.ke, &marker, aad)
+}
+
+fn create_key<const OKVS_K_SIZE: usize>(h: &[u8], i: usize) -> OkvsKey<OKVS_K_SIZE> {
+    let mut h = secret(h.to_vec());
+    h.extend_from_slice(&(i as u64).to_le_bytes());
+
+    OkvsKey::try_from(hash(&h, OKVS_K_SIZE).as_slice()).unwrap()
+}
+
+/// OKVS key of the length entry, disjoint from the keys of the values.
+fn create_len_key<const OKVS_K_SIZE: usize>(h: &[u8]) -> OkvsKey<OKVS_K_SIZE> {
+    let mut h = secret(h.to_vec());
+    h.extend_from_slice(b"len");
+
+    OkvsKey::try_from(hash(&h, OKVS_K_SIZE).as_slice()).unwrap()
+}
+
+fn encode_value<C: EmmCipher, V: EmmV, const OKVS_V_SIZE: usize>(
     ke: &KE,
-    mut h: Vec<u8>,
+    h: &[u8],
     v: &V,
-) -> OkvsValue<OKVS_V_SIZE> {
-    h.extend_from_slice(&v.encode());
+    aad: &[u8],
+) -> Result<OkvsValue<OKVS_V_SIZE>> {
+    let mut plaintext = secret(h.to_vec());
+    plaintext.extend_from_slice(&secret(v.encode()));
+    encrypt::<C, OKVS_V_SIZE>(ke, &plaintext, aad)
+}
+
+/// Encrypts client state of any length like [`ClientState::seal`].
+pub(crate) fn seal_bytes(plaintext: &[u8], passphrase: &[u8]) -> Vec<u8> {
+    let mut salt = [0u8; SALT_LEN];
+    OsRng.fill_bytes(&mut salt);
+    let key = seal_key(passphrase, &salt);
 
-    let key = AesKey::<Aes256Gcm>::from_slice(ke);
-    let cipher = Aes256Gcm::new(key);
-    let ciphertext = cipher.encrypt(&Default::default(), h.as_slice()).unwrap(); // TODO: randome nonce
+    let mut bytes = vec![STATE_VERSION];
+    bytes.extend_from_slice(&salt);
+    bytes.extend(AesGcmCipher::encrypt(&key, plaintext, &[]));
+    bytes
+}
 
-    let mut v = [0u8; OKVS_V_SIZE];
-    v.copy_from_slice(&ciphertext);
-    OkvsValue(v)
+/// Reverses [`seal_bytes`], see [`ClientState::unseal`].
+pub(crate) fn unseal_bytes(bytes: &[u8], passphrase: &[u8]) -> Result<Secret> {
+    match bytes {
+        [STATE_VERSION, sealed @ ..] if sealed.len() >= SALT_LEN + NONCE_LEN + TAG_LEN => {
+            let (salt, sealed) = sealed.split_at(SALT_LEN);
+            let key = seal_key(passphrase, salt);
+            AesGcmCipher::decrypt(&key, sealed, &[])
+                .map(secret)
+                .ok_or(Error::Auth(0))
+        }
+        _ => Err(Error::Message("malformed sealed client state".into())),
+    }
+}
+
+fn seal_key(passphrase: &[u8], salt: &[u8]) -> Secret<[u8; 32]> {
+    let mut key = secret([0u8; 32]);
+    Argon2::default()
+        .hash_password_into(passphrase, salt, &mut key[..])
+        .unwrap();
+    key
+}
+
+/// Fails with [`Error::ValueSizeMismatch`] unless the ciphertext fills
+/// `OKVS_V_SIZE` exactly.
+fn encrypt<C: EmmCipher, const OKVS_V_SIZE: usize>(
+    ke: &KE,
+    plaintext: &[u8],
+    aad: &[u8],
+) -> Result<OkvsValue<OKVS_V_SIZE>> {
+    let ciphertext = C::encrypt(ke, plaintext, aad);
+    let v = ciphertext
+        .try_into()
+        .map_err(|c: Vec<u8>| Error::ValueSizeMismatch {
+            expected: OKVS_V_SIZE,
+            actual: c.len(),
+        })?;
+    Ok(OkvsValue(v))
 }
 
-fn decode_value<V: EmmV, const OKVS_V_SIZE: usize>(
+fn decrypt<C: EmmCipher, const OKVS_V_SIZE: usize>(
     ke: &KE,
-    v: OkvsValue<OKVS_V_SIZE>,
-) -> (Vec<u8>, V) {
-    let key = AesKey::<Aes256Gcm>::from_slice(ke);
-    let cipher = Aes256Gcm::new(key);
-    let decode = cipher.encrypt(&Default::default(), v.0.as_slice()).unwrap(); // TODO: randome nonce
-    (
-        decode[..H_LEN].into(),
-        V::decode(&decode[H_LEN..H_LEN + V::len()]),
-    )
+    v: &OkvsValue<OKVS_V_SIZE>,
+    aad: &[u8],
+) -> Option<Secret> {
+    C::decrypt(ke, &v.0, aad).map(secret)
 }
 
 #[cfg(test)]
 mod test {
+    use crate::cipher::XChaChaCipher;
+    use crate::executor::StdThreads;
     use crate::okvs::RbOkvs;
+    use crate::prf::{AesCmacPrf, Blake2bPrf};
 
     use super::*;
 
     #[test]
     fn test_rb_mm_vu64() {
-        struct EmmKey(pub u64);
+        let mut pairs: Vec<EmmPair<u64, Vec<u64>>> = vec![];
+        for i in 0..200 {
+            pairs.push((i as u64, vec![i as u64]));
+        }
+        let rb_okvs = RbOkvs::new(pairs.len() * 2);
 
-        impl EmmK for EmmKey {
-            fn to_bytes(&self) -> Vec<u8> {
-                self.0.to_le_bytes().into()
-            }
+        // 68 = 60 + <u64 as EmmV>::len()
+        let rb_mm = VhEmm::<RbOkvs, 8, 68>::new(rb_okvs);
+        let client_state = ClientState::new_random();
+        let emm = rb_mm.setup(pairs, &client_state).unwrap();
+
+        for i in 0..200 {
+            let value: Vec<u64> = rb_mm.query(i as u64, &client_state, &emm).unwrap();
+            assert_eq!(value[0], i as u64);
         }
 
-        struct EmmValue(pub u64);
+        // Tampering with the stored ciphertext fails authentication
+        let h = rb_mm.tokens().h(&client_state.kf, &7u64);
+        let mut response = rb_mm.response(1, h.to_vec(), &emm).unwrap();
+        response[0].0[NONCE_LEN] ^= 1;
+        let tampered = rb_mm.decode::<u64, u64>(7u64, response, &client_state);
+        assert!(matches!(tampered, Err(Error::Auth(0))));
+    }
 
-        impl EmmV for EmmValue {
-            fn len() -> usize {
-                8
-            }
+    #[test]
+    fn test_rb_mm_client_server() {
+        let mut pairs: Vec<EmmPair<u64, Vec<u64>>> = vec![];
+        for i in 0..100 {
+            pairs.push((i as u64, vec![i as u64, i as u64 + 1]));
+        }
+        let rb_okvs = RbOkvs::new(pairs.len() * 3);
 
-            fn encode(&self) -> Vec<u8> {
-                self.0.to_le_bytes().into()
-            }
+        let rb_mm = VhEmm::<RbOkvs, 8, 68>::new(rb_okvs.clone());
+        let client_state = ClientState::new_random();
+        let emm = rb_mm.setup(pairs, &client_state).unwrap();
 
-            fn decode(b: &[u8]) -> Self {
-                let mut v = [0u8; 8];
-                v.copy_from_slice(b);
-                Self(u64::from_le_bytes(v))
-            }
+        let client = VhEmmClient::new(client_state, rb_okvs.fingerprint());
+        let server = VhEmmServer::<RbOkvs, 8, 68>::new(rb_okvs, emm);
+
+        for i in 0..100 {
+            let token = client.gen_token(&(i as u64));
+            let v_len = client
+                .resolve_len(&token, server.answer_len(&token).unwrap())
+                .unwrap();
+            assert_eq!(v_len, 2);
+            let response = server.answer(&token, v_len).unwrap();
+            let value: Vec<u64> = client.resolve(&token, response).unwrap();
+            assert_eq!(value[0], i as u64);
+            assert_eq!(value[1], i as u64 + 1);
         }
 
-        let mut pairs: Vec<EmmPair<EmmKey, Vec<EmmValue>>> = vec![];
+        // Over the wire
+        let token = client.gen_token(&7u64);
+        let token = QueryToken::from_bytes(&token.to_bytes()).unwrap();
+        let response = server.answer(&token, 2).unwrap().to_bytes().unwrap();
+        let response = QueryResponse::<68>::from_bytes(&response).unwrap();
+        let value: Vec<u64> = client.resolve(&token, response).unwrap();
+        assert_eq!(value[0], 7);
+
+        // Batched
+        let keys: Vec<u64> = (0..100).collect();
+        let tokens = client.gen_tokens(&keys);
+        let responses = server.answer_batch(&Sequential, &tokens, 2).unwrap();
+        let values: Vec<Vec<u64>> = client.resolve_batch(&tokens, responses).unwrap();
+        for (i, value) in values.iter().enumerate() {
+            assert_eq!(value[0], i as u64);
+            assert_eq!(value[1], i as u64 + 1);
+        }
+        let responses = server
+            .answer_batch(&StdThreads::new(2), &tokens[..3], 2)
+            .unwrap();
+        assert!(client.resolve_batch::<u64, 68>(&tokens, responses).is_err());
+
+        // A client with another PRF derives other tokens
+        let other =
+            VhEmmClient::<_>::with_prf(ClientState::default(), *server.fingerprint(), Blake2bPrf);
+        let token = other.gen_token(&1u64);
+        assert!(matches!(
+            other.resolve_len(&token, server.answer_len(&token).unwrap()),
+            Err(Error::NotFound)
+        ));
+
+        // Nor does the server answer tokens meant for another OKVS
+        let stale = VhEmmClient::new(ClientState::default(), RbOkvs::new(301).fingerprint());
+        let token = stale.gen_token(&1u64);
+        assert!(matches!(
+            server.answer_len(&token),
+            Err(Error::ParamsMismatch("OKVS"))
+        ));
+        assert!(server.answer_batch(&Sequential, &[token], 2).is_err());
+
+        // A response for another key doesn't resolve
+        let token = client.gen_token(&1u64);
+        let response = server.answer(&client.gen_token(&2u64), 2).unwrap();
+        assert!(client.resolve::<u64, 68>(&token, response).is_err());
+
+        // Nor one with the entries of a key reordered
+        let mut swapped = server.answer(&token, 2).unwrap();
+        swapped.values.swap(0, 1);
+        assert!(matches!(
+            client.resolve::<u64, 68>(&token, swapped),
+            Err(Error::Auth(0))
+        ));
+    }
+
+    #[test]
+    fn test_from_master_secret() {
+        let state = ClientState::from_master_secret(b"master secret");
+        assert_ne!(state.kf, state.ke);
+        assert_ne!(state.kf, [0u8; 32]);
+
+        let again = ClientState::from_master_secret(b"master secret");
+        assert_eq!((state.kf, state.ke), (again.kf, again.ke));
+
+        let other = ClientState::from_master_secret(b"other secret");
+        assert_ne!(state.kf, other.kf);
+    }
+
+    #[test]
+    fn test_client_state_bytes() {
+        let state = ClientState::new_random();
+        let bytes = state.to_bytes();
+        let restored = ClientState::from_bytes(&bytes).unwrap();
+        assert_eq!((state.kf, state.ke), (restored.kf, restored.ke));
+        assert!(ClientState::from_bytes(&bytes[1..]).is_err());
+
+        let sealed = state.seal(b"correct horse");
+        let restored = ClientState::unseal(&sealed, b"correct horse").unwrap();
+        assert_eq!((state.kf, state.ke), (restored.kf, restored.ke));
+
+        assert!(matches!(
+            ClientState::unseal(&sealed, b"wrong horse"),
+            Err(Error::Auth(_))
+        ));
+        assert!(ClientState::unseal(&sealed[..10], b"correct horse").is_err());
+    }
+
+    #[cfg(feature = "zeroize")]
+    #[test]
+    fn test_zeroize() {
+        use std::mem::ManuallyDrop;
+
+        let mut state = ManuallyDrop::new(ClientState::new_random());
+        // The keys are inline, so the wiped bytes can be read back
+        unsafe { ManuallyDrop::drop(&mut state) };
+        assert_eq!((state.kf, state.ke), ([0; 32], [0; 32]));
+    }
+
+    #[test]
+    fn test_message_bytes() {
+        let token = QueryToken {
+            fingerprint: [3; 8],
+            h: vec![7; H_LEN],
+        };
+        let bytes = token.to_bytes();
+        assert_eq!(bytes.len(), 9 + H_LEN);
+        assert_eq!(QueryToken::from_bytes(&bytes).unwrap(), token);
+        assert!(QueryToken::from_bytes(&bytes[..10]).is_err());
+        assert!(QueryToken::from_bytes(&[]).is_err());
+
+        let mut bytes = bytes;
+        bytes[0] = MESSAGE_VERSION + 1;
+        assert!(matches!(
+            QueryToken::from_bytes(&bytes),
+            Err(Error::Message(_))
+        ));
+
+        let response = QueryResponse {
+            values: vec![OkvsValue([1u8; 4]), OkvsValue([2u8; 4])],
+        };
+        let bytes = response.to_bytes().unwrap();
+        assert_eq!(bytes.len(), 9 + 8);
+        assert_eq!(QueryResponse::<4>::from_bytes(&bytes).unwrap(), response);
+        assert!(QueryResponse::<5>::from_bytes(&bytes).is_err());
+        assert!(QueryResponse::<4>::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_message_serde() {
+        let token = QueryToken {
+            fingerprint: [3; 8],
+            h: vec![7; H_LEN],
+        };
+        let json = serde_json::to_string(&token).unwrap();
+        assert_eq!(serde_json::from_str::<QueryToken>(&json).unwrap(), token);
+
+        let response = QueryResponse {
+            values: vec![OkvsValue([1u8; 4])],
+        };
+        let json = serde_json::to_string(&response).unwrap();
+        assert_eq!(
+            serde_json::from_str::<QueryResponse<4>>(&json).unwrap(),
+            response
+        );
+    }
+
+    #[test]
+    fn test_rb_mm_vu32() {
+        let mut pairs: Vec<EmmPair<Vec<u8>, Vec<u32>>> = vec![];
         for i in 0..200 {
-            pairs.push((EmmKey(i as u64), vec![EmmValue(i as u64)]));
+            pairs.push((vec![i as u8; 8], vec![i as u32]));
         }
-        let rb_okvs = RbOkvs::new(pairs.len());
+        let rb_okvs = RbOkvs::new(pairs.len() * 2);
 
-        // 83 = 80 + EmmValue.len()
-        let rb_mm = VhEmm::<RbOkvs, 8, 88>::new(rb_okvs);
-        let (emm, client_state) = rb_mm.setup(pairs).unwrap();
+        // 64 = 60 + <u32 as EmmV>::len()
+        let rb_mm = VhEmm::<RbOkvs, 8, 64>::new(rb_okvs);
+        let client_state = ClientState::default();
+        let emm = rb_mm.setup(pairs, &client_state).unwrap();
 
         for i in 0..200 {
-            let value: Vec<EmmValue> = rb_mm
-                .query(EmmKey(i as u64), 1, &client_state, &emm)
-                .unwrap();
-            assert_eq!(value[0].0, i as u64);
+            let value: Vec<u32> = rb_mm.query(vec![i as u8; 8], &client_state, &emm).unwrap();
+            assert_eq!(value[0], i as u32);
+        }
+
+        let keys: Vec<Vec<u8>> = (0..200).map(|i| vec![i as u8; 8]).collect();
+        let values: Vec<Vec<u32>> = rb_mm.query_batch(&keys, &client_state, emm).unwrap();
+        for (i, value) in values.iter().enumerate() {
+            assert_eq!(value[0], i as u32);
         }
     }
 
     #[test]
-    fn test_rb_mm_vu32() {
-        struct EmmKey(pub [u8; 8]);
+    fn test_rb_mm_prf() {
+        let mut pairs: Vec<EmmPair<u32, Vec<u32>>> = vec![];
+        for i in 0..100 {
+            pairs.push((i, vec![i]));
+        }
+        let rb_okvs = RbOkvs::new(pairs.len() * 2);
 
-        impl EmmK for EmmKey {
-            fn to_bytes(&self) -> Vec<u8> {
-                self.0.into()
-            }
+        // 64 = 60 + <u32 as EmmV>::len()
+        let rb_mm = VhEmm::<RbOkvs, 8, 64, _>::with_prf(rb_okvs.clone(), AesCmacPrf);
+        let client_state = ClientState::new_random();
+        let emm = rb_mm.setup(pairs, &client_state).unwrap();
+
+        for i in 0..100 {
+            let value: Vec<u32> = rb_mm.query(i, &client_state, &emm).unwrap();
+            assert_eq!(value[0], i);
         }
 
-        struct EmmValue(pub u32);
+        let default = VhEmm::<RbOkvs, 8, 64>::new(rb_okvs);
+        assert!(default
+            .query::<u32, u32>(1u32, &client_state, &emm)
+            .is_err());
+    }
 
-        impl EmmV for EmmValue {
-            fn len() -> usize {
-                4
-            }
+    #[test]
+    fn test_rb_mm_cipher() {
+        let mut pairs: Vec<EmmPair<u32, Vec<u32>>> = vec![];
+        for i in 0..100 {
+            pairs.push((i, vec![i, i + 1]));
+        }
+        let rb_okvs = RbOkvs::new(pairs.len() * 3);
 
-            fn encode(&self) -> Vec<u8> {
-                self.0.to_le_bytes().into()
-            }
+        // 76 = 40 + 32 + <u32 as EmmV>::len()
+        let rb_mm = VhEmm::<RbOkvs, 8, 76, HmacSha256, XChaChaCipher>::with_prf(
+            rb_okvs.clone(),
+            HmacSha256,
+        );
+        let client_state = ClientState::new_random();
+        let emm = rb_mm.setup(pairs, &client_state).unwrap();
 
-            fn decode(b: &[u8]) -> Self {
-                let mut v = [0u8; 4];
-                v.copy_from_slice(b);
-                Self(u32::from_le_bytes(v))
-            }
+        let client = VhEmmClient::<HmacSha256, XChaChaCipher>::with_prf(
+            client_state.clone(),
+            rb_okvs.fingerprint(),
+            HmacSha256,
+        );
+        let server = VhEmmServer::<RbOkvs, 8, 76>::new(rb_okvs, emm);
+        for i in 0..100 {
+            let token = client.gen_token(&i);
+            let v_len = client
+                .resolve_len(&token, server.answer_len(&token).unwrap())
+                .unwrap();
+            let values: Vec<u32> = client
+                .resolve(&token, server.answer(&token, v_len).unwrap())
+                .unwrap();
+            assert_eq!(values.len(), 2);
+            assert_eq!(values[0], i);
         }
 
-        let mut pairs: Vec<EmmPair<EmmKey, Vec<EmmValue>>> = vec![];
-        for i in 0..200 {
-            pairs.push((EmmKey([i as u8; 8]), vec![EmmValue(i as u32)]));
+        // Entries don't authenticate under another cipher
+        let aes = VhEmmClient::new(client_state, *server.fingerprint());
+        let token = aes.gen_token(&1u32);
+        assert!(matches!(
+            aes.resolve_len(&token, server.answer_len(&token).unwrap()),
+            Err(Error::NotFound)
+        ));
+
+        // AES-GCM entries take 64 bytes
+        let aes_mm = VhEmm::<RbOkvs, 8, 76>::new(RbOkvs::new(30));
+        assert_eq!(aes_mm.value_size::<u32>(), 64);
+        assert!(matches!(
+            aes_mm.setup(vec![(0u32, vec![0u32])], &ClientState::new_random()),
+            Err(Error::ValueSizeMismatch {
+                expected: 76,
+                actual: 64,
+            })
+        ));
+    }
+
+    #[test]
+    fn test_rb_mm_hex_tokens() {
+        assert_eq!(TokenFormat::Hex.token_len(), 64);
+
+        let mut pairs: Vec<EmmPair<u32, Vec<u32>>> = vec![];
+        for i in 0..100 {
+            pairs.push((i, vec![i]));
         }
-        let rb_okvs = RbOkvs::new(pairs.len());
+        let rb_okvs = RbOkvs::new(pairs.len() * 2);
 
-        // 84 = 80 + EmmValue.len()
-        let rb_mm = VhEmm::<RbOkvs, 8, 84>::new(rb_okvs);
-        let (emm, client_state) = rb_mm.setup(pairs).unwrap();
+        // 96 = 92 + <u32 as EmmV>::len(), a hex token takes 64 bytes
+        let rb_mm = VhEmm::<RbOkvs, 8, 96>::new(rb_okvs.clone()).token_format(TokenFormat::Hex);
+        let client_state = ClientState::new_random();
+        let emm = rb_mm.setup(pairs, &client_state).unwrap();
 
-        for i in 0..200 {
-            let value: Vec<EmmValue> = rb_mm
-                .query(EmmKey([i as u8; 8]), 1, &client_state, &emm)
+        let client =
+            VhEmmClient::new(client_state, rb_okvs.fingerprint()).token_format(TokenFormat::Hex);
+        let server = VhEmmServer::<RbOkvs, 8, 96>::new(rb_okvs, emm);
+        for i in 0..100 {
+            let token = client.gen_token(&i);
+            assert_eq!(token.h.len(), 64);
+            let token = QueryToken::from_bytes(&token.to_bytes()).unwrap();
+            let v_len = client
+                .resolve_len(&token, server.answer_len(&token).unwrap())
                 .unwrap();
-            assert_eq!(value[0].0, i as u32);
+            let value: Vec<u32> = client
+                .resolve(&token, server.answer(&token, v_len).unwrap())
+                .unwrap();
+            assert_eq!(value[0], i);
         }
     }
 
     #[test]
-    fn test_rb_mm_vstring() {
-        struct EmmKey(pub u32);
+    fn test_rb_mm_composite_keys() {
+        let mut pairs: Vec<EmmPair<(String, u32), Vec<u32>>> = vec![];
+        for i in 0..100 {
+            pairs.push(((format!("user-{i}"), i % 3), vec![i]));
+        }
+        let rb_okvs = RbOkvs::new(pairs.len() * 2);
 
-        impl EmmK for EmmKey {
-            fn to_bytes(&self) -> Vec<u8> {
-                self.0.to_le_bytes().into()
-            }
+        // 64 = 60 + <u32 as EmmV>::len()
+        let rb_mm = VhEmm::<RbOkvs, 8, 64>::new(rb_okvs);
+        let client_state = ClientState::new_random();
+        let emm = rb_mm.setup(pairs, &client_state).unwrap();
+
+        for i in 0..100 {
+            let key = (format!("user-{i}"), i % 3);
+            let value: Vec<u32> = rb_mm.query(key, &client_state, &emm).unwrap();
+            assert_eq!(value[0], i);
         }
+        let other = (String::from("user-1"), 2);
+        assert!(matches!(
+            rb_mm.query::<_, u32>(other, &client_state, &emm),
+            Err(Error::NotFound)
+        ));
+    }
 
+    #[test]
+    fn test_rb_mm_vstring() {
         struct EmmValue(pub String);
 
         impl EmmV for EmmValue {
@@ -279,21 +1411,93 @@ mod test {
             }
         }
 
-        let mut pairs: Vec<EmmPair<EmmKey, Vec<EmmValue>>> = vec![];
+        let mut pairs: Vec<EmmPair<u32, Vec<EmmValue>>> = vec![];
         for i in 0..200 {
-            pairs.push((EmmKey(i as u32), vec![EmmValue(format!("{:03}", i))]));
+            pairs.push((i as u32, vec![EmmValue(format!("{:03}", i))]));
         }
-        let rb_okvs = RbOkvs::new(pairs.len());
+        let rb_okvs = RbOkvs::new(pairs.len() * 2);
 
-        // 83 = 80 + EmmValue.len()
-        let rb_mm = VhEmm::<RbOkvs, 8, 83>::new(rb_okvs);
-        let (emm, client_state) = rb_mm.setup(pairs).unwrap();
+        let rb_mm = VhEmm::<RbOkvs, 8, { okvs_v_size::<AesGcmCipher>(3) }>::new(rb_okvs);
+        let client_state = ClientState::new_random();
+        let emm = rb_mm.setup(pairs, &client_state).unwrap();
 
         for i in 0..200 {
-            let value: Vec<EmmValue> = rb_mm
-                .query(EmmKey(i as u32), 1, &client_state, &emm)
-                .unwrap();
+            let value: Vec<EmmValue> = rb_mm.query(i as u32, &client_state, &emm).unwrap();
             assert_eq!(value[0].0, format!("{:03}", i));
         }
     }
+
+    #[test]
+    fn test_rb_mm_empty_values() {
+        let mut pairs: Vec<EmmPair<u64, Vec<u64>>> = vec![];
+        for i in 0..200 {
+            let values = if i % 2 == 0 { vec![] } else { vec![i as u64] };
+            pairs.push((i as u64, values));
+        }
+        let rb_okvs = RbOkvs::new(pairs.len() * 2);
+
+        let rb_mm = VhEmm::<RbOkvs, 8, 68>::new(rb_okvs);
+        let client_state = ClientState::new_random();
+        let emm = rb_mm.setup(pairs, &client_state).unwrap();
+
+        for i in 0..200 {
+            let value: Vec<u64> = rb_mm.query(i as u64, &client_state, &emm).unwrap();
+            if i % 2 == 0 {
+                assert!(value.is_empty());
+            } else {
+                assert_eq!(value[0], i as u64);
+            }
+        }
+
+        let unknown = rb_mm.query::<u64, u64>(1000u64, &client_state, &emm);
+        assert!(matches!(unknown, Err(Error::NotFound)));
+    }
+
+    #[test]
+    fn test_rb_mm_padding() {
+        assert_eq!(Padding::PowerOfTwo.padded_len(0).unwrap(), 1);
+        assert_eq!(Padding::PowerOfTwo.padded_len(5).unwrap(), 8);
+        assert_eq!(Padding::Fixed(4).padded_len(4).unwrap(), 4);
+        assert!(Padding::Fixed(4).padded_len(5).is_err());
+
+        assert_eq!(Padding::Fixed(4).rows(2).unwrap(), 5);
+
+        let mut pairs: Vec<EmmPair<u64, Vec<u64>>> = vec![];
+        for i in 0..100 {
+            let values = (0..i % 4).collect();
+            pairs.push((i, values));
+        }
+        let rb_okvs = RbOkvs::new(pairs.len() * 5);
+
+        let rb_mm = VhEmm::<RbOkvs, 8, 68>::new(rb_okvs).padding(Padding::Fixed(4));
+        let client_state = ClientState::new_random();
+        let emm = rb_mm.setup(pairs, &client_state).unwrap();
+
+        // Every key answers the same number of entries
+        for i in 0..100 {
+            let value: Vec<u64> = rb_mm.query(i, &client_state, &emm).unwrap();
+            assert_eq!(value, (0..i % 4).collect::<Vec<_>>());
+        }
+
+        let pairs = vec![(0u64, (0..5).collect())];
+        assert!(rb_mm.setup(pairs, &client_state).is_err());
+
+        // Rotation keeps the padding
+        let new_state = ClientState::new_random();
+        let keys = (0..100).collect();
+        let new_emm = rb_mm
+            .rotate_keys::<u64, u64>(&client_state, &new_state, &emm, keys)
+            .unwrap();
+        for i in 0..100 {
+            let value: Vec<u64> = rb_mm.query(i, &new_state, &new_emm).unwrap();
+            assert_eq!(value, (0..i % 4).collect::<Vec<_>>());
+        }
+        let old = rb_mm.query::<u64, u64>(1u64, &client_state, &new_emm);
+        assert!(matches!(old, Err(Error::NotFound)));
+
+        let keys = vec![1000u64];
+        assert!(rb_mm
+            .rotate_keys::<u64, u64>(&client_state, &new_state, &emm, keys)
+            .is_err());
+    }
 }
@@ -0,0 +1,298 @@
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+use blake2::digest::{Update, VariableOutput};
+use blake2::Blake2bVar;
+
+use crate::band::Band;
+use crate::error::{Error, Result};
+use crate::hasher::KeyHasher;
+use crate::okvs::{Fingerprint, RbOkvs, FORMAT_VERSION};
+use crate::solver::{back_substitute, eliminate_rows};
+use crate::types::{Encoding, OkvsK, OkvsV, OkvsValue, Pair};
+use crate::utils::to_usize;
+
+const CHECKPOINT_DOMAIN: &[u8] = b"rb-okvs/checkpoint";
+
+/// An encode whose forward elimination runs a batch of rows at a time, so
+/// its progress can be saved with [`Encoder::checkpoint`] and picked up
+/// after a crash with [`Encoder::resume`].
+///
+/// Rows are hashed and sorted once, up front. A checkpoint holds the rows
+/// as eliminated so far, so resuming needs neither the input nor the
+/// instance.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Encoder<const N: usize, const W: usize = 4> {
+    fingerprint: Fingerprint,
+    columns:     usize,
+    bands:       Vec<Band<W>>,
+    start_pos:   Vec<usize>,
+    y:           Vec<OkvsValue<N>>,
+    next:        usize,
+}
+
+impl<const N: usize, const W: usize> Encoder<N, W> {
+    pub fn new<K: OkvsK, H: KeyHasher>(
+        okvs: &RbOkvs<W, H>,
+        input: &[Pair<K, OkvsValue<N>>],
+    ) -> Result<Self> {
+        let (bands, start_pos, y) = okvs.create_sorted_matrix(input)?;
+        Ok(Self {
+            fingerprint: okvs.fingerprint(),
+            columns: okvs.columns(),
+            bands,
+            start_pos,
+            y,
+            next: 0,
+        })
+    }
+
+    /// Fingerprint of the instance the encode was started with.
+    pub fn fingerprint(&self) -> &Fingerprint {
+        &self.fingerprint
+    }
+
+    pub fn rows(&self) -> usize {
+        self.bands.len()
+    }
+
+    /// Rows eliminated so far.
+    pub fn eliminated(&self) -> usize {
+        self.next
+    }
+
+    /// Eliminates up to `rows` more rows. True once every row is.
+    pub fn step(&mut self, rows: usize) -> Result<bool> {
+        let end = self.next.saturating_add(rows).min(self.rows());
+        eliminate_rows(
+            &mut self.y,
+            &mut self.bands,
+            &self.start_pos,
+            self.next..end,
+        )?;
+        self.next = end;
+        Ok(self.next == self.rows())
+    }
+
+    /// Eliminates the remaining rows and back-substitutes.
+    pub fn finish(mut self) -> Result<Encoding<OkvsValue<N>>> {
+        self.step(usize::MAX)?;
+        let mut x = vec![OkvsValue::default(); self.columns];
+        back_substitute(&self.bands, &self.start_pos, &self.y, &mut x);
+        Ok(x)
+    }
+
+    /// Saves the encode to `path`. The state is written next to it first
+    /// and renamed over it, so a crash mid-write leaves the previous
+    /// checkpoint intact.
+    ///
+    /// `version || fingerprint || columns (u64 LE) || W (u32 LE) || N (u32
+    /// LE) || rows (u64 LE) || eliminated (u64 LE) || rows of start (u64
+    /// LE), band words (u64 LE) and value || checksum`
+    pub fn checkpoint(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+
+        let mut w = Checksummed::new(BufWriter::new(File::create(&tmp)?));
+        w.write_all(&[FORMAT_VERSION])?;
+        w.write_all(&self.fingerprint)?;
+        w.write_all(&(self.columns as u64).to_le_bytes())?;
+        w.write_all(&(W as u32).to_le_bytes())?;
+        w.write_all(&(N as u32).to_le_bytes())?;
+        w.write_all(&(self.rows() as u64).to_le_bytes())?;
+        w.write_all(&(self.next as u64).to_le_bytes())?;
+        for ((band, start), y) in self.bands.iter().zip(&self.start_pos).zip(&self.y) {
+            w.write_all(&(*start as u64).to_le_bytes())?;
+            for word in &band.0 {
+                w.write_all(&word.to_le_bytes())?;
+            }
+            w.write_all(&y.0)?;
+        }
+        let (mut file, checksum) = w.finish();
+        file.write_all(&checksum)?;
+        file.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+        fs::rename(&tmp, path)?;
+        Ok(())
+    }
+
+    /// Loads an encode saved by [`Encoder::checkpoint`]. Fails with
+    /// [`Error::ChecksumMismatch`] if the file was corrupted.
+    ///
+    /// The header is only checksummed once every row is read, so the row
+    /// count is checked against the file length before anything is
+    /// allocated for it.
+    pub fn resume(path: impl AsRef<Path>) -> Result<Self> {
+        let file = File::open(path)?;
+        let len = file.metadata()?.len();
+        let mut r = Checksummed::new(BufReader::new(file));
+        let mut version = [0u8];
+        r.read_exact(&mut version)?;
+        if version[0] != FORMAT_VERSION {
+            return Err(Error::Message(format!(
+                "unsupported checkpoint version {}",
+                version[0]
+            )));
+        }
+        let mut fingerprint = Fingerprint::default();
+        r.read_exact(&mut fingerprint)?;
+        let columns = to_usize(read_u64(&mut r)?)?;
+        let (words, size) = (read_u32(&mut r)?, read_u32(&mut r)?);
+        if (words, size) != (W as u32, N as u32) {
+            return Err(Error::Message(format!(
+                "checkpoint of {words}-word bands and {size}-byte values"
+            )));
+        }
+        let rows = read_u64(&mut r)?;
+        let row_size = (8 * (1 + W) + N) as u64;
+        if rows > len / row_size {
+            return Err(Error::Message(format!(
+                "checkpoint of {rows} rows in {len} bytes"
+            )));
+        }
+        let rows = to_usize(rows)?;
+        let next = to_usize(read_u64(&mut r)?)?;
+        if next > rows {
+            return Err(Error::Message(format!("{next} of {rows} rows eliminated")));
+        }
+
+        let mut encoder = Self {
+            fingerprint,
+            columns,
+            bands: Vec::with_capacity(rows),
+            start_pos: Vec::with_capacity(rows),
+            y: Vec::with_capacity(rows),
+            next,
+        };
+        for _ in 0..rows {
+            let start = to_usize(read_u64(&mut r)?)?;
+            let mut band = Band::default();
+            for word in &mut band.0 {
+                *word = read_u64(&mut r)?;
+            }
+            if start.saturating_add(band.bits()) > columns {
+                return Err(Error::BandOutOfRange {
+                    row: encoder.bands.len(),
+                    columns,
+                });
+            }
+            let mut y = OkvsValue::default();
+            r.read_exact(&mut y.0)?;
+            encoder.start_pos.push(start);
+            encoder.bands.push(band);
+            encoder.y.push(y);
+        }
+
+        let (mut file, checksum) = r.finish();
+        let mut expected = [0u8; 8];
+        file.read_exact(&mut expected)?;
+        if checksum != expected {
+            return Err(Error::ChecksumMismatch);
+        }
+        Ok(encoder)
+    }
+}
+
+fn read_u64(r: &mut impl Read) -> Result<u64> {
+    let mut bytes = [0u8; 8];
+    r.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+fn read_u32(r: &mut impl Read) -> Result<u32> {
+    let mut bytes = [0u8; 4];
+    r.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+/// A reader or writer that hashes every byte passing through it.
+struct Checksummed<T> {
+    inner:  T,
+    hasher: Blake2bVar,
+}
+
+impl<T> Checksummed<T> {
+    fn new(inner: T) -> Self {
+        let mut hasher = Blake2bVar::new(8).unwrap();
+        hasher.update(CHECKPOINT_DOMAIN);
+        Self { inner, hasher }
+    }
+
+    fn finish(self) -> (T, [u8; 8]) {
+        let mut checksum = [0u8; 8];
+        self.hasher.finalize_variable(&mut checksum).unwrap();
+        (self.inner, checksum)
+    }
+}
+
+impl<T: Write> Write for Checksummed<T> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.hasher.update(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<T: Read> Read for Checksummed<T> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.hasher.update(&buf[..n]);
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::process;
+
+    use super::*;
+    use crate::types::{Okvs, OkvsKey};
+
+    #[test]
+    fn test_checkpoint() {
+        let pairs: Vec<Pair<OkvsKey, OkvsValue<8>>> = (0..2000u64)
+            .map(|i| (OkvsKey(i.to_le_bytes()), OkvsValue((i + 5).to_le_bytes())))
+            .collect();
+        let rb_okvs = RbOkvs::new(pairs.len());
+        let path = env::temp_dir().join(format!("rb-okvs-checkpoint-{}", process::id()));
+
+        let mut encoder = Encoder::new(&rb_okvs, &pairs).unwrap();
+        assert!(!encoder.step(700).unwrap());
+        encoder.checkpoint(&path).unwrap();
+        drop(encoder);
+
+        let mut resumed = Encoder::<8>::resume(&path).unwrap();
+        assert_eq!((resumed.eliminated(), resumed.rows()), (700, 2000));
+        assert_eq!(*resumed.fingerprint(), rb_okvs.fingerprint());
+        assert!(resumed.step(2000).unwrap());
+        assert_eq!(
+            resumed.finish().unwrap(),
+            rb_okvs.encode_ref(&pairs).unwrap()
+        );
+
+        let mut bytes = fs::read(&path).unwrap();
+        bytes[130] ^= 1;
+        fs::write(&path, &bytes).unwrap();
+        assert!(matches!(
+            Encoder::<8>::resume(&path),
+            Err(Error::ChecksumMismatch)
+        ));
+        assert!(Encoder::<16>::resume(&path).is_err());
+
+        // A huge row count fails before allocating for it
+        bytes[130] ^= 1;
+        bytes[25..33].copy_from_slice(&(u64::MAX >> 4).to_le_bytes());
+        fs::write(&path, &bytes).unwrap();
+        assert!(matches!(
+            Encoder::<8>::resume(&path),
+            Err(Error::Message(_))
+        ));
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -7,6 +7,79 @@ pub enum Error {
     #[error("Row {0} is 0")]
     ZeroRow(usize),
 
+    /// A [`ZeroRow`](Error::ZeroRow) of an OKVS encode, traced back to the
+    /// input. `eliminated` is the row's position in start order, i.e. the
+    /// rows eliminated before it.
+    #[error(
+        "Row of pair {index} is 0 after {eliminated} rows eliminated \
+         (start {start}, key hash {key_hash:016x})"
+    )]
+    Unsolvable {
+        index: usize,
+        start: usize,
+        key_hash: u64,
+        eliminated: usize,
+    },
+
     #[error("Decode error: {0}")]
     Decode(usize),
+
+    #[error("Authentication failed: {0}")]
+    Auth(usize),
+
+    #[error("Key not found")]
+    NotFound,
+
+    #[error("Invalid configuration: {0}")]
+    Config(String),
+
+    #[error("Malformed message: {0}")]
+    Message(String),
+
+    #[error("Encoding sink closed")]
+    SinkClosed,
+
+    #[error("Value of {actual} bytes for OKVS values of {expected} bytes")]
+    ValueSizeMismatch { expected: usize, actual: usize },
+
+    #[error("Encoding of {actual} values, {required} required")]
+    EncodingTooShort { required: usize, actual: usize },
+
+    /// Two pairs of an OKVS encode with the same start and band, e.g. equal
+    /// keys, which no encoding solves unless their values are equal too.
+    #[error("Pairs {0} and {1} hash to the same row")]
+    CollidingKeys(usize, usize),
+
+    /// The rows a [`SparseOkvs`](crate::sparse::SparseOkvs) encode could not
+    /// peel are linearly dependent, at pair `index` of the input.
+    #[error("Core of {rows} unpeeled rows is singular at pair {index}")]
+    SingularCore { index: usize, rows: usize },
+
+    #[error("Verification failed at pair {0}")]
+    VerificationFailed(usize),
+
+    #[error("Band of row {row} exceeds {columns} columns")]
+    BandOutOfRange { row: usize, columns: usize },
+
+    #[error("Shard {shard} got {rows} rows, capacity {capacity}")]
+    ShardOverflow {
+        shard: usize,
+        rows: usize,
+        capacity: usize,
+    },
+
+    #[error("Invalid proof for column {0}")]
+    InvalidProof(usize),
+
+    #[error("Encoding made with a different {0}")]
+    ParamsMismatch(&'static str),
+
+    #[error("Encoding checksum mismatch")]
+    ChecksumMismatch,
+
+    #[error("GPU error: {0}")]
+    Gpu(String),
+
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
 }
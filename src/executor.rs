@@ -0,0 +1,254 @@
+use std::num::NonZeroUsize;
+#[cfg(feature = "rayon")]
+use std::sync::Arc;
+use std::thread;
+
+#[cfg(feature = "rayon")]
+use crate::error::{Error, Result};
+
+/// Execution backend for the parallel APIs.
+///
+/// Every parallel operation in this crate goes through an `Executor`, so the
+/// same code path serves multi-threaded builds and targets without threads
+/// (use [`Sequential`] there).
+pub trait Executor: Sync {
+    /// Applies `f` to every item and returns the results in input order.
+    fn map<T, R, F>(&self, items: &[T], f: F) -> Vec<R>
+    where
+        T: Sync,
+        R: Send,
+        F: Fn(&T) -> R + Sync;
+
+    /// Runs `a` and `b`, potentially in parallel, and returns both results.
+    fn join<A, B, RA, RB>(&self, a: A, b: B) -> (RA, RB)
+    where
+        A: FnOnce() -> RA + Send,
+        B: FnOnce() -> RB + Send,
+        RA: Send,
+        RB: Send;
+}
+
+/// Runs everything on the calling thread.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Sequential;
+
+impl Executor for Sequential {
+    fn map<T, R, F>(&self, items: &[T], f: F) -> Vec<R>
+    where
+        T: Sync,
+        R: Send,
+        F: Fn(&T) -> R + Sync,
+    {
+        items.iter().map(f).collect()
+    }
+
+    fn join<A, B, RA, RB>(&self, a: A, b: B) -> (RA, RB)
+    where
+        A: FnOnce() -> RA + Send,
+        B: FnOnce() -> RB + Send,
+        RA: Send,
+        RB: Send,
+    {
+        (a(), b())
+    }
+}
+
+/// Splits work across scoped `std::thread`s.
+#[derive(Clone, Copy, Debug)]
+pub struct StdThreads {
+    threads: usize,
+}
+
+impl StdThreads {
+    pub fn new(threads: usize) -> Self {
+        Self {
+            threads: threads.max(1),
+        }
+    }
+
+    pub fn threads(&self) -> usize {
+        self.threads
+    }
+}
+
+impl Default for StdThreads {
+    /// One thread per available core.
+    fn default() -> Self {
+        Self::new(
+            thread::available_parallelism()
+                .map(NonZeroUsize::get)
+                .unwrap_or(1),
+        )
+    }
+}
+
+impl Executor for StdThreads {
+    fn map<T, R, F>(&self, items: &[T], f: F) -> Vec<R>
+    where
+        T: Sync,
+        R: Send,
+        F: Fn(&T) -> R + Sync,
+    {
+        if self.threads == 1 || items.len() < 2 {
+            return Sequential.map(items, f);
+        }
+
+        let chunk_size = items.len().div_ceil(self.threads);
+        let f = &f;
+        thread::scope(|s| {
+            let handles: Vec<_> = items
+                .chunks(chunk_size)
+                .map(|chunk| s.spawn(move || chunk.iter().map(f).collect::<Vec<R>>()))
+                .collect();
+
+            let mut result = Vec::with_capacity(items.len());
+            for handle in handles {
+                result.extend(handle.join().unwrap());
+            }
+            result
+        })
+    }
+
+    fn join<A, B, RA, RB>(&self, a: A, b: B) -> (RA, RB)
+    where
+        A: FnOnce() -> RA + Send,
+        B: FnOnce() -> RB + Send,
+        RA: Send,
+        RB: Send,
+    {
+        if self.threads == 1 {
+            return Sequential.join(a, b);
+        }
+
+        thread::scope(|s| {
+            let handle = s.spawn(b);
+            let ra = a();
+            (ra, handle.join().unwrap())
+        })
+    }
+}
+
+/// Delegates to rayon's global thread pool.
+#[cfg(feature = "rayon")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Rayon;
+
+#[cfg(feature = "rayon")]
+impl Executor for Rayon {
+    fn map<T, R, F>(&self, items: &[T], f: F) -> Vec<R>
+    where
+        T: Sync,
+        R: Send,
+        F: Fn(&T) -> R + Sync,
+    {
+        use rayon::prelude::*;
+
+        let f = &f;
+        items.par_iter().map(f).collect()
+    }
+
+    fn join<A, B, RA, RB>(&self, a: A, b: B) -> (RA, RB)
+    where
+        A: FnOnce() -> RA + Send,
+        B: FnOnce() -> RB + Send,
+        RA: Send,
+        RB: Send,
+    {
+        rayon::join(a, b)
+    }
+}
+
+/// Runs on a caller's rayon pool, so OKVS work shares the threads of the
+/// rest of the application instead of the global pool.
+#[cfg(feature = "rayon")]
+#[derive(Clone, Debug)]
+pub struct RayonPool {
+    pool: Arc<rayon::ThreadPool>,
+}
+
+#[cfg(feature = "rayon")]
+impl RayonPool {
+    pub fn new(pool: Arc<rayon::ThreadPool>) -> Self {
+        Self { pool }
+    }
+
+    /// A dedicated pool of `threads` workers.
+    pub fn with_threads(threads: usize) -> Result<Self> {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(threads.max(1))
+            .build()
+            .map_err(|e| Error::Config(e.to_string()))?;
+        Ok(Self::new(Arc::new(pool)))
+    }
+
+    pub fn threads(&self) -> usize {
+        self.pool.current_num_threads()
+    }
+}
+
+#[cfg(feature = "rayon")]
+impl Executor for RayonPool {
+    fn map<T, R, F>(&self, items: &[T], f: F) -> Vec<R>
+    where
+        T: Sync,
+        R: Send,
+        F: Fn(&T) -> R + Sync,
+    {
+        use rayon::prelude::*;
+
+        let f = &f;
+        self.pool.install(|| items.par_iter().map(f).collect())
+    }
+
+    fn join<A, B, RA, RB>(&self, a: A, b: B) -> (RA, RB)
+    where
+        A: FnOnce() -> RA + Send,
+        B: FnOnce() -> RB + Send,
+        RA: Send,
+        RB: Send,
+    {
+        self.pool.join(a, b)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check_map<E: Executor>(executor: &E) {
+        let items: Vec<usize> = (0..1000).collect();
+        let result = executor.map(&items, |i| i * 2);
+        assert_eq!(result, items.iter().map(|i| i * 2).collect::<Vec<_>>());
+        assert!(executor.map(&[] as &[usize], |i| *i).is_empty());
+    }
+
+    #[test]
+    fn test_sequential() {
+        check_map(&Sequential);
+        assert_eq!(Sequential.join(|| 1, || 2), (1, 2));
+    }
+
+    #[test]
+    fn test_std_threads() {
+        check_map(&StdThreads::new(1));
+        check_map(&StdThreads::new(3));
+        check_map(&StdThreads::default());
+        assert_eq!(StdThreads::new(0).threads(), 1);
+        assert_eq!(StdThreads::new(4).join(|| 1, || 2), (1, 2));
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn test_rayon() {
+        check_map(&Rayon);
+        assert_eq!(Rayon.join(|| 1, || 2), (1, 2));
+
+        let pool = RayonPool::with_threads(2).unwrap();
+        assert_eq!(pool.threads(), 2);
+        check_map(&pool);
+        assert_eq!(pool.join(|| 1, || 2), (1, 2));
+        // Work runs on the pool's threads
+        let threads = pool.map(&[0; 8], |_| rayon::current_num_threads());
+        assert!(threads.iter().all(|t| *t == 2));
+    }
+}
@@ -0,0 +1,373 @@
+//! Encoding of inputs larger than memory. Rows are hashed into sorted runs
+//! on disk, merged by start position and eliminated through a window of one
+//! band width, so memory holds a run and about a band's worth of rows and
+//! columns at a time.
+
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, VecDeque};
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::band::Band;
+use crate::error::{Error, Result};
+use crate::hasher::{Blake2Hasher, KeyHasher};
+use crate::okvs::RbOkvs;
+use crate::solver::solve_row;
+use crate::types::{Encoding, EncodingSink, OkvsK, OkvsV, OkvsValue, Pair};
+
+/// Rows read at once when back-substituting, last to first.
+const READ_BLOCK: usize = 4096;
+
+static SPILLS: AtomicUsize = AtomicUsize::new(0);
+
+/// Encodes with an [`RbOkvs`] whose rows don't fit in memory, producing the
+/// same encoding as [`Okvs::encode_ref`](crate::types::Okvs::encode_ref)
+/// on the pushed pairs.
+///
+/// Pushed rows are hashed and kept until `run_rows` of them are buffered,
+/// then sorted by start position and spilled to a file in `dir`. Finishing
+/// merges the runs, writes the eliminated rows to another file and reads it
+/// back last to first. Files are removed once the encoder is dropped.
+/// Instances built with `verify` are not verified.
+pub struct ExternalEncoder<const N: usize, const W: usize = 4, H = Blake2Hasher> {
+    okvs:     RbOkvs<W, H>,
+    dir:      PathBuf,
+    run_rows: usize,
+    run:      Vec<Row<N, W>>,
+    runs:     Vec<Spill>,
+    rows:     usize,
+}
+
+impl<const N: usize, const W: usize, H: KeyHasher> ExternalEncoder<N, W, H> {
+    pub fn new(okvs: RbOkvs<W, H>, dir: impl Into<PathBuf>, run_rows: usize) -> Self {
+        Self {
+            okvs,
+            dir: dir.into(),
+            run_rows: run_rows.max(1),
+            run: vec![],
+            runs: vec![],
+            rows: 0,
+        }
+    }
+
+    pub fn okvs(&self) -> &RbOkvs<W, H> {
+        &self.okvs
+    }
+
+    /// Rows pushed so far.
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
+
+    pub fn push<K: OkvsK>(&mut self, key: &K, value: OkvsValue<N>) -> Result<()> {
+        let (start, band) = self.okvs.locate(key);
+        self.run.push(Row {
+            start,
+            band,
+            y: value,
+        });
+        self.rows += 1;
+        if self.run.len() >= self.run_rows {
+            self.spill_run()?;
+        }
+        Ok(())
+    }
+
+    pub fn extend<K, I>(&mut self, pairs: I) -> Result<()>
+    where
+        K: OkvsK,
+        I: IntoIterator<Item = Pair<K, OkvsValue<N>>>,
+    {
+        for (k, v) in pairs {
+            self.push(&k, v)?;
+        }
+        Ok(())
+    }
+
+    /// Hands every column to `sink` once final, in decreasing order like
+    /// [`Eliminated::finalize_streaming`](crate::okvs::Eliminated::finalize_streaming),
+    /// so the encoding needn't fit in memory either.
+    pub fn finish_streaming<S: EncodingSink<OkvsValue<N>>>(mut self, sink: &mut S) -> Result<()> {
+        if !self.run.is_empty() {
+            self.spill_run()?;
+        }
+        let eliminated = self.eliminate()?;
+        self.back_substitute(&eliminated, sink)
+    }
+
+    /// The whole encoding, for callers whose output fits in memory.
+    pub fn finish(self) -> Result<Encoding<OkvsValue<N>>> {
+        let mut encoding = Columns(vec![OkvsValue::default(); self.okvs.columns()]);
+        self.finish_streaming(&mut encoding)?;
+        Ok(encoding.0)
+    }
+
+    fn spill_run(&mut self) -> Result<()> {
+        // Stable, so rows of equal start keep their push order
+        self.run.sort_by_key(|row| row.start);
+        let (spill, mut w) = Spill::create(&self.dir)?;
+        for row in &self.run {
+            row.write(&mut w)?;
+        }
+        w.flush()?;
+        self.run.clear();
+        self.runs.push(spill);
+        Ok(())
+    }
+
+    /// Forward elimination of the merged runs, as in
+    /// [`simple_gauss`](crate::solver::simple_gauss). The pivot of a row is
+    /// within its band, so it only reaches rows starting less than a band
+    /// width after it.
+    fn eliminate(&self) -> Result<Spill> {
+        let band_width = self.okvs.band_width();
+        let mut merged = Merge::new(&self.runs)?;
+        let (spill, mut w) = Spill::create(&self.dir)?;
+        let mut window: VecDeque<Row<N, W>> = VecDeque::new();
+        let mut next = merged.next()?;
+
+        for i in 0..self.rows {
+            if window.is_empty() {
+                window.extend(next.take());
+                next = merged.next()?;
+            }
+            let limit = window[0].start + band_width;
+            while next.as_ref().is_some_and(|row| row.start < limit) {
+                window.extend(next.take());
+                next = merged.next()?;
+            }
+
+            let row = window.pop_front().unwrap();
+            if row.band.is_zero() {
+                return Err(Error::ZeroRow(i));
+            }
+            let pivot = row.start + row.band.trailing_zeros();
+            for other in window.iter_mut() {
+                if other.start > pivot {
+                    break;
+                }
+                if other.band.bit(pivot - other.start) {
+                    other.band.xor_shr(&row.band, other.start - row.start);
+                    other.y.in_place_xor(&row.y);
+                }
+            }
+            row.write(&mut w)?;
+        }
+        w.flush()?;
+        Ok(spill)
+    }
+
+    /// Back substitution over the eliminated rows, last to first. Earlier
+    /// rows start no later, so once a row is solved every column from its
+    /// start plus the band width is final.
+    fn back_substitute<S: EncodingSink<OkvsValue<N>>>(
+        &self,
+        eliminated: &Spill,
+        sink: &mut S,
+    ) -> Result<()> {
+        let band_width = self.okvs.band_width();
+        let mut file = File::open(&eliminated.path)?;
+        let mut block = vec![];
+        // `x[0]` is column `lo`, columns from `lo + x.len()` are sent
+        let mut x: VecDeque<OkvsValue<N>> = VecDeque::new();
+        let mut lo = self.okvs.columns();
+
+        let mut end = self.rows;
+        while end > 0 {
+            let first = end.saturating_sub(READ_BLOCK);
+            block.resize((end - first) * Row::<N, W>::LEN, 0);
+            file.seek(SeekFrom::Start((first * Row::<N, W>::LEN) as u64))?;
+            file.read_exact(&mut block)?;
+            end = first;
+
+            for bytes in block.chunks_exact(Row::<N, W>::LEN).rev() {
+                let row = Row::<N, W>::read(&mut &bytes[..])?.unwrap();
+                send_columns(&mut x, &mut lo, row.start + band_width, sink)?;
+                while lo > row.start {
+                    lo -= 1;
+                    x.push_front(OkvsValue::default());
+                }
+                let x = x.make_contiguous();
+                let pivot = row.band.trailing_zeros();
+                x[pivot] = OkvsValue::default();
+                x[pivot] = solve_row(&row.band, x, &row.y);
+            }
+        }
+        send_columns(&mut x, &mut lo, 0, sink)
+    }
+}
+
+/// Sends columns from `done` up, highest first. Columns below `lo` were
+/// never reached by a row and are free, so zero.
+fn send_columns<V: OkvsV, S: EncodingSink<V>>(
+    x: &mut VecDeque<V>,
+    lo: &mut usize,
+    done: usize,
+    sink: &mut S,
+) -> Result<()> {
+    let mut column = *lo + x.len();
+    while column > done {
+        column -= 1;
+        match x.pop_back() {
+            Some(v) => sink.put(column, &v)?,
+            None => {
+                *lo -= 1;
+                sink.put(column, &V::default())?;
+            }
+        }
+    }
+    Ok(())
+}
+
+struct Row<const N: usize, const W: usize> {
+    start: usize,
+    band:  Band<W>,
+    y:     OkvsValue<N>,
+}
+
+impl<const N: usize, const W: usize> Row<N, W> {
+    /// `start (u64 LE) || band words (u64 LE) || value`
+    const LEN: usize = 8 + 8 * W + N;
+
+    fn write(&self, w: &mut impl Write) -> Result<()> {
+        w.write_all(&(self.start as u64).to_le_bytes())?;
+        for word in &self.band.0 {
+            w.write_all(&word.to_le_bytes())?;
+        }
+        w.write_all(&self.y.0)?;
+        Ok(())
+    }
+
+    /// None at the end of `r`.
+    fn read(r: &mut impl Read) -> Result<Option<Self>> {
+        let mut word = [0u8; 8];
+        match r.read_exact(&mut word) {
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+            result => result?,
+        }
+        let start = u64::from_le_bytes(word) as usize;
+        let mut band = Band::default();
+        for w in &mut band.0 {
+            r.read_exact(&mut word)?;
+            *w = u64::from_le_bytes(word);
+        }
+        let mut y = OkvsValue::default();
+        r.read_exact(&mut y.0)?;
+        Ok(Some(Self { start, band, y }))
+    }
+}
+
+/// The rows of sorted runs in order of start, ties in run order.
+struct Merge<const N: usize, const W: usize> {
+    readers: Vec<BufReader<File>>,
+    heads:   Vec<Option<Row<N, W>>>,
+    heap:    BinaryHeap<Reverse<(usize, usize)>>,
+}
+
+impl<const N: usize, const W: usize> Merge<N, W> {
+    fn new(runs: &[Spill]) -> Result<Self> {
+        let mut merge = Self {
+            readers: vec![],
+            heads: vec![],
+            heap: BinaryHeap::new(),
+        };
+        for (i, run) in runs.iter().enumerate() {
+            let mut reader = BufReader::new(File::open(&run.path)?);
+            let head = Row::read(&mut reader)?;
+            if let Some(row) = &head {
+                merge.heap.push(Reverse((row.start, i)));
+            }
+            merge.readers.push(reader);
+            merge.heads.push(head);
+        }
+        Ok(merge)
+    }
+
+    fn next(&mut self) -> Result<Option<Row<N, W>>> {
+        let Some(Reverse((_, i))) = self.heap.pop() else {
+            return Ok(None);
+        };
+        let row = self.heads[i].take();
+        self.heads[i] = Row::read(&mut self.readers[i])?;
+        if let Some(next) = &self.heads[i] {
+            self.heap.push(Reverse((next.start, i)));
+        }
+        Ok(row)
+    }
+}
+
+/// A temporary file, removed on drop.
+struct Spill {
+    path: PathBuf,
+}
+
+impl Spill {
+    fn create(dir: &Path) -> Result<(Self, BufWriter<File>)> {
+        let path = dir.join(format!(
+            "rb-okvs-{}-{}.spill",
+            process::id(),
+            SPILLS.fetch_add(1, Ordering::Relaxed)
+        ));
+        let file = File::options().write(true).create_new(true).open(&path)?;
+        Ok((Self { path }, BufWriter::new(file)))
+    }
+}
+
+impl Drop for Spill {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+struct Columns<V>(Vec<V>);
+
+impl<V: Clone> EncodingSink<V> for Columns<V> {
+    fn put(&mut self, column: usize, value: &V) -> Result<()> {
+        self.0[column] = value.clone();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::*;
+    use crate::types::{Okvs, OkvsKey};
+
+    #[test]
+    fn test_external_encoder() {
+        let dir = env::temp_dir().join(format!("rb-okvs-external-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        let pairs: Vec<Pair<OkvsKey, OkvsValue<8>>> = (0..3000u64)
+            .map(|i| (OkvsKey(i.to_le_bytes()), OkvsValue((i * 7).to_le_bytes())))
+            .collect();
+        let rb_okvs = RbOkvs::new(pairs.len());
+        let expected = rb_okvs.encode_ref(&pairs).unwrap();
+
+        for run_rows in [128, 5000] {
+            let mut encoder = ExternalEncoder::new(rb_okvs.clone(), &dir, run_rows);
+            encoder.extend(pairs.iter().cloned()).unwrap();
+            assert_eq!(encoder.rows(), 3000);
+            assert_eq!(encoder.finish().unwrap(), expected);
+        }
+
+        let mut encoder = ExternalEncoder::new(rb_okvs.clone(), &dir, 1000);
+        encoder.extend(pairs.iter().cloned()).unwrap();
+        let mut columns = vec![];
+        encoder.finish_streaming(&mut columns).unwrap();
+        assert!(columns
+            .iter()
+            .rev()
+            .map(|(c, _)| *c)
+            .eq(0..rb_okvs.columns()));
+        assert!(columns.iter().all(|(c, v)| *v == expected[*c]));
+
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 0);
+        fs::remove_dir(&dir).unwrap();
+    }
+}
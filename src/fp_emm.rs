@@ -0,0 +1,323 @@
+use std::collections::HashMap;
+use std::marker::PhantomData;
+
+use crate::cipher::{AesGcmCipher, EmmCipher};
+use crate::emm::{seal_bytes, secret, unseal_bytes, ClientState, STATE_LEN};
+use crate::error::{Error, Result};
+use crate::prf::{EmmPrf, HmacSha256};
+use crate::types::{EmmK, EmmV};
+
+type Label = [u8; 32];
+
+/// A key state and its counter in [`FpEmmClient::seal`].
+const COUNTER_LEN: usize = 32 + 8;
+
+/// Adds one value to the list of a key, see [`FpEmmClient::update_token`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UpdateToken {
+    pub label: Label,
+    pub value: Vec<u8>,
+}
+
+/// Labels of every update of a key so far, see [`FpEmmClient::search_token`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SearchToken {
+    pub labels: Vec<Label>,
+}
+
+/// Client of the forward-private updatable EMM.
+///
+/// The `i`-th update of a key is stored under `PRF(PRF(kf, key), i)`, and
+/// the client ratchets a counter per key. A search only reveals the labels
+/// of updates already made, so the server can't link later updates to
+/// earlier searches; unlike [`DynVhEmm`](crate::dyn_emm::DynVhEmm), updates
+/// are cheap but searches reveal how many values a key has.
+///
+/// The counters are client state: losing them loses access to the values.
+/// [`FpEmmClient::seal`] stores them with the keys.
+pub struct FpEmmClient<P: EmmPrf = HmacSha256, C: EmmCipher = AesGcmCipher> {
+    state:    ClientState,
+    prf:      P,
+    counters: HashMap<Label, u64>,
+    cipher:   PhantomData<C>,
+}
+
+/// Append-only server of the forward-private updatable EMM.
+#[derive(Clone, Debug, Default)]
+pub struct FpEmmServer {
+    entries: HashMap<Label, Vec<u8>>,
+}
+
+impl FpEmmClient {
+    pub fn new(state: ClientState) -> Self {
+        Self::with_prf(state, HmacSha256)
+    }
+
+    pub fn unseal(bytes: &[u8], passphrase: &[u8]) -> Result<Self> {
+        Self::unseal_with_prf(bytes, passphrase, HmacSha256)
+    }
+}
+
+impl<P: EmmPrf, C: EmmCipher> FpEmmClient<P, C> {
+    /// See [`VhEmm::with_prf`](crate::emm::VhEmm::with_prf).
+    pub fn with_prf(state: ClientState, prf: P) -> Self {
+        Self {
+            state,
+            prf,
+            counters: HashMap::new(),
+            cipher: PhantomData,
+        }
+    }
+
+    /// Encrypts the keys and counters for storage at rest like
+    /// [`ClientState::seal`]. The plaintext is
+    /// `client state || count || (key state || counter)*`.
+    pub fn seal(&self, passphrase: &[u8]) -> Vec<u8> {
+        let mut counters: Vec<_> = self.counters.iter().collect();
+        counters.sort_unstable();
+
+        let mut plaintext = secret(self.state.to_bytes());
+        plaintext.extend_from_slice(&(counters.len() as u64).to_le_bytes());
+        for (kw, counter) in counters {
+            plaintext.extend_from_slice(kw);
+            plaintext.extend_from_slice(&counter.to_le_bytes());
+        }
+        seal_bytes(&plaintext, passphrase)
+    }
+
+    /// Fails with [`Error::Auth`] for a wrong passphrase or a corrupted blob.
+    pub fn unseal_with_prf(bytes: &[u8], passphrase: &[u8], prf: P) -> Result<Self> {
+        let plaintext = unseal_bytes(bytes, passphrase)?;
+        if plaintext.len() < STATE_LEN + 8 {
+            return Err(Error::Message("malformed client counters".into()));
+        }
+        let (state, counters) = plaintext.split_at(STATE_LEN);
+        let (count, counters) = counters.split_at(8);
+        let count = u64::from_le_bytes(count.try_into().unwrap());
+        if counters.len() % COUNTER_LEN != 0 || (counters.len() / COUNTER_LEN) as u64 != count {
+            return Err(Error::Message("malformed client counters".into()));
+        }
+
+        let mut client = Self::with_prf(ClientState::from_bytes(state)?, prf);
+        client.counters = counters
+            .chunks_exact(COUNTER_LEN)
+            .map(|entry| {
+                let (kw, counter) = entry.split_at(32);
+                (
+                    kw.try_into().unwrap(),
+                    u64::from_le_bytes(counter.try_into().unwrap()),
+                )
+            })
+            .collect();
+        Ok(client)
+    }
+
+    /// Encrypts `value` under a fresh label and advances the counter of `key`.
+    pub fn update_token<K: EmmK, V: EmmV>(&mut self, key: &K, value: &V) -> UpdateToken {
+        let kw = self.key_state(key);
+        let counter = self.counters.entry(kw).or_insert(0);
+        let label = self.prf.eval(&kw, &counter.to_le_bytes());
+        *counter += 1;
+
+        let mut plaintext = label.to_vec();
+        plaintext.extend_from_slice(&value.encode());
+        UpdateToken {
+            label,
+            value: C::encrypt(&self.state.ke, &plaintext, &[]),
+        }
+    }
+
+    /// Update tokens appending `values` to the list of `key`, in order.
+    pub fn update_tokens<K: EmmK, V: EmmV>(&mut self, key: &K, values: &[V]) -> Vec<UpdateToken> {
+        values.iter().map(|v| self.update_token(key, v)).collect()
+    }
+
+    /// Empty for keys that were never updated.
+    pub fn search_token<K: EmmK>(&self, key: &K) -> SearchToken {
+        let kw = self.key_state(key);
+        let count = self.counters.get(&kw).copied().unwrap_or(0);
+        SearchToken {
+            labels: (0..count)
+                .map(|i| self.prf.eval(&kw, &i.to_le_bytes()))
+                .collect(),
+        }
+    }
+
+    /// Decrypts the values [`FpEmmServer::search`] returned for `token`.
+    pub fn resolve<V: EmmV>(&self, token: &SearchToken, values: Vec<Vec<u8>>) -> Result<Vec<V>> {
+        if token.labels.len() != values.len() {
+            return Err(Error::Message(format!(
+                "{} values for {} labels",
+                values.len(),
+                token.labels.len()
+            )));
+        }
+        token
+            .labels
+            .iter()
+            .zip(values)
+            .enumerate()
+            .map(|(i, (label, value))| {
+                let plaintext = C::decrypt(&self.state.ke, &value, &[]).ok_or(Error::Auth(i))?;
+                // Binds each value to its label, so the server can't swap them
+                if plaintext.len() != label.len() + V::len()
+                    || plaintext[..label.len()] != label[..]
+                {
+                    return Err(Error::Decode(i));
+                }
+                Ok(V::decode(&plaintext[label.len()..]))
+            })
+            .collect()
+    }
+
+    fn key_state<K: EmmK>(&self, key: &K) -> Label {
+        self.prf.eval(&self.state.kf, &key.to_bytes())
+    }
+}
+
+impl FpEmmServer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Stores the entry of an update; labels are never overwritten.
+    pub fn apply(&mut self, token: UpdateToken) -> Result<()> {
+        match self.entries.contains_key(&token.label) {
+            true => Err(Error::Message("label already updated".into())),
+            false => {
+                self.entries.insert(token.label, token.value);
+                Ok(())
+            }
+        }
+    }
+
+    /// The values stored under the labels of `token`, in order. Fails with
+    /// [`Error::NotFound`] if an update is missing.
+    pub fn search(&self, token: &SearchToken) -> Result<Vec<Vec<u8>>> {
+        token
+            .labels
+            .iter()
+            .map(|label| self.entries.get(label).cloned().ok_or(Error::NotFound))
+            .collect()
+    }
+
+    /// Number of updates stored.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cipher::XChaChaCipher;
+
+    #[test]
+    fn test_update_search() {
+        let mut client = FpEmmClient::new(ClientState::new_random());
+        let mut server = FpEmmServer::new();
+
+        for i in 0..20u64 {
+            server.apply(client.update_token(&(i % 5), &i)).unwrap();
+        }
+        assert_eq!(server.len(), 20);
+
+        for k in 0..5u64 {
+            let token = client.search_token(&k);
+            let response = server.search(&token).unwrap();
+            assert_eq!(
+                client.resolve::<u64>(&token, response).unwrap(),
+                vec![k, k + 5, k + 10, k + 15]
+            );
+        }
+        let token = client.search_token(&5u64);
+        assert!(token.labels.is_empty());
+        let response = server.search(&token).unwrap();
+        assert!(client.resolve::<u64>(&token, response).unwrap().is_empty());
+
+        // Later updates use labels no earlier search revealed
+        let before = client.search_token(&3u64);
+        let update = client.update_token(&3u64, &100u64);
+        assert!(!before.labels.contains(&update.label));
+        server.apply(update.clone()).unwrap();
+        assert!(server.apply(update).is_err());
+
+        let token = client.search_token(&3u64);
+        assert_eq!(token.labels[..4], before.labels[..]);
+        let response = server.search(&token).unwrap();
+        assert_eq!(
+            client.resolve::<u64>(&token, response).unwrap(),
+            vec![3, 8, 13, 18, 100]
+        );
+    }
+
+    #[test]
+    fn test_seal() {
+        let mut client = FpEmmClient::new(ClientState::new_random());
+        let mut server = FpEmmServer::new();
+        for i in 0..6u64 {
+            server.apply(client.update_token(&(i % 2), &i)).unwrap();
+        }
+
+        let sealed = client.seal(b"passphrase");
+        assert!(matches!(
+            FpEmmClient::unseal(&sealed, b"wrong"),
+            Err(Error::Auth(0))
+        ));
+        let mut unsealed = FpEmmClient::unseal(&sealed, b"passphrase").unwrap();
+        for k in 0..2u64 {
+            assert_eq!(unsealed.search_token(&k), client.search_token(&k));
+        }
+
+        // The counters carry on, so new labels don't reuse old ones
+        let update = unsealed.update_token(&1u64, &6u64);
+        assert_eq!(update.label, client.update_token(&1u64, &6u64).label);
+        server.apply(update).unwrap();
+        let token = unsealed.search_token(&1u64);
+        let response = server.search(&token).unwrap();
+        assert_eq!(
+            unsealed.resolve::<u64>(&token, response).unwrap(),
+            vec![1, 3, 5, 6]
+        );
+    }
+
+    #[test]
+    fn test_tampering() {
+        let mut client = FpEmmClient::<HmacSha256, XChaChaCipher>::with_prf(
+            ClientState::new_random(),
+            HmacSha256,
+        );
+        let mut server = FpEmmServer::new();
+        for token in client.update_tokens(&"key", &[1u64, 2u64]) {
+            server.apply(token).unwrap();
+        }
+        let token = client.search_token(&"key");
+        let values = server.search(&token).unwrap();
+
+        let mut swapped = values.clone();
+        swapped.swap(0, 1);
+        assert!(matches!(
+            client.resolve::<u64>(&token, swapped),
+            Err(Error::Decode(0))
+        ));
+
+        let mut tampered = values.clone();
+        tampered[1][0] ^= 1;
+        assert!(matches!(
+            client.resolve::<u64>(&token, tampered),
+            Err(Error::Auth(1))
+        ));
+
+        assert_eq!(client.resolve::<u64>(&token, values).unwrap(), vec![1, 2]);
+        assert!(matches!(
+            server.search(&SearchToken {
+                labels: vec![[0u8; 32]]
+            }),
+            Err(Error::NotFound)
+        ));
+    }
+}
@@ -0,0 +1,302 @@
+//! Bulk decoding on a GPU through wgpu compute shaders.
+//!
+//! [`GpuDecoder`] uploads an encoding once and evaluates the band inner
+//! products of whole batches of keys on the device. Keys are still hashed
+//! on the CPU, on an [`Executor`], so only starts and bands cross the bus.
+
+use std::sync::mpsc;
+
+use wgpu::util::{BufferInitDescriptor, DeviceExt};
+
+use crate::error::{Error, Result};
+use crate::executor::Executor;
+use crate::hasher::{Blake2Hasher, KeyHasher};
+use crate::okvs::RbOkvs;
+use crate::types::{Encoding, OkvsK, OkvsValue};
+
+const WORKGROUP_SIZE: u32 = 64;
+
+/// One invocation per key: XORs the columns its band selects, a value of
+/// `stride` words each, into its output row.
+const SHADER: &str = r"
+struct Params {
+    keys:       u32,
+    stride:     u32,
+    band_words: u32,
+    _pad:       u32,
+}
+
+@group(0) @binding(0) var<uniform> params: Params;
+@group(0) @binding(1) var<storage, read> encoding: array<u32>;
+@group(0) @binding(2) var<storage, read> queries: array<u32>;
+@group(0) @binding(3) var<storage, read_write> out: array<u32>;
+
+@compute @workgroup_size(64)
+fn main(
+    @builtin(global_invocation_id) id: vec3<u32>,
+    @builtin(num_workgroups) groups: vec3<u32>,
+) {
+    let key = id.y * groups.x * 64u + id.x;
+    if key >= params.keys {
+        return;
+    }
+    let query = key * (1u + params.band_words);
+    let start = queries[query];
+    let row = key * params.stride;
+    for (var w = 0u; w < params.band_words; w++) {
+        var bits = queries[query + 1u + w];
+        while bits != 0u {
+            let column = (start + w * 32u + countTrailingZeros(bits)) * params.stride;
+            bits &= bits - 1u;
+            for (var s = 0u; s < params.stride; s++) {
+                out[row + s] ^= encoding[column + s];
+            }
+        }
+    }
+}
+";
+
+/// An [`RbOkvs`] encoding resident on a GPU, for servers answering decode
+/// batches too large for the CPU.
+///
+/// Decodes agree with [`Okvs::decode`](crate::types::Okvs::decode). Batches
+/// larger than the device's storage buffers are split, so any number of
+/// keys can be passed at once; small batches are faster on the CPU.
+pub struct GpuDecoder<const N: usize, const W: usize = 4, H = Blake2Hasher> {
+    okvs:     RbOkvs<W, H>,
+    device:   wgpu::Device,
+    queue:    wgpu::Queue,
+    pipeline: wgpu::ComputePipeline,
+    encoding: wgpu::Buffer,
+}
+
+impl<const N: usize, const W: usize, H: KeyHasher> GpuDecoder<N, W, H> {
+    /// Words of a value on the device, padded up from `N` bytes.
+    const STRIDE: usize = N.div_ceil(4);
+
+    /// Uploads `encoding` to the first GPU wgpu finds. Fails with
+    /// [`Error::EncodingTooShort`] like [`OkvsReader::new`](crate::reader::OkvsReader::new),
+    /// and with [`Error::Gpu`] when there is no usable device or the
+    /// encoding exceeds its buffers.
+    pub fn new(okvs: RbOkvs<W, H>, encoding: &Encoding<OkvsValue<N>>) -> Result<Self> {
+        if encoding.len() < okvs.columns() {
+            return Err(Error::EncodingTooShort {
+                required: okvs.columns(),
+                actual: encoding.len(),
+            });
+        }
+        let instance = wgpu::Instance::new(wgpu::InstanceDescriptor::new_without_display_handle());
+        let adapter = pollster::block_on(instance.request_adapter(&Default::default()))
+            .map_err(|e| Error::Gpu(e.to_string()))?;
+        let (device, queue) = pollster::block_on(adapter.request_device(&wgpu::DeviceDescriptor {
+            label: Some("rb-okvs"),
+            required_limits: adapter.limits(),
+            ..Default::default()
+        }))
+        .map_err(|e| Error::Gpu(e.to_string()))?;
+
+        let mut words = Vec::with_capacity(okvs.columns() * Self::STRIDE);
+        for v in &encoding[..okvs.columns()] {
+            words.extend(to_words(&v.0));
+        }
+        let bytes = words.len() as u64 * 4;
+        if bytes > device.limits().max_storage_buffer_binding_size {
+            return Err(Error::Gpu(format!(
+                "encoding of {bytes} bytes exceeds the device's storage buffers"
+            )));
+        }
+        let encoding = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("encoding"),
+            contents: &as_bytes(&words),
+            usage: wgpu::BufferUsages::STORAGE,
+        });
+
+        let module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("decode"),
+            source: wgpu::ShaderSource::Wgsl(SHADER.into()),
+        });
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("decode"),
+            layout: None,
+            module: &module,
+            entry_point: Some("main"),
+            compilation_options: Default::default(),
+            cache: None,
+        });
+        Ok(Self {
+            okvs,
+            device,
+            queue,
+            pipeline,
+            encoding,
+        })
+    }
+
+    pub fn okvs(&self) -> &RbOkvs<W, H> {
+        &self.okvs
+    }
+
+    /// Decodes every key in `keys`, results in key order, like
+    /// [`Okvs::decode_batch`](crate::types::Okvs::decode_batch). Keys are
+    /// hashed on `executor`, inner products evaluated on the GPU.
+    pub fn decode_batch<K, E>(&self, executor: &E, keys: &[K]) -> Result<Vec<OkvsValue<N>>>
+    where
+        K: OkvsK + Sync,
+        H: Sync,
+        E: Executor,
+    {
+        let located = executor.map(keys, |k| self.okvs.locate(k));
+        let query_words = 1 + 2 * W;
+        let row_bytes = 4 * query_words.max(Self::STRIDE) as u64;
+        let limits = self.device.limits();
+        let batch = (limits.max_storage_buffer_binding_size / row_bytes).max(1);
+
+        let mut out = Vec::with_capacity(keys.len());
+        for chunk in located.chunks(batch as usize) {
+            let mut queries = Vec::with_capacity(chunk.len() * query_words);
+            for (start, band) in chunk {
+                queries.push(*start as u32);
+                for word in &band.0 {
+                    queries.extend([*word as u32, (*word >> 32) as u32]);
+                }
+            }
+            out.extend(self.run(&queries, chunk.len())?);
+        }
+        Ok(out)
+    }
+
+    /// Runs the shader over `keys` packed queries and reads back their
+    /// values.
+    fn run(&self, queries: &[u32], keys: usize) -> Result<Vec<OkvsValue<N>>> {
+        let params = [keys as u32, Self::STRIDE as u32, 2 * W as u32, 0];
+        let params = self.device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("params"),
+            contents: &as_bytes(&params),
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+        let queries = self.device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("queries"),
+            contents: &as_bytes(queries),
+            usage: wgpu::BufferUsages::STORAGE,
+        });
+        let size = (keys * Self::STRIDE * 4).max(4) as u64;
+        let out = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("out"),
+            size,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let staging = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("staging"),
+            size,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let bind_group = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: None,
+            layout: &self.pipeline.get_bind_group_layout(0),
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: params.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: self.encoding.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: queries.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: out.as_entire_binding(),
+                },
+            ],
+        });
+
+        // Workgroups beyond the per-dimension limit wrap into rows
+        let groups = (keys as u32).div_ceil(WORKGROUP_SIZE).max(1);
+        let max = self.device.limits().max_compute_workgroups_per_dimension;
+        let (x, y) = (groups.min(max), groups.div_ceil(max));
+        let mut encoder = self.device.create_command_encoder(&Default::default());
+        {
+            let mut pass = encoder.begin_compute_pass(&Default::default());
+            pass.set_pipeline(&self.pipeline);
+            pass.set_bind_group(0, &bind_group, &[]);
+            pass.dispatch_workgroups(x, y, 1);
+        }
+        encoder.copy_buffer_to_buffer(&out, 0, &staging, 0, size);
+        self.queue.submit([encoder.finish()]);
+
+        let slice = staging.slice(..);
+        let (tx, rx) = mpsc::channel();
+        slice.map_async(wgpu::MapMode::Read, move |mapped| {
+            let _ = tx.send(mapped);
+        });
+        self.device
+            .poll(wgpu::PollType::wait_indefinitely())
+            .map_err(|e| Error::Gpu(e.to_string()))?;
+        rx.recv()
+            .map_err(|e| Error::Gpu(e.to_string()))?
+            .map_err(|e| Error::Gpu(e.to_string()))?;
+        let bytes = slice.get_mapped_range();
+        let values = bytes
+            .chunks_exact(Self::STRIDE * 4)
+            .take(keys)
+            .map(|row| OkvsValue(row[..N].try_into().unwrap()))
+            .collect();
+        Ok(values)
+    }
+}
+
+/// `bytes` as little-endian words, zero-padded to whole words.
+fn to_words(bytes: &[u8]) -> impl Iterator<Item = u32> + '_ {
+    bytes.chunks(4).map(|chunk| {
+        let mut word = [0u8; 4];
+        word[..chunk.len()].copy_from_slice(chunk);
+        u32::from_le_bytes(word)
+    })
+}
+
+fn as_bytes(words: &[u32]) -> Vec<u8> {
+    words.iter().flat_map(|w| w.to_le_bytes()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::executor::StdThreads;
+    use crate::types::{Okvs, OkvsKey, Pair};
+
+    #[test]
+    fn test_gpu_decode_batch() {
+        let pairs: Vec<Pair<OkvsKey, OkvsValue<10>>> = (0..5000u64)
+            .map(|i| {
+                let mut value = [0u8; 10];
+                value[..8].copy_from_slice(&(i * 11).to_le_bytes());
+                (OkvsKey(i.to_le_bytes()), OkvsValue(value))
+            })
+            .collect();
+        let okvs = RbOkvs::new(pairs.len());
+        let encoding = okvs.encode_ref(&pairs).unwrap();
+        assert!(matches!(
+            GpuDecoder::new(okvs.clone(), &encoding[..10].to_vec()),
+            Err(Error::EncodingTooShort { actual: 10, .. })
+        ));
+
+        // Machines without a GPU, or a software rasterizer, skip the rest
+        let decoder = match GpuDecoder::new(okvs, &encoding) {
+            Ok(decoder) => decoder,
+            Err(Error::Gpu(_)) => return,
+            Err(e) => panic!("{e}"),
+        };
+        let keys: Vec<OkvsKey> = pairs.iter().map(|(k, _)| k.clone()).collect();
+        let values = decoder.decode_batch(&StdThreads::new(2), &keys).unwrap();
+        assert!(values.iter().zip(&pairs).all(|(d, (_, v))| d == v));
+        assert!(decoder
+            .decode_batch(&StdThreads::new(2), &keys[..0])
+            .unwrap()
+            .is_empty());
+    }
+}
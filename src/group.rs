@@ -0,0 +1,202 @@
+//! OKVS values in prime-order groups, for OPRF and PSI constructions that
+//! need decoding to commute with the group operation.
+//!
+//! [`OkvsV`](crate::types::OkvsV) values combine with XOR, and the GF(2)
+//! solver relies on every value being its own inverse. Curve25519 scalars and
+//! Ristretto points are not, so they get their own trait, [`GroupV`], and a
+//! solver over the scalar field. Bands and hashing are the same as
+//! [`RbOkvs::encode`](crate::types::Okvs::encode): a key decodes to the sum
+//! of the columns its band selects.
+
+use curve25519_dalek::ristretto::RistrettoPoint;
+use curve25519_dalek::traits::Identity;
+use curve25519_dalek::Scalar;
+
+use crate::error::{Error, Result};
+use crate::hasher::KeyHasher;
+use crate::okvs::RbOkvs;
+use crate::types::{Encoding, OkvsK, Pair};
+
+/// A vector space over the curve25519 scalar field.
+pub trait GroupV: Clone {
+    fn zero() -> Self;
+    fn add(&self, other: &Self) -> Self;
+    fn sub(&self, other: &Self) -> Self;
+    fn mul(&self, scalar: &Scalar) -> Self;
+}
+
+impl GroupV for Scalar {
+    fn zero() -> Self {
+        Scalar::ZERO
+    }
+
+    fn add(&self, other: &Self) -> Self {
+        self + other
+    }
+
+    fn sub(&self, other: &Self) -> Self {
+        self - other
+    }
+
+    fn mul(&self, scalar: &Scalar) -> Self {
+        self * scalar
+    }
+}
+
+impl GroupV for RistrettoPoint {
+    fn zero() -> Self {
+        RistrettoPoint::identity()
+    }
+
+    fn add(&self, other: &Self) -> Self {
+        self + other
+    }
+
+    fn sub(&self, other: &Self) -> Self {
+        self - other
+    }
+
+    fn mul(&self, scalar: &Scalar) -> Self {
+        self * scalar
+    }
+}
+
+/// A row of the system over the scalar field: coefficients of the columns
+/// from `start`, in a window of the band width.
+struct Row<V> {
+    start:  usize,
+    coeffs: Vec<Scalar>,
+    y:      V,
+}
+
+impl<const W: usize, H: KeyHasher> RbOkvs<W, H> {
+    /// Encodes `input` so that each key's band sums to its value in the
+    /// group. Elimination works on scalars, `O(rows · band width²)` field
+    /// operations, so it is much slower than `encode`. Fails like `encode`,
+    /// with [`Error::CollidingKeys`] or [`Error::Unsolvable`].
+    pub fn encode_group<K: OkvsK, V: GroupV>(&self, input: &[Pair<K, V>]) -> Result<Encoding<V>> {
+        let width = self.band_width();
+        let mut rows: Vec<Row<V>> = input
+            .iter()
+            .map(|(k, v)| {
+                let (start, band) = self.locate(k);
+                let mut coeffs = vec![Scalar::ZERO; width];
+                band.for_each_one(|i| coeffs[i] = Scalar::ONE);
+                Row {
+                    start,
+                    coeffs,
+                    y: v.clone(),
+                }
+            })
+            .collect();
+        // Stable, so rows are in the order `unsolvable` traces them in
+        rows.sort_by_key(|row| row.start);
+
+        // Forward elimination, normalizing each pivot to one.
+        let mut pivots = vec![0; rows.len()];
+        for i in 0..rows.len() {
+            let (done, rest) = rows.split_at_mut(i + 1);
+            let row = &mut done[i];
+            let Some(offset) = row.coeffs.iter().position(|c| *c != Scalar::ZERO) else {
+                return Err(self.unsolvable(input, Error::ZeroRow(i)));
+            };
+            let inverse = row.coeffs[offset].invert();
+            row.coeffs.iter_mut().for_each(|c| *c *= inverse);
+            row.y = row.y.mul(&inverse);
+
+            let pivot = row.start + offset;
+            pivots[i] = pivot;
+            // Later rows start no earlier, so the rest of this row, from the
+            // pivot on, fits in their windows.
+            for other in rest.iter_mut().take_while(|other| other.start <= pivot) {
+                let factor = other.coeffs[pivot - other.start];
+                if factor == Scalar::ZERO {
+                    continue;
+                }
+                let coeffs = other.coeffs[pivot - other.start..].iter_mut();
+                for (c, r) in coeffs.zip(&row.coeffs[offset..]) {
+                    *c -= factor * r;
+                }
+                other.y = other.y.sub(&row.y.mul(&factor));
+            }
+        }
+
+        // Back substitution, free columns left at zero.
+        let mut x = vec![V::zero(); self.columns()];
+        for (row, &pivot) in rows.iter().zip(&pivots).rev() {
+            let mut value = row.y.clone();
+            for (offset, c) in row.coeffs.iter().enumerate() {
+                let column = row.start + offset;
+                if column > pivot && *c != Scalar::ZERO {
+                    value = value.sub(&x[column].mul(c));
+                }
+            }
+            x[pivot] = value;
+        }
+        Ok(x)
+    }
+
+    /// Sum of the columns of `encoding` selected by the band of `key`.
+    /// Fails with [`Error::EncodingTooShort`] like
+    /// [`Okvs::decode`](crate::types::Okvs::decode).
+    pub fn decode_group<V: GroupV>(&self, encoding: &Encoding<V>, key: &impl OkvsK) -> Result<V> {
+        if encoding.len() < self.columns() {
+            return Err(Error::EncodingTooShort {
+                required: self.columns(),
+                actual: encoding.len(),
+            });
+        }
+        let (start, band) = self.locate(key);
+        let mut value = V::zero();
+        band.for_each_one(|i| value = value.add(&encoding[start + i]));
+        Ok(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use aes_gcm::aead::OsRng;
+
+    use super::*;
+    use crate::types::OkvsKey;
+
+    #[test]
+    fn test_encode_scalars() {
+        let pairs: Vec<Pair<OkvsKey, Scalar>> = (0..100u64)
+            .map(|i| (OkvsKey(i.to_le_bytes()), Scalar::random(&mut OsRng)))
+            .collect();
+        let okvs = RbOkvs::new(pairs.len());
+        let encoding = okvs.encode_group(&pairs).unwrap();
+        for (k, v) in &pairs {
+            assert_eq!(okvs.decode_group(&encoding, k).unwrap(), *v);
+        }
+        assert!(matches!(
+            okvs.decode_group(&encoding[..10].to_vec(), &pairs[0].0),
+            Err(Error::EncodingTooShort { actual: 10, .. })
+        ));
+
+        let duplicate = vec![pairs[0].clone(), pairs[0].clone()];
+        assert!(matches!(
+            okvs.encode_group(&duplicate),
+            Err(Error::CollidingKeys(0, 1))
+        ));
+    }
+
+    #[test]
+    fn test_encode_points() {
+        let pairs: Vec<Pair<OkvsKey, RistrettoPoint>> = (0..30u64)
+            .map(|i| (OkvsKey(i.to_le_bytes()), RistrettoPoint::random(&mut OsRng)))
+            .collect();
+        let okvs = RbOkvs::new(pairs.len());
+        let encoding = okvs.encode_group(&pairs).unwrap();
+
+        // Decoding is a group homomorphism, so scaling the encoding scales
+        // every decoded value.
+        let k = Scalar::random(&mut OsRng);
+        let scaled: Vec<_> = encoding.iter().map(|p| p * k).collect();
+        for (key, v) in &pairs {
+            assert_eq!(okvs.decode_group(&encoding, key).unwrap(), *v);
+            assert_eq!(okvs.decode_group(&scaled, key).unwrap(), v * k);
+        }
+    }
+}
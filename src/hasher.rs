@@ -0,0 +1,228 @@
+use aes::cipher::{BlockEncrypt, KeyInit};
+use aes::Aes128;
+
+use crate::band::Band;
+use crate::types::{OkvsK, Seed};
+use crate::utils::reduce;
+
+/// Derives the start position and band of a key, selected per [`RbOkvs`]
+/// with [`RbOkvs::with_hasher`]. Encoder and decoder must use the same one.
+///
+/// [`RbOkvs`]: crate::okvs::RbOkvs
+/// [`RbOkvs::with_hasher`]: crate::okvs::RbOkvs::with_hasher
+pub trait KeyHasher: Clone + Send + Sync {
+    /// Identifies the hash functions in [`RbOkvs::fingerprint`](crate::okvs::RbOkvs::fingerprint).
+    fn name(&self) -> &'static str;
+
+    /// Start position in `[0, range)`. Both hashes must depend on `seed`
+    /// and be independent of each other.
+    fn hash_to_index<K: OkvsK>(&self, seed: &Seed, key: &K, range: usize) -> usize;
+
+    /// Band of `band_width` bits, the lowest one set.
+    fn hash_to_band<K: OkvsK, const W: usize>(
+        &self,
+        seed: &Seed,
+        key: &K,
+        band_width: usize,
+    ) -> Band<W>;
+}
+
+/// The key's own [`HashToIndex`](crate::types::HashToIndex) and
+/// [`OkvsK::hash_to_band`], BLAKE2b for [`OkvsKey`](crate::types::OkvsKey).
+/// The default.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Blake2Hasher;
+
+/// Fixed-key AES-128 in Matyas–Meyer–Oseas mode, several times faster than
+/// BLAKE2b on CPUs with AES instructions. Relies on AES behaving as an ideal
+/// permutation; the key need not be secret.
+#[derive(Clone)]
+pub struct AesHasher {
+    cipher: Aes128,
+}
+
+/// XXH3, for when keys are not chosen adversarially. Encoding can fail far
+/// more often than the band width suggests if they are.
+#[cfg(feature = "xxhash")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct XxHasher;
+
+impl KeyHasher for Blake2Hasher {
+    fn name(&self) -> &'static str {
+        "blake2b-mulshift"
+    }
+
+    fn hash_to_index<K: OkvsK>(&self, seed: &Seed, key: &K, range: usize) -> usize {
+        key.hash_to_index(seed, range)
+    }
+
+    fn hash_to_band<K: OkvsK, const W: usize>(
+        &self,
+        seed: &Seed,
+        key: &K,
+        band_width: usize,
+    ) -> Band<W> {
+        key.hash_to_band(seed, band_width)
+    }
+}
+
+impl AesHasher {
+    pub fn new(key: [u8; 16]) -> Self {
+        Self {
+            cipher: Aes128::new(&key.into()),
+        }
+    }
+
+    /// `E(x) ^ x`
+    fn compress(&self, x: [u8; 16]) -> [u8; 16] {
+        let mut block = x.into();
+        self.cipher.encrypt_block(&mut block);
+        let mut out: [u8; 16] = block.into();
+        for (o, x) in out.iter_mut().zip(x) {
+            *o ^= x;
+        }
+        out
+    }
+
+    /// Chains the seed and the 16-byte blocks of the key, the last one padded
+    /// and followed by the key length.
+    fn digest(&self, seed: &Seed, key: &[u8]) -> [u8; 16] {
+        let mut h = self.compress(*seed);
+        let mut absorb = |block: &[u8]| {
+            for (h, b) in h.iter_mut().zip(block) {
+                *h ^= b;
+            }
+            h = self.compress(h);
+        };
+        for block in key.chunks(16) {
+            absorb(block);
+        }
+        absorb(&(key.len() as u128).to_le_bytes());
+        h
+    }
+
+    /// Block `i` of the output stream of `digest`: block 0 for the index,
+    /// the following ones for the band.
+    fn expand(&self, digest: &[u8; 16], i: u64) -> [u8; 16] {
+        let mut x = *digest;
+        for (x, c) in x.iter_mut().zip(i.to_le_bytes()) {
+            *x ^= c;
+        }
+        self.compress(x)
+    }
+}
+
+impl Default for AesHasher {
+    /// The all-zero key.
+    fn default() -> Self {
+        Self::new([0u8; 16])
+    }
+}
+
+impl KeyHasher for AesHasher {
+    fn name(&self) -> &'static str {
+        "aes128-mmo-mulshift"
+    }
+
+    fn hash_to_index<K: OkvsK>(&self, seed: &Seed, key: &K, range: usize) -> usize {
+        let block = self.expand(&self.digest(seed, &key.to_bytes()), 0);
+        reduce(u64::from_le_bytes(block[..8].try_into().unwrap()), range)
+    }
+
+    fn hash_to_band<K: OkvsK, const W: usize>(
+        &self,
+        seed: &Seed,
+        key: &K,
+        band_width: usize,
+    ) -> Band<W> {
+        let digest = self.digest(seed, &key.to_bytes());
+        let mut v: Vec<u8> = (1..)
+            .flat_map(|i| self.expand(&digest, i))
+            .take(band_width.div_ceil(8))
+            .collect();
+        v[0] |= 1;
+        Band::from_le_bits(&v, band_width)
+    }
+}
+
+#[cfg(feature = "xxhash")]
+impl KeyHasher for XxHasher {
+    fn name(&self) -> &'static str {
+        "xxh3-mulshift"
+    }
+
+    /// Seeds XXH3 with the hash of `seed`, index and band with different
+    /// seeds.
+    fn hash_to_index<K: OkvsK>(&self, seed: &Seed, key: &K, range: usize) -> usize {
+        let seed = xxhash_rust::xxh3::xxh3_64(seed);
+        reduce(
+            xxhash_rust::xxh3::xxh3_64_with_seed(&key.to_bytes(), seed),
+            range,
+        )
+    }
+
+    fn hash_to_band<K: OkvsK, const W: usize>(
+        &self,
+        seed: &Seed,
+        key: &K,
+        band_width: usize,
+    ) -> Band<W> {
+        let seed = xxhash_rust::xxh3::xxh3_64(seed);
+        let bytes = key.to_bytes();
+        let mut v: Vec<u8> = (1..)
+            .flat_map(|i| xxhash_rust::xxh3::xxh3_128_with_seed(&bytes, seed ^ i).to_le_bytes())
+            .take(band_width.div_ceil(8))
+            .collect();
+        v[0] |= 1;
+        Band::from_le_bits(&v, band_width)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::okvs::RbOkvs;
+    use crate::types::{HashToIndex, Okvs, OkvsKey, OkvsValue, Pair};
+
+    fn round_trip<H: KeyHasher>(hasher: H) {
+        let pairs: Vec<Pair<OkvsKey, OkvsValue<4>>> = (0..1000usize)
+            .map(|i| {
+                (
+                    OkvsKey(i.to_le_bytes()),
+                    OkvsValue((i as u32).to_le_bytes()),
+                )
+            })
+            .collect();
+        let rb_okvs = RbOkvs::new(pairs.len()).with_hasher(hasher);
+        let encoding = rb_okvs.encode_ref(&pairs).unwrap();
+        for (k, v) in &pairs {
+            assert_eq!(&rb_okvs.decode(&encoding, k).unwrap(), v);
+        }
+        assert_ne!(
+            rb_okvs.fingerprint(),
+            RbOkvs::new(pairs.len()).fingerprint()
+        );
+    }
+
+    #[test]
+    fn test_hashers() {
+        round_trip(AesHasher::default());
+        #[cfg(feature = "xxhash")]
+        round_trip(XxHasher);
+
+        let key = OkvsKey([7u8; 8]);
+        let seed = [0u8; 16];
+        let aes = AesHasher::default();
+        let band: Band = aes.hash_to_band(&seed, &key, 128);
+        assert!(band.bit(0) && band.bits() <= 128);
+        assert_ne!(
+            band,
+            AesHasher::new([1u8; 16]).hash_to_band(&seed, &key, 128)
+        );
+        assert_ne!(band, aes.hash_to_band(&[1u8; 16], &key, 128));
+        assert_eq!(
+            Blake2Hasher.hash_to_index(&seed, &key, 1000),
+            key.hash_to_index(&seed, 1000)
+        );
+    }
+}
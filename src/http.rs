@@ -0,0 +1,213 @@
+use std::sync::Arc;
+
+use tokio::io::{
+    AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader,
+};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::Mutex;
+
+use crate::emm::{QueryResponse, QueryToken};
+use crate::error::{Error, Result};
+use crate::server::EmmServer;
+use crate::tcp::{answer_request, len_request, query_request, MAX_FRAME};
+
+/// Longest request or status line and headers either side accepts.
+const MAX_HEAD: u64 = 8 << 10;
+
+/// A start line and the body of an HTTP/1.1 message. Headers other than
+/// `Content-Length` are ignored.
+struct Message {
+    start: String,
+    body:  Vec<u8>,
+}
+
+/// Serves `server` over HTTP/1.1 to every connection `listener` accepts.
+/// `POST /query` takes a request body of the [`tcp`](crate::tcp) protocol
+/// and answers `200` with a [`QueryResponse`], or `400` with the error.
+pub async fn serve_http<S, const OKVS_V_SIZE: usize>(
+    listener: TcpListener,
+    server: Arc<S>,
+) -> Result<()>
+where
+    S: EmmServer<OKVS_V_SIZE> + 'static,
+{
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let server = server.clone();
+        tokio::spawn(async move {
+            // A broken connection only ends its own task
+            let _ = handle(stream, &*server).await;
+        });
+    }
+}
+
+/// Answers the requests of one connection until the peer closes it.
+async fn handle<S, const OKVS_V_SIZE: usize>(stream: TcpStream, server: &S) -> Result<()>
+where
+    S: EmmServer<OKVS_V_SIZE>,
+{
+    let mut stream = BufReader::new(stream);
+    while let Some(request) = read_message(&mut stream).await? {
+        let (status, body) = match request.start.split(' ').collect::<Vec<_>>()[..] {
+            ["POST", "/query", _] => match answer_request(server, &request.body)
+                .await
+                .and_then(|response| response.to_bytes())
+            {
+                Ok(response) => ("200 OK", response),
+                Err(e) => ("400 Bad Request", e.to_string().into_bytes()),
+            },
+            [_, "/query", _] => ("405 Method Not Allowed", vec![]),
+            [_, _, _] => ("404 Not Found", vec![]),
+            _ => ("400 Bad Request", b"malformed request line".to_vec()),
+        };
+        write_message(stream.get_mut(), &format!("HTTP/1.1 {status}"), "", &body).await?;
+    }
+    Ok(())
+}
+
+/// An [`EmmServer`] reached over HTTP, served by [`serve_http`]. Requests
+/// share one keep-alive connection and are answered in order.
+pub struct HttpEmmServer<const OKVS_V_SIZE: usize> {
+    stream: Mutex<BufReader<TcpStream>>,
+    host:   String,
+}
+
+impl<const OKVS_V_SIZE: usize> HttpEmmServer<OKVS_V_SIZE> {
+    /// Connects to `addr`, a `host:port` also sent as the `Host` header.
+    pub async fn connect(addr: &str) -> Result<Self> {
+        Ok(Self {
+            stream: Mutex::new(BufReader::new(TcpStream::connect(addr).await?)),
+            host: addr.to_string(),
+        })
+    }
+
+    async fn request(&self, request: &[u8]) -> Result<QueryResponse<OKVS_V_SIZE>> {
+        let mut stream = self.stream.lock().await;
+        let host = format!("Host: {}\r\n", self.host);
+        write_message(stream.get_mut(), "POST /query HTTP/1.1", &host, request).await?;
+        let response = read_message(&mut *stream)
+            .await?
+            .ok_or_else(|| Error::Message("connection closed".into()))?;
+        match response.start.split(' ').nth(1) {
+            Some("200") => QueryResponse::from_bytes(&response.body),
+            Some(_) if !response.body.is_empty() => Err(Error::Message(
+                String::from_utf8_lossy(&response.body).into_owned(),
+            )),
+            Some(_) => Err(Error::Message(response.start)),
+            None => Err(Error::Message("malformed status line".into())),
+        }
+    }
+}
+
+impl<const OKVS_V_SIZE: usize> EmmServer<OKVS_V_SIZE> for HttpEmmServer<OKVS_V_SIZE> {
+    async fn answer_len(&self, token: QueryToken) -> Result<QueryResponse<OKVS_V_SIZE>> {
+        self.request(&len_request(&token)).await
+    }
+
+    async fn answer_query(
+        &self,
+        token: QueryToken,
+        v_len: usize,
+    ) -> Result<QueryResponse<OKVS_V_SIZE>> {
+        self.request(&query_request(&token, v_len)).await
+    }
+}
+
+/// None when the peer closed the connection between messages.
+async fn read_message<R: AsyncBufRead + Unpin>(reader: &mut R) -> Result<Option<Message>> {
+    let mut head = (&mut *reader).take(MAX_HEAD);
+    let mut start = String::new();
+    if head.read_line(&mut start).await? == 0 {
+        return Ok(None);
+    }
+    let mut len = 0;
+    loop {
+        let mut line = String::new();
+        if head.read_line(&mut line).await? == 0 {
+            return Err(Error::Message("truncated HTTP head".into()));
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                len = value
+                    .trim()
+                    .parse()
+                    .map_err(|_| Error::Message(format!("content length {value}")))?;
+            }
+        }
+    }
+    if len > MAX_FRAME {
+        return Err(Error::Message(format!("body of {len} bytes")));
+    }
+    let mut body = vec![0u8; len];
+    reader.read_exact(&mut body).await?;
+    Ok(Some(Message {
+        start: start.trim_end().to_string(),
+        body,
+    }))
+}
+
+async fn write_message<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    start: &str,
+    headers: &str,
+    body: &[u8],
+) -> Result<()> {
+    let mut message = format!(
+        "{start}\r\n{headers}Content-Type: application/octet-stream\r\nContent-Length: {}\r\n\r\n",
+        body.len()
+    )
+    .into_bytes();
+    message.extend_from_slice(body);
+    writer.write_all(&message).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emm::{ClientState, EmmPair, VhEmm, VhEmmClient, VhEmmServer};
+    use crate::okvs::RbOkvs;
+    use crate::server::query;
+
+    #[tokio::test]
+    async fn test_http() {
+        let pairs: Vec<EmmPair<u64, Vec<u64>>> = (0..100).map(|i| (i, vec![i, i + 1])).collect();
+        let rb_okvs = RbOkvs::new(pairs.len() * 3);
+        let client_state = ClientState::new_random();
+        let emm = VhEmm::<RbOkvs, 8, 68>::new(rb_okvs.clone())
+            .setup(pairs, &client_state)
+            .unwrap();
+        let server = Arc::new(VhEmmServer::<RbOkvs, 8, 68>::new(rb_okvs, emm));
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        tokio::spawn(serve_http::<_, 68>(listener, server.clone()));
+
+        let client = VhEmmClient::new(client_state, *server.fingerprint());
+        let remote = HttpEmmServer::<68>::connect(&addr).await.unwrap();
+        for i in [0u64, 42, 99] {
+            let values: Vec<u64> = query(&client, &remote, &i).await.unwrap();
+            assert_eq!((values[0], values[1]), (i, i + 1));
+        }
+        assert!(matches!(
+            query::<_, _, _, _, u64, 68>(&client, &remote, &1000u64).await,
+            Err(Error::NotFound)
+        ));
+
+        // Malformed bodies get a 400 and keep the connection
+        assert!(remote.request(&[9]).await.is_err());
+        let values: Vec<u64> = query(&client, &remote, &7u64).await.unwrap();
+        assert_eq!(values[0], 7);
+
+        let mut stream = BufReader::new(TcpStream::connect(&addr).await.unwrap());
+        write_message(stream.get_mut(), "GET /other HTTP/1.1", "", &[])
+            .await
+            .unwrap();
+        let response = read_message(&mut stream).await.unwrap().unwrap();
+        assert_eq!(response.start, "HTTP/1.1 404 Not Found");
+    }
+}
@@ -0,0 +1,265 @@
+use std::collections::HashSet;
+
+use crate::error::Result;
+use crate::okvs::RbOkvs;
+use crate::trace::trace_event;
+use crate::types::{Encoding, Okvs, OkvsK, OkvsV, Pair, Seed};
+use crate::utils::blake2b;
+
+const TAG_DOMAIN: &[u8] = b"rb-okvs/incremental/tag";
+/// Seeds a level encoding is tried with before giving up.
+const ENCODE_ATTEMPTS: u64 = 4;
+
+/// An OKVS that takes insertions without re-encoding everything, by keeping
+/// the pairs in levels of doubling capacity like a binary counter.
+///
+/// Level `i` holds up to `base << i` pairs in its own encoding. An insertion
+/// goes into level 0, and when that is full it is merged with the levels below
+/// into the first one with room, so every pair is re-encoded `O(log n)`
+/// times overall. Later insertions of a key shadow earlier ones.
+///
+/// Values are encoded with a keyed tag of their key, so a decoder can tell
+/// which level holds a key: [`IncrementalOkvs::get`] checks levels from
+/// newest to oldest. [`IncrementalOkvs::snapshot`] is what a decoder needs.
+pub struct IncrementalOkvs<K, V> {
+    base:   usize,
+    seed:   Seed,
+    levels: Vec<Level<K, V>>,
+}
+
+/// The encodings of an [`IncrementalOkvs`], without its pairs.
+#[derive(Clone, Debug)]
+pub struct IncrementalSnapshot<V> {
+    seed:   Seed,
+    levels: Vec<Option<(RbOkvs, Encoding<Tagged<V>>)>>,
+}
+
+struct Level<K, V> {
+    pairs:    Vec<Pair<K, V>>,
+    okvs:     RbOkvs,
+    encoding: Encoding<Tagged<V>>,
+}
+
+/// A value with the tag of its key.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Tagged<V>(V, [u8; 8]);
+
+impl<K: OkvsK, V: OkvsV> IncrementalOkvs<K, V> {
+    /// `base` is the capacity of level 0, the most pairs an insertion
+    /// re-encodes when no merge is due.
+    pub fn new(base: usize) -> Self {
+        Self {
+            base: base.max(1),
+            seed: [0u8; 16],
+            levels: vec![],
+        }
+    }
+
+    /// Keys the level encodings and tags, see [`RbOkvs::with_seed`].
+    pub fn with_seed(mut self, seed: Seed) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    /// Number of levels, including empty ones.
+    pub fn levels(&self) -> usize {
+        self.levels.len()
+    }
+
+    /// Inserts `key`, shadowing an earlier insertion of it. On error nothing
+    /// changes.
+    pub fn insert(&mut self, key: K, value: V) -> Result<()> {
+        // Pairs of levels 0..=target, newest first, the first of each key.
+        let mut seen = HashSet::new();
+        let mut merged = vec![(&key, &value)];
+        seen.insert(key.to_bytes());
+        let mut target = 0;
+        loop {
+            if let Some(level) = self.levels.get(target) {
+                for (k, v) in &level.pairs {
+                    if seen.insert(k.to_bytes()) {
+                        merged.push((k, v));
+                    }
+                }
+            }
+            if merged.len() <= self.base << target {
+                break;
+            }
+            target += 1;
+        }
+        let (okvs, encoding) = self.encode_level(target, &merged)?;
+
+        let mut pairs = Vec::with_capacity(merged.len());
+        let mut seen = HashSet::from([key.to_bytes()]);
+        pairs.push((key, value));
+        for level in self.levels.iter_mut().take(target + 1) {
+            for (k, v) in level.pairs.drain(..) {
+                if seen.insert(k.to_bytes()) {
+                    pairs.push((k, v));
+                }
+            }
+            level.encoding.clear();
+        }
+        let level = Level {
+            pairs,
+            okvs,
+            encoding,
+        };
+        match self.levels.get_mut(target) {
+            Some(slot) => *slot = level,
+            None => self.levels.push(level),
+        }
+        Ok(())
+    }
+
+    /// The value last inserted for `key`, read from the encodings.
+    pub fn get(&self, key: &K) -> Option<V> {
+        lookup(
+            &self.seed,
+            self.levels
+                .iter()
+                .filter(|level| !level.pairs.is_empty())
+                .map(|level| (&level.okvs, &level.encoding)),
+            key,
+        )
+    }
+
+    pub fn snapshot(&self) -> IncrementalSnapshot<V> {
+        IncrementalSnapshot {
+            seed: self.seed,
+            levels: self
+                .levels
+                .iter()
+                .map(|level| {
+                    (!level.pairs.is_empty()).then(|| (level.okvs.clone(), level.encoding.clone()))
+                })
+                .collect(),
+        }
+    }
+
+    /// Encodes `pairs` as level `index`, retrying with fresh seeds.
+    fn encode_level(
+        &self,
+        index: usize,
+        pairs: &[(&K, &V)],
+    ) -> Result<(RbOkvs, Encoding<Tagged<V>>)> {
+        let tagged: Vec<_> = pairs
+            .iter()
+            .map(|(k, v)| (*k, Tagged((*v).clone(), tag(&self.seed, *k))))
+            .collect();
+        let mut attempt = 0u64;
+        loop {
+            let data = [
+                &self.seed[..],
+                &(index as u64).to_le_bytes(),
+                &attempt.to_le_bytes(),
+            ];
+            let okvs = RbOkvs::new(self.base << index).with_seed(blake2b(&data.concat()));
+            match okvs.encode_ref(&tagged) {
+                Ok(encoding) => return Ok((okvs, encoding)),
+                Err(e) if attempt + 1 == ENCODE_ATTEMPTS => return Err(e),
+                Err(_) => {
+                    trace_event!(
+                        "level encode failed, retrying",
+                        level = index,
+                        attempt = attempt
+                    );
+                    attempt += 1
+                }
+            }
+        }
+    }
+}
+
+impl<V: OkvsV> IncrementalSnapshot<V> {
+    /// See [`IncrementalOkvs::get`].
+    pub fn get<K: OkvsK>(&self, key: &K) -> Option<V> {
+        lookup(
+            &self.seed,
+            self.levels
+                .iter()
+                .flatten()
+                .map(|(okvs, encoding)| (okvs, encoding)),
+            key,
+        )
+    }
+}
+
+impl<V: OkvsV> OkvsV for Tagged<V> {
+    fn default() -> Self {
+        Tagged(V::default(), [0u8; 8])
+    }
+
+    fn is_zero(&self) -> bool {
+        self.0.is_zero() && self.1 == [0u8; 8]
+    }
+
+    fn xor(&self, other: &Self) -> Self {
+        let mut result = self.clone();
+        result.in_place_xor(other);
+        result
+    }
+
+    fn in_place_xor(&mut self, other: &Self) {
+        self.0.in_place_xor(&other.0);
+        for (a, b) in self.1.iter_mut().zip(other.1) {
+            *a ^= b;
+        }
+    }
+}
+
+fn tag<K: OkvsK>(seed: &Seed, key: &K) -> [u8; 8] {
+    blake2b(&[TAG_DOMAIN, seed, &key.to_bytes()].concat())
+}
+
+/// First level, newest first, decoding `key` to a value with its tag.
+fn lookup<'a, K: OkvsK, V: OkvsV + 'a>(
+    seed: &Seed,
+    mut levels: impl Iterator<Item = (&'a RbOkvs, &'a Encoding<Tagged<V>>)>,
+    key: &K,
+) -> Option<V> {
+    let tag = tag(seed, key);
+    levels.find_map(|(okvs, encoding)| {
+        // Levels are encoded here, so their encodings have the right length
+        let Tagged(v, t) = okvs.decode(encoding, key).ok()?;
+        (t == tag).then_some(v)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{OkvsKey, OkvsValue};
+
+    fn key(i: u64) -> OkvsKey {
+        OkvsKey(i.to_le_bytes())
+    }
+
+    #[test]
+    fn test_incremental_okvs() {
+        let mut okvs = IncrementalOkvs::new(256);
+        for i in 0..3000u64 {
+            okvs.insert(key(i), OkvsValue(i.to_le_bytes())).unwrap();
+        }
+        assert!(okvs.levels() <= 5);
+        for i in 0..3000u64 {
+            assert_eq!(okvs.get(&key(i)), Some(OkvsValue(i.to_le_bytes())));
+        }
+        assert_eq!(okvs.get(&key(3000)), None);
+
+        let snapshot = okvs.snapshot();
+        assert_eq!(snapshot.get(&key(42)), Some(OkvsValue(42u64.to_le_bytes())));
+        assert_eq!(snapshot.get(&key(3000)), None);
+    }
+
+    #[test]
+    fn test_shadowing() {
+        let mut okvs = IncrementalOkvs::new(64).with_seed([9; 16]);
+        for i in 0..500u64 {
+            okvs.insert(key(i % 100), OkvsValue([i as u8])).unwrap();
+        }
+        for i in 0..100u64 {
+            assert_eq!(okvs.get(&key(i)), Some(OkvsValue([(400 + i) as u8])));
+        }
+    }
+}
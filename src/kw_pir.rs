@@ -0,0 +1,212 @@
+use aes_gcm::aead::{rand_core::RngCore, OsRng};
+
+use crate::error::{Error, Result};
+use crate::okvs::RbOkvs;
+use crate::oprf::{encode_padded, okvs_key};
+use crate::types::{Encoding, Okvs, OkvsValue};
+use crate::utils::{hash, to_usize};
+
+/// Bytes of an index entry: the index (u64 LE) and a check of the keyword.
+pub const INDEX_V_SIZE: usize = 16;
+const CHECK_DOMAIN: &[u8] = b"rb-okvs/kw-pir/check";
+
+/// Retrieves a record by index without the server learning which, the
+/// backend keyword lookups are layered on.
+pub trait IndexPir {
+    type Query;
+    type Answer;
+    /// What the client keeps between `query` and `recover`.
+    type Secret;
+
+    /// Client side: a query for record `index` of `len`.
+    fn query(&self, index: usize, len: usize) -> Result<(Self::Query, Self::Secret)>;
+
+    /// Server side: answers `query` over `records`.
+    fn answer(&self, records: &[Vec<u8>], query: &Self::Query) -> Result<Self::Answer>;
+
+    /// Client side: the record `answer` holds.
+    fn recover(&self, secret: Self::Secret, answer: Self::Answer) -> Result<Vec<u8>>;
+}
+
+/// Sends every record, the baseline any [`IndexPir`] should beat.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TrivialPir;
+
+impl IndexPir for TrivialPir {
+    type Query = ();
+    type Answer = Vec<Vec<u8>>;
+    type Secret = usize;
+
+    fn query(&self, index: usize, _len: usize) -> Result<((), usize)> {
+        Ok(((), index))
+    }
+
+    fn answer(&self, records: &[Vec<u8>], _query: &()) -> Result<Vec<Vec<u8>>> {
+        Ok(records.to_vec())
+    }
+
+    fn recover(&self, index: usize, mut answer: Vec<Vec<u8>>) -> Result<Vec<u8>> {
+        match index < answer.len() {
+            true => Ok(answer.swap_remove(index)),
+            false => Err(Error::Message(format!(
+                "{} records for index {index}",
+                answer.len()
+            ))),
+        }
+    }
+}
+
+/// Encodes each of `keys` to its position, so that [`lookup_index`] maps a
+/// keyword to the index of its record. The encoding reveals nothing about
+/// the keywords and can be published to every client.
+pub fn build_index<K: AsRef<[u8]>>(keys: &[K]) -> Result<Encoding<OkvsValue<INDEX_V_SIZE>>> {
+    let pairs = keys
+        .iter()
+        .enumerate()
+        .map(|(i, key)| {
+            let mut v = [0u8; INDEX_V_SIZE];
+            v[..8].copy_from_slice(&(i as u64).to_le_bytes());
+            v[8..].copy_from_slice(&check(key.as_ref()));
+            (okvs_key(key.as_ref()), OkvsValue(v))
+        })
+        .collect();
+    Ok(encode_padded(pairs)?.2)
+}
+
+/// The index of `key`, or `None` for keywords that were not indexed, with a
+/// false positive probability of 2^-64.
+pub fn lookup_index(encoding: &Encoding<OkvsValue<INDEX_V_SIZE>>, key: &[u8]) -> Option<usize> {
+    let okvs = RbOkvs::for_columns(encoding.len());
+    let v = okvs.decode(encoding, &okvs_key(key)).ok()?.0;
+    match v[8..] == check(key) {
+        true => to_usize(u64::from_le_bytes(v[..8].try_into().unwrap())).ok(),
+        false => None,
+    }
+}
+
+/// Server of the keyword PIR: the records in index order and their index.
+pub struct KeywordPirServer<P: IndexPir> {
+    pir:     P,
+    index:   Encoding<OkvsValue<INDEX_V_SIZE>>,
+    records: Vec<Vec<u8>>,
+}
+
+/// Client of the keyword PIR, holding the published index.
+pub struct KeywordPirClient<P: IndexPir> {
+    pir:     P,
+    index:   Encoding<OkvsValue<INDEX_V_SIZE>>,
+    records: usize,
+}
+
+/// What a [`KeywordPirClient`] keeps between a query and its answer.
+pub struct KeywordSecret<S> {
+    secret: S,
+    found:  bool,
+}
+
+impl<P: IndexPir> KeywordPirServer<P> {
+    /// Keywords must be distinct.
+    pub fn new<K: AsRef<[u8]>>(pir: P, pairs: Vec<(K, Vec<u8>)>) -> Result<Self> {
+        let keys: Vec<&[u8]> = pairs.iter().map(|(k, _)| k.as_ref()).collect();
+        let index = build_index(&keys)?;
+        Ok(Self {
+            pir,
+            index,
+            records: pairs.into_iter().map(|(_, v)| v).collect(),
+        })
+    }
+
+    /// To be published, see [`KeywordPirClient::new`].
+    pub fn index(&self) -> &Encoding<OkvsValue<INDEX_V_SIZE>> {
+        &self.index
+    }
+
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+
+    pub fn answer(&self, query: &P::Query) -> Result<P::Answer> {
+        self.pir.answer(&self.records, query)
+    }
+}
+
+impl<P: IndexPir> KeywordPirClient<P> {
+    /// `index` and `records` as published by [`KeywordPirServer::index`] and
+    /// [`KeywordPirServer::len`].
+    pub fn new(pir: P, index: Encoding<OkvsValue<INDEX_V_SIZE>>, records: usize) -> Self {
+        Self {
+            pir,
+            index,
+            records,
+        }
+    }
+
+    /// Queries a random record for keywords that aren't indexed, so the
+    /// server can't tell hits from misses.
+    pub fn query(&self, key: &[u8]) -> Result<(P::Query, KeywordSecret<P::Secret>)> {
+        let (index, found) = match lookup_index(&self.index, key) {
+            Some(index) if index < self.records => (index, true),
+            _ => (OsRng.next_u64() as usize % self.records.max(1), false),
+        };
+        let (query, secret) = self.pir.query(index, self.records)?;
+        Ok((query, KeywordSecret { secret, found }))
+    }
+
+    /// The record of the queried keyword, `None` if it isn't indexed.
+    pub fn recover(
+        &self,
+        secret: KeywordSecret<P::Secret>,
+        answer: P::Answer,
+    ) -> Result<Option<Vec<u8>>> {
+        let record = self.pir.recover(secret.secret, answer)?;
+        Ok(secret.found.then_some(record))
+    }
+}
+
+fn check(key: &[u8]) -> Vec<u8> {
+    hash(&[CHECK_DOMAIN, key].concat(), INDEX_V_SIZE - 8)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_index() {
+        let keys: Vec<String> = (0..3000).map(|i| format!("key{i}")).collect();
+        let index = build_index(&keys).unwrap();
+        for (i, key) in keys.iter().enumerate() {
+            assert_eq!(lookup_index(&index, key.as_bytes()), Some(i));
+        }
+        assert_eq!(lookup_index(&index, b"key3000"), None);
+
+        let small = build_index(&["a", "b"]).unwrap();
+        assert_eq!(lookup_index(&small, b"b"), Some(1));
+        assert_eq!(lookup_index(&small, b"c"), None);
+    }
+
+    #[test]
+    fn test_keyword_pir() {
+        let pairs: Vec<(String, Vec<u8>)> = (0..100)
+            .map(|i| (format!("user{i}"), format!("record {i}").into_bytes()))
+            .collect();
+        let server = KeywordPirServer::new(TrivialPir, pairs).unwrap();
+        let client = KeywordPirClient::new(TrivialPir, server.index().clone(), server.len());
+
+        let (query, secret) = client.query(b"user42").unwrap();
+        let record = client
+            .recover(secret, server.answer(&query).unwrap())
+            .unwrap();
+        assert_eq!(record, Some(b"record 42".to_vec()));
+
+        let (query, secret) = client.query(b"user100").unwrap();
+        let record = client
+            .recover(secret, server.answer(&query).unwrap())
+            .unwrap();
+        assert_eq!(record, None);
+    }
+}
@@ -1,7 +1,56 @@
 #![feature(test)]
 
+// Lets `#[derive(EmmV)]` name `::rb_okvs` inside this crate too
+extern crate self as rb_okvs;
+
+pub mod aligned;
+#[cfg(feature = "analysis")]
+pub mod analysis;
+#[cfg(feature = "audit")]
+pub mod audit;
+pub mod band;
+pub mod cache;
+pub mod checked;
+pub mod cipher;
+pub mod circuit_psi;
+pub mod config;
+pub mod dyn_emm;
 pub mod emm;
+pub mod encoder;
 pub mod error;
+pub mod executor;
+pub mod external;
+pub mod fp_emm;
+#[cfg(feature = "gpu")]
+pub mod gpu;
+#[cfg(feature = "curve25519")]
+pub mod group;
+pub mod hasher;
+#[cfg(feature = "http")]
+pub mod http;
+pub mod incremental;
+pub mod kw_pir;
+pub mod merkle;
 pub mod okvs;
+pub mod opprf;
+mod oprf;
+pub mod params;
+pub mod perf;
+pub mod prf;
+#[cfg(feature = "proto")]
+pub mod proto;
+pub mod psi;
+pub mod reader;
+pub mod server;
+pub mod set_okvs;
+pub mod sharded;
+pub mod shares;
+pub mod solver;
+pub mod sparse;
+#[cfg(feature = "tokio")]
+pub mod tcp;
+#[cfg(feature = "testvectors")]
+pub mod testvectors;
+mod trace;
 pub mod types;
 mod utils;
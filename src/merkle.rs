@@ -0,0 +1,235 @@
+use sha2::{Digest, Sha256};
+
+use crate::error::{Error, Result};
+use crate::hasher::KeyHasher;
+use crate::okvs::RbOkvs;
+use crate::types::{Encoding, OkvsK, OkvsV};
+
+pub type Hash = [u8; 32];
+
+/// Hash of the columns past the end of the encoding, up to a power of two.
+const EMPTY_LEAF: Hash = [0u8; 32];
+
+/// An encoding with a Merkle tree over its columns. The encoder publishes
+/// [`AuthenticatedEncoding::root`]; a verifier holding only the root checks
+/// decodes with [`DecodeProof::verify`].
+#[derive(Clone, Debug)]
+pub struct AuthenticatedEncoding<V> {
+    encoding: Encoding<V>,
+    /// Leaves of the tree, the columns of the instance it was built for.
+    columns:  usize,
+    /// Leaf hashes first, the root last.
+    levels:   Vec<Vec<Hash>>,
+}
+
+/// The columns a decode touched, each with its Merkle path.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DecodeProof<V> {
+    pub columns: Vec<(usize, V)>,
+    pub paths:   Vec<Vec<Hash>>,
+}
+
+impl<V: OkvsV + AsRef<[u8]>> AuthenticatedEncoding<V> {
+    /// Builds the tree over the columns `okvs` decodes from, the first
+    /// `okvs.columns()` values of `encoding`, so it has the depth
+    /// [`DecodeProof::verify`] expects. Fails with
+    /// [`Error::EncodingTooShort`] when `encoding` has fewer values.
+    pub fn new<const W: usize, H: KeyHasher>(
+        okvs: &RbOkvs<W, H>,
+        encoding: Encoding<V>,
+    ) -> Result<Self> {
+        let columns = okvs.columns();
+        if encoding.len() < columns {
+            return Err(Error::EncodingTooShort {
+                required: columns,
+                actual: encoding.len(),
+            });
+        }
+        let mut leaves: Vec<Hash> = encoding[..columns]
+            .iter()
+            .map(|v| leaf(v.as_ref()))
+            .collect();
+        leaves.resize(columns.next_power_of_two(), EMPTY_LEAF);
+
+        let mut levels = vec![leaves];
+        while levels.last().unwrap().len() > 1 {
+            let next = levels
+                .last()
+                .unwrap()
+                .chunks_exact(2)
+                .map(|pair| node(&pair[0], &pair[1]))
+                .collect();
+            levels.push(next);
+        }
+        Ok(Self {
+            encoding,
+            columns,
+            levels,
+        })
+    }
+
+    pub fn root(&self) -> Hash {
+        self.levels.last().unwrap()[0]
+    }
+
+    pub fn encoding(&self) -> &Encoding<V> {
+        &self.encoding
+    }
+
+    /// Decodes `key` along with the proof of the columns it touched. Fails
+    /// with [`Error::ParamsMismatch`] unless `okvs` has the columns the tree
+    /// was built for.
+    pub fn decode_with_proof<const W: usize, H: KeyHasher>(
+        &self,
+        okvs: &RbOkvs<W, H>,
+        key: &impl OkvsK,
+    ) -> Result<(V, DecodeProof<V>)> {
+        if okvs.columns() != self.columns {
+            return Err(Error::ParamsMismatch("columns"));
+        }
+        let (start, band) = okvs.locate(key);
+        let mut value = V::default();
+        let mut proof = DecodeProof {
+            columns: vec![],
+            paths: vec![],
+        };
+        band.for_each_one(|i| {
+            let column = start + i;
+            value.in_place_xor(&self.encoding[column]);
+            proof.columns.push((column, self.encoding[column].clone()));
+            proof.paths.push(self.path(column));
+        });
+        Ok((value, proof))
+    }
+
+    /// Sibling hashes from the leaf of `column` up to the root.
+    fn path(&self, column: usize) -> Vec<Hash> {
+        let levels = &self.levels[..self.levels.len() - 1];
+        levels
+            .iter()
+            .enumerate()
+            .map(|(depth, level)| level[(column >> depth) ^ 1])
+            .collect()
+    }
+}
+
+impl<V: OkvsV + AsRef<[u8]>> DecodeProof<V> {
+    /// The value `key` decodes to in the encoding committed to by `root`,
+    /// failing with [`Error::InvalidProof`] at the first column that is not
+    /// one `key` touches or does not hash to `root`.
+    pub fn verify<const W: usize, H: KeyHasher>(
+        &self,
+        okvs: &RbOkvs<W, H>,
+        root: &Hash,
+        key: &impl OkvsK,
+    ) -> Result<V> {
+        let (start, band) = okvs.locate(key);
+        let mut expected = vec![];
+        band.for_each_one(|i| expected.push(start + i));
+        if self.columns.len() != expected.len() || self.paths.len() != expected.len() {
+            return Err(Error::InvalidProof(start));
+        }
+
+        let depth = okvs.columns().next_power_of_two().trailing_zeros() as usize;
+        let mut value = V::default();
+        for (((column, v), path), expected) in self.columns.iter().zip(&self.paths).zip(expected) {
+            if *column != expected || path.len() != depth {
+                return Err(Error::InvalidProof(expected));
+            }
+            let mut hash = leaf(v.as_ref());
+            for (d, sibling) in path.iter().enumerate() {
+                hash = match (column >> d) & 1 {
+                    0 => node(&hash, sibling),
+                    _ => node(sibling, &hash),
+                };
+            }
+            if hash != *root {
+                return Err(Error::InvalidProof(expected));
+            }
+            value.in_place_xor(v);
+        }
+        Ok(value)
+    }
+}
+
+fn leaf(bytes: &[u8]) -> Hash {
+    Sha256::new()
+        .chain_update([0u8])
+        .chain_update(bytes)
+        .finalize()
+        .into()
+}
+
+fn node(left: &Hash, right: &Hash) -> Hash {
+    Sha256::new()
+        .chain_update([1u8])
+        .chain_update(left)
+        .chain_update(right)
+        .finalize()
+        .into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{Okvs, OkvsKey, OkvsValue, Pair};
+
+    #[test]
+    fn test_decode_with_proof() {
+        let pairs: Vec<Pair<OkvsKey, OkvsValue<8>>> = (0..1000u64)
+            .map(|i| (OkvsKey(i.to_le_bytes()), OkvsValue((i * 7).to_le_bytes())))
+            .collect();
+        let okvs = RbOkvs::new(pairs.len());
+        let authenticated =
+            AuthenticatedEncoding::new(&okvs, okvs.encode_ref(&pairs).unwrap()).unwrap();
+        let root = authenticated.root();
+
+        for (k, v) in pairs.iter().take(50) {
+            let (value, proof) = authenticated.decode_with_proof(&okvs, k).unwrap();
+            assert_eq!(value, *v);
+            assert_eq!(proof.verify(&okvs, &root, k).unwrap(), *v);
+        }
+
+        let (k, _) = &pairs[0];
+        let (_, proof) = authenticated.decode_with_proof(&okvs, k).unwrap();
+
+        let mut tampered = proof.clone();
+        tampered.columns[0].1 .0[0] ^= 1;
+        assert!(tampered.verify(&okvs, &root, k).is_err());
+
+        let mut truncated = proof.clone();
+        truncated.columns.pop();
+        assert!(truncated.verify(&okvs, &root, k).is_err());
+
+        assert!(proof.verify(&okvs, &root, &pairs[1].0).is_err());
+        assert!(proof.verify(&okvs, &[0u8; 32], k).is_err());
+
+        assert!(matches!(
+            AuthenticatedEncoding::new(&okvs, vec![OkvsValue([0u8; 8]); 100]),
+            Err(Error::EncodingTooShort { actual: 100, .. })
+        ));
+        assert!(matches!(
+            authenticated.decode_with_proof(&RbOkvs::new(500), k),
+            Err(Error::ParamsMismatch("columns"))
+        ));
+    }
+
+    #[test]
+    fn test_trailing_values() {
+        let pairs: Vec<Pair<OkvsKey, OkvsValue<8>>> = (0..1000u64)
+            .map(|i| (OkvsKey(i.to_le_bytes()), OkvsValue(i.to_le_bytes())))
+            .collect();
+        let okvs = RbOkvs::new(pairs.len());
+        // Past the next power of two of the columns, which would deepen a
+        // tree over every value
+        let mut encoding = okvs.encode_ref(&pairs).unwrap();
+        encoding.resize(2100, OkvsValue([0u8; 8]));
+        let authenticated = AuthenticatedEncoding::new(&okvs, encoding).unwrap();
+
+        let root = authenticated.root();
+        for (k, v) in pairs.iter().take(20) {
+            let (_, proof) = authenticated.decode_with_proof(&okvs, k).unwrap();
+            assert_eq!(proof.verify(&okvs, &root, k).unwrap(), *v);
+        }
+    }
+}
@@ -1,7 +1,18 @@
-use sp_core::U256;
+use std::fmt;
+use std::iter;
+use std::mem;
 
-use crate::error::Result;
-use crate::types::{Encoding, Okvs, OkvsK, OkvsV, Pair};
+use aes_gcm::aead::rand_core::RngCore;
+
+use crate::band::Band;
+use crate::error::{Error, Result};
+use crate::executor::Executor;
+use crate::hasher::{Blake2Hasher, KeyHasher};
+use crate::params::{estimated_failure_prob, OkvsParams};
+pub use crate::solver::solve_in_place;
+use crate::solver::{gauss_eliminate, gauss_with_free, inner_product, par_simple_gauss, solve_row};
+use crate::trace::{trace_event, trace_span};
+use crate::types::{Encoding, EncodingSink, Okvs, OkvsK, OkvsV, Pair, RandomV, Seed};
 use crate::utils::*;
 
 /// For small encoding sizes (i.e., high rate), one should try to fix small
@@ -11,87 +22,822 @@ use crate::utils::*;
 const EPSILON: f64 = 0.1;
 const _LAMBDA: usize = 20;
 const BAND_WIDTH: usize = 128; // ((LAMBDA as f64 + 15.21) / 0.2691) as usize = 130
+/// Columns beyond the row count of instances too small for their band, whose
+/// rows are then nearly dense and fail with probability about
+/// `2^-MIN_SLACK`.
+const MIN_SLACK: usize = 40;
+/// Multiple of columns of instances built with
+/// [`RbOkvsBuilder::pad_columns`], so encodings split into whole 64-value
+/// blocks.
+const COLUMN_ALIGN: usize = 64;
+
+/// Version of the encoding layout, bumped on incompatible changes.
+/// 2: domain-separated, seeded key hashes.
+pub const FORMAT_VERSION: u8 = 2;
+
+/// Short digest identifying an OKVS instance, see [`RbOkvs::fingerprint`].
+pub type Fingerprint = [u8; 8];
 
 /// RB-OKVS, Oblivious Key-Value Stores
-pub struct RbOkvs {
-    columns:    usize,
+///
+/// Bands are held in `W` 64-bit words, which bounds the band width to
+/// `64 * W` bits. The default fits any band width the builder accepts; a
+/// smaller `W` shortens the loops scanning bands, see
+/// [`RbOkvs::fixed_width`]. Instances of equal parameters produce and decode
+/// the same encodings whatever their `W`.
+///
+/// Keys are hashed with `H` under a seed, see [`RbOkvs::with_hasher`] and
+/// [`RbOkvs::with_seed`].
+#[derive(Clone, Debug)]
+pub struct RbOkvs<const W: usize = 4, H = Blake2Hasher> {
+    columns:         usize,
+    logical_columns: usize,
+    band_width:      usize,
+    epsilon:         f64,
+    verify:          bool,
+    seed:            Seed,
+    hasher:          H,
+}
+
+/// Parameters of an [`RbOkvs`], see [`RbOkvs::describe`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct OkvsDescription {
+    pub columns:                usize,
+    pub logical_columns:        usize,
+    pub band_width:             usize,
+    pub epsilon:                f64,
+    pub estimated_failure_prob: Option<f64>,
+    pub fingerprint:            Fingerprint,
+}
+
+/// Expected heap bytes of an encode, see [`RbOkvs::estimate_peak_memory`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MemoryEstimate {
+    /// Bands and start positions of the rows.
+    pub matrix: usize,
+    /// Values of the rows.
+    pub values: usize,
+    /// The encoding.
+    pub output: usize,
+    /// Most bytes held at once, when the output or the counting sort's
+    /// scratch is live next to the rows.
+    pub peak:   usize,
+}
+
+/// The temporary buffers of an encode, reused across encodes by
+/// [`RbOkvs::encode_with_scratch`].
+#[derive(Clone, Debug)]
+pub struct EncoderScratch<V, const W: usize = 4> {
+    starts:    Vec<usize>,
+    slots:     Vec<usize>,
+    bands:     Vec<Band<W>>,
+    start_pos: Vec<usize>,
+    y:         Vec<V>,
+}
+
+impl<V, const W: usize> EncoderScratch<V, W> {
+    pub fn new() -> Self {
+        Self {
+            starts: vec![],
+            slots: vec![],
+            bands: vec![],
+            start_pos: vec![],
+            y: vec![],
+        }
+    }
+
+    /// Bytes the buffers hold on to.
+    pub fn capacity_bytes(&self) -> usize {
+        let word = mem::size_of::<usize>();
+        (self.starts.capacity() + self.slots.capacity() + self.start_pos.capacity()) * word
+            + self.bands.capacity() * mem::size_of::<Band<W>>()
+            + self.y.capacity() * mem::size_of::<V>()
+    }
+}
+
+impl<V, const W: usize> Default for EncoderScratch<V, W> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// An encoding system after forward elimination, waiting for back
+/// substitution.
+pub struct Eliminated<V, const W: usize = 4> {
+    bands:     Vec<Band<W>>,
+    start_pos: Vec<usize>,
+    y:         Vec<V>,
+    pivot:     Vec<usize>,
+    columns:   usize,
+}
+
+/// Builder for an [`RbOkvs`] with non-default parameters.
+#[derive(Clone, Debug)]
+pub struct RbOkvsBuilder {
+    epsilon:    f64,
     band_width: usize,
+    fallbacks:  Vec<(f64, usize)>,
+    verify:     bool,
+    seed:       Seed,
+    pad:        bool,
 }
 
 impl RbOkvs {
+    /// An instance for `kv_count` pairs, which may be zero. Fewer pairs than
+    /// the band width get 40 spare columns instead of `epsilon`.
     pub fn new(kv_count: usize) -> RbOkvs {
-        let columns = ((1.0 + EPSILON) * kv_count as f64) as usize;
+        Self::with_params(kv_count, EPSILON, BAND_WIDTH, 1)
+    }
 
+    pub fn builder() -> RbOkvsBuilder {
+        RbOkvsBuilder::default()
+    }
+
+    /// The default instance whose encodings have `columns` values, to decode
+    /// an encoding of unknown row count.
+    pub(crate) fn for_columns(columns: usize) -> RbOkvs {
+        Self::with_columns(columns, EPSILON, BAND_WIDTH)
+    }
+}
+
+impl<const W: usize> RbOkvs<W> {
+    /// An instance whose band width is the compile-time constant `64 * W`,
+    /// e.g. `RbOkvs::<2>::fixed_width(kv_count)` for the default width of 128.
+    pub fn fixed_width(kv_count: usize) -> Self {
+        Self::with_params(kv_count, EPSILON, Band::<W>::BITS, 1)
+    }
+
+    /// The instance with exactly these parameters, to decode an encoding
+    /// that carries them, see [`CheckedEncoding`](crate::checked::CheckedEncoding).
+    /// `epsilon` doesn't change the encodings, only what the instance
+    /// reports, e.g. [`RbOkvs::failure_prob`].
+    pub fn from_params(
+        columns: usize,
+        band_width: usize,
+        epsilon: f64,
+        seed: Seed,
+    ) -> Result<Self> {
+        if band_width == 0 || band_width > Band::<W>::BITS || band_width >= columns {
+            return Err(Error::Config(format!(
+                "band width {band_width} for {columns} columns, at most {} bits",
+                Band::<W>::BITS
+            )));
+        }
+        if !epsilon.is_finite() || epsilon <= 0.0 {
+            return Err(Error::Config(format!(
+                "epsilon must be positive, got {epsilon}"
+            )));
+        }
+        Ok(Self::with_columns(columns, epsilon, band_width).with_seed(seed))
+    }
+
+    /// Columns for `kv_count` rows, rounded up to a multiple of `align`.
+    fn with_params(kv_count: usize, epsilon: f64, band_width: usize, align: usize) -> Self {
+        let mut columns = ((1.0 + epsilon) * kv_count as f64) as usize;
+        if columns <= band_width {
+            // Down to zero rows, rather than overhead too small to solve
+            columns = columns.max(kv_count + MIN_SLACK);
+        }
+        let mut okvs = Self::with_columns(columns.next_multiple_of(align), epsilon, band_width);
+        okvs.logical_columns = columns;
+        okvs
+    }
+
+    fn with_columns(columns: usize, epsilon: f64, band_width: usize) -> Self {
         Self {
             columns,
-            band_width: if BAND_WIDTH < columns {
-                BAND_WIDTH
+            logical_columns: columns,
+            band_width: if band_width < columns {
+                band_width
             } else {
                 columns * 80 / 100
             },
+            epsilon,
+            verify: false,
+            seed: [0u8; 16],
+            hasher: Blake2Hasher,
+        }
+    }
+}
+
+impl<const W: usize, H: KeyHasher> RbOkvs<W, H> {
+    /// The same instance hashing keys with `hasher`, e.g. an [`AesHasher`]
+    /// for faster encoding.
+    ///
+    /// [`AesHasher`]: crate::hasher::AesHasher
+    pub fn with_hasher<H2: KeyHasher>(self, hasher: H2) -> RbOkvs<W, H2> {
+        RbOkvs {
+            columns: self.columns,
+            logical_columns: self.logical_columns,
+            band_width: self.band_width,
+            epsilon: self.epsilon,
+            verify: self.verify,
+            seed: self.seed,
+            hasher,
+        }
+    }
+
+    /// The same instance with its key hashes salted by `seed`. Encoder and
+    /// decoder must agree on it; a fresh seed per encoding makes its bands
+    /// independent of every other encoding of the same keys.
+    pub fn with_seed(mut self, seed: Seed) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    pub fn seed(&self) -> &Seed {
+        &self.seed
+    }
+
+    /// Start position and band of `key` in this instance, the two hashes a
+    /// decode needs, see [`RbOkvs::decode_with`].
+    pub fn locate<K: OkvsK>(&self, key: &K) -> (usize, Band<W>) {
+        (
+            self.hasher
+                .hash_to_index(&self.seed, key, self.columns - self.band_width),
+            self.hasher.hash_to_band(&self.seed, key, self.band_width),
+        )
+    }
+
+    /// Digest of the parameters, hash backend and format version. Two
+    /// instances with equal fingerprints produce and decode the same
+    /// encodings.
+    pub fn fingerprint(&self) -> Fingerprint {
+        let mut data = vec![FORMAT_VERSION];
+        data.extend_from_slice(self.hasher.name().as_bytes());
+        data.extend_from_slice(&self.seed);
+        data.extend_from_slice(&(self.columns as u64).to_le_bytes());
+        data.extend_from_slice(&(self.band_width as u64).to_le_bytes());
+        blake2b::<8>(&data)
+    }
+
+    /// Number of values in the encodings of this instance.
+    pub fn columns(&self) -> usize {
+        self.columns
+    }
+
+    /// Columns the row count called for, before
+    /// [`pad_columns`](RbOkvsBuilder::pad_columns) rounded them up.
+    pub fn logical_columns(&self) -> usize {
+        self.logical_columns
+    }
+
+    /// Width of the random band of every row.
+    pub fn band_width(&self) -> usize {
+        self.band_width
+    }
+
+    pub fn epsilon(&self) -> f64 {
+        self.epsilon
+    }
+
+    /// In-memory size of an encoding of `V` values.
+    pub fn expected_size_bytes<V>(&self) -> usize {
+        self.columns * mem::size_of::<V>()
+    }
+
+    /// Heap use of encoding `n` pairs of `V` values, not counting the input.
+    pub fn estimate_peak_memory<V>(&self, n: usize) -> MemoryEstimate {
+        let word = mem::size_of::<usize>();
+        let matrix = n.saturating_mul(mem::size_of::<Band<W>>() + word);
+        let values = n.saturating_mul(mem::size_of::<V>());
+        let output = self.expected_size_bytes::<V>();
+        // Start of every row and a slot per start position
+        let sort = (n + self.columns - self.band_width + 1).saturating_mul(word);
+        MemoryEstimate {
+            matrix,
+            values,
+            output,
+            peak: matrix
+                .saturating_add(values)
+                .saturating_add(output.max(sort)),
+        }
+    }
+
+    /// The parameters of this instance, for logging and validation.
+    pub fn describe(&self) -> OkvsDescription {
+        OkvsDescription {
+            columns: self.columns,
+            logical_columns: self.logical_columns,
+            band_width: self.band_width,
+            epsilon: self.epsilon,
+            estimated_failure_prob: self.estimated_failure_prob(),
+            fingerprint: self.fingerprint(),
         }
     }
+
+    /// Failure probability of `encode` predicted by the paper's fit for the
+    /// band width, or `None` when epsilon is below the range the fit covers.
+    pub fn estimated_failure_prob(&self) -> Option<f64> {
+        estimated_failure_prob(self.epsilon, self.band_width)
+    }
 }
 
-impl Okvs for RbOkvs {
+impl fmt::Display for OkvsDescription {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "rb-okvs columns={} band_width={} epsilon={}",
+            self.columns, self.band_width, self.epsilon
+        )?;
+        if self.logical_columns != self.columns {
+            write!(f, " logical_columns={}", self.logical_columns)?;
+        }
+        if let Some(p) = self.estimated_failure_prob {
+            write!(f, " failure_prob={p:.1e}")?;
+        }
+        write!(f, " fingerprint=")?;
+        for b in self.fingerprint {
+            write!(f, "{b:02x}")?;
+        }
+        Ok(())
+    }
+}
+
+impl Default for RbOkvsBuilder {
+    fn default() -> Self {
+        Self {
+            epsilon: EPSILON,
+            band_width: BAND_WIDTH,
+            fallbacks: vec![],
+            verify: false,
+            seed: [0u8; 16],
+            pad: false,
+        }
+    }
+}
+
+impl RbOkvsBuilder {
+    /// Column overhead: the encoding has `(1 + epsilon) * kv_count` columns.
+    pub fn epsilon(mut self, epsilon: f64) -> Self {
+        self.epsilon = epsilon;
+        self
+    }
+
+    /// Number of bits in each row's random band, in `[1, 256]`. Narrower
+    /// instances take `0.8 * columns` instead.
+    pub fn band_width(mut self, band_width: usize) -> Self {
+        self.band_width = band_width;
+        self
+    }
+
+    /// Makes every encode decode its input again and fail with
+    /// [`Error::VerificationFailed`] on a mismatch, see
+    /// [`RbOkvs::encode_verified`].
+    pub fn verify(mut self, verify: bool) -> Self {
+        self.verify = verify;
+        self
+    }
+
+    /// See [`RbOkvs::with_seed`].
+    pub fn seed(mut self, seed: Seed) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    /// Rounds columns up to a multiple of 64, so decoders working on whole
+    /// blocks of the encoding have no ragged tail. Padding columns are
+    /// ordinary columns: bands start anywhere in the padded range. See
+    /// [`AlignedEncoding`](crate::aligned::AlignedEncoding) for a buffer
+    /// aligned to match.
+    ///
+    /// Decoders need the padded column count, which the [`OkvsParams`] of
+    /// [`encode_with_fallback`](Self::encode_with_fallback) record.
+    pub fn pad_columns(mut self, pad: bool) -> Self {
+        self.pad = pad;
+        self
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if !self.epsilon.is_finite() || self.epsilon <= 0.0 {
+            return Err(Error::Config(format!(
+                "epsilon must be positive, got {}",
+                self.epsilon
+            )));
+        }
+        if self.band_width == 0 || self.band_width > 256 {
+            return Err(Error::Config(format!(
+                "band width must be in [1, 256], got {}",
+                self.band_width
+            )));
+        }
+        Ok(())
+    }
+
+    /// Adds parameters for [`encode_with_fallback`](Self::encode_with_fallback)
+    /// to retry with, tried in the order they were added.
+    pub fn fallback(mut self, epsilon: f64, band_width: usize) -> Self {
+        self.fallbacks.push((epsilon, band_width));
+        self
+    }
+
+    pub fn build(&self, kv_count: usize) -> Result<RbOkvs> {
+        self.validate()?;
+        let align = if self.pad { COLUMN_ALIGN } else { 1 };
+        let mut okvs = RbOkvs::with_params(kv_count, self.epsilon, self.band_width, align);
+        okvs.verify = self.verify;
+        Ok(okvs.with_seed(self.seed))
+    }
+
+    /// Encodes `input`, moving on to the next fallback parameters whenever
+    /// elimination fails. Returns the parameters that succeeded, which
+    /// decoders need to rebuild the instance.
+    pub fn encode_with_fallback<K: OkvsK, V: OkvsV>(
+        &self,
+        input: &[Pair<K, V>],
+    ) -> Result<(OkvsParams, Encoding<V>)> {
+        let steps = iter::once((self.epsilon, self.band_width)).chain(self.fallbacks.clone());
+
+        let mut last_err = None;
+        for (epsilon, band_width) in steps {
+            let params = OkvsParams {
+                kv_count: input.len(),
+                epsilon,
+                band_width,
+                pad_columns: self.pad,
+            };
+            let okvs = RbOkvsBuilder {
+                epsilon,
+                band_width,
+                ..self.clone()
+            }
+            .build(input.len())?;
+            match okvs.encode_ref(input) {
+                Ok(encoding) => return Ok((params, encoding)),
+                Err(err @ (Error::Unsolvable { .. } | Error::CollidingKeys(..))) => {
+                    trace_event!(
+                        "encode failed, falling back",
+                        epsilon = epsilon,
+                        band_width = band_width
+                    );
+                    last_err = Some(err)
+                }
+                Err(err) => return Err(err),
+            }
+        }
+        Err(last_err.unwrap())
+    }
+}
+
+impl<const W: usize, H: KeyHasher> Okvs for RbOkvs<W, H> {
     fn encode<K: OkvsK, V: OkvsV>(&self, input: Vec<Pair<K, V>>) -> Result<Encoding<V>> {
+        self.encode_ref(&input)
+    }
+
+    fn encode_ref<K: OkvsK, V: OkvsV>(&self, input: &[Pair<K, V>]) -> Result<Encoding<V>> {
+        let mut encoding = vec![];
+        self.encode_into(input, &mut encoding)?;
+        Ok(encoding)
+    }
+
+    fn encode_into<K: OkvsK, V: OkvsV>(
+        &self,
+        input: &[Pair<K, V>],
+        out: &mut Vec<V>,
+    ) -> Result<()> {
+        self.encode_with_scratch(input, &mut EncoderScratch::new(), out)
+    }
+
+    fn encode_with_rng<K, V, R>(&self, input: &[Pair<K, V>], rng: &mut R) -> Result<Encoding<V>>
+    where
+        K: OkvsK,
+        V: RandomV,
+        R: RngCore + ?Sized,
+    {
+        trace_span!("encode", rows = input.len(), columns = self.columns);
         let (matrix, start_pos, y) = self.create_sorted_matrix(input)?;
-        simple_gauss::<V>(y, matrix, start_pos, self.columns)
+        let x = (0..self.columns).map(|_| V::random(rng)).collect();
+        let encoding =
+            gauss_with_free(y, matrix, start_pos, x).map_err(|err| self.unsolvable(input, err))?;
+        if self.verify {
+            self.check(input, &encoding)?;
+        }
+        Ok(encoding)
+    }
+
+    fn decode<V: OkvsV>(&self, encoding: &Encoding<V>, key: &impl OkvsK) -> Result<V> {
+        if encoding.len() < self.columns {
+            return Err(Error::EncodingTooShort {
+                required: self.columns,
+                actual: encoding.len(),
+            });
+        }
+        let (start, band) = self.locate(key);
+        self.decode_with(start, &band, encoding)
     }
 
-    fn decode<V: OkvsV>(&self, encoding: &Encoding<V>, key: &impl OkvsK) -> V {
-        let start = key.hash_to_index(self.columns - self.band_width);
-        let band = key.hash_to_band(self.band_width);
-        inner_product(&band, &encoding[start..])
+    fn fingerprint(&self) -> Fingerprint {
+        RbOkvs::fingerprint(self)
     }
 }
 
-impl RbOkvs {
-    fn create_sorted_matrix<K: OkvsK, V: OkvsV>(
+impl<const W: usize, H: KeyHasher> RbOkvs<W, H> {
+    /// Decodes at a start position and band computed elsewhere, e.g. by
+    /// [`RbOkvs::locate`] ahead of time or by another implementation of the
+    /// hashes. Fails with [`Error::EncodingTooShort`] when the band reaches
+    /// past the end of `encoding`.
+    pub fn decode_with<V: OkvsV>(&self, start: usize, band: &Band<W>, encoding: &[V]) -> Result<V> {
+        let required = start.saturating_add(self.band_width);
+        if encoding.len() < required {
+            return Err(Error::EncodingTooShort {
+                required,
+                actual: encoding.len(),
+            });
+        }
+        Ok(inner_product(band, &encoding[start..required]))
+    }
+
+    /// [`Okvs::encode_into`] with the temporary buffers of the encode taken
+    /// from `scratch` and left there, so repeated encodes of similar size
+    /// allocate only on the first one.
+    pub fn encode_with_scratch<K: OkvsK, V: OkvsV>(
+        &self,
+        input: &[Pair<K, V>],
+        scratch: &mut EncoderScratch<V, W>,
+        out: &mut Vec<V>,
+    ) -> Result<()> {
+        trace_span!("encode", rows = input.len(), columns = self.columns);
+        self.fill_sorted_matrix(input, scratch);
+        out.resize(self.columns, V::default());
+        solve_in_place(&mut scratch.bands, &scratch.start_pos, &mut scratch.y, out)
+            .map_err(|err| self.unsolvable(input, err))?;
+        if self.verify {
+            self.check(input, out)?;
+        }
+        Ok(())
+    }
+
+    /// Encodes `input` and checks every key decodes to its value, failing
+    /// with [`Error::VerificationFailed`] otherwise. Costs a decode per pair.
+    pub fn encode_verified<K: OkvsK, V: OkvsV>(&self, input: &[Pair<K, V>]) -> Result<Encoding<V>> {
+        let encoding = self.encode_ref(input)?;
+        if !self.verify {
+            self.check(input, &encoding)?;
+        }
+        Ok(encoding)
+    }
+
+    /// Adds a uniformly random solution of the homogeneous system of `keys`
+    /// to `encoding`, so every key still decodes to the same value but the
+    /// refreshed encoding is unlinkable to the old one beyond that.
+    ///
+    /// The solution is `r ^ s`, where `r` is random and `s` the encoding of
+    /// `keys` to their values under `r`. Costs an encode. Fails with
+    /// [`Error::ParamsMismatch`] unless `encoding` has exactly the columns
+    /// of this instance.
+    pub fn rerandomize<K, V, R>(
+        &self,
+        keys: &[K],
+        encoding: &mut Encoding<V>,
+        rng: &mut R,
+    ) -> Result<()>
+    where
+        K: OkvsK,
+        V: RandomV,
+        R: RngCore + ?Sized,
+    {
+        if encoding.len() != self.columns {
+            return Err(Error::ParamsMismatch("columns"));
+        }
+        let r: Encoding<V> = (0..self.columns).map(|_| V::random(rng)).collect();
+        let pairs = keys
+            .iter()
+            .map(|k| Ok((k, self.decode(&r, k)?)))
+            .collect::<Result<Vec<_>>>()?;
+        let s = self.encode_ref(&pairs)?;
+        for ((e, r), s) in encoding.iter_mut().zip(&r).zip(&s) {
+            e.in_place_xor(r);
+            e.in_place_xor(s);
+        }
+        Ok(())
+    }
+
+    fn check<K: OkvsK, V: OkvsV>(
+        &self,
+        input: &[Pair<K, V>],
+        encoding: &Encoding<V>,
+    ) -> Result<()> {
+        for (i, (k, v)) in input.iter().enumerate() {
+            if !self.decode(encoding, k)?.xor(v).is_zero() {
+                return Err(Error::VerificationFailed(i));
+            }
+        }
+        Ok(())
+    }
+
+    /// Runs the forward elimination of `encode`, leaving back substitution to
+    /// [`Eliminated::finalize`] or [`Eliminated::finalize_streaming`].
+    pub fn eliminate<K: OkvsK, V: OkvsV>(
         &self,
         input: Vec<Pair<K, V>>,
-    ) -> Result<(Vec<U256>, Vec<usize>, Vec<V>)> {
+    ) -> Result<Eliminated<V, W>> {
+        let (mut bands, start_pos, mut y) = self.create_sorted_matrix(&input)?;
+        let pivot = gauss_eliminate(&mut y, &mut bands, &start_pos)
+            .map_err(|err| self.unsolvable(&input, err))?;
+        Ok(Eliminated {
+            bands,
+            start_pos,
+            y,
+            pivot,
+            columns: self.columns,
+        })
+    }
+
+    /// Encodes with the two halves of the system solved concurrently on
+    /// `executor`, see [`par_simple_gauss`].
+    pub fn par_encode<K, V, E>(&self, executor: &E, input: &[Pair<K, V>]) -> Result<Encoding<V>>
+    where
+        K: OkvsK,
+        V: OkvsV + Send + Sync,
+        E: Executor,
+    {
+        trace_span!("par_encode", rows = input.len(), columns = self.columns);
+        let (matrix, start_pos, y) = self.create_sorted_matrix(input)?;
+        let encoding = par_simple_gauss(
+            executor,
+            y,
+            matrix,
+            start_pos,
+            self.columns,
+            self.band_width,
+        )
+        .map_err(|err| self.unsolvable(input, err))?;
+        if self.verify {
+            self.check(input, &encoding)?;
+        }
+        Ok(encoding)
+    }
+
+    /// Traces a [`Error::ZeroRow`] of the rows of `input` in start order back
+    /// to its pair, as [`Error::CollidingKeys`] if another pair has the same
+    /// row and [`Error::Unsolvable`] otherwise. Rehashes the keys, so costs
+    /// about as much as the sort, on failure only.
+    pub(crate) fn unsolvable<K: OkvsK, V>(&self, input: &[Pair<K, V>], err: Error) -> Error {
+        let Error::ZeroRow(row) = err else {
+            return err;
+        };
+        let range = self.columns - self.band_width;
+        let starts: Vec<usize> = input
+            .iter()
+            .map(|(k, _)| self.hasher.hash_to_index(&self.seed, k, range))
+            .collect();
+        // Stable like the counting sort, so ties keep input order
+        let mut order: Vec<usize> = (0..input.len()).collect();
+        order.sort_by_key(|&i| starts[i]);
+        let index = order[row];
+
+        let band = |i: usize| -> Band<W> {
+            self.hasher
+                .hash_to_band(&self.seed, &input[i].0, self.band_width)
+        };
+        let row_band = band(index);
+        if let Some(other) = (0..input.len())
+            .find(|&i| i != index && starts[i] == starts[index] && band(i) == row_band)
+        {
+            return Error::CollidingKeys(other.min(index), other.max(index));
+        }
+        Error::Unsolvable {
+            index,
+            start: starts[index],
+            key_hash: u64::from_le_bytes(blake2b::<8>(&input[index].0.to_bytes())),
+            eliminated: row,
+        }
+    }
+
+    pub(crate) fn create_sorted_matrix<K: OkvsK, V: OkvsV>(
+        &self,
+        input: &[Pair<K, V>],
+    ) -> Result<(Vec<Band<W>>, Vec<usize>, Vec<V>)> {
+        let mut scratch = EncoderScratch::new();
+        self.fill_sorted_matrix(input, &mut scratch);
+        Ok((scratch.bands, scratch.start_pos, scratch.y))
+    }
+
+    /// Hashes `input` into rows sorted by start position, in the buffers of
+    /// `scratch`.
+    fn fill_sorted_matrix<K: OkvsK, V: OkvsV>(
+        &self,
+        input: &[Pair<K, V>],
+        scratch: &mut EncoderScratch<V, W>,
+    ) {
         let n = input.len();
-        let mut start_pos: Vec<(usize, usize)> = vec![(0, 0); n];
+        let range = self.columns - self.band_width;
+        let EncoderScratch {
+            starts,
+            slots,
+            bands,
+            start_pos,
+            y,
+        } = scratch;
+        // Counting sort by start position: `slots[s]` is the next row of
+        // start `s`, filled in input order so ties keep their order.
+        slots.clear();
+        slots.resize(range + 1, 0);
 
-        input.iter().enumerate().for_each(|(i, (k, _))| {
-            start_pos[i] = (i, k.hash_to_index(self.columns - self.band_width))
-        });
+        {
+            trace_span!("hash_index", rows = n);
+            starts.clear();
+            starts.extend(
+                input
+                    .iter()
+                    .map(|(k, _)| self.hasher.hash_to_index(&self.seed, k, range)),
+            );
+            starts.iter().for_each(|s| slots[s + 1] += 1);
+        }
 
-        radix_sort(&mut start_pos, self.columns - self.band_width - 1);
+        {
+            trace_span!("sort", rows = n);
+            for s in 1..slots.len() {
+                slots[s] += slots[s - 1];
+            }
+        }
 
-        let mut matrix: Vec<U256> = vec![U256::default(); n];
-        let mut start_ids: Vec<usize> = vec![0; n];
-        let mut y: Vec<V> = vec![V::default(); n];
+        bands.clear();
+        bands.resize(n, Band([0; W]));
+        start_pos.clear();
+        start_pos.resize(n, 0);
+        y.clear();
+        y.resize(n, V::default());
 
-        // Generate binary matrix
-        start_pos
-            .into_iter()
-            .enumerate()
-            .for_each(|(k, (i, start))| {
-                matrix[k] = input[i].0.hash_to_band(self.band_width);
-                y[k] = input[i].1.to_owned();
-                start_ids[k] = start;
-            });
+        // Hash every band and copy every value once, straight into its row
+        trace_span!("hash_band", rows = n);
+        for ((k, v), &start) in input.iter().zip(starts.iter()) {
+            let row = slots[start];
+            slots[start] += 1;
+            bands[row] = self.hasher.hash_to_band(&self.seed, k, self.band_width);
+            start_pos[row] = start;
+            y[row] = v.clone();
+        }
+    }
+}
+
+impl<V: OkvsV, const W: usize> Eliminated<V, W> {
+    pub fn finalize(self) -> Encoding<V> {
+        let mut x = vec![V::default(); self.columns];
+        for i in (0..self.bands.len()).rev() {
+            x[self.pivot[i]] = self.back_substitute(i, &x);
+        }
+        x
+    }
+
+    /// Back substitution that hands every column to `sink` as soon as it is
+    /// final, so the encoding can be shipped while the remaining columns are
+    /// still being solved. Columns arrive in decreasing order.
+    pub fn finalize_streaming<S: EncodingSink<V>>(self, sink: &mut S) -> Result<()> {
+        let rows = self.bands.len();
+
+        // Columns above the largest pivot of rows 0..i are final once row i
+        // is solved.
+        let mut max_pivot = Vec::with_capacity(rows);
+        let mut max = 0;
+        for p in &self.pivot {
+            max = max.max(*p + 1);
+            max_pivot.push(max);
+        }
+
+        let mut x = vec![V::default(); self.columns];
+        let mut next = self.columns;
+        for i in (0..rows).rev() {
+            x[self.pivot[i]] = self.back_substitute(i, &x);
+
+            let done = if i == 0 { 0 } else { max_pivot[i - 1] };
+            while next > done {
+                next -= 1;
+                sink.put(next, &x[next])?;
+            }
+        }
+        while next > 0 {
+            next -= 1;
+            sink.put(next, &x[next])?;
+        }
+        Ok(())
+    }
 
-        Ok((matrix, start_ids, y))
+    fn back_substitute(&self, i: usize, x: &[V]) -> V {
+        solve_row(&self.bands[i], &x[self.start_pos[i]..], &self.y[i])
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::collections::{BTreeMap, HashMap};
+
+    use aes_gcm::aead::OsRng;
+
     use super::*;
-    use crate::types::{OkvsKey, OkvsValue};
+    use crate::executor::{Sequential, StdThreads};
+    use crate::solver::simple_gauss;
+    use crate::types::{HashToIndex, OkvsKey, OkvsValue};
     extern crate test;
 
     #[test]
     fn test_okvs_key() {
         let key = OkvsKey([0; 8]);
 
-        let pos = key.hash_to_index(30);
+        let pos = key.hash_to_index(&[0; 16], 30);
         assert!(pos < 30);
 
-        let band = key.hash_to_band(10);
+        let band: Band = key.hash_to_band(&[0; 16], 10);
         assert!(band.bits() <= 10);
     }
 
@@ -102,7 +848,7 @@ mod tests {
             pairs.push((OkvsKey([i; 8]), OkvsValue([i; 32])));
         }
         let rb_okvs = RbOkvs::new(pairs.len());
-        let res = rb_okvs.create_sorted_matrix(pairs);
+        let res = rb_okvs.create_sorted_matrix(&pairs);
         assert!(res.is_ok());
     }
 
@@ -117,14 +863,529 @@ mod tests {
         }
         let rb_okvs = RbOkvs::new(pairs.len());
 
+        let encode_ref = rb_okvs.encode_ref(&pairs).unwrap();
         let encode = rb_okvs.encode(pairs).unwrap();
+        assert_eq!(encode, encode_ref);
 
         for i in 0..1000 {
-            let decode = rb_okvs.decode(&encode, &OkvsKey((i as usize).to_le_bytes()));
+            let decode = rb_okvs
+                .decode(&encode, &OkvsKey((i as usize).to_le_bytes()))
+                .unwrap();
             assert_eq!(decode, OkvsValue((i as u32).to_le_bytes()));
         }
     }
 
+    #[test]
+    fn test_fixed_width() {
+        let pairs: Vec<Pair<OkvsKey, OkvsValue<4>>> = (0..1000usize)
+            .map(|i| {
+                (
+                    OkvsKey(i.to_le_bytes()),
+                    OkvsValue((i as u32).to_le_bytes()),
+                )
+            })
+            .collect();
+        let fixed = RbOkvs::<2>::fixed_width(pairs.len());
+        assert_eq!(fixed.band_width(), 128);
+        assert_eq!(fixed.fingerprint(), RbOkvs::new(pairs.len()).fingerprint());
+
+        // Interchangeable with the default instance of the same width
+        let encoding = RbOkvs::new(pairs.len()).encode_ref(&pairs).unwrap();
+        assert_eq!(fixed.encode_ref(&pairs).unwrap(), encoding);
+        for (k, v) in &pairs {
+            assert_eq!(&fixed.decode(&encoding, k).unwrap(), v);
+        }
+
+        let narrow = RbOkvs::<1>::fixed_width(pairs.len());
+        assert_eq!(narrow.band_width(), 64);
+        let encoding = narrow.encode_ref(&pairs).unwrap();
+        for (k, v) in &pairs {
+            assert_eq!(&narrow.decode(&encoding, k).unwrap(), v);
+        }
+    }
+
+    #[test]
+    fn test_small() {
+        for n in [0u64, 1, 2, 3, 10, 100, 117, 200] {
+            let pairs: Vec<Pair<OkvsKey, OkvsValue<8>>> = (0..n)
+                .map(|i| (OkvsKey(i.to_le_bytes()), OkvsValue((i + 1).to_le_bytes())))
+                .collect();
+            let rb_okvs = RbOkvs::new(pairs.len());
+            assert!(rb_okvs.band_width() > 0 && rb_okvs.band_width() < rb_okvs.columns());
+            let encoding = rb_okvs.encode_ref(&pairs).unwrap();
+            for (k, v) in &pairs {
+                assert_eq!(rb_okvs.decode(&encoding, k).unwrap(), *v);
+            }
+            assert!(rb_okvs.decode(&encoding, &OkvsKey([9; 8])).is_ok());
+        }
+    }
+
+    #[test]
+    fn test_pad_columns() {
+        let pairs: Vec<Pair<OkvsKey, OkvsValue<8>>> = (0..1000u64)
+            .map(|i| (OkvsKey(i.to_le_bytes()), OkvsValue(i.to_le_bytes())))
+            .collect();
+        let rb_okvs = RbOkvs::builder()
+            .pad_columns(true)
+            .build(pairs.len())
+            .unwrap();
+        assert_eq!((rb_okvs.columns(), rb_okvs.logical_columns()), (1152, 1100));
+        let encoding = rb_okvs.encode_ref(&pairs).unwrap();
+        assert_eq!(encoding.len(), 1152);
+        for (k, v) in &pairs {
+            assert_eq!(rb_okvs.decode(&encoding, k).unwrap(), *v);
+        }
+        assert!(rb_okvs
+            .describe()
+            .to_string()
+            .contains(" logical_columns=1100"));
+
+        // Fallback parameters rebuild the padded instance
+        let (params, encoding) = RbOkvs::builder()
+            .pad_columns(true)
+            .encode_with_fallback(&pairs)
+            .unwrap();
+        assert!(params.pad_columns);
+        assert_eq!(params.build().unwrap().columns(), encoding.len());
+
+        let small = RbOkvs::builder().pad_columns(true).build(10).unwrap();
+        assert_eq!((small.columns(), small.logical_columns()), (64, 50));
+        assert_eq!(RbOkvs::new(1000).logical_columns(), 1100);
+    }
+
+    #[test]
+    fn test_builder() {
+        let rb_okvs = RbOkvs::builder()
+            .epsilon(0.05)
+            .band_width(64)
+            .build(1000)
+            .unwrap();
+        assert_eq!(rb_okvs.columns, 1050);
+        assert_eq!(rb_okvs.band_width, 64);
+
+        assert!(RbOkvs::builder().epsilon(0.0).build(1000).is_err());
+        assert!(RbOkvs::builder().band_width(260).build(1000).is_err());
+        assert!(RbOkvs::builder().band_width(0).build(1000).is_err());
+
+        // Widths need not be whole bytes
+        let pairs: Vec<Pair<OkvsKey, OkvsValue<8>>> = (0..1000u64)
+            .map(|i| (OkvsKey(i.to_le_bytes()), OkvsValue(i.to_le_bytes())))
+            .collect();
+        let rb_okvs = RbOkvs::builder().band_width(130).build(1000).unwrap();
+        let (_, band) = rb_okvs.locate(&pairs[0].0);
+        assert!(band.bits() <= 130);
+        let encoding = rb_okvs.encode_ref(&pairs).unwrap();
+        for (k, v) in &pairs {
+            assert_eq!(rb_okvs.decode(&encoding, k).unwrap(), *v);
+        }
+    }
+
+    #[test]
+    fn test_verify() {
+        let pairs: Vec<Pair<OkvsKey, OkvsValue<4>>> = (0..1000usize)
+            .map(|i| {
+                (
+                    OkvsKey(i.to_le_bytes()),
+                    OkvsValue((i as u32).to_le_bytes()),
+                )
+            })
+            .collect();
+        let rb_okvs = RbOkvs::builder().verify(true).build(pairs.len()).unwrap();
+        let encoding = rb_okvs.encode_ref(&pairs).unwrap();
+        assert_eq!(
+            encoding,
+            RbOkvs::new(pairs.len()).encode_verified(&pairs).unwrap()
+        );
+        assert!(rb_okvs.par_encode(&StdThreads::new(2), &pairs).is_ok());
+
+        let mut x = RbOkvs::new(pairs.len()).encode_ref(&pairs).unwrap();
+        let pivot = x.iter().rposition(|v| !v.is_zero()).unwrap();
+        x[pivot] = OkvsValue([0xff; 4]);
+        assert!(matches!(
+            RbOkvs::new(pairs.len()).check(&pairs, &x),
+            Err(Error::VerificationFailed(_))
+        ));
+    }
+
+    #[test]
+    fn test_finalize_streaming() {
+        let mut pairs: Vec<Pair<OkvsKey, OkvsValue<4>>> = vec![];
+        for i in 0..1000 {
+            pairs.push((
+                OkvsKey((i as usize).to_le_bytes()),
+                OkvsValue((i as u32).to_le_bytes()),
+            ));
+        }
+        let rb_okvs = RbOkvs::new(pairs.len());
+        let encode = rb_okvs.encode(pairs.clone()).unwrap();
+        assert_eq!(rb_okvs.eliminate(pairs.clone()).unwrap().finalize(), encode);
+
+        let (mut tx, rx) = std::sync::mpsc::sync_channel(16);
+        let eliminated = rb_okvs.eliminate(pairs).unwrap();
+        let handle = std::thread::spawn(move || rx.into_iter().collect::<Vec<_>>());
+        eliminated.finalize_streaming(&mut tx).unwrap();
+        drop(tx);
+
+        let columns = handle.join().unwrap();
+        assert_eq!(columns.len(), encode.len());
+        for (n, (column, value)) in columns.into_iter().enumerate() {
+            assert_eq!(column, encode.len() - 1 - n);
+            assert_eq!(value, encode[column]);
+        }
+    }
+
+    #[test]
+    fn test_par_encode() {
+        let mut pairs: Vec<Pair<OkvsKey, OkvsValue<4>>> = vec![];
+        for i in 0..10000 {
+            pairs.push((
+                OkvsKey((i as usize).to_le_bytes()),
+                OkvsValue((i as u32).to_le_bytes()),
+            ));
+        }
+        let rb_okvs = RbOkvs::new(pairs.len());
+
+        let encode = rb_okvs.par_encode(&StdThreads::new(2), &pairs).unwrap();
+
+        for (k, v) in &pairs {
+            assert_eq!(&rb_okvs.decode(&encode, k).unwrap(), v);
+        }
+    }
+
+    #[test]
+    fn test_encode_with_fallback() {
+        let mut pairs: Vec<Pair<OkvsKey, OkvsValue<4>>> = vec![];
+        for i in 0..1000 {
+            pairs.push((
+                OkvsKey((i as usize).to_le_bytes()),
+                OkvsValue((i as u32).to_le_bytes()),
+            ));
+        }
+
+        // Far too narrow a band, only the last fallback succeeds
+        let builder = RbOkvs::builder()
+            .band_width(8)
+            .fallback(0.1, 16)
+            .fallback(0.1, 128);
+        let (params, encode) = builder.encode_with_fallback(&pairs).unwrap();
+        assert_eq!(params.band_width, 128);
+
+        let rb_okvs = params.build().unwrap();
+        for (k, v) in &pairs {
+            assert_eq!(&rb_okvs.decode(&encode, k).unwrap(), v);
+        }
+
+        let builder = RbOkvs::builder().band_width(8).fallback(0.1, 16);
+        let Err(Error::Unsolvable {
+            index,
+            start,
+            key_hash,
+            ..
+        }) = builder.encode_with_fallback(&pairs)
+        else {
+            panic!("encode of a band of 16 succeeded");
+        };
+        let okvs = RbOkvs::builder().band_width(16).build(pairs.len()).unwrap();
+        let key = &pairs[index].0;
+        assert_eq!(okvs.locate(key).0, start);
+        assert_eq!(key_hash, u64::from_le_bytes(blake2b::<8>(&key.to_bytes())));
+    }
+
+    #[test]
+    fn test_colliding_keys() {
+        let mut pairs: Vec<Pair<OkvsKey, OkvsValue<8>>> = (0..100u64)
+            .map(|i| (OkvsKey(i.to_le_bytes()), OkvsValue(i.to_le_bytes())))
+            .collect();
+        pairs.push((pairs[10].0.clone(), OkvsValue([1; 8])));
+        let rb_okvs = RbOkvs::new(pairs.len());
+        assert!(matches!(
+            rb_okvs.encode_ref(&pairs),
+            Err(Error::CollidingKeys(10, 100))
+        ));
+        assert!(matches!(
+            rb_okvs.par_encode(&StdThreads::new(2), &pairs),
+            Err(Error::CollidingKeys(10, 100))
+        ));
+    }
+
+    #[test]
+    fn test_estimated_failure_prob() {
+        let p = RbOkvs::new(1000).estimated_failure_prob().unwrap();
+        assert!(p > 0.0 && p < 1e-5);
+
+        let wide = RbOkvs::builder().band_width(208).build(1000).unwrap();
+        assert!(wide.estimated_failure_prob().unwrap() < 1e-12);
+
+        let tight = RbOkvs::builder().epsilon(0.03).build(1000).unwrap();
+        assert!(tight.estimated_failure_prob().is_none());
+    }
+
+    #[test]
+    fn test_fingerprint() {
+        assert_eq!(
+            RbOkvs::new(1000).fingerprint(),
+            RbOkvs::new(1000).fingerprint()
+        );
+        assert_ne!(
+            RbOkvs::new(1000).fingerprint(),
+            RbOkvs::new(1001).fingerprint()
+        );
+
+        let narrow = RbOkvs::builder().band_width(64).build(1000).unwrap();
+        assert_ne!(RbOkvs::new(1000).fingerprint(), narrow.fingerprint());
+    }
+
+    #[test]
+    fn test_encode_with_rng() {
+        let pairs: Vec<Pair<OkvsKey, OkvsValue<8>>> = (0..1000u64)
+            .map(|i| (OkvsKey(i.to_le_bytes()), OkvsValue(i.to_le_bytes())))
+            .collect();
+        let rb_okvs = RbOkvs::new(pairs.len());
+        let a = rb_okvs.encode_with_rng(&pairs, &mut OsRng).unwrap();
+        let b = rb_okvs.encode_with_rng(&pairs, &mut OsRng).unwrap();
+        assert_ne!(a, b);
+        assert!(a.iter().all(|v| !v.is_zero()));
+        for (k, v) in &pairs {
+            assert_eq!(rb_okvs.decode(&a, k).unwrap(), *v);
+            assert_eq!(rb_okvs.decode(&b, k).unwrap(), *v);
+        }
+    }
+
+    #[test]
+    fn test_encode_map() {
+        let pairs: Vec<Pair<OkvsKey, OkvsValue<8>>> = (0..1000u64)
+            .map(|i| (OkvsKey(i.to_le_bytes()), OkvsValue((i * 7).to_le_bytes())))
+            .collect();
+        let hash_map: HashMap<_, _> = pairs.iter().cloned().collect();
+        let btree_map: BTreeMap<_, _> = pairs.iter().rev().cloned().collect();
+        let rb_okvs = RbOkvs::builder().seed([4; 16]).build(pairs.len()).unwrap();
+        let encoding = rb_okvs.encode_map(&hash_map).unwrap();
+        assert_eq!(rb_okvs.encode_map(&btree_map).unwrap(), encoding);
+        for (k, v) in &pairs {
+            assert_eq!(rb_okvs.decode(&encoding, k).unwrap(), *v);
+        }
+    }
+
+    #[test]
+    fn test_rerandomize() {
+        let pairs: Vec<Pair<OkvsKey, OkvsValue<8>>> = (0..1000u64)
+            .map(|i| (OkvsKey(i.to_le_bytes()), OkvsValue(i.to_le_bytes())))
+            .collect();
+        let keys: Vec<_> = pairs.iter().map(|(k, _)| k.clone()).collect();
+        let rb_okvs = RbOkvs::new(pairs.len());
+        let original = rb_okvs.encode_ref(&pairs).unwrap();
+
+        let mut refreshed = original.clone();
+        rb_okvs
+            .rerandomize(&keys, &mut refreshed, &mut OsRng)
+            .unwrap();
+        assert_ne!(refreshed, original);
+        for (k, v) in &pairs {
+            assert_eq!(rb_okvs.decode(&refreshed, k).unwrap(), *v);
+        }
+
+        let mut short = original[1..].to_vec();
+        assert!(matches!(
+            rb_okvs.rerandomize(&keys, &mut short, &mut OsRng),
+            Err(Error::ParamsMismatch("columns"))
+        ));
+        let mut long = original.clone();
+        long.push(OkvsValue::default());
+        assert!(matches!(
+            rb_okvs.rerandomize(&keys, &mut long, &mut OsRng),
+            Err(Error::ParamsMismatch("columns"))
+        ));
+    }
+
+    #[test]
+    fn test_seed() {
+        let pairs: Vec<Pair<OkvsKey, OkvsValue<4>>> = (0..1000usize)
+            .map(|i| {
+                (
+                    OkvsKey(i.to_le_bytes()),
+                    OkvsValue((i as u32).to_le_bytes()),
+                )
+            })
+            .collect();
+        let a = RbOkvs::new(pairs.len());
+        let b = RbOkvs::builder().seed([7; 16]).build(pairs.len()).unwrap();
+        assert_eq!(b.seed(), &[7; 16]);
+        assert_ne!(a.fingerprint(), b.fingerprint());
+
+        let encoding_a = a.encode(pairs.clone()).unwrap();
+        let encoding_b = b.encode(pairs.clone()).unwrap();
+        assert_ne!(encoding_a, encoding_b);
+        for (k, v) in &pairs {
+            assert_eq!(a.decode(&encoding_a, k).unwrap(), *v);
+            assert_eq!(b.decode(&encoding_b, k).unwrap(), *v);
+        }
+        assert!(pairs
+            .iter()
+            .any(|(k, v)| a.decode(&encoding_b, k).unwrap() != *v));
+    }
+
+    #[test]
+    fn test_decode_too_short() {
+        let rb_okvs = RbOkvs::new(1000);
+        let encoding = vec![OkvsValue([1u8]); 1100];
+        let key = OkvsKey([0u8; 8]);
+        assert!(rb_okvs.decode(&encoding, &key).is_ok());
+        assert!(matches!(
+            rb_okvs.decode(&encoding[..1099].to_vec(), &key),
+            Err(Error::EncodingTooShort {
+                required: 1100,
+                actual: 1099,
+            })
+        ));
+    }
+
+    #[test]
+    fn test_decode_with() {
+        let pairs: Vec<Pair<OkvsKey, OkvsValue<8>>> = (0..1000u64)
+            .map(|i| (OkvsKey(i.to_le_bytes()), OkvsValue(i.to_le_bytes())))
+            .collect();
+        let rb_okvs = RbOkvs::new(pairs.len());
+        let encoding = rb_okvs.encode_ref(&pairs).unwrap();
+        for (k, v) in &pairs {
+            let (start, band) = rb_okvs.locate(k);
+            assert_eq!(rb_okvs.decode_with(start, &band, &encoding).unwrap(), *v);
+        }
+
+        let (_, band) = rb_okvs.locate(&pairs[0].0);
+        assert!(matches!(
+            rb_okvs.decode_with(encoding.len() - 1, &band, &encoding),
+            Err(Error::EncodingTooShort { .. })
+        ));
+    }
+
+    #[test]
+    fn test_from_params() {
+        let rb_okvs = RbOkvs::builder()
+            .seed([3; 16])
+            .epsilon(0.05)
+            .build(1000)
+            .unwrap();
+        let rebuilt =
+            RbOkvs::<4>::from_params(rb_okvs.columns(), rb_okvs.band_width(), 0.05, [3; 16])
+                .unwrap();
+        assert_eq!(rebuilt.fingerprint(), rb_okvs.fingerprint());
+        assert_eq!(rebuilt.epsilon(), 0.05);
+        assert!(RbOkvs::<4>::from_params(100, 512, 0.1, [0; 16]).is_err());
+        assert!(RbOkvs::<4>::from_params(100, 100, 0.1, [0; 16]).is_err());
+        assert!(RbOkvs::<4>::from_params(1000, 100, 0.0, [0; 16]).is_err());
+    }
+
+    #[test]
+    fn test_describe() {
+        let rb_okvs = RbOkvs::new(1000);
+        assert_eq!(rb_okvs.band_width(), 128);
+        assert_eq!(rb_okvs.epsilon(), 0.1);
+        assert_eq!(rb_okvs.expected_size_bytes::<OkvsValue<16>>(), 1100 * 16);
+
+        let description = rb_okvs.describe();
+        assert_eq!(description.columns, 1100);
+        assert_eq!(description.fingerprint, rb_okvs.fingerprint());
+
+        assert!(description
+            .to_string()
+            .starts_with("rb-okvs columns=1100 band_width=128 epsilon=0.1 failure_prob="));
+
+        let small = RbOkvs::new(10);
+        assert_eq!((small.columns(), small.band_width()), (50, 40));
+    }
+
+    #[test]
+    fn test_encode_with_scratch() {
+        let rb_okvs = RbOkvs::new(1000);
+        let mut scratch = EncoderScratch::new();
+        let mut encoding = vec![];
+        let mut capacity = 0;
+        for n in [1000u64, 900, 1000] {
+            let pairs: Vec<Pair<OkvsKey, OkvsValue<8>>> = (0..n)
+                .map(|i| (OkvsKey((i + n).to_le_bytes()), OkvsValue(i.to_le_bytes())))
+                .collect();
+            rb_okvs
+                .encode_with_scratch(&pairs, &mut scratch, &mut encoding)
+                .unwrap();
+            assert_eq!(encoding, rb_okvs.encode_ref(&pairs).unwrap());
+            if capacity == 0 {
+                capacity = scratch.capacity_bytes();
+            }
+            // Smaller and equal encodes fit in the first one's buffers
+            assert_eq!(scratch.capacity_bytes(), capacity);
+        }
+    }
+
+    #[test]
+    fn test_estimate_peak_memory() {
+        let estimate = RbOkvs::new(1000).estimate_peak_memory::<OkvsValue<16>>(1000);
+        assert_eq!(
+            estimate,
+            MemoryEstimate {
+                matrix: 1000 * (32 + 8),
+                values: 1000 * 16,
+                output: 1100 * 16,
+                peak: 1000 * (32 + 8) + 1000 * 16 + 1100 * 16,
+            }
+        );
+        // One-byte values leave the sort scratch as the larger of the two
+        let estimate = RbOkvs::new(1000).estimate_peak_memory::<OkvsValue<1>>(1000);
+        assert_eq!(estimate.peak, 1000 * 41 + (1000 + 1100 - 128 + 1) * 8);
+    }
+
+    #[test]
+    fn test_decode_batch() {
+        let mut pairs: Vec<Pair<OkvsKey, OkvsValue<4>>> = vec![];
+        for i in 0..1000 {
+            pairs.push((
+                OkvsKey((i as usize).to_le_bytes()),
+                OkvsValue((i as u32).to_le_bytes()),
+            ));
+        }
+        let rb_okvs = RbOkvs::new(pairs.len());
+        let keys: Vec<OkvsKey> = pairs.iter().map(|(k, _)| k.clone()).collect();
+
+        let encode = rb_okvs.encode(pairs).unwrap();
+
+        let sequential = rb_okvs.decode_batch(&Sequential, &encode, &keys).unwrap();
+        let threaded = rb_okvs
+            .decode_batch(&StdThreads::new(4), &encode, &keys)
+            .unwrap();
+        assert_eq!(sequential, threaded);
+        for (i, v) in threaded.into_iter().enumerate() {
+            assert_eq!(v, OkvsValue((i as u32).to_le_bytes()));
+        }
+    }
+
+    #[test]
+    fn test_encode_into() {
+        let rb_okvs = RbOkvs::new(1000);
+        let mut encoding = vec![];
+        let mut decoded = vec![OkvsValue::default(); 1000];
+        for round in 0..3u64 {
+            let pairs: Vec<Pair<OkvsKey, OkvsValue<8>>> = (0..1000u64)
+                .map(|i| {
+                    (
+                        OkvsKey(i.to_le_bytes()),
+                        OkvsValue((i ^ round).to_le_bytes()),
+                    )
+                })
+                .collect();
+            rb_okvs.encode_into(&pairs, &mut encoding).unwrap();
+            assert_eq!(encoding, rb_okvs.encode_ref(&pairs).unwrap());
+
+            let keys: Vec<OkvsKey> = pairs.iter().map(|(k, _)| k.clone()).collect();
+            rb_okvs
+                .decode_batch_into(&encoding, &keys, &mut decoded)
+                .unwrap();
+            assert!(pairs.iter().zip(&decoded).all(|((_, v), d)| v == d));
+        }
+        assert_eq!(encoding.len(), rb_okvs.columns());
+        assert!(rb_okvs
+            .decode_batch_into(&encoding, &[OkvsKey([0; 8])], &mut decoded)
+            .is_err());
+    }
+
     #[bench]
     fn bench_create_sorted_matrix(b: &mut test::Bencher) {
         let mut pairs: Vec<Pair<OkvsKey, OkvsValue<1>>> = vec![];
@@ -137,7 +1398,22 @@ mod tests {
         let rb_okvs = RbOkvs::new(pairs.len());
 
         b.iter(|| {
-            rb_okvs.create_sorted_matrix(pairs.clone()).unwrap();
+            rb_okvs.create_sorted_matrix(&pairs).unwrap();
+        });
+    }
+
+    #[bench]
+    fn bench_decode_fixed_width(b: &mut test::Bencher) {
+        let pairs: Vec<Pair<OkvsKey, OkvsValue<8>>> = (0..100000usize)
+            .map(|i| (OkvsKey(i.to_le_bytes()), OkvsValue(i.to_le_bytes())))
+            .collect();
+        let rb_okvs = RbOkvs::<2>::fixed_width(pairs.len());
+        let encoding = rb_okvs.encode_ref(&pairs).unwrap();
+
+        b.iter(|| {
+            for (k, _) in pairs.iter().take(1000) {
+                test::black_box(rb_okvs.decode(&encoding, k).unwrap());
+            }
         });
     }
 
@@ -151,14 +1427,40 @@ mod tests {
             ));
         }
         let rb_okvs = RbOkvs::new(pairs.len());
-        let (matrix, start_pos, y) = rb_okvs.create_sorted_matrix(pairs).unwrap();
+        let (matrix, start_pos, y) = rb_okvs.create_sorted_matrix(&pairs).unwrap();
+
+        b.iter(|| {
+            simple_gauss::<OkvsValue<1>, 4>(
+                y.clone(),
+                matrix.clone(),
+                start_pos.clone(),
+                rb_okvs.columns,
+            )
+            .unwrap();
+        });
+    }
+
+    #[bench]
+    fn bench_par_simple_gauss(b: &mut test::Bencher) {
+        let mut pairs: Vec<Pair<OkvsKey, OkvsValue<1>>> = vec![];
+        for i in 0..100000 {
+            pairs.push((
+                OkvsKey((i as usize).to_le_bytes()),
+                OkvsValue((i as u8).to_le_bytes()),
+            ));
+        }
+        let rb_okvs = RbOkvs::new(pairs.len());
+        let (matrix, start_pos, y) = rb_okvs.create_sorted_matrix(&pairs).unwrap();
+        let executor = StdThreads::new(2);
 
         b.iter(|| {
-            simple_gauss::<OkvsValue<1>>(
+            par_simple_gauss::<OkvsValue<1>, _, 4>(
+                &executor,
                 y.clone(),
                 matrix.clone(),
                 start_pos.clone(),
                 rb_okvs.columns,
+                rb_okvs.band_width,
             )
             .unwrap();
         });
@@ -180,6 +1482,23 @@ mod tests {
         });
     }
 
+    #[bench]
+    fn bench_par_encode(b: &mut test::Bencher) {
+        let mut pairs: Vec<Pair<OkvsKey, OkvsValue<1>>> = vec![];
+        for i in 0..1000000 {
+            pairs.push((
+                OkvsKey((i as usize).to_le_bytes()),
+                OkvsValue((i as u8).to_le_bytes()),
+            ));
+        }
+        let rb_okvs = RbOkvs::new(pairs.len());
+        let executor = StdThreads::new(2);
+
+        b.iter(|| {
+            rb_okvs.par_encode(&executor, &pairs).unwrap();
+        });
+    }
+
     #[bench]
     fn bench_decode(b: &mut test::Bencher) {
         let mut pairs: Vec<Pair<OkvsKey, OkvsValue<1>>> = vec![];
@@ -195,7 +1514,9 @@ mod tests {
 
         b.iter(|| {
             for i in 0..100000 {
-                rb_okvs.decode(&encode, &OkvsKey((i as usize).to_le_bytes()));
+                rb_okvs
+                    .decode(&encode, &OkvsKey((i as usize).to_le_bytes()))
+                    .unwrap();
             }
         });
     }
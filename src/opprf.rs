@@ -0,0 +1,284 @@
+use curve25519_dalek::ristretto::RistrettoPoint;
+#[cfg(feature = "serde")]
+use serde::Deserialize;
+
+use crate::error::{Error, Result};
+use crate::okvs::Fingerprint;
+use crate::oprf::{
+    check_version, decoder, encode_padded, okvs_key, read_points, write_points, Blind, OprfKey,
+    Point,
+};
+use crate::types::{Encoding, Okvs, OkvsV, OkvsValue};
+#[cfg(feature = "serde")]
+use crate::utils::serialize_to_bytes;
+use crate::utils::{hash, to_usize};
+
+/// Leading byte of serialized [`OpprfRequest`]s and [`OpprfResponse`]s.
+pub const MESSAGE_VERSION: u8 = 2;
+const MASK_DOMAIN: &[u8] = b"rb-okvs/opprf/mask";
+
+/// Sender of the oblivious programmable PRF: programs `N`-byte outputs at
+/// chosen points.
+///
+/// The receiver blinds its points for the DH-based OPRF, `F_k(x) = k · H(x)`,
+/// in an [`OpprfRequest`]. The sender evaluates them and encodes every
+/// programmed pair `(x, y)` as `x -> y ^ mask(x, F_k(x))` into an OKVS, sent
+/// back in an [`OpprfResponse`]. The receiver decodes the OKVS at each of its
+/// points and removes the mask: programmed points yield their `y`, all others
+/// a pseudorandom value, and the receiver can't tell which is which.
+pub struct OpprfSender<const N: usize> {
+    pairs: Vec<(Vec<u8>, [u8; N])>,
+}
+
+/// Receiver of the OPPRF, see [`OpprfSender`].
+pub struct OpprfReceiver {
+    points: Vec<Vec<u8>>,
+    blind:  Blind,
+}
+
+/// The receiver's points under a random blind, in order.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+#[cfg_attr(feature = "serde", serde(try_from = "Vec<u8>"))]
+pub struct OpprfRequest {
+    pub blinded: Vec<Point>,
+}
+
+#[cfg(feature = "serde")]
+serialize_to_bytes!(OpprfRequest);
+
+/// The request under a fresh sender key, and the masked programmed pairs
+/// encoded into an OKVS of `kv_count` rows and fingerprint `fingerprint`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+#[cfg_attr(feature = "serde", serde(try_from = "Vec<u8>"))]
+pub struct OpprfResponse<const N: usize> {
+    pub evaluated:   Vec<Point>,
+    pub kv_count:    usize,
+    pub fingerprint: Fingerprint,
+    pub encoding:    Encoding<OkvsValue<N>>,
+}
+
+#[cfg(feature = "serde")]
+serialize_to_bytes!(OpprfResponse<N>, N);
+
+impl<const N: usize> OpprfSender<N> {
+    /// Points must be distinct.
+    pub fn new<T: AsRef<[u8]>>(pairs: &[(T, [u8; N])]) -> Self {
+        Self {
+            pairs: pairs
+                .iter()
+                .map(|(x, y)| (x.as_ref().to_vec(), *y))
+                .collect(),
+        }
+    }
+
+    /// Answers `request` under a fresh OPRF key, so responses to different
+    /// requests can't be combined.
+    pub fn program(&self, request: &OpprfRequest) -> Result<OpprfResponse<N>> {
+        let key = OprfKey::random();
+        let evaluated = key.eval_blinded(&request.blinded)?;
+        let pairs = self
+            .pairs
+            .iter()
+            .map(|(x, y)| (okvs_key(x), mask(x, &key.eval(x)).xor(&OkvsValue(*y))))
+            .collect();
+        let (kv_count, fingerprint, encoding) = encode_padded(pairs)?;
+        Ok(OpprfResponse {
+            evaluated,
+            kv_count,
+            fingerprint,
+            encoding,
+        })
+    }
+}
+
+impl OpprfReceiver {
+    pub fn new<T: AsRef<[u8]>>(points: &[T]) -> Self {
+        Self {
+            points: points.iter().map(|x| x.as_ref().to_vec()).collect(),
+            blind: Blind::random(),
+        }
+    }
+
+    pub fn request(&self) -> OpprfRequest {
+        OpprfRequest {
+            blinded: self.blind.blind(&self.points),
+        }
+    }
+
+    /// The output at each of the receiver's points, in order.
+    pub fn evaluate<const N: usize>(&self, response: &OpprfResponse<N>) -> Result<Vec<[u8; N]>> {
+        if response.evaluated.len() != self.points.len() {
+            return Err(Error::Message(format!(
+                "{} evaluations for {} points",
+                response.evaluated.len(),
+                self.points.len()
+            )));
+        }
+        let okvs = decoder(response.kv_count, &response.fingerprint, &response.encoding)?;
+        let outputs = self.blind.unblind(&response.evaluated)?;
+        self.points
+            .iter()
+            .zip(outputs)
+            .map(|(x, f)| {
+                let v = okvs.decode(&response.encoding, &okvs_key(x))?;
+                Ok(v.xor(&mask(x, &f)).0)
+            })
+            .collect()
+    }
+}
+
+impl OpprfRequest {
+    /// `version || count (u32 LE) || points`
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        let mut bytes = vec![MESSAGE_VERSION];
+        write_points(&mut bytes, &self.blinded)?;
+        Ok(bytes)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let (blinded, rest) = read_points(check_version(bytes, MESSAGE_VERSION)?)?;
+        if !rest.is_empty() {
+            return Err(Error::Message(format!("{} trailing bytes", rest.len())));
+        }
+        Ok(Self { blinded })
+    }
+}
+
+impl<const N: usize> OpprfResponse<N> {
+    /// `version || count (u32 LE) || points || kv_count (u64 LE) ||
+    /// fingerprint || value size (u32 LE) || values`
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        let mut bytes = vec![MESSAGE_VERSION];
+        write_points(&mut bytes, &self.evaluated)?;
+        bytes.extend_from_slice(&(self.kv_count as u64).to_le_bytes());
+        bytes.extend_from_slice(&self.fingerprint);
+        bytes.extend_from_slice(&(N as u32).to_le_bytes());
+        for value in &self.encoding {
+            bytes.extend_from_slice(&value.0);
+        }
+        Ok(bytes)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let (evaluated, rest) = read_points(check_version(bytes, MESSAGE_VERSION)?)?;
+        if rest.len() < 20 {
+            return Err(Error::Message("truncated response header".into()));
+        }
+        let kv_count = to_usize(u64::from_le_bytes(rest[..8].try_into().unwrap()))?;
+        let size = u32::from_le_bytes(rest[16..20].try_into().unwrap()) as usize;
+        if size != N {
+            return Err(Error::Message(format!("value size {size}, expected {N}")));
+        }
+        let body = &rest[20..];
+        if N == 0 || body.len() % N != 0 {
+            return Err(Error::Message(format!("{} bytes of values", body.len())));
+        }
+        let encoding = body
+            .chunks_exact(N)
+            .map(|chunk| OkvsValue(chunk.try_into().unwrap()))
+            .collect();
+        Ok(Self {
+            evaluated,
+            kv_count,
+            fingerprint: rest[8..16].try_into().unwrap(),
+            encoding,
+        })
+    }
+}
+
+impl TryFrom<OpprfRequest> for Vec<u8> {
+    type Error = Error;
+
+    fn try_from(request: OpprfRequest) -> Result<Self> {
+        request.to_bytes()
+    }
+}
+
+impl TryFrom<Vec<u8>> for OpprfRequest {
+    type Error = Error;
+
+    fn try_from(bytes: Vec<u8>) -> Result<Self> {
+        Self::from_bytes(&bytes)
+    }
+}
+
+impl<const N: usize> TryFrom<OpprfResponse<N>> for Vec<u8> {
+    type Error = Error;
+
+    fn try_from(response: OpprfResponse<N>) -> Result<Self> {
+        response.to_bytes()
+    }
+}
+
+impl<const N: usize> TryFrom<Vec<u8>> for OpprfResponse<N> {
+    type Error = Error;
+
+    fn try_from(bytes: Vec<u8>) -> Result<Self> {
+        Self::from_bytes(&bytes)
+    }
+}
+
+/// Pad of the output at `x`, which only holders of `F_k(x)` can remove.
+fn mask<const N: usize>(x: &[u8], f: &RistrettoPoint) -> OkvsValue<N> {
+    let data = [MASK_DOMAIN, f.compress().as_bytes(), x].concat();
+    OkvsValue(hash(&data, N).try_into().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::okvs::RbOkvs;
+
+    #[test]
+    fn test_opprf() {
+        let pairs: Vec<(Vec<u8>, [u8; 20])> = (0..100u32)
+            .map(|i| (i.to_le_bytes().to_vec(), [i as u8; 20]))
+            .collect();
+        let points: Vec<Vec<u8>> = (50..150u32).map(|i| i.to_le_bytes().to_vec()).collect();
+
+        let sender = OpprfSender::new(&pairs);
+        let receiver = OpprfReceiver::new(&points);
+        let request = OpprfRequest::from_bytes(&receiver.request().to_bytes().unwrap()).unwrap();
+        let response = sender.program(&request).unwrap();
+        let response = OpprfResponse::<20>::from_bytes(&response.to_bytes().unwrap()).unwrap();
+
+        let outputs = receiver.evaluate(&response).unwrap();
+        for (i, output) in (50..150u32).zip(&outputs) {
+            match i < 100 {
+                true => assert_eq!(*output, [i as u8; 20]),
+                false => assert!(!pairs.iter().any(|(_, y)| y == output)),
+            }
+        }
+
+        // A fresh key per response
+        let again = sender.program(&request).unwrap();
+        assert_ne!(again.evaluated, response.evaluated);
+        assert_eq!(receiver.evaluate(&again).unwrap()[..50], outputs[..50]);
+    }
+
+    #[test]
+    fn test_malformed() {
+        let receiver = OpprfReceiver::new(&["a", "b"]);
+        let response = OpprfSender::new(&[("a", [1u8; 4])])
+            .program(&receiver.request())
+            .unwrap();
+        assert!(OpprfResponse::<8>::from_bytes(&response.to_bytes().unwrap()).is_err());
+
+        let mut bytes = response.to_bytes().unwrap();
+        bytes.pop();
+        assert!(OpprfResponse::<4>::from_bytes(&bytes).is_err());
+
+        let mut short = response.clone();
+        short.evaluated.pop();
+        assert!(receiver.evaluate(&short).is_err());
+
+        let mut foreign = response.clone();
+        foreign.fingerprint = RbOkvs::new(1024).with_seed([1; 16]).fingerprint();
+        assert!(matches!(
+            receiver.evaluate(&foreign),
+            Err(Error::ParamsMismatch("OKVS"))
+        ));
+    }
+}
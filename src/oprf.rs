@@ -0,0 +1,153 @@
+use aes_gcm::aead::{rand_core::RngCore, OsRng};
+use curve25519_dalek::ristretto::{CompressedRistretto, RistrettoPoint};
+use curve25519_dalek::Scalar;
+use sha2::Sha512;
+
+use crate::error::{Error, Result};
+use crate::okvs::{Fingerprint, RbOkvs};
+use crate::types::{Encoding, Okvs, OkvsKey, OkvsValue, Pair};
+use crate::utils::{hash, to_u32};
+
+/// Bytes of a compressed Ristretto point.
+pub(crate) const POINT_LEN: usize = 32;
+pub(crate) type Point = [u8; POINT_LEN];
+/// Bytes of the OKVS keys items are hashed to.
+pub(crate) const OKVS_K_SIZE: usize = 16;
+/// Smallest OKVS the protocols encode into, narrow encodings fail too often.
+/// Inputs are padded with random rows up to its size.
+const MIN_OKVS_ROWS: usize = 1024;
+const HASH_DOMAIN: &[u8] = b"rb-okvs/oprf/point";
+
+/// Key of the DH-based OPRF `F_k(x) = k · H(x)`, held by the sender.
+pub(crate) struct OprfKey(Scalar);
+
+/// Random factor a receiver blinds its items with before sending them.
+pub(crate) struct Blind(Scalar);
+
+impl OprfKey {
+    pub(crate) fn random() -> Self {
+        Self(Scalar::random(&mut OsRng))
+    }
+
+    pub(crate) fn eval(&self, item: &[u8]) -> RistrettoPoint {
+        hash_to_point(item) * self.0
+    }
+
+    /// Evaluates blinded items, without learning them.
+    pub(crate) fn eval_blinded(&self, blinded: &[Point]) -> Result<Vec<Point>> {
+        blinded
+            .iter()
+            .map(|p| Ok((decompress(p)? * self.0).compress().to_bytes()))
+            .collect()
+    }
+}
+
+impl Blind {
+    pub(crate) fn random() -> Self {
+        Self(Scalar::random(&mut OsRng))
+    }
+
+    pub(crate) fn blind<T: AsRef<[u8]>>(&self, items: &[T]) -> Vec<Point> {
+        items
+            .iter()
+            .map(|x| (hash_to_point(x.as_ref()) * self.0).compress().to_bytes())
+            .collect()
+    }
+
+    /// Recovers `F_k(x)` from the evaluation of a blinded `x`.
+    pub(crate) fn unblind(&self, evaluated: &[Point]) -> Result<Vec<RistrettoPoint>> {
+        let inverse = self.0.invert();
+        evaluated
+            .iter()
+            .map(|p| Ok(decompress(p)? * inverse))
+            .collect()
+    }
+}
+
+pub(crate) fn okvs_key(item: &[u8]) -> OkvsKey<OKVS_K_SIZE> {
+    OkvsKey(hash(&item, OKVS_K_SIZE).try_into().unwrap())
+}
+
+/// Encodes `pairs`, padded with random rows, into an OKVS of at least
+/// [`MIN_OKVS_ROWS`] rows; returns the row count and the OKVS fingerprint
+/// with the encoding.
+pub(crate) fn encode_padded<const N: usize>(
+    mut pairs: Vec<Pair<OkvsKey<OKVS_K_SIZE>, OkvsValue<N>>>,
+) -> Result<(usize, Fingerprint, Encoding<OkvsValue<N>>)> {
+    let kv_count = pairs.len().max(MIN_OKVS_ROWS);
+    while pairs.len() < kv_count {
+        let mut k = OkvsKey([0u8; OKVS_K_SIZE]);
+        let mut v = OkvsValue([0u8; N]);
+        OsRng.fill_bytes(&mut k.0);
+        OsRng.fill_bytes(&mut v.0);
+        pairs.push((k, v));
+    }
+    let okvs = RbOkvs::new(kv_count);
+    let encoding = okvs.encode_with_rng(&pairs, &mut OsRng)?;
+    Ok((kv_count, okvs.fingerprint(), encoding))
+}
+
+/// The OKVS a peer encoded `encoding` with, checking it was sent whole and
+/// that the peer's OKVS has the same `fingerprint`.
+pub(crate) fn decoder<V>(
+    kv_count: usize,
+    fingerprint: &Fingerprint,
+    encoding: &Encoding<V>,
+) -> Result<RbOkvs> {
+    let okvs = RbOkvs::new(kv_count);
+    if encoding.len() != okvs.columns() {
+        return Err(Error::Message(format!(
+            "encoding of {} values for {kv_count} rows",
+            encoding.len()
+        )));
+    }
+    match okvs.fingerprint() == *fingerprint {
+        true => Ok(okvs),
+        false => Err(Error::ParamsMismatch("OKVS")),
+    }
+}
+
+/// Strips the version byte of a serialized message.
+pub(crate) fn check_version(bytes: &[u8], version: u8) -> Result<&[u8]> {
+    match bytes.split_first() {
+        Some((v, rest)) if *v == version => Ok(rest),
+        Some((v, _)) => Err(Error::Message(format!("unsupported version {v}"))),
+        None => Err(Error::Message("empty message".into())),
+    }
+}
+
+/// `count (u32 LE) || points`
+pub(crate) fn write_points(bytes: &mut Vec<u8>, points: &[Point]) -> Result<()> {
+    bytes.extend_from_slice(&to_u32(points.len())?.to_le_bytes());
+    for point in points {
+        bytes.extend_from_slice(point);
+    }
+    Ok(())
+}
+
+pub(crate) fn read_points(bytes: &[u8]) -> Result<(Vec<Point>, &[u8])> {
+    if bytes.len() < 4 {
+        return Err(Error::Message("truncated point count".into()));
+    }
+    let count = u32::from_le_bytes(bytes[..4].try_into().unwrap()) as usize;
+    let rest = &bytes[4..];
+    let len = count
+        .checked_mul(POINT_LEN)
+        .filter(|len| *len <= rest.len())
+        .ok_or_else(|| Error::Message(format!("{} bytes for {count} points", rest.len())))?;
+    let points = rest[..len]
+        .chunks_exact(POINT_LEN)
+        .map(|chunk| chunk.try_into().unwrap())
+        .collect();
+    Ok((points, &rest[len..]))
+}
+
+fn hash_to_point(item: &[u8]) -> RistrettoPoint {
+    RistrettoPoint::hash_from_bytes::<Sha512>(&[HASH_DOMAIN, item].concat())
+}
+
+fn decompress(bytes: &Point) -> Result<RistrettoPoint> {
+    CompressedRistretto(*bytes)
+        .decompress()
+        .ok_or_else(|| Error::Message("invalid point".into()))
+}
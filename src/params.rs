@@ -0,0 +1,176 @@
+use crate::error::Result;
+use crate::okvs::RbOkvs;
+#[cfg(feature = "simulate")]
+use crate::types::{Okvs, OkvsKey, OkvsValue, Pair};
+#[cfg(feature = "simulate")]
+use crate::utils::blake2b;
+
+/// Epsilon the band width fit below was measured for.
+const FIT_EPSILON: f64 = 0.1;
+/// Linear fit of the statistical security against the band width from the
+/// RB-OKVS paper: λ ≈ 0.2691 · w − 15.21 at ϵ = 0.1.
+const FIT_SLOPE: f64 = 0.2691;
+const FIT_INTERCEPT: f64 = -15.21;
+
+/// Target failure probability of encoding, 2^-λ.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SecurityLevel {
+    Lambda20,
+    Lambda30,
+    Lambda40,
+}
+
+/// Parameters of an [`RbOkvs`] instance.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct OkvsParams {
+    pub kv_count:    usize,
+    pub epsilon:     f64,
+    pub band_width:  usize,
+    /// See [`RbOkvsBuilder::pad_columns`](crate::okvs::RbOkvsBuilder::pad_columns).
+    pub pad_columns: bool,
+}
+
+impl SecurityLevel {
+    pub fn lambda(&self) -> u32 {
+        match self {
+            SecurityLevel::Lambda20 => 20,
+            SecurityLevel::Lambda30 => 30,
+            SecurityLevel::Lambda40 => 40,
+        }
+    }
+}
+
+impl OkvsParams {
+    /// Picks epsilon and the smallest band width (rounded up to whole bytes)
+    /// that reach `level` for `kv_count` pairs.
+    pub fn for_(kv_count: usize, level: SecurityLevel) -> Self {
+        Self::for_lambda(kv_count, level.lambda())
+    }
+
+    /// Like [`OkvsParams::for_`], for a failure probability of 2^-`lambda`.
+    pub fn for_lambda(kv_count: usize, lambda: u32) -> Self {
+        let width = (lambda as f64 - FIT_INTERCEPT) / FIT_SLOPE;
+        Self {
+            kv_count,
+            epsilon: FIT_EPSILON,
+            band_width: (width.ceil() as usize).next_multiple_of(8),
+            pad_columns: false,
+        }
+    }
+
+    pub fn build(&self) -> Result<RbOkvs> {
+        RbOkvs::builder()
+            .epsilon(self.epsilon)
+            .band_width(self.band_width)
+            .pad_columns(self.pad_columns)
+            .build(self.kv_count)
+    }
+}
+
+/// 2^-λ from the fit, which only holds for epsilon at least [`FIT_EPSILON`]
+/// (larger epsilon only lowers the failure probability).
+pub(crate) fn estimated_failure_prob(epsilon: f64, band_width: usize) -> Option<f64> {
+    if epsilon < FIT_EPSILON {
+        return None;
+    }
+    let lambda = FIT_SLOPE * band_width as f64 + FIT_INTERCEPT;
+    Some(2f64.powf(-lambda).min(1.0))
+}
+
+/// Fraction of `trials` encodings of `params.kv_count` pseudo-random keys that
+/// failed. Keys are derived from the trial number, so results are
+/// reproducible.
+#[cfg(feature = "simulate")]
+pub fn simulate_failure_rate(params: &OkvsParams, trials: usize) -> Result<f64> {
+    let rb_okvs = params.build()?;
+
+    let mut failures = 0;
+    for trial in 0..trials as u64 {
+        let pairs: Vec<Pair<OkvsKey<16>, OkvsValue<1>>> = (0..params.kv_count as u64)
+            .map(|i| {
+                let mut seed = trial.to_le_bytes().to_vec();
+                seed.extend_from_slice(&i.to_le_bytes());
+                (OkvsKey(blake2b::<16>(&seed)), OkvsValue([1]))
+            })
+            .collect();
+        if rb_okvs.encode(pairs).is_err() {
+            failures += 1;
+        }
+    }
+    Ok(failures as f64 / trials.max(1) as f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{Okvs, OkvsKey, OkvsValue, Pair};
+
+    #[test]
+    fn test_for_security_level() {
+        let params = OkvsParams::for_(1000, SecurityLevel::Lambda20);
+        assert_eq!(params.epsilon, 0.1);
+        assert_eq!(params.band_width, 136);
+
+        assert_eq!(
+            OkvsParams::for_(1000, SecurityLevel::Lambda30).band_width,
+            176
+        );
+        assert_eq!(
+            OkvsParams::for_(1000, SecurityLevel::Lambda40).band_width,
+            208
+        );
+
+        for level in [
+            SecurityLevel::Lambda20,
+            SecurityLevel::Lambda30,
+            SecurityLevel::Lambda40,
+        ] {
+            let params = OkvsParams::for_(1000, level);
+            let reached = FIT_SLOPE * params.band_width as f64 + FIT_INTERCEPT;
+            assert!(reached >= level.lambda() as f64);
+            assert!(params.build().is_ok());
+        }
+    }
+
+    #[test]
+    fn test_estimated_failure_prob() {
+        let p = estimated_failure_prob(0.1, 136).unwrap();
+        assert!(p <= 2f64.powi(-20));
+        assert_eq!(estimated_failure_prob(0.1, 8), Some(1.0));
+        assert!(estimated_failure_prob(0.05, 136).is_none());
+    }
+
+    #[cfg(feature = "simulate")]
+    #[test]
+    fn test_simulate_failure_rate() {
+        let params = OkvsParams {
+            kv_count: 1000,
+            epsilon: 0.1,
+            band_width: 128,
+            pad_columns: false,
+        };
+        assert_eq!(simulate_failure_rate(&params, 5).unwrap(), 0.0);
+
+        // A band this narrow fails almost always
+        let params = OkvsParams {
+            band_width: 8,
+            ..params
+        };
+        assert!(simulate_failure_rate(&params, 5).unwrap() > 0.5);
+    }
+
+    #[test]
+    fn test_encode_with_preset() {
+        let pairs: Vec<Pair<OkvsKey, OkvsValue<8>>> = (0..1000u64)
+            .map(|i| (OkvsKey(i.to_le_bytes()), OkvsValue(i.to_le_bytes())))
+            .collect();
+        let rb_okvs = OkvsParams::for_(pairs.len(), SecurityLevel::Lambda40)
+            .build()
+            .unwrap();
+
+        let encode = rb_okvs.encode_ref(&pairs).unwrap();
+        for (k, v) in &pairs {
+            assert_eq!(&rb_okvs.decode(&encode, k).unwrap(), v);
+        }
+    }
+}
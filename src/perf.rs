@@ -0,0 +1,212 @@
+use std::time::Instant;
+
+use crate::error::{Error, Result};
+use crate::okvs::RbOkvsBuilder;
+use crate::types::{DynOkvs, Okvs, OkvsKey, OkvsValue, Pair};
+
+/// Machine-specific throughput measured by [`calibrate`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct Baseline {
+    pub kv_count:            usize,
+    pub encode_rows_per_sec: f64,
+    pub decode_keys_per_sec: f64,
+}
+
+/// Throughput of a run relative to a [`Baseline`], `1.0` means unchanged.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Comparison {
+    pub encode_ratio: f64,
+    pub decode_ratio: f64,
+    /// Whether either ratio dropped below `1.0 - tolerance`.
+    pub regressed:    bool,
+}
+
+/// Encodes and decodes `kv_count` synthetic 8-byte pairs with the parameters
+/// of `builder` and reports the measured throughput.
+pub fn calibrate(builder: &RbOkvsBuilder, kv_count: usize) -> Result<Baseline> {
+    if kv_count == 0 {
+        return Err(Error::Config("calibration needs at least one pair".into()));
+    }
+
+    let rb_okvs = builder.build(kv_count)?;
+    let pairs: Vec<Pair<OkvsKey, OkvsValue<8>>> = (0..kv_count as u64)
+        .map(|i| (OkvsKey(i.to_le_bytes()), OkvsValue(i.to_le_bytes())))
+        .collect();
+    let keys: Vec<OkvsKey> = pairs.iter().map(|(k, _)| k.clone()).collect();
+
+    let now = Instant::now();
+    let encoding = rb_okvs.encode(pairs)?;
+    let encode_secs = now.elapsed().as_secs_f64();
+
+    let now = Instant::now();
+    for key in &keys {
+        std::hint::black_box(rb_okvs.decode(&encoding, key)?);
+    }
+    let decode_secs = now.elapsed().as_secs_f64();
+
+    Ok(Baseline {
+        kv_count,
+        encode_rows_per_sec: kv_count as f64 / encode_secs.max(f64::EPSILON),
+        decode_keys_per_sec: kv_count as f64 / decode_secs.max(f64::EPSILON),
+    })
+}
+
+/// Compares `current` against `baseline`, flagging a regression when either
+/// throughput fell by more than `tolerance` (e.g. `0.2` for 20%).
+pub fn compare_to_baseline(baseline: &Baseline, current: &Baseline, tolerance: f64) -> Comparison {
+    let encode_ratio = current.encode_rows_per_sec / baseline.encode_rows_per_sec;
+    let decode_ratio = current.decode_keys_per_sec / baseline.decode_keys_per_sec;
+
+    Comparison {
+        encode_ratio,
+        decode_ratio,
+        regressed: encode_ratio < 1.0 - tolerance || decode_ratio < 1.0 - tolerance,
+    }
+}
+
+/// One scheme's results from [`compare_schemes`]. Times are means over the
+/// trials that encoded.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SchemeReport {
+    pub name:         String,
+    /// Values in an encoding, `0` if no trial encoded.
+    pub encoding_len: usize,
+    pub encode_secs:  f64,
+    /// Time to decode every key of a trial.
+    pub decode_secs:  f64,
+    pub trials:       usize,
+    pub failures:     usize,
+}
+
+impl SchemeReport {
+    pub fn failure_rate(&self) -> f64 {
+        self.failures as f64 / self.trials as f64
+    }
+}
+
+/// Runs every scheme of `schemes`, built for `kv_count` pairs, on the same
+/// `trials` sets of `kv_count` synthetic 8-byte pairs, a fresh set per trial
+/// so failure rates reflect independent key sets.
+///
+/// A trial fails if its encode fails or a key decodes to a wrong value.
+pub fn compare_schemes(
+    schemes: &[(&str, DynOkvs<OkvsKey, OkvsValue<8>>)],
+    kv_count: usize,
+    trials: usize,
+) -> Result<Vec<SchemeReport>> {
+    if kv_count == 0 || trials == 0 {
+        return Err(Error::Config(
+            "comparison needs at least one pair and one trial".into(),
+        ));
+    }
+
+    let mut reports: Vec<SchemeReport> = schemes
+        .iter()
+        .map(|(name, _)| SchemeReport {
+            name: name.to_string(),
+            encoding_len: 0,
+            encode_secs: 0.0,
+            decode_secs: 0.0,
+            trials,
+            failures: 0,
+        })
+        .collect();
+    for trial in 0..trials {
+        let offset = (trial * kv_count) as u64;
+        let pairs: Vec<Pair<OkvsKey, OkvsValue<8>>> = (offset..offset + kv_count as u64)
+            .map(|i| (OkvsKey(i.to_le_bytes()), OkvsValue(i.to_be_bytes())))
+            .collect();
+
+        for ((_, okvs), report) in schemes.iter().zip(&mut reports) {
+            let now = Instant::now();
+            let Ok(encoding) = okvs.encode(&pairs) else {
+                report.failures += 1;
+                continue;
+            };
+            let encode_secs = now.elapsed().as_secs_f64();
+
+            let now = Instant::now();
+            let correct = pairs
+                .iter()
+                .all(|(k, v)| okvs.decode(&encoding, k).is_ok_and(|d| d == *v));
+            let decode_secs = now.elapsed().as_secs_f64();
+            if !correct {
+                report.failures += 1;
+                continue;
+            }
+            report.encoding_len = encoding.len();
+            report.encode_secs += encode_secs;
+            report.decode_secs += decode_secs;
+        }
+    }
+
+    for report in &mut reports {
+        let encoded = (report.trials - report.failures).max(1) as f64;
+        report.encode_secs /= encoded;
+        report.decode_secs /= encoded;
+    }
+    Ok(reports)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::okvs::RbOkvs;
+    use crate::sparse::SparseOkvs;
+
+    #[test]
+    fn test_calibrate() {
+        let baseline = calibrate(&RbOkvs::builder(), 1000).unwrap();
+        assert_eq!(baseline.kv_count, 1000);
+        assert!(baseline.encode_rows_per_sec > 0.0);
+        assert!(baseline.decode_keys_per_sec > 0.0);
+
+        assert!(calibrate(&RbOkvs::builder(), 0).is_err());
+    }
+
+    #[test]
+    fn test_compare_to_baseline() {
+        let baseline = Baseline {
+            kv_count: 1000,
+            encode_rows_per_sec: 100.0,
+            decode_keys_per_sec: 100.0,
+        };
+        let slower = Baseline {
+            encode_rows_per_sec: 70.0,
+            ..baseline.clone()
+        };
+
+        let comparison = compare_to_baseline(&baseline, &baseline, 0.2);
+        assert_eq!(comparison.encode_ratio, 1.0);
+        assert!(!comparison.regressed);
+
+        let comparison = compare_to_baseline(&baseline, &slower, 0.2);
+        assert!(comparison.regressed);
+        assert!(!compare_to_baseline(&baseline, &slower, 0.5).regressed);
+    }
+
+    #[test]
+    fn test_compare_schemes() {
+        let schemes = [
+            ("rb-okvs", DynOkvs::new(RbOkvs::new(1000))),
+            ("sparse", DynOkvs::new(SparseOkvs::new(1000))),
+            // 1% overhead is far below the ~22% weight-3 rows need to peel,
+            // leaving a core with more rows than columns, so every encode fails
+            (
+                "too-small",
+                DynOkvs::new(SparseOkvs::with_params(1000, 3, 0.01).unwrap()),
+            ),
+        ];
+        let reports = compare_schemes(&schemes, 1000, 3).unwrap();
+
+        assert_eq!(reports[0].name, "rb-okvs");
+        assert_eq!(reports[0].encoding_len, RbOkvs::new(1000).columns());
+        assert_eq!(reports[1].encoding_len, SparseOkvs::new(1000).columns());
+        for report in &reports[..2] {
+            assert_eq!((report.trials, report.failures), (3, 0));
+            assert!(report.encode_secs > 0.0 && report.decode_secs > 0.0);
+        }
+        assert_eq!(reports[2].failure_rate(), 1.0);
+        assert!(compare_schemes(&schemes, 1000, 0).is_err());
+    }
+}
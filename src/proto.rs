@@ -0,0 +1,381 @@
+//! Protocol Buffers encodings of the EMM and PSI wire messages, so peers in
+//! other languages can generate their types from `proto/rb_okvs.proto`. The
+//! codecs here follow that schema by hand, without a code generator.
+
+use crate::emm::{QueryResponse, QueryToken, TokenFormat};
+use crate::error::{Error, Result};
+use crate::oprf::{Point, POINT_LEN};
+use crate::psi::{PsiCaResponse, PsiEncoding, PsiEvaluation, PsiRequest, PsiResponse, TAG_LEN};
+use crate::types::OkvsValue;
+use crate::utils::to_usize;
+
+const VARINT: u64 = 0;
+const FIXED64: u64 = 1;
+const LEN: u64 = 2;
+const FIXED32: u64 = 5;
+
+/// A message of `proto/rb_okvs.proto`.
+pub trait Proto: Sized {
+    fn to_proto(&self) -> Vec<u8>;
+    fn from_proto(bytes: &[u8]) -> Result<Self>;
+}
+
+impl Proto for QueryToken {
+    fn to_proto(&self) -> Vec<u8> {
+        let mut w = Writer::default();
+        w.bytes(1, &self.h);
+        w.bytes(2, &self.fingerprint);
+        w.0
+    }
+
+    fn from_proto(bytes: &[u8]) -> Result<Self> {
+        let mut h = vec![];
+        let mut fingerprint = None;
+        for field in fields(bytes)? {
+            match field {
+                (1, value) => h = value.bytes()?.to_vec(),
+                (2, value) => fingerprint = Some(fixed(value.bytes()?)?),
+                _ => {}
+            }
+        }
+        if h.len() != TokenFormat::Binary.token_len() && h.len() != TokenFormat::Hex.token_len() {
+            return Err(Error::Message(format!("token of {} bytes", h.len())));
+        }
+        Ok(Self {
+            fingerprint: fingerprint.ok_or_else(|| Error::Message("missing fingerprint".into()))?,
+            h,
+        })
+    }
+}
+
+impl<const OKVS_V_SIZE: usize> Proto for QueryResponse<OKVS_V_SIZE> {
+    fn to_proto(&self) -> Vec<u8> {
+        let mut w = Writer::default();
+        w.uint(1, OKVS_V_SIZE as u64);
+        for value in &self.values {
+            w.bytes(2, &value.0);
+        }
+        w.0
+    }
+
+    fn from_proto(bytes: &[u8]) -> Result<Self> {
+        let mut size = 0;
+        let mut values = vec![];
+        for field in fields(bytes)? {
+            match field {
+                (1, value) => size = value.uint()?,
+                (2, value) => values.push(OkvsValue(fixed(value.bytes()?)?)),
+                _ => {}
+            }
+        }
+        if size != OKVS_V_SIZE as u64 {
+            return Err(Error::Message(format!(
+                "value size {size}, expected {OKVS_V_SIZE}"
+            )));
+        }
+        Ok(Self { values })
+    }
+}
+
+impl Proto for PsiEncoding {
+    fn to_proto(&self) -> Vec<u8> {
+        let mut w = Writer::default();
+        w.uint(1, self.kv_count as u64);
+        w.uint(2, TAG_LEN as u64);
+        let columns: Vec<u8> = self.encoding.iter().flat_map(|v| v.0).collect();
+        w.bytes(3, &columns);
+        w.bytes(4, &self.fingerprint);
+        w.0
+    }
+
+    fn from_proto(bytes: &[u8]) -> Result<Self> {
+        let (mut kv_count, mut size, mut columns) = (0, 0, &[][..]);
+        let mut fingerprint = None;
+        for field in fields(bytes)? {
+            match field {
+                (1, value) => kv_count = value.uint()?,
+                (2, value) => size = value.uint()?,
+                (3, value) => columns = value.bytes()?,
+                (4, value) => fingerprint = Some(fixed(value.bytes()?)?),
+                _ => {}
+            }
+        }
+        if size != TAG_LEN as u64 || !columns.len().is_multiple_of(TAG_LEN) {
+            return Err(Error::Message(format!(
+                "{} bytes of columns of size {size}",
+                columns.len()
+            )));
+        }
+        Ok(Self {
+            kv_count: to_usize(kv_count)?,
+            fingerprint: fingerprint.ok_or_else(|| Error::Message("missing fingerprint".into()))?,
+            encoding: columns
+                .chunks_exact(TAG_LEN)
+                .map(|chunk| OkvsValue(chunk.try_into().unwrap()))
+                .collect(),
+        })
+    }
+}
+
+impl Proto for PsiRequest {
+    fn to_proto(&self) -> Vec<u8> {
+        let mut w = Writer::default();
+        w.points(1, &self.blinded);
+        w.0
+    }
+
+    fn from_proto(bytes: &[u8]) -> Result<Self> {
+        let mut blinded = vec![];
+        for field in fields(bytes)? {
+            if let (1, value) = field {
+                blinded.push(fixed::<POINT_LEN>(value.bytes()?)?);
+            }
+        }
+        Ok(Self { blinded })
+    }
+}
+
+impl Proto for PsiResponse {
+    fn to_proto(&self) -> Vec<u8> {
+        let mut w = Writer::default();
+        w.points(1, &self.evaluated);
+        let encoding = PsiEncoding {
+            kv_count: self.kv_count,
+            fingerprint: self.fingerprint,
+            encoding: self.encoding.clone(),
+        };
+        w.bytes(2, &encoding.to_proto());
+        w.0
+    }
+
+    fn from_proto(bytes: &[u8]) -> Result<Self> {
+        let mut evaluated = vec![];
+        let mut encoding = None;
+        for field in fields(bytes)? {
+            match field {
+                (1, value) => evaluated.push(fixed::<POINT_LEN>(value.bytes()?)?),
+                (2, value) => encoding = Some(PsiEncoding::from_proto(value.bytes()?)?),
+                _ => {}
+            }
+        }
+        let encoding = encoding.ok_or_else(|| Error::Message("missing encoding".into()))?;
+        Ok(Self {
+            evaluated,
+            kv_count: encoding.kv_count,
+            fingerprint: encoding.fingerprint,
+            encoding: encoding.encoding,
+        })
+    }
+}
+
+impl Proto for PsiEvaluation {
+    fn to_proto(&self) -> Vec<u8> {
+        let mut w = Writer::default();
+        w.points(1, &self.evaluated);
+        w.0
+    }
+
+    fn from_proto(bytes: &[u8]) -> Result<Self> {
+        let mut evaluated = vec![];
+        for field in fields(bytes)? {
+            if let (1, value) = field {
+                evaluated.push(fixed::<POINT_LEN>(value.bytes()?)?);
+            }
+        }
+        Ok(Self { evaluated })
+    }
+}
+
+impl Proto for PsiCaResponse {
+    fn to_proto(&self) -> Vec<u8> {
+        let mut w = Writer::default();
+        w.points(1, &self.evaluated);
+        for tag in &self.tags {
+            w.bytes(2, tag);
+        }
+        w.0
+    }
+
+    fn from_proto(bytes: &[u8]) -> Result<Self> {
+        let mut evaluated = vec![];
+        let mut tags = vec![];
+        for field in fields(bytes)? {
+            match field {
+                (1, value) => evaluated.push(fixed::<POINT_LEN>(value.bytes()?)?),
+                (2, value) => tags.push(fixed::<TAG_LEN>(value.bytes()?)?),
+                _ => {}
+            }
+        }
+        Ok(Self { evaluated, tags })
+    }
+}
+
+#[derive(Default)]
+struct Writer(Vec<u8>);
+
+impl Writer {
+    fn varint(&mut self, mut v: u64) {
+        while v >= 0x80 {
+            self.0.push(v as u8 | 0x80);
+            v >>= 7;
+        }
+        self.0.push(v as u8);
+    }
+
+    /// Zero is the default of a proto3 scalar, so it is left out.
+    fn uint(&mut self, field: u64, v: u64) {
+        if v != 0 {
+            self.varint(field << 3 | VARINT);
+            self.varint(v);
+        }
+    }
+
+    fn bytes(&mut self, field: u64, bytes: &[u8]) {
+        self.varint(field << 3 | LEN);
+        self.varint(bytes.len() as u64);
+        self.0.extend_from_slice(bytes);
+    }
+
+    fn points(&mut self, field: u64, points: &[Point]) {
+        for point in points {
+            self.bytes(field, point);
+        }
+    }
+}
+
+enum Value<'a> {
+    Varint(u64),
+    Bytes(&'a [u8]),
+    /// A fixed-width field, which no message here uses.
+    Fixed,
+}
+
+impl<'a> Value<'a> {
+    fn uint(&self) -> Result<u64> {
+        match self {
+            Value::Varint(v) => Ok(*v),
+            _ => Err(Error::Message("expected a varint field".into())),
+        }
+    }
+
+    fn bytes(&self) -> Result<&'a [u8]> {
+        match self {
+            Value::Bytes(bytes) => Ok(bytes),
+            _ => Err(Error::Message("expected a bytes field".into())),
+        }
+    }
+}
+
+/// The fields of a message in order, with their numbers. Unknown fields
+/// are returned too, for the caller to skip.
+fn fields(mut bytes: &[u8]) -> Result<Vec<(u64, Value<'_>)>> {
+    let mut fields = vec![];
+    while !bytes.is_empty() {
+        let key = read_varint(&mut bytes)?;
+        let value = match key & 7 {
+            VARINT => Value::Varint(read_varint(&mut bytes)?),
+            LEN => {
+                let len = read_varint(&mut bytes)?;
+                if len > bytes.len() as u64 {
+                    return Err(Error::Message(format!("field of {len} bytes")));
+                }
+                let (field, rest) = bytes.split_at(len as usize);
+                bytes = rest;
+                Value::Bytes(field)
+            }
+            wire @ (FIXED64 | FIXED32) => {
+                let len = if wire == FIXED64 { 8 } else { 4 };
+                bytes = bytes
+                    .get(len..)
+                    .ok_or_else(|| Error::Message("truncated fixed field".into()))?;
+                Value::Fixed
+            }
+            wire => return Err(Error::Message(format!("wire type {wire}"))),
+        };
+        fields.push((key >> 3, value));
+    }
+    Ok(fields)
+}
+
+fn read_varint(bytes: &mut &[u8]) -> Result<u64> {
+    let mut v = 0u64;
+    for (i, byte) in bytes.iter().enumerate().take(10) {
+        v |= ((byte & 0x7f) as u64) << (7 * i);
+        if byte & 0x80 == 0 {
+            *bytes = &bytes[i + 1..];
+            return Ok(v);
+        }
+    }
+    Err(Error::Message("malformed varint".into()))
+}
+
+fn fixed<const N: usize>(bytes: &[u8]) -> Result<[u8; N]> {
+    bytes
+        .try_into()
+        .map_err(|_| Error::Message(format!("{} bytes, expected {N}", bytes.len())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_messages() {
+        let token = QueryToken {
+            fingerprint: [9; 8],
+            h: vec![7; 32],
+        };
+        let bytes = token.to_proto();
+        // Field 1, length-delimited, 32 bytes
+        assert_eq!(bytes[..2], [0x0a, 0x20]);
+        assert_eq!(QueryToken::from_proto(&bytes).unwrap(), token);
+        assert!(QueryToken::from_proto(&bytes[..10]).is_err());
+        // A token without its fingerprint
+        assert!(QueryToken::from_proto(&bytes[..34]).is_err());
+
+        let response = QueryResponse::<4> {
+            values: vec![OkvsValue([1, 2, 3, 4]), OkvsValue([5; 4])],
+        };
+        let bytes = response.to_proto();
+        assert_eq!(bytes[..6], [0x08, 0x04, 0x12, 0x04, 1, 2]);
+        assert_eq!(QueryResponse::from_proto(&bytes).unwrap(), response);
+        assert!(QueryResponse::<8>::from_proto(&bytes).is_err());
+
+        let encoding = PsiEncoding {
+            kv_count: 300,
+            fingerprint: [9; 8],
+            encoding: vec![OkvsValue([3; TAG_LEN]); 5],
+        };
+        // Unknown fields of every wire type are skipped
+        let mut bytes = encoding.to_proto();
+        bytes.extend([
+            0x78, 0x01, 0x81, 0x01, 0, 0, 0, 0, 0, 0, 0, 0, 0x85, 0x01, 0, 0, 0, 0,
+        ]);
+        assert_eq!(PsiEncoding::from_proto(&bytes).unwrap(), encoding);
+
+        let response = PsiResponse {
+            evaluated: vec![[1; POINT_LEN], [2; POINT_LEN]],
+            kv_count: encoding.kv_count,
+            fingerprint: encoding.fingerprint,
+            encoding: encoding.encoding.clone(),
+        };
+        assert_eq!(
+            PsiResponse::from_proto(&response.to_proto()).unwrap(),
+            response
+        );
+
+        let ca = PsiCaResponse {
+            evaluated: vec![[4; POINT_LEN]],
+            tags: vec![[5; TAG_LEN], [6; TAG_LEN]],
+        };
+        assert_eq!(PsiCaResponse::from_proto(&ca.to_proto()).unwrap(), ca);
+        let request = PsiRequest {
+            blinded: vec![[8; POINT_LEN]],
+        };
+        assert_eq!(
+            PsiRequest::from_proto(&request.to_proto()).unwrap(),
+            request
+        );
+        assert!(PsiEvaluation::from_proto(&request.to_proto()[..20]).is_err());
+    }
+}
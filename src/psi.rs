@@ -0,0 +1,610 @@
+use std::collections::HashSet;
+
+use curve25519_dalek::ristretto::RistrettoPoint;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::error::{Error, Result};
+use crate::okvs::Fingerprint;
+use crate::oprf::{
+    check_version, decoder, encode_padded, okvs_key, read_points, write_points, Blind, OprfKey,
+    Point,
+};
+use crate::types::{Encoding, Okvs, OkvsValue};
+#[cfg(feature = "serde")]
+use crate::utils::serialize_to_bytes;
+use crate::utils::{hash, to_u32, to_usize};
+
+/// Bytes of the tag the sender encodes per item. A receiver item matches a
+/// tag decoded for it by chance with probability 2^-128.
+pub const TAG_LEN: usize = 16;
+/// Leading byte of serialized [`PsiRequest`]s and [`PsiResponse`]s.
+pub const MESSAGE_VERSION: u8 = 2;
+const TAG_DOMAIN: &[u8] = b"rb-okvs/psi/tag";
+const CA_TAG_DOMAIN: &[u8] = b"rb-okvs/psi/ca-tag";
+
+/// Sender of the PSI: learns nothing, its set is only revealed to the
+/// receiver where the two intersect.
+///
+/// The protocol takes one round trip. The receiver blinds its items for a
+/// DH-based OPRF, `F_k(x) = k · H(x)`, in a [`PsiRequest`]. The sender
+/// evaluates them under its key `k` and encodes its own set, each item
+/// mapped to a tag of its OPRF output, into an OKVS, both sent back in a
+/// [`PsiResponse`]. The receiver unblinds its OPRF outputs and keeps the
+/// items whose tags match the OKVS decoded at them. Secure against
+/// semi-honest parties.
+///
+/// In the cardinality variant, [`PsiSender::respond_cardinality`], the
+/// sender returns the evaluations in an order unrelated to the request and
+/// the tags of its set instead of an OKVS, so the receiver can count matches
+/// without learning which of its items they are.
+pub struct PsiSender {
+    set: Vec<Vec<u8>>,
+    key: OprfKey,
+}
+
+/// Sender of the PSI for a large set that rarely changes, queried by many
+/// receivers with small sets.
+///
+/// The set is encoded once, offline, under a long-term OPRF key into a
+/// [`PsiEncoding`] that can be published or cached by receivers. Each
+/// session then only evaluates the receiver's blinded items into a
+/// [`PsiEvaluation`], linear in the receiver's set. A receiver holding the
+/// key's outputs of its items learns which of them are in the set, as in
+/// [`PsiSender`].
+pub struct StaticSetSender {
+    key:      OprfKey,
+    encoding: PsiEncoding,
+    bytes:    Vec<u8>,
+}
+
+/// Receiver of the PSI, learns the intersection, see [`PsiSender`].
+pub struct PsiReceiver {
+    set:   Vec<Vec<u8>>,
+    blind: Blind,
+}
+
+/// The receiver's items under a random blind, in set order.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+#[cfg_attr(feature = "serde", serde(try_from = "Vec<u8>"))]
+pub struct PsiRequest {
+    pub blinded: Vec<Point>,
+}
+
+#[cfg(feature = "serde")]
+serialize_to_bytes!(PsiRequest);
+
+/// The request under the sender's key, and the sender's set encoded into an
+/// OKVS of `kv_count` rows and fingerprint `fingerprint`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+#[cfg_attr(feature = "serde", serde(try_from = "Vec<u8>"))]
+pub struct PsiResponse {
+    pub evaluated:   Vec<Point>,
+    pub kv_count:    usize,
+    pub fingerprint: Fingerprint,
+    pub encoding:    Encoding<OkvsValue<TAG_LEN>>,
+}
+
+#[cfg(feature = "serde")]
+serialize_to_bytes!(PsiResponse);
+
+/// A [`StaticSetSender`]'s set encoded into an OKVS of `kv_count` rows and
+/// fingerprint `fingerprint`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(into = "Vec<u8>", try_from = "Vec<u8>"))]
+pub struct PsiEncoding {
+    pub kv_count:    usize,
+    pub fingerprint: Fingerprint,
+    pub encoding:    Encoding<OkvsValue<TAG_LEN>>,
+}
+
+/// A request under a [`StaticSetSender`]'s key.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+#[cfg_attr(feature = "serde", serde(try_from = "Vec<u8>"))]
+pub struct PsiEvaluation {
+    pub evaluated: Vec<Point>,
+}
+
+#[cfg(feature = "serde")]
+serialize_to_bytes!(PsiEvaluation);
+
+/// The request under the sender's key and the tags of the sender's set, both
+/// sorted so their order reveals nothing.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+#[cfg_attr(feature = "serde", serde(try_from = "Vec<u8>"))]
+pub struct PsiCaResponse {
+    pub evaluated: Vec<Point>,
+    pub tags:      Vec<[u8; TAG_LEN]>,
+}
+
+#[cfg(feature = "serde")]
+serialize_to_bytes!(PsiCaResponse);
+
+impl PsiSender {
+    pub fn new<T: AsRef<[u8]>>(set: &[T]) -> Self {
+        Self {
+            set: set.iter().map(|x| x.as_ref().to_vec()).collect(),
+            key: OprfKey::random(),
+        }
+    }
+
+    pub fn respond(&self, request: &PsiRequest) -> Result<PsiResponse> {
+        let evaluated = self.key.eval_blinded(&request.blinded)?;
+        let pairs = self
+            .set
+            .iter()
+            .map(|x| (okvs_key(x), tag(x, &self.key.eval(x))))
+            .collect();
+        let (kv_count, fingerprint, encoding) = encode_padded(pairs)?;
+        Ok(PsiResponse {
+            evaluated,
+            kv_count,
+            fingerprint,
+            encoding,
+        })
+    }
+
+    /// Like [`PsiSender::respond`], for a receiver that may only learn the
+    /// size of the intersection.
+    pub fn respond_cardinality(&self, request: &PsiRequest) -> Result<PsiCaResponse> {
+        let mut evaluated = self.key.eval_blinded(&request.blinded)?;
+        evaluated.sort_unstable();
+        let mut tags: Vec<_> = self.set.iter().map(|x| ca_tag(&self.key.eval(x))).collect();
+        tags.sort_unstable();
+        Ok(PsiCaResponse { evaluated, tags })
+    }
+}
+
+impl StaticSetSender {
+    /// The offline phase: encodes `set` under a fresh key.
+    pub fn new<T: AsRef<[u8]>>(set: &[T]) -> Result<Self> {
+        let key = OprfKey::random();
+        let pairs = set
+            .iter()
+            .map(|x| (okvs_key(x.as_ref()), tag(x.as_ref(), &key.eval(x.as_ref()))))
+            .collect();
+        let (kv_count, fingerprint, encoding) = encode_padded(pairs)?;
+        let encoding = PsiEncoding {
+            kv_count,
+            fingerprint,
+            encoding,
+        };
+        Ok(Self {
+            key,
+            bytes: encoding.to_bytes(),
+            encoding,
+        })
+    }
+
+    /// The same for every session.
+    pub fn encoding(&self) -> &PsiEncoding {
+        &self.encoding
+    }
+
+    /// [`PsiEncoding::to_bytes`] of the encoding, serialized once.
+    pub fn encoding_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// The online phase of one session.
+    pub fn evaluate(&self, request: &PsiRequest) -> Result<PsiEvaluation> {
+        Ok(PsiEvaluation {
+            evaluated: self.key.eval_blinded(&request.blinded)?,
+        })
+    }
+}
+
+impl PsiReceiver {
+    pub fn new<T: AsRef<[u8]>>(set: &[T]) -> Self {
+        Self {
+            set: set.iter().map(|y| y.as_ref().to_vec()).collect(),
+            blind: Blind::random(),
+        }
+    }
+
+    pub fn request(&self) -> PsiRequest {
+        PsiRequest {
+            blinded: self.blind.blind(&self.set),
+        }
+    }
+
+    /// Items of the receiver's set the sender holds too, in set order.
+    pub fn intersect(&self, response: &PsiResponse) -> Result<Vec<Vec<u8>>> {
+        self.intersect_with(
+            &response.evaluated,
+            response.kv_count,
+            &response.fingerprint,
+            &response.encoding,
+        )
+    }
+
+    /// Like [`PsiReceiver::intersect`], with a [`StaticSetSender`]'s
+    /// published encoding and its evaluation of this receiver's request.
+    pub fn intersect_static(
+        &self,
+        encoding: &PsiEncoding,
+        evaluation: &PsiEvaluation,
+    ) -> Result<Vec<Vec<u8>>> {
+        self.intersect_with(
+            &evaluation.evaluated,
+            encoding.kv_count,
+            &encoding.fingerprint,
+            &encoding.encoding,
+        )
+    }
+
+    fn intersect_with(
+        &self,
+        evaluated: &[Point],
+        kv_count: usize,
+        fingerprint: &Fingerprint,
+        encoding: &Encoding<OkvsValue<TAG_LEN>>,
+    ) -> Result<Vec<Vec<u8>>> {
+        self.check_evaluated(evaluated)?;
+        let okvs = decoder(kv_count, fingerprint, encoding)?;
+        let outputs = self.blind.unblind(evaluated)?;
+        let mut intersection = vec![];
+        for (y, f) in self.set.iter().zip(outputs) {
+            if okvs.decode(encoding, &okvs_key(y))? == tag(y, &f) {
+                intersection.push(y.clone());
+            }
+        }
+        Ok(intersection)
+    }
+
+    /// Size of the intersection, from a [`PsiSender::respond_cardinality`]
+    /// response.
+    pub fn cardinality(&self, response: &PsiCaResponse) -> Result<usize> {
+        self.check_evaluated(&response.evaluated)?;
+        let tags: HashSet<&[u8; TAG_LEN]> = response.tags.iter().collect();
+        let outputs = self.blind.unblind(&response.evaluated)?;
+        Ok(outputs.iter().filter(|f| tags.contains(&ca_tag(f))).count())
+    }
+
+    fn check_evaluated(&self, evaluated: &[Point]) -> Result<()> {
+        match evaluated.len() == self.set.len() {
+            true => Ok(()),
+            false => Err(Error::Message(format!(
+                "{} evaluations for {} items",
+                evaluated.len(),
+                self.set.len()
+            ))),
+        }
+    }
+}
+
+impl PsiRequest {
+    /// `version || count (u32 LE) || points`
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        let mut bytes = vec![MESSAGE_VERSION];
+        write_points(&mut bytes, &self.blinded)?;
+        Ok(bytes)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let (blinded, rest) = read_points(check_version(bytes, MESSAGE_VERSION)?)?;
+        if !rest.is_empty() {
+            return Err(Error::Message(format!("{} trailing bytes", rest.len())));
+        }
+        Ok(Self { blinded })
+    }
+}
+
+impl PsiResponse {
+    /// `version || count (u32 LE) || points || kv_count (u64 LE) ||
+    /// fingerprint || values`
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        let mut bytes = vec![MESSAGE_VERSION];
+        write_points(&mut bytes, &self.evaluated)?;
+        bytes.extend_from_slice(&(self.kv_count as u64).to_le_bytes());
+        bytes.extend_from_slice(&self.fingerprint);
+        for value in &self.encoding {
+            bytes.extend_from_slice(&value.0);
+        }
+        Ok(bytes)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let (evaluated, rest) = read_points(check_version(bytes, MESSAGE_VERSION)?)?;
+        let (kv_count, fingerprint, encoding) = read_encoding(rest)?;
+        Ok(Self {
+            evaluated,
+            kv_count,
+            fingerprint,
+            encoding,
+        })
+    }
+}
+
+impl PsiEncoding {
+    /// `version || kv_count (u64 LE) || fingerprint || values`
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![MESSAGE_VERSION];
+        bytes.extend_from_slice(&(self.kv_count as u64).to_le_bytes());
+        bytes.extend_from_slice(&self.fingerprint);
+        for value in &self.encoding {
+            bytes.extend_from_slice(&value.0);
+        }
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let (kv_count, fingerprint, encoding) =
+            read_encoding(check_version(bytes, MESSAGE_VERSION)?)?;
+        Ok(Self {
+            kv_count,
+            fingerprint,
+            encoding,
+        })
+    }
+}
+
+impl PsiEvaluation {
+    /// `version || count (u32 LE) || points`
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        let mut bytes = vec![MESSAGE_VERSION];
+        write_points(&mut bytes, &self.evaluated)?;
+        Ok(bytes)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let (evaluated, rest) = read_points(check_version(bytes, MESSAGE_VERSION)?)?;
+        if !rest.is_empty() {
+            return Err(Error::Message(format!("{} trailing bytes", rest.len())));
+        }
+        Ok(Self { evaluated })
+    }
+}
+
+impl PsiCaResponse {
+    /// `version || count (u32 LE) || points || count (u32 LE) || tags`
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        let mut bytes = vec![MESSAGE_VERSION];
+        write_points(&mut bytes, &self.evaluated)?;
+        bytes.extend_from_slice(&to_u32(self.tags.len())?.to_le_bytes());
+        for tag in &self.tags {
+            bytes.extend_from_slice(tag);
+        }
+        Ok(bytes)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let (evaluated, rest) = read_points(check_version(bytes, MESSAGE_VERSION)?)?;
+        if rest.len() < 4 {
+            return Err(Error::Message("truncated tag count".into()));
+        }
+        let count = u32::from_le_bytes(rest[..4].try_into().unwrap()) as usize;
+        let body = &rest[4..];
+        if count.checked_mul(TAG_LEN) != Some(body.len()) {
+            return Err(Error::Message(format!(
+                "{} bytes for {count} tags",
+                body.len()
+            )));
+        }
+        let tags = body
+            .chunks_exact(TAG_LEN)
+            .map(|chunk| chunk.try_into().unwrap())
+            .collect();
+        Ok(Self { evaluated, tags })
+    }
+}
+
+impl TryFrom<PsiRequest> for Vec<u8> {
+    type Error = Error;
+
+    fn try_from(request: PsiRequest) -> Result<Self> {
+        request.to_bytes()
+    }
+}
+
+impl TryFrom<Vec<u8>> for PsiRequest {
+    type Error = Error;
+
+    fn try_from(bytes: Vec<u8>) -> Result<Self> {
+        Self::from_bytes(&bytes)
+    }
+}
+
+impl TryFrom<PsiResponse> for Vec<u8> {
+    type Error = Error;
+
+    fn try_from(response: PsiResponse) -> Result<Self> {
+        response.to_bytes()
+    }
+}
+
+impl TryFrom<Vec<u8>> for PsiResponse {
+    type Error = Error;
+
+    fn try_from(bytes: Vec<u8>) -> Result<Self> {
+        Self::from_bytes(&bytes)
+    }
+}
+
+impl From<PsiEncoding> for Vec<u8> {
+    fn from(encoding: PsiEncoding) -> Self {
+        encoding.to_bytes()
+    }
+}
+
+impl TryFrom<Vec<u8>> for PsiEncoding {
+    type Error = Error;
+
+    fn try_from(bytes: Vec<u8>) -> Result<Self> {
+        Self::from_bytes(&bytes)
+    }
+}
+
+impl TryFrom<PsiEvaluation> for Vec<u8> {
+    type Error = Error;
+
+    fn try_from(evaluation: PsiEvaluation) -> Result<Self> {
+        evaluation.to_bytes()
+    }
+}
+
+impl TryFrom<Vec<u8>> for PsiEvaluation {
+    type Error = Error;
+
+    fn try_from(bytes: Vec<u8>) -> Result<Self> {
+        Self::from_bytes(&bytes)
+    }
+}
+
+impl TryFrom<PsiCaResponse> for Vec<u8> {
+    type Error = Error;
+
+    fn try_from(response: PsiCaResponse) -> Result<Self> {
+        response.to_bytes()
+    }
+}
+
+impl TryFrom<Vec<u8>> for PsiCaResponse {
+    type Error = Error;
+
+    fn try_from(bytes: Vec<u8>) -> Result<Self> {
+        Self::from_bytes(&bytes)
+    }
+}
+
+/// `kv_count (u64 LE) || fingerprint || values`
+fn read_encoding(bytes: &[u8]) -> Result<(usize, Fingerprint, Encoding<OkvsValue<TAG_LEN>>)> {
+    if bytes.len() < 16 || !(bytes.len() - 16).is_multiple_of(TAG_LEN) {
+        return Err(Error::Message("truncated encoding".into()));
+    }
+    let kv_count = to_usize(u64::from_le_bytes(bytes[..8].try_into().unwrap()))?;
+    let encoding = bytes[16..]
+        .chunks_exact(TAG_LEN)
+        .map(|chunk| OkvsValue(chunk.try_into().unwrap()))
+        .collect();
+    Ok((kv_count, bytes[8..16].try_into().unwrap(), encoding))
+}
+
+/// Binds the OPRF output to its item, so tags of different items never
+/// collide on purpose.
+fn tag(item: &[u8], f: &RistrettoPoint) -> OkvsValue<TAG_LEN> {
+    let data = [TAG_DOMAIN, f.compress().as_bytes(), item].concat();
+    OkvsValue(hash(&data, TAG_LEN).try_into().unwrap())
+}
+
+/// Tag of the cardinality variant, which the receiver compares without
+/// knowing the item it belongs to.
+fn ca_tag(f: &RistrettoPoint) -> [u8; TAG_LEN] {
+    let data = [CA_TAG_DOMAIN, f.compress().as_bytes()].concat();
+    hash(&data, TAG_LEN).try_into().unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::oprf::POINT_LEN;
+
+    #[test]
+    fn test_psi() {
+        let sender_set: Vec<Vec<u8>> = (0..2000u32).map(|i| i.to_le_bytes().to_vec()).collect();
+        let receiver_set: Vec<Vec<u8>> =
+            (1500..2500u32).map(|i| i.to_le_bytes().to_vec()).collect();
+
+        let sender = PsiSender::new(&sender_set);
+        let receiver = PsiReceiver::new(&receiver_set);
+        let request = PsiRequest::from_bytes(&receiver.request().to_bytes().unwrap()).unwrap();
+        let response = sender.respond(&request).unwrap();
+        let response = PsiResponse::from_bytes(&response.to_bytes().unwrap()).unwrap();
+
+        let intersection = receiver.intersect(&response).unwrap();
+        assert_eq!(intersection, receiver_set[..500].to_vec());
+
+        // Another sender key doesn't match
+        let other = PsiSender::new(&sender_set).respond(&request).unwrap();
+        let mut mixed = response.clone();
+        mixed.evaluated = other.evaluated;
+        assert!(receiver.intersect(&mixed).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_small_sets() {
+        let sender = PsiSender::new(&["apple", "pear"]);
+        let receiver = PsiReceiver::new(&["pear", "plum", "apple"]);
+        let response = sender.respond(&receiver.request()).unwrap();
+        assert_eq!(
+            receiver.intersect(&response).unwrap(),
+            vec![b"pear".to_vec(), b"apple".to_vec()]
+        );
+
+        let empty = PsiSender::new::<&str>(&[]);
+        assert!(receiver
+            .intersect(&empty.respond(&receiver.request()).unwrap())
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_cardinality() {
+        let sender = PsiSender::new(&["a", "b", "c", "d"]);
+        let receiver = PsiReceiver::new(&["d", "e", "b"]);
+        let response = sender.respond_cardinality(&receiver.request()).unwrap();
+        let response = PsiCaResponse::from_bytes(&response.to_bytes().unwrap()).unwrap();
+        assert_eq!(receiver.cardinality(&response).unwrap(), 2);
+
+        let mut sorted = response.evaluated.clone();
+        sorted.sort_unstable();
+        assert_eq!(response.evaluated, sorted);
+        assert_eq!(response.tags.len(), 4);
+
+        let disjoint = PsiSender::new(&["x"]);
+        let response = disjoint.respond_cardinality(&receiver.request()).unwrap();
+        assert_eq!(receiver.cardinality(&response).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_static_set() {
+        let server_set: Vec<Vec<u8>> = (0..5000u32).map(|i| i.to_le_bytes().to_vec()).collect();
+        let sender = StaticSetSender::new(&server_set).unwrap();
+        let published = PsiEncoding::from_bytes(sender.encoding_bytes()).unwrap();
+        assert_eq!(&published, sender.encoding());
+
+        for query in [[4999u32, 5000], [7, 8]] {
+            let items: Vec<Vec<u8>> = query.iter().map(|i| i.to_le_bytes().to_vec()).collect();
+            let receiver = PsiReceiver::new(&items);
+            let evaluation = sender.evaluate(&receiver.request()).unwrap();
+            let evaluation = PsiEvaluation::from_bytes(&evaluation.to_bytes().unwrap()).unwrap();
+            let intersection = receiver.intersect_static(&published, &evaluation).unwrap();
+            let expected: Vec<Vec<u8>> = query
+                .iter()
+                .filter(|i| **i < 5000)
+                .map(|i| i.to_le_bytes().to_vec())
+                .collect();
+            assert_eq!(intersection, expected);
+        }
+    }
+
+    #[test]
+    fn test_malformed() {
+        let receiver = PsiReceiver::new(&["a", "b"]);
+        let request = receiver.request();
+        let mut bytes = request.to_bytes().unwrap();
+        bytes.pop();
+        assert!(PsiRequest::from_bytes(&bytes).is_err());
+
+        let mut invalid = request.clone();
+        invalid.blinded[0] = [0xff; POINT_LEN];
+        assert!(PsiSender::new(&["a"]).respond(&invalid).is_err());
+
+        let mut response = PsiSender::new(&["a"]).respond(&request).unwrap();
+        let mut foreign = response.clone();
+        foreign.fingerprint[0] ^= 1;
+        assert!(matches!(
+            receiver.intersect(&foreign),
+            Err(Error::ParamsMismatch("OKVS"))
+        ));
+        response.encoding.pop();
+        assert!(receiver.intersect(&response).is_err());
+        response.evaluated.pop();
+        assert!(receiver.intersect(&response).is_err());
+    }
+}
@@ -0,0 +1,134 @@
+use std::sync::Arc;
+
+use crate::error::{Error, Result};
+use crate::executor::Executor;
+use crate::hasher::{Blake2Hasher, KeyHasher};
+use crate::okvs::RbOkvs;
+use crate::types::{Encoding, Okvs, OkvsK, OkvsV};
+
+/// An [`RbOkvs`] and one of its encodings behind [`Arc`]s, for servers that
+/// decode from many threads.
+///
+/// A reader is `Send + Sync` whenever its values and hasher are, and
+/// cloning it only bumps the reference counts. Decoding takes `&self` and
+/// reads the instance and the encoding without any interior mutability, so
+/// any number of threads may decode from one reader or its clones at once,
+/// without locking, and get the values a single thread would.
+pub struct OkvsReader<V, const W: usize = 4, H = Blake2Hasher> {
+    okvs:     Arc<RbOkvs<W, H>>,
+    encoding: Arc<Encoding<V>>,
+}
+
+impl<V: OkvsV, const W: usize, H: KeyHasher> OkvsReader<V, W, H> {
+    /// Fails with [`Error::EncodingTooShort`] when `encoding` has fewer
+    /// values than the encodings of `okvs`, so decodes can't.
+    pub fn new(okvs: RbOkvs<W, H>, encoding: Encoding<V>) -> Result<Self> {
+        if encoding.len() < okvs.columns() {
+            return Err(Error::EncodingTooShort {
+                required: okvs.columns(),
+                actual: encoding.len(),
+            });
+        }
+        Ok(Self {
+            okvs: Arc::new(okvs),
+            encoding: Arc::new(encoding),
+        })
+    }
+
+    pub fn okvs(&self) -> &RbOkvs<W, H> {
+        &self.okvs
+    }
+
+    pub fn encoding(&self) -> &Encoding<V> {
+        &self.encoding
+    }
+
+    pub fn decode(&self, key: &impl OkvsK) -> Result<V> {
+        self.okvs.decode(&self.encoding, key)
+    }
+
+    /// See [`Okvs::decode_batch`].
+    pub fn decode_batch<K, E>(&self, executor: &E, keys: &[K]) -> Result<Vec<V>>
+    where
+        K: OkvsK + Sync,
+        V: Send + Sync,
+        H: Sync,
+        E: Executor,
+    {
+        self.okvs.decode_batch(executor, &self.encoding, keys)
+    }
+}
+
+impl<V, const W: usize, H> Clone for OkvsReader<V, W, H> {
+    fn clone(&self) -> Self {
+        Self {
+            okvs: self.okvs.clone(),
+            encoding: self.encoding.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+    use crate::types::{OkvsKey, OkvsValue, Pair};
+    extern crate test;
+
+    fn reader(kv_count: usize) -> (Vec<Pair<OkvsKey, OkvsValue<8>>>, OkvsReader<OkvsValue<8>>) {
+        let pairs: Vec<Pair<OkvsKey, OkvsValue<8>>> = (0..kv_count as u64)
+            .map(|i| (OkvsKey(i.to_le_bytes()), OkvsValue((i * 3).to_le_bytes())))
+            .collect();
+        let rb_okvs = RbOkvs::new(pairs.len());
+        let encoding = rb_okvs.encode_ref(&pairs).unwrap();
+        (pairs, OkvsReader::new(rb_okvs, encoding).unwrap())
+    }
+
+    /// Decodes 1000 keys on each of `threads` threads, so the time stays
+    /// flat as threads are added while decoding scales linearly.
+    fn decode_on_threads(
+        pairs: &[Pair<OkvsKey, OkvsValue<8>>],
+        reader: &OkvsReader<OkvsValue<8>>,
+        threads: usize,
+    ) {
+        thread::scope(|s| {
+            for chunk in pairs.chunks(1000).take(threads) {
+                let reader = reader.clone();
+                s.spawn(move || {
+                    for (k, v) in chunk {
+                        assert_eq!(test::black_box(reader.decode(k).unwrap()), *v);
+                    }
+                });
+            }
+        });
+    }
+
+    #[test]
+    fn test_reader() {
+        fn shareable<T: Send + Sync + Clone>() {}
+        shareable::<OkvsReader<OkvsValue<8>>>();
+
+        let (pairs, reader) = reader(10000);
+        decode_on_threads(&pairs, &reader, 8);
+        assert_eq!(reader.encoding().len(), reader.okvs().columns());
+
+        let rb_okvs = RbOkvs::new(pairs.len());
+        assert!(matches!(
+            OkvsReader::new(rb_okvs, vec![OkvsValue([0u8; 8]); 100]),
+            Err(Error::EncodingTooShort { actual: 100, .. })
+        ));
+    }
+
+    #[bench]
+    fn bench_reader_1_thread(b: &mut test::Bencher) {
+        let (pairs, reader) = reader(100000);
+        b.iter(|| decode_on_threads(&pairs, &reader, 1));
+    }
+
+    #[bench]
+    fn bench_reader_4_threads(b: &mut test::Bencher) {
+        let (pairs, reader) = reader(100000);
+        b.iter(|| decode_on_threads(&pairs, &reader, 4));
+    }
+}
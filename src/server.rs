@@ -0,0 +1,66 @@
+use std::future::Future;
+
+use crate::cipher::EmmCipher;
+use crate::emm::{QueryResponse, QueryToken, VhEmmClient, VhEmmServer};
+use crate::error::Result;
+use crate::prf::EmmPrf;
+use crate::types::{EmmK, EmmV, Okvs};
+
+/// Server role of the EMM behind an async interface, so the same client code
+/// runs against an in-process [`VhEmmServer`] or a remote one.
+///
+/// A query takes two rounds: the length entry of the token, then `v_len`
+/// entries, see [`query`]. With the `tokio` feature,
+/// [`serve`](crate::tcp::serve) exposes one over TCP.
+pub trait EmmServer<const OKVS_V_SIZE: usize>: Send + Sync {
+    /// See [`VhEmmServer::answer_len`].
+    fn answer_len(
+        &self,
+        token: QueryToken,
+    ) -> impl Future<Output = Result<QueryResponse<OKVS_V_SIZE>>> + Send;
+
+    /// See [`VhEmmServer::answer`].
+    fn answer_query(
+        &self,
+        token: QueryToken,
+        v_len: usize,
+    ) -> impl Future<Output = Result<QueryResponse<OKVS_V_SIZE>>> + Send;
+}
+
+impl<T, const OKVS_K_SIZE: usize, const OKVS_V_SIZE: usize> EmmServer<OKVS_V_SIZE>
+    for VhEmmServer<T, OKVS_K_SIZE, OKVS_V_SIZE>
+where
+    T: Okvs + Send + Sync,
+{
+    async fn answer_len(&self, token: QueryToken) -> Result<QueryResponse<OKVS_V_SIZE>> {
+        VhEmmServer::answer_len(self, &token)
+    }
+
+    async fn answer_query(
+        &self,
+        token: QueryToken,
+        v_len: usize,
+    ) -> Result<QueryResponse<OKVS_V_SIZE>> {
+        self.answer(&token, v_len)
+    }
+}
+
+/// Looks up the values of `key` on `server`. Keys that were never set up fail
+/// with [`Error::NotFound`](crate::error::Error::NotFound).
+pub async fn query<S, P, C, K, V, const OKVS_V_SIZE: usize>(
+    client: &VhEmmClient<P, C>,
+    server: &S,
+    key: &K,
+) -> Result<Vec<V>>
+where
+    S: EmmServer<OKVS_V_SIZE>,
+    P: EmmPrf,
+    C: EmmCipher,
+    K: EmmK,
+    V: EmmV,
+{
+    let token = client.gen_token(key);
+    let v_len = client.resolve_len(&token, server.answer_len(token.clone()).await?)?;
+    let response = server.answer_query(token.clone(), v_len).await?;
+    client.resolve(&token, response)
+}
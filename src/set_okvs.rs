@@ -0,0 +1,215 @@
+use aes_gcm::aead::OsRng;
+
+use crate::error::{Error, Result};
+use crate::okvs::RbOkvs;
+use crate::prf::{EmmPrf, HmacSha256};
+use crate::types::{Okvs, OkvsKey, OkvsValue};
+use crate::utils::to_usize;
+
+/// Widest tag, in bits.
+pub const MAX_TAG_BITS: u32 = 32;
+const WORD_BITS: usize = u64::BITS as usize;
+
+/// Set membership over an OKVS: each member encodes to a `bits`-bit PRF tag
+/// of itself, and a key is a member when the encoding decodes to its tag.
+/// Non-members decode to a random value, so false positives occur with
+/// probability 2^-bits.
+///
+/// The OKVS positions and tags are keyed, so without `key` the encoding
+/// reveals nothing about the set but its size.
+pub struct SetOkvs<P = HmacSha256> {
+    key:  [u8; 32],
+    bits: u32,
+    prf:  P,
+}
+
+/// Encoding of a [`SetOkvs`], `bits` bits per column packed into words.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PackedEncoding {
+    bits:    u32,
+    columns: usize,
+    words:   Vec<u64>,
+}
+
+impl SetOkvs {
+    pub fn new(key: [u8; 32], bits: u32) -> Result<Self> {
+        Self::with_prf(HmacSha256, key, bits)
+    }
+}
+
+impl<P: EmmPrf> SetOkvs<P> {
+    /// `bits` must be in `1..=MAX_TAG_BITS`.
+    pub fn with_prf(prf: P, key: [u8; 32], bits: u32) -> Result<Self> {
+        if !(1..=MAX_TAG_BITS).contains(&bits) {
+            return Err(Error::Config(format!(
+                "tag bits must be in 1..={MAX_TAG_BITS}, got {bits}"
+            )));
+        }
+        Ok(Self { key, bits, prf })
+    }
+
+    /// Members must be distinct.
+    pub fn encode<T: AsRef<[u8]>>(&self, set: &[T]) -> Result<PackedEncoding> {
+        let pairs: Vec<_> = set
+            .iter()
+            .map(|x| {
+                let (k, tag) = self.locate(x.as_ref());
+                (k, OkvsValue(tag.to_le_bytes()))
+            })
+            .collect();
+        let encoding = RbOkvs::new(set.len()).encode_with_rng(&pairs, &mut OsRng)?;
+
+        let mut packed = PackedEncoding {
+            bits: self.bits,
+            columns: encoding.len(),
+            words: vec![0; (encoding.len() * self.bits as usize).div_ceil(WORD_BITS)],
+        };
+        for (i, v) in encoding.iter().enumerate() {
+            packed.set(i, u32::from_le_bytes(v.0));
+        }
+        Ok(packed)
+    }
+
+    pub fn contains(&self, encoding: &PackedEncoding, key: &[u8]) -> bool {
+        if encoding.bits != self.bits || encoding.columns == 0 {
+            return false;
+        }
+        let okvs = RbOkvs::for_columns(encoding.columns);
+        let (k, tag) = self.locate(key);
+        let (start, band) = okvs.locate(&k);
+        let mut v = 0;
+        band.for_each_one(|i| v ^= encoding.get(start + i));
+        v == tag
+    }
+
+    /// OKVS key and tag of `key`.
+    fn locate(&self, key: &[u8]) -> (OkvsKey<16>, u32) {
+        let out = self.prf.eval(&self.key, key);
+        let tag = u32::from_le_bytes(out[16..20].try_into().unwrap());
+        (
+            OkvsKey(out[..16].try_into().unwrap()),
+            tag & mask(self.bits),
+        )
+    }
+}
+
+impl PackedEncoding {
+    pub fn bits(&self) -> u32 {
+        self.bits
+    }
+
+    pub fn columns(&self) -> usize {
+        self.columns
+    }
+
+    /// `bits (u8) || columns (u64 LE) || words (u64 LE)`
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![self.bits as u8];
+        bytes.extend_from_slice(&(self.columns as u64).to_le_bytes());
+        for word in &self.words {
+            bytes.extend_from_slice(&word.to_le_bytes());
+        }
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() < 9 {
+            return Err(Error::Message("truncated set encoding".into()));
+        }
+        let bits = bytes[0] as u32;
+        let columns = to_usize(u64::from_le_bytes(bytes[1..9].try_into().unwrap()))?;
+        let words = columns
+            .checked_mul(bits as usize)
+            .map(|b| b.div_ceil(WORD_BITS));
+        match words {
+            Some(words) if (1..=MAX_TAG_BITS).contains(&bits) && bytes.len() - 9 == words * 8 => {
+                Ok(Self {
+                    bits,
+                    columns,
+                    words: bytes[9..]
+                        .chunks_exact(8)
+                        .map(|c| u64::from_le_bytes(c.try_into().unwrap()))
+                        .collect(),
+                })
+            }
+            _ => Err(Error::Message(format!(
+                "{} bytes for {columns} columns of {bits} bits",
+                bytes.len()
+            ))),
+        }
+    }
+
+    fn get(&self, column: usize) -> u32 {
+        let bit = column * self.bits as usize;
+        let (word, offset) = (bit / WORD_BITS, bit % WORD_BITS);
+        let mut v = self.words[word] >> offset;
+        if offset + self.bits as usize > WORD_BITS {
+            v |= self.words[word + 1] << (WORD_BITS - offset);
+        }
+        v as u32 & mask(self.bits)
+    }
+
+    fn set(&mut self, column: usize, value: u32) {
+        let bit = column * self.bits as usize;
+        let (word, offset) = (bit / WORD_BITS, bit % WORD_BITS);
+        let value = (value & mask(self.bits)) as u64;
+        self.words[word] |= value << offset;
+        if offset + self.bits as usize > WORD_BITS {
+            self.words[word + 1] |= value >> (WORD_BITS - offset);
+        }
+    }
+}
+
+fn mask(bits: u32) -> u32 {
+    u32::MAX >> (MAX_TAG_BITS - bits)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_set_okvs() {
+        let set: Vec<[u8; 4]> = (0..2000u32).map(|i| i.to_le_bytes()).collect();
+        for bits in [1, 7, 20, 32] {
+            let okvs = SetOkvs::new([3u8; 32], bits).unwrap();
+            let encoding = okvs.encode(&set).unwrap();
+            assert_eq!(
+                encoding.to_bytes().len(),
+                9 + (2200 * bits as usize).div_ceil(64) * 8
+            );
+            assert!(set.iter().all(|x| okvs.contains(&encoding, x)));
+
+            let false_positives = (2000..4000u32)
+                .filter(|i| okvs.contains(&encoding, &i.to_le_bytes()))
+                .count();
+            if bits == 32 {
+                assert_eq!(false_positives, 0);
+            }
+            if bits == 1 {
+                assert!((700..1300).contains(&false_positives));
+            }
+
+            let other = SetOkvs::new([4u8; 32], bits).unwrap();
+            assert!(!set.iter().all(|x| other.contains(&encoding, x)));
+        }
+        assert!(SetOkvs::new([3u8; 32], 0).is_err());
+        assert!(SetOkvs::new([3u8; 32], 33).is_err());
+    }
+
+    #[test]
+    fn test_packed_bytes() {
+        let set: Vec<String> = (0..500).map(|i| format!("item{i}")).collect();
+        let okvs = SetOkvs::new([5u8; 32], 12).unwrap();
+        let encoding = okvs.encode(&set).unwrap();
+
+        let bytes = encoding.to_bytes();
+        let decoded = PackedEncoding::from_bytes(&bytes).unwrap();
+        assert_eq!(decoded, encoding);
+        assert!(okvs.contains(&decoded, b"item0"));
+
+        assert!(PackedEncoding::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+        assert!(PackedEncoding::from_bytes(&[0; 9]).is_err());
+        assert!(PackedEncoding::from_bytes(&[]).is_err());
+    }
+}
@@ -0,0 +1,232 @@
+use aes_gcm::aead::rand_core::RngCore;
+
+use crate::error::{Error, Result};
+use crate::executor::Executor;
+use crate::okvs::{Fingerprint, RbOkvs};
+use crate::params::{OkvsParams, SecurityLevel};
+use crate::solver::inner_product;
+use crate::types::{Encoding, Okvs, OkvsK, OkvsV, Pair, RandomV, Seed};
+use crate::utils::{blake2b, reduce};
+
+const SHARD_DOMAIN: &[u8] = b"rb-okvs/shard";
+/// Standard deviations of slack a shard gets over its expected row count.
+const CAPACITY_SIGMAS: f64 = 8.0;
+
+/// An OKVS split into independent [`RbOkvs`] shards of equal size, for inputs
+/// too large for a single elimination.
+///
+/// Keys are hashed to a shard, so shards encode independently (in parallel
+/// with [`ShardedOkvs::par_encode`]) and a decode only reads its own shard.
+/// The encoding is the shard encodings back to back.
+///
+/// Each shard holds up to [`ShardedOkvs::capacity`] rows, a few standard
+/// deviations above the average; a fuller shard fails the encoding with
+/// [`Error::ShardOverflow`], which is negligibly likely for random keys.
+#[derive(Clone, Debug)]
+pub struct ShardedOkvs {
+    okvs:     RbOkvs,
+    shards:   usize,
+    capacity: usize,
+}
+
+impl ShardedOkvs {
+    /// `kv_count` pairs over `shards` shards with the default parameters.
+    pub fn new(kv_count: usize, shards: usize) -> Result<Self> {
+        let capacity = capacity(kv_count, shards)?;
+        Ok(Self {
+            okvs: RbOkvs::new(capacity),
+            shards,
+            capacity,
+        })
+    }
+
+    /// Splits the failure probability budget of `level` across the shards:
+    /// each shard is built for 2^-(λ + log2 shards), so by the union bound
+    /// the whole encoding fails with probability at most 2^-λ.
+    pub fn for_(kv_count: usize, shards: usize, level: SecurityLevel) -> Result<Self> {
+        let capacity = capacity(kv_count, shards)?;
+        let lambda = level.lambda() + shards.next_power_of_two().ilog2();
+        Ok(Self {
+            okvs: OkvsParams::for_lambda(capacity, lambda).build()?,
+            shards,
+            capacity,
+        })
+    }
+
+    /// The same instance with every shard salted by `seed`, see
+    /// [`RbOkvs::with_seed`]. Routing keys to shards uses it too.
+    pub fn with_seed(mut self, seed: Seed) -> Self {
+        self.okvs = self.okvs.with_seed(seed);
+        self
+    }
+
+    pub fn shards(&self) -> usize {
+        self.shards
+    }
+
+    /// Most rows a shard takes.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Values in an encoding, over all shards.
+    pub fn columns(&self) -> usize {
+        self.shards * self.okvs.columns()
+    }
+
+    /// Instance every shard is encoded with.
+    pub fn shard(&self) -> &RbOkvs {
+        &self.okvs
+    }
+
+    /// Shard `key` is encoded in.
+    pub fn shard_of<K: OkvsK>(&self, key: &K) -> usize {
+        let data = [SHARD_DOMAIN, self.okvs.seed(), &key.to_bytes()].concat();
+        reduce(u64::from_le_bytes(blake2b::<8>(&data)), self.shards)
+    }
+
+    /// Union bound over the shards, see [`RbOkvs::estimated_failure_prob`].
+    pub fn estimated_failure_prob(&self) -> Option<f64> {
+        self.okvs
+            .estimated_failure_prob()
+            .map(|p| (p * self.shards as f64).min(1.0))
+    }
+
+    /// Encodes the shards concurrently on `executor`.
+    pub fn par_encode<K, V, E>(&self, executor: &E, input: &[Pair<K, V>]) -> Result<Encoding<V>>
+    where
+        K: OkvsK + Sync,
+        V: OkvsV + Send + Sync,
+        E: Executor,
+    {
+        let shards = self.partition(input)?;
+        let encodings = executor.map(&shards, |pairs| self.okvs.encode_ref(pairs));
+        Ok(encodings.into_iter().collect::<Result<Vec<_>>>()?.concat())
+    }
+
+    /// Pairs of every shard, borrowing the keys.
+    fn partition<'a, K: OkvsK, V: OkvsV>(
+        &self,
+        input: &'a [Pair<K, V>],
+    ) -> Result<Vec<Vec<Pair<&'a K, V>>>> {
+        let mut shards = vec![vec![]; self.shards];
+        for (k, v) in input {
+            shards[self.shard_of(k)].push((k, v.clone()));
+        }
+        match shards.iter().position(|s| s.len() > self.capacity) {
+            Some(shard) => Err(Error::ShardOverflow {
+                shard,
+                rows: shards[shard].len(),
+                capacity: self.capacity,
+            }),
+            None => Ok(shards),
+        }
+    }
+}
+
+impl Okvs for ShardedOkvs {
+    fn encode<K: OkvsK, V: OkvsV>(&self, input: Vec<Pair<K, V>>) -> Result<Encoding<V>> {
+        self.encode_ref(&input)
+    }
+
+    fn encode_ref<K: OkvsK, V: OkvsV>(&self, input: &[Pair<K, V>]) -> Result<Encoding<V>> {
+        let mut encoding = Vec::with_capacity(self.columns());
+        for pairs in self.partition(input)? {
+            encoding.extend(self.okvs.encode(pairs)?);
+        }
+        Ok(encoding)
+    }
+
+    fn encode_with_rng<K, V, R>(&self, input: &[Pair<K, V>], rng: &mut R) -> Result<Encoding<V>>
+    where
+        K: OkvsK,
+        V: RandomV,
+        R: RngCore + ?Sized,
+    {
+        let mut encoding = Vec::with_capacity(self.columns());
+        for pairs in self.partition(input)? {
+            encoding.extend(self.okvs.encode_with_rng(&pairs, rng)?);
+        }
+        Ok(encoding)
+    }
+
+    fn decode<V: OkvsV>(&self, encoding: &Encoding<V>, key: &impl OkvsK) -> Result<V> {
+        if encoding.len() < self.columns() {
+            return Err(Error::EncodingTooShort {
+                required: self.columns(),
+                actual: encoding.len(),
+            });
+        }
+        let offset = self.shard_of(key) * self.okvs.columns();
+        let (start, band) = self.okvs.locate(key);
+        Ok(inner_product(&band, &encoding[offset + start..]))
+    }
+
+    fn fingerprint(&self) -> Fingerprint {
+        let mut data = SHARD_DOMAIN.to_vec();
+        data.extend_from_slice(&(self.shards as u64).to_le_bytes());
+        data.extend_from_slice(&self.okvs.fingerprint());
+        blake2b::<8>(&data)
+    }
+}
+
+/// Expected rows per shard plus [`CAPACITY_SIGMAS`] standard deviations.
+fn capacity(kv_count: usize, shards: usize) -> Result<usize> {
+    if shards == 0 {
+        return Err(Error::Config("at least one shard required".into()));
+    }
+    let mean = kv_count.div_ceil(shards) as f64;
+    Ok((mean + CAPACITY_SIGMAS * (mean.sqrt() + 1.0)).ceil() as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::executor::StdThreads;
+    use crate::types::{OkvsKey, OkvsValue};
+
+    fn pairs(n: u64) -> Vec<Pair<OkvsKey, OkvsValue<8>>> {
+        (0..n)
+            .map(|i| (OkvsKey(i.to_le_bytes()), OkvsValue((i * 3).to_le_bytes())))
+            .collect()
+    }
+
+    #[test]
+    fn test_sharded_okvs() {
+        let pairs = pairs(20000);
+        let okvs = ShardedOkvs::new(pairs.len(), 8).unwrap();
+        let encoding = okvs.encode_ref(&pairs).unwrap();
+        assert_eq!(encoding.len(), okvs.columns());
+        for (k, v) in &pairs {
+            assert_eq!(okvs.decode(&encoding, k).unwrap(), *v);
+        }
+        assert_eq!(
+            okvs.par_encode(&StdThreads::new(4), &pairs).unwrap(),
+            encoding
+        );
+
+        let seeded = okvs.clone().with_seed([1; 16]);
+        assert!((0..100u64).any(|i| {
+            let key = OkvsKey(i.to_le_bytes());
+            okvs.shard_of(&key) != seeded.shard_of(&key)
+        }));
+    }
+
+    #[test]
+    fn test_shard_params() {
+        assert!(ShardedOkvs::new(1000, 0).is_err());
+
+        let okvs = ShardedOkvs::new(100, 4).unwrap();
+        assert!(matches!(
+            okvs.encode(pairs(1000)),
+            Err(Error::ShardOverflow { capacity: 73, .. })
+        ));
+
+        let okvs = ShardedOkvs::for_(1 << 16, 16, SecurityLevel::Lambda20).unwrap();
+        assert_eq!(
+            okvs.shard().band_width(),
+            OkvsParams::for_lambda(0, 24).band_width
+        );
+        assert!(okvs.estimated_failure_prob().unwrap() <= 2f64.powi(-20));
+    }
+}
@@ -0,0 +1,100 @@
+use aes_gcm::aead::rand_core::RngCore;
+
+use crate::error::{Error, Result};
+use crate::types::{Encoding, Okvs, OkvsK, OkvsV, RandomV};
+
+/// Splits `encoding` into `k` XOR shares, any `k - 1` of which are uniformly
+/// random: `k - 1` random encodings, and the encoding masked by all of them.
+pub fn split_shares<V: RandomV, R: RngCore + ?Sized>(
+    encoding: &Encoding<V>,
+    k: usize,
+    rng: &mut R,
+) -> Result<Vec<Encoding<V>>> {
+    if k == 0 {
+        return Err(Error::Config("at least one share required".into()));
+    }
+    let mut last = encoding.clone();
+    let mut shares: Vec<Encoding<V>> = Vec::with_capacity(k);
+    for _ in 1..k {
+        let share: Encoding<V> = (0..encoding.len()).map(|_| V::random(rng)).collect();
+        for (l, s) in last.iter_mut().zip(&share) {
+            l.in_place_xor(s);
+        }
+        shares.push(share);
+    }
+    shares.push(last);
+    Ok(shares)
+}
+
+/// XOR of `shares`, the encoding they were split from.
+pub fn combine_shares<V: OkvsV>(shares: &[Encoding<V>]) -> Result<Encoding<V>> {
+    let Some((first, rest)) = shares.split_first() else {
+        return Err(Error::Config("no shares to combine".into()));
+    };
+    let mut encoding = first.clone();
+    for share in rest {
+        if share.len() != encoding.len() {
+            return Err(Error::Message(format!(
+                "share of {} values for an encoding of {}",
+                share.len(),
+                encoding.len()
+            )));
+        }
+        for (e, s) in encoding.iter_mut().zip(share) {
+            e.in_place_xor(s);
+        }
+    }
+    Ok(encoding)
+}
+
+/// A server's partial answer for `key`. Decoding is linear in the encoding,
+/// so the XOR of the partial answers of all shares, see [`reconstruct`], is
+/// the decoded value; a single one is uniformly random.
+pub fn decode_share<O: Okvs, V: OkvsV>(
+    okvs: &O,
+    share: &Encoding<V>,
+    key: &impl OkvsK,
+) -> Result<V> {
+    okvs.decode(share, key)
+}
+
+/// Decoded value from the partial answers of all shares.
+pub fn reconstruct<V: OkvsV>(partials: &[V]) -> V {
+    partials.iter().fold(V::default(), |acc, p| acc.xor(p))
+}
+
+#[cfg(test)]
+mod tests {
+    use aes_gcm::aead::OsRng;
+
+    use super::*;
+    use crate::okvs::RbOkvs;
+    use crate::types::{OkvsKey, OkvsValue, Pair};
+
+    #[test]
+    fn test_shares() {
+        let pairs: Vec<Pair<OkvsKey, OkvsValue<16>>> = (0..1000u64)
+            .map(|i| (OkvsKey(i.to_le_bytes()), OkvsValue([i as u8; 16])))
+            .collect();
+        let okvs = RbOkvs::new(pairs.len());
+        let encoding = okvs.encode_ref(&pairs).unwrap();
+
+        let shares = split_shares(&encoding, 3, &mut OsRng).unwrap();
+        assert_eq!(shares.len(), 3);
+        assert!(shares.iter().all(|s| *s != encoding));
+        assert_eq!(combine_shares(&shares).unwrap(), encoding);
+
+        for (k, v) in &pairs {
+            let partials: Vec<_> = shares
+                .iter()
+                .map(|s| decode_share(&okvs, s, k).unwrap())
+                .collect();
+            assert_eq!(reconstruct(&partials), *v);
+        }
+
+        assert_eq!(split_shares(&encoding, 1, &mut OsRng).unwrap()[0], encoding);
+        assert!(split_shares(&encoding, 0, &mut OsRng).is_err());
+        assert!(combine_shares::<OkvsValue<16>>(&[]).is_err());
+        assert!(combine_shares(&[encoding.clone(), encoding[1..].to_vec()]).is_err());
+    }
+}
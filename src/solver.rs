@@ -0,0 +1,547 @@
+//! Gaussian elimination for banded linear systems over GF(2), the core of
+//! RB-OKVS encoding.
+
+use std::ops::Range;
+
+use crate::band::Band;
+use crate::error::{Error, Result};
+use crate::executor::Executor;
+use crate::trace::{trace_event, trace_span};
+use crate::types::OkvsV;
+
+/// A banded linear system `Ax = y` over GF(2): every row has ones only in a
+/// band of at most `64 * W` columns from its start, and a right-hand side
+/// in any [`OkvsV`].
+///
+/// Rows may be pushed in any order. Solving sorts them by start and runs
+/// [`simple_gauss`], which takes `O(rows · band width)` word operations.
+#[derive(Clone, Debug)]
+pub struct BandSystem<V, const W: usize = 4> {
+    columns:   usize,
+    bands:     Vec<Band<W>>,
+    start_pos: Vec<usize>,
+    y:         Vec<V>,
+}
+
+impl<V: OkvsV, const W: usize> BandSystem<V, W> {
+    /// An empty system over `columns` unknowns.
+    pub fn new(columns: usize) -> Self {
+        Self {
+            columns,
+            bands: vec![],
+            start_pos: vec![],
+            y: vec![],
+        }
+    }
+
+    /// Adds the row `<band, x[start..]> = y`. Fails with
+    /// [`Error::BandOutOfRange`] if the band reaches past the last column.
+    pub fn push_row(&mut self, start: usize, band: Band<W>, y: V) -> Result<()> {
+        if start.saturating_add(band.bits()) > self.columns {
+            return Err(Error::BandOutOfRange {
+                row: self.bands.len(),
+                columns: self.columns,
+            });
+        }
+        self.bands.push(band);
+        self.start_pos.push(start);
+        self.y.push(y);
+        Ok(())
+    }
+
+    pub fn columns(&self) -> usize {
+        self.columns
+    }
+
+    pub fn rows(&self) -> usize {
+        self.bands.len()
+    }
+
+    /// A solution with every free unknown set to zero. Fails with
+    /// [`Error::ZeroRow`], naming a row in push order, when the rows are
+    /// linearly dependent.
+    pub fn solve(self) -> Result<Vec<V>> {
+        let mut order: Vec<usize> = (0..self.bands.len()).collect();
+        order.sort_by_key(|&i| self.start_pos[i]);
+
+        let bands = order.iter().map(|&i| self.bands[i]).collect();
+        let start_pos = order.iter().map(|&i| self.start_pos[i]).collect();
+        let y = order.iter().map(|&i| self.y[i].clone()).collect();
+        simple_gauss(y, bands, start_pos, self.columns).map_err(|e| match e {
+            Error::ZeroRow(i) => Error::ZeroRow(order[i]),
+            e => e,
+        })
+    }
+}
+
+/// Martin Dietzfelbinger and Stefan Walzer. Efficient Gauss Elimination for
+/// Near-Quadratic Matrices with One Short Random Block per Row, with
+/// Applications. In 27th Annual European Symposium on Algorithms (ESA 2019).
+/// Schloss Dagstuhl-Leibniz-Zentrum fuer Informatik, 2019.
+pub fn simple_gauss<V: OkvsV, const W: usize>(
+    y: Vec<V>,
+    bands: Vec<Band<W>>,
+    start_pos: Vec<usize>,
+    cols: usize,
+) -> Result<Vec<V>> {
+    gauss_with_free(y, bands, start_pos, vec![V::default(); cols])
+}
+
+/// [`simple_gauss`] with the free unknowns taken from `x` rather than set to
+/// zero: pivot columns of `x` are overwritten, the rest kept. Solutions are
+/// in bijection with the values of the free unknowns, so a uniformly random
+/// `x` gives a uniformly random solution.
+pub fn gauss_with_free<V: OkvsV, const W: usize>(
+    mut y: Vec<V>,
+    mut bands: Vec<Band<W>>,
+    start_pos: Vec<usize>,
+    mut x: Vec<V>,
+) -> Result<Vec<V>> {
+    eliminate_in_place(&mut y, &mut bands, &start_pos)?;
+    back_substitute(&bands, &start_pos, &y, &mut x);
+    Ok(x)
+}
+
+/// [`simple_gauss`] on caller-owned buffers, without allocating.
+///
+/// Rows must be sorted by `start_pos`, and `out` must have room for every
+/// band, i.e. `out.len() >= start_pos[i] + band width`. `bands` and `y` are
+/// left in their eliminated form.
+pub fn solve_in_place<V: OkvsV, const W: usize>(
+    bands: &mut [Band<W>],
+    start_pos: &[usize],
+    y: &mut [V],
+    out: &mut [V],
+) -> Result<()> {
+    eliminate_in_place(y, bands, start_pos)?;
+    out.fill(V::default());
+    back_substitute(bands, start_pos, y, out);
+    Ok(())
+}
+
+/// Solves the eliminated rows last to first. A row's band holds its own
+/// pivot, which is cleared first, and pivots of later rows only, so every
+/// other column it reads is final or free.
+pub(crate) fn back_substitute<V: OkvsV, const W: usize>(
+    bands: &[Band<W>],
+    start_pos: &[usize],
+    y: &[V],
+    x: &mut [V],
+) {
+    trace_span!("back_substitute", rows = bands.len());
+    for i in (0..bands.len()).rev() {
+        let p = pivot(&bands[i], start_pos[i]);
+        x[p] = V::default();
+        x[p] = solve_row(&bands[i], &x[start_pos[i]..], &y[i]);
+    }
+}
+
+/// Forward elimination of [`simple_gauss`], returns the pivot column of every
+/// row.
+pub fn gauss_eliminate<V: OkvsV, const W: usize>(
+    y: &mut [V],
+    bands: &mut [Band<W>],
+    start_pos: &[usize],
+) -> Result<Vec<usize>> {
+    eliminate_in_place(y, bands, start_pos)?;
+    Ok(bands
+        .iter()
+        .zip(start_pos)
+        .map(|(band, start)| pivot(band, *start))
+        .collect())
+}
+
+/// A row's pivot is its first one, which elimination of later rows leaves
+/// untouched, so it doesn't need to be stored.
+fn pivot<const W: usize>(band: &Band<W>, start: usize) -> usize {
+    start + band.trailing_zeros()
+}
+
+fn eliminate_in_place<V: OkvsV, const W: usize>(
+    y: &mut [V],
+    bands: &mut [Band<W>],
+    start_pos: &[usize],
+) -> Result<()> {
+    eliminate_rows(y, bands, start_pos, 0..bands.len())
+}
+
+/// Forward elimination pivoting on `pivot_rows` only, later rows are updated
+/// but not eliminated themselves. Rows before `pivot_rows` must already be
+/// eliminated.
+pub(crate) fn eliminate_rows<V: OkvsV, const W: usize>(
+    y: &mut [V],
+    bands: &mut [Band<W>],
+    start_pos: &[usize],
+    pivot_rows: Range<usize>,
+) -> Result<()> {
+    let rows = bands.len();
+    assert_eq!(rows, start_pos.len());
+    assert_eq!(rows, y.len());
+    trace_span!("eliminate", rows = rows);
+    let mut xors = 0usize;
+    let eliminated = pivot_rows.len();
+
+    for i in pivot_rows {
+        if bands[i].is_zero() {
+            return Err(Error::ZeroRow(i));
+        }
+        let pivot_i = pivot(&bands[i], start_pos[i]);
+
+        // Borrow row i and the rows below it at the same time
+        let (bands_head, bands_tail) = bands.split_at_mut(i + 1);
+        let (y_head, y_tail) = y.split_at_mut(i + 1);
+        let (band_i, y_i) = (&bands_head[i], &y_head[i]);
+
+        for (k, (band_k, y_k)) in bands_tail.iter_mut().zip(y_tail).enumerate() {
+            let start_k = start_pos[i + 1 + k];
+            if start_k > pivot_i {
+                break;
+            }
+            if band_k.bit(pivot_i - start_k) {
+                // start_k >= start_pos[i] since rows are sorted
+                band_k.xor_shr(band_i, start_k - start_pos[i]);
+                y_k.in_place_xor(y_i);
+                xors += 1;
+            }
+        }
+    }
+    trace_event!("eliminated", rows = eliminated, xors = xors);
+    Ok(())
+}
+
+/// Right-to-left elimination: every row pivots on its last one, which is
+/// cleared from the rows before it and from `sep`, rows that precede `bands`
+/// in sorted order.
+fn eliminate_backward<V: OkvsV, const W: usize>(
+    y: &mut [V],
+    bands: &mut [Band<W>],
+    start_pos: &[usize],
+    sep_y: &mut [V],
+    sep_bands: &mut [Band<W>],
+    sep_start_pos: &[usize],
+    band_width: usize,
+) -> Result<()> {
+    for j in (0..bands.len()).rev() {
+        if bands[j].is_zero() {
+            return Err(Error::ZeroRow(j));
+        }
+        let pivot_j = last_pivot(&bands[j], start_pos[j]);
+
+        let (bands_head, bands_tail) = bands.split_at_mut(j);
+        let (y_head, y_tail) = y.split_at_mut(j);
+        let (band_j, y_j) = (&bands_tail[0], &y_tail[0]);
+
+        let above = bands_head.iter_mut().zip(y_head).zip(&start_pos[..j]);
+        let sep = sep_bands.iter_mut().zip(&mut *sep_y).zip(sep_start_pos);
+        for ((band_k, y_k), start_k) in above.rev().chain(sep.rev()) {
+            if start_k + band_width <= pivot_j {
+                break;
+            }
+            if band_k.bit(pivot_j - start_k) {
+                // start_k <= start_pos[j] since rows are sorted
+                band_k.xor_shl(band_j, start_pos[j] - start_k);
+                y_k.in_place_xor(y_j);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn last_pivot<const W: usize>(band: &Band<W>, start: usize) -> usize {
+    start + band.bits() - 1
+}
+
+fn offset_row(err: Error, offset: usize) -> Error {
+    match err {
+        Error::ZeroRow(i) => Error::ZeroRow(i + offset),
+        err => err,
+    }
+}
+
+/// [`simple_gauss`] with the two halves of the system eliminated concurrently
+/// on `executor`.
+///
+/// Rows left of a separator pivot on their first one, rows right of it on
+/// their last one, so the halves never touch each other's rows and only
+/// update disjoint columns of the separator. The separator, the rows within
+/// `band_width` columns of both halves, is eliminated sequentially afterwards.
+/// Falls back to [`simple_gauss`] when the system is too small to split.
+pub fn par_simple_gauss<V, E, const W: usize>(
+    executor: &E,
+    mut y: Vec<V>,
+    mut bands: Vec<Band<W>>,
+    start_pos: Vec<usize>,
+    cols: usize,
+    band_width: usize,
+) -> Result<Vec<V>>
+where
+    V: OkvsV + Send + Sync,
+    E: Executor,
+{
+    let rows = bands.len();
+    let a = rows / 2;
+    let b = match a.checked_sub(1) {
+        Some(last) => {
+            let end = start_pos[last] + band_width;
+            a + start_pos[a..].partition_point(|start| *start < end)
+        }
+        None => rows,
+    };
+    if b >= rows {
+        return simple_gauss(y, bands, start_pos, cols);
+    }
+
+    // The left half updates the separator in place, the right half a copy
+    let sep_bands_orig = bands[a..b].to_vec();
+    let sep_y_orig = y[a..b].to_vec();
+    let mut sep_bands = sep_bands_orig.clone();
+    let mut sep_y = sep_y_orig.clone();
+
+    let (bands_left, bands_right) = bands.split_at_mut(b);
+    let (y_left, y_right) = y.split_at_mut(b);
+    let (start_left, start_right) = start_pos.split_at(b);
+    let (left, right) = executor.join(
+        || eliminate_rows(y_left, bands_left, start_left, 0..a),
+        || {
+            eliminate_backward(
+                y_right,
+                bands_right,
+                start_right,
+                &mut sep_y,
+                &mut sep_bands,
+                &start_pos[a..b],
+                band_width,
+            )
+        },
+    );
+    left?;
+    right.map_err(|e| offset_row(e, b))?;
+
+    for k in 0..b - a {
+        sep_bands[k] ^= &sep_bands_orig[k];
+        sep_y[k].in_place_xor(&sep_y_orig[k]);
+        bands[a + k] ^= &sep_bands[k];
+        y[a + k].in_place_xor(&sep_y[k]);
+    }
+    eliminate_in_place(&mut y[a..b], &mut bands[a..b], &start_pos[a..b])
+        .map_err(|e| offset_row(e, a))?;
+
+    // back substitution: separator first, then both halves concurrently
+    let mut x = vec![V::default(); cols];
+    for i in (a..b).rev() {
+        x[pivot(&bands[i], start_pos[i])] = solve_row(&bands[i], &x[start_pos[i]..], &y[i]);
+    }
+
+    let split = start_pos[b];
+    let (x_left, x_right) = x.split_at_mut(split);
+    executor.join(
+        || {
+            for i in (0..a).rev() {
+                x_left[pivot(&bands[i], start_pos[i])] =
+                    solve_row(&bands[i], &x_left[start_pos[i]..], &y[i]);
+            }
+        },
+        || {
+            for j in b..rows {
+                x_right[last_pivot(&bands[j], start_pos[j]) - split] =
+                    solve_row(&bands[j], &x_right[start_pos[j] - split..], &y[j]);
+            }
+        },
+    );
+    Ok(x)
+}
+
+pub fn inner_product<V: OkvsV, const W: usize>(m: &Band<W>, x: &[V]) -> V {
+    let mut result = V::default();
+    m.for_each_one(|i| result.in_place_xor(&x[i]));
+    result
+}
+
+/// `y ^ <m, x>`, the value of a row's pivot in back substitution, built in
+/// one value rather than an inner product and its XOR with `y`.
+pub(crate) fn solve_row<V: OkvsV, const W: usize>(m: &Band<W>, x: &[V], y: &V) -> V {
+    let mut result = y.clone();
+    m.for_each_one(|i| result.in_place_xor(&x[i]));
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::OkvsValue;
+    use crate::utils::{blake2b, hash};
+
+    #[test]
+    fn test_band_system() {
+        let columns = 1100;
+        let mut system = BandSystem::<OkvsValue<8>, 1>::new(columns);
+        let mut rows = vec![];
+        for i in 0..1000u64 {
+            let h = u64::from_le_bytes(blake2b::<8>(&i.to_le_bytes()));
+            let start = (h % (columns as u64 - 64)) as usize;
+            let band = Band::from(h | 1);
+            system
+                .push_row(start, band, OkvsValue(i.to_le_bytes()))
+                .unwrap();
+            rows.push((start, band));
+        }
+        assert_eq!(system.rows(), 1000);
+
+        let x = system.solve().unwrap();
+        assert_eq!(x.len(), columns);
+        for (i, (start, band)) in rows.iter().enumerate() {
+            assert_eq!(
+                inner_product(band, &x[*start..]).0,
+                (i as u64).to_le_bytes()
+            );
+        }
+
+        // Rows 2 and 0 are equal but disagree
+        let mut system = BandSystem::<OkvsValue<1>>::new(10);
+        system
+            .push_row(3, Band::from(0b101), OkvsValue([1]))
+            .unwrap();
+        system
+            .push_row(1, Band::from(0b11), OkvsValue([1]))
+            .unwrap();
+        system
+            .push_row(3, Band::from(0b101), OkvsValue([0]))
+            .unwrap();
+        assert!(matches!(system.solve(), Err(Error::ZeroRow(2))));
+
+        let mut system = BandSystem::<OkvsValue<1>>::new(10);
+        assert!(matches!(
+            system.push_row(8, Band::from(0b111), OkvsValue([1])),
+            Err(Error::BandOutOfRange {
+                row: 0,
+                columns: 10
+            })
+        ));
+    }
+    #[test]
+    fn test_gaussian() {
+        let u: Band = Band::from(0b11);
+        let matrix = vec![u, u, u];
+
+        let start_pos = vec![0, 1, 2];
+
+        let y = vec![
+            OkvsValue([0u8; 32]),
+            OkvsValue([1u8; 32]),
+            OkvsValue([2u8; 32]),
+        ];
+
+        let x = simple_gauss::<OkvsValue<32>, 4>(y.clone(), matrix.clone(), start_pos, 4).unwrap();
+
+        assert_eq!(inner_product(&matrix[0], &x), y[0]);
+        assert_eq!(inner_product(&matrix[1], &x[1..]), y[1]);
+        assert_eq!(inner_product(&matrix[2], &x[2..]), y[2]);
+    }
+
+    #[test]
+    fn test_solve_in_place() {
+        let u: Band = Band::from(0b11);
+        let mut bands = vec![u, u, u];
+        let start_pos = vec![0, 1, 2];
+        let mut y = vec![
+            OkvsValue([0u8; 32]),
+            OkvsValue([1u8; 32]),
+            OkvsValue([2u8; 32]),
+        ];
+        let mut x = vec![OkvsValue([9u8; 32]); 4];
+
+        solve_in_place(&mut bands, &start_pos, &mut y, &mut x).unwrap();
+
+        assert_eq!(inner_product(&u, &x), OkvsValue([0u8; 32]));
+        assert_eq!(inner_product(&u, &x[1..]), OkvsValue([1u8; 32]));
+        assert_eq!(inner_product(&u, &x[2..]), OkvsValue([2u8; 32]));
+
+        let mut bands = vec![u, Band::default()];
+        let mut y = vec![OkvsValue([0u8; 32]); 2];
+        assert!(solve_in_place(&mut bands, &[0, 1], &mut y, &mut x).is_err());
+    }
+
+    #[test]
+    fn test_par_gaussian() {
+        use crate::executor::{Sequential, StdThreads};
+
+        let rows = 2000;
+        let band_width = 64;
+        let cols = rows + rows / 10;
+        let mut start_pos: Vec<usize> = (0..rows)
+            .map(|i| blake2b::<8>(&(i as u64).to_le_bytes()))
+            .map(|h| (u64::from_le_bytes(h) % (cols - band_width) as u64) as usize)
+            .collect();
+        start_pos.sort();
+        let bands: Vec<Band> = (0..rows)
+            .map(|i| {
+                let mut h = hash(&(i as u64).to_be_bytes(), band_width / 8);
+                h[0] |= 1;
+                Band::from_le_bytes(&h)
+            })
+            .collect();
+        let y: Vec<OkvsValue<8>> = (0..rows)
+            .map(|i| OkvsValue((i as u64).to_le_bytes()))
+            .collect();
+
+        for x in [
+            par_simple_gauss(
+                &Sequential,
+                y.clone(),
+                bands.clone(),
+                start_pos.clone(),
+                cols,
+                band_width,
+            )
+            .unwrap(),
+            par_simple_gauss(
+                &StdThreads::new(2),
+                y.clone(),
+                bands.clone(),
+                start_pos.clone(),
+                cols,
+                band_width,
+            )
+            .unwrap(),
+        ] {
+            for i in 0..rows {
+                assert_eq!(inner_product(&bands[i], &x[start_pos[i]..]), y[i]);
+            }
+        }
+
+        // Too small to split
+        let x = par_simple_gauss(
+            &Sequential,
+            y[..10].to_vec(),
+            bands[..10].to_vec(),
+            start_pos[..10].to_vec(),
+            cols,
+            band_width,
+        )
+        .unwrap();
+        for i in 0..10 {
+            assert_eq!(inner_product(&bands[i], &x[start_pos[i]..]), y[i]);
+        }
+    }
+
+    #[test]
+    fn test_bit() {
+        let a = Band::<4>::from(3); // 1 1 0
+
+        assert!(a.bit(0));
+        assert!(a.bit(1));
+        assert!(!a.bit(2));
+    }
+
+    #[test]
+    fn test_inner_product() {
+        let a: Band = Band::from(3); // 1 1 0
+
+        let b = vec![
+            OkvsValue([0u8; 32]),
+            OkvsValue([0u8; 32]),
+            OkvsValue([0u8; 32]),
+        ];
+        assert!(inner_product(&a, &b).is_zero());
+    }
+}
@@ -0,0 +1,294 @@
+use std::collections::HashMap;
+
+use aes_gcm::aead::rand_core::RngCore;
+
+use crate::error::{Error, Result};
+use crate::okvs::Fingerprint;
+use crate::types::{Encoding, Okvs, OkvsK, OkvsV, Pair, RandomV, Seed};
+use crate::utils::{blake2b, reduce};
+
+const SPARSE_DOMAIN: &[u8] = b"rb-okvs/sparse";
+const WEIGHT: usize = 3;
+/// Random rows of weight 3 peel completely with high probability above
+/// about 1.23 columns per row.
+const EPSILON: f64 = 0.3;
+
+/// A sparse-row OKVS: every key XORs `weight` random columns, one in each of
+/// `weight` equal segments, like a garbled Bloom filter.
+///
+/// Encoding peels the rows, repeatedly setting aside a row that has a column
+/// no other remaining row touches, in time linear in the rows. Rows left
+/// over, the core, are solved by Gaussian elimination before the peeled
+/// rows are solved in reverse. For weight 3 the core is empty with high
+/// probability above about `1.23 n` columns, so encoding is much faster
+/// than banded elimination there, and still solvable down to about
+/// `1.09 n` at the cost of eliminating a dense core. A singular core fails
+/// with [`Error::SingularCore`].
+#[derive(Clone, Debug)]
+pub struct SparseOkvs {
+    columns: usize,
+    weight:  usize,
+    seed:    Seed,
+}
+
+impl SparseOkvs {
+    /// `kv_count` pairs, rows of weight 3 and 30% more columns than pairs.
+    pub fn new(kv_count: usize) -> Self {
+        Self::with_params(kv_count, WEIGHT, EPSILON).unwrap()
+    }
+
+    /// `kv_count` pairs over `(1 + epsilon) * kv_count` columns, rounded up
+    /// to a multiple of `weight`.
+    pub fn with_params(kv_count: usize, weight: usize, epsilon: f64) -> Result<Self> {
+        if !epsilon.is_finite() || epsilon <= 0.0 {
+            return Err(Error::Config(format!(
+                "epsilon must be positive, got {epsilon}"
+            )));
+        }
+        if weight == 0 || weight > 255 {
+            return Err(Error::Config(format!(
+                "weight must be in [1, 255], got {weight}"
+            )));
+        }
+        let columns = ((1.0 + epsilon) * kv_count as f64) as usize;
+        Ok(Self {
+            columns: columns.max(1).next_multiple_of(weight),
+            weight,
+            seed: [0u8; 16],
+        })
+    }
+
+    /// The same instance with its key hashes salted by `seed`.
+    pub fn with_seed(mut self, seed: Seed) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    pub fn columns(&self) -> usize {
+        self.columns
+    }
+
+    /// Columns every row touches.
+    pub fn weight(&self) -> usize {
+        self.weight
+    }
+
+    pub fn seed(&self) -> &Seed {
+        &self.seed
+    }
+
+    /// The columns of `key`, one per segment, in increasing order.
+    pub fn positions<K: OkvsK>(&self, key: &K) -> Vec<usize> {
+        let segment = self.columns / self.weight;
+        let key = key.to_bytes();
+        (0..self.weight)
+            .map(|i| {
+                let data = [SPARSE_DOMAIN, &self.seed, &[i as u8], &key].concat();
+                i * segment + reduce(u64::from_le_bytes(blake2b::<8>(&data)), segment)
+            })
+            .collect()
+    }
+
+    /// Solves the rows of `input` over `x`, whose columns no row is solved
+    /// for are kept.
+    fn solve<K: OkvsK, V: OkvsV>(&self, input: &[Pair<K, V>], mut x: Vec<V>) -> Result<Vec<V>> {
+        let rows: Vec<Vec<usize>> = input.iter().map(|(k, _)| self.positions(k)).collect();
+
+        // A column of degree one is touched by the row `rows_xor` holds
+        let mut degree = vec![0usize; self.columns];
+        let mut rows_xor = vec![0usize; self.columns];
+        for (r, row) in rows.iter().enumerate() {
+            for &c in row {
+                degree[c] += 1;
+                rows_xor[c] ^= r;
+            }
+        }
+
+        let mut queue: Vec<usize> = (0..self.columns).filter(|&c| degree[c] == 1).collect();
+        let mut order = Vec::with_capacity(rows.len());
+        while let Some(c) = queue.pop() {
+            if degree[c] != 1 {
+                continue;
+            }
+            let r = rows_xor[c];
+            order.push((r, c));
+            for &p in &rows[r] {
+                degree[p] -= 1;
+                rows_xor[p] ^= r;
+                if degree[p] == 1 {
+                    queue.push(p);
+                }
+            }
+        }
+
+        if order.len() < rows.len() {
+            let mut peeled = vec![false; rows.len()];
+            order.iter().for_each(|(r, _)| peeled[*r] = true);
+            let core: Vec<usize> = (0..rows.len()).filter(|&r| !peeled[r]).collect();
+            // No core row touches a column a row was peeled on
+            solve_core(input, &rows, &core, &mut x)?;
+        }
+
+        // Rows peeled later don't touch the column a row was peeled on, so
+        // they are solved first
+        for &(r, c) in order.iter().rev() {
+            let mut v = input[r].1.clone();
+            for &p in rows[r].iter().filter(|&&p| p != c) {
+                v.in_place_xor(&x[p]);
+            }
+            x[c] = v;
+        }
+        Ok(x)
+    }
+}
+
+/// Gaussian elimination of the `core` rows, as dense bit vectors over the
+/// columns they touch.
+fn solve_core<K, V: OkvsV>(
+    input: &[Pair<K, V>],
+    rows: &[Vec<usize>],
+    core: &[usize],
+    x: &mut [V],
+) -> Result<()> {
+    let mut compact = HashMap::new();
+    let mut columns = vec![];
+    for &c in core.iter().flat_map(|&r| &rows[r]) {
+        compact.entry(c).or_insert_with(|| {
+            columns.push(c);
+            columns.len() - 1
+        });
+    }
+    let words = columns.len().div_ceil(64);
+    let mut matrix: Vec<Vec<u64>> = core
+        .iter()
+        .map(|&r| {
+            let mut bits = vec![0u64; words];
+            for c in &rows[r] {
+                let i = compact[c];
+                bits[i / 64] ^= 1 << (i % 64);
+            }
+            bits
+        })
+        .collect();
+    let mut y: Vec<V> = core.iter().map(|&r| input[r].1.clone()).collect();
+
+    let bit = |bits: &[u64], i: usize| bits[i / 64] >> (i % 64) & 1 == 1;
+    let mut pivots = Vec::with_capacity(core.len());
+    for i in 0..core.len() {
+        let Some(pivot) = (0..columns.len()).find(|&c| bit(&matrix[i], c)) else {
+            return Err(Error::SingularCore {
+                index: core[i],
+                rows: core.len(),
+            });
+        };
+        let (head, tail) = matrix.split_at_mut(i + 1);
+        let (y_head, y_tail) = y.split_at_mut(i + 1);
+        for (row, y_row) in tail.iter_mut().zip(y_tail) {
+            if bit(row, pivot) {
+                row.iter_mut().zip(&head[i]).for_each(|(a, b)| *a ^= b);
+                y_row.in_place_xor(&y_head[i]);
+            }
+        }
+        pivots.push(pivot);
+    }
+
+    for i in (0..core.len()).rev() {
+        let mut v = y[i].clone();
+        for c in (0..columns.len()).filter(|&c| c != pivots[i] && bit(&matrix[i], c)) {
+            v.in_place_xor(&x[columns[c]]);
+        }
+        x[columns[pivots[i]]] = v;
+    }
+    Ok(())
+}
+
+impl Okvs for SparseOkvs {
+    fn encode<K: OkvsK, V: OkvsV>(&self, input: Vec<Pair<K, V>>) -> Result<Encoding<V>> {
+        self.encode_ref(&input)
+    }
+
+    fn encode_ref<K: OkvsK, V: OkvsV>(&self, input: &[Pair<K, V>]) -> Result<Encoding<V>> {
+        self.solve(input, vec![V::default(); self.columns])
+    }
+
+    fn encode_with_rng<K, V, R>(&self, input: &[Pair<K, V>], rng: &mut R) -> Result<Encoding<V>>
+    where
+        K: OkvsK,
+        V: RandomV,
+        R: RngCore + ?Sized,
+    {
+        let x = (0..self.columns).map(|_| V::random(rng)).collect();
+        self.solve(input, x)
+    }
+
+    fn decode<V: OkvsV>(&self, encoding: &Encoding<V>, key: &impl OkvsK) -> Result<V> {
+        if encoding.len() < self.columns {
+            return Err(Error::EncodingTooShort {
+                required: self.columns,
+                actual: encoding.len(),
+            });
+        }
+        let mut v = V::default();
+        for p in self.positions(key) {
+            v.in_place_xor(&encoding[p]);
+        }
+        Ok(v)
+    }
+
+    fn fingerprint(&self) -> Fingerprint {
+        let mut data = SPARSE_DOMAIN.to_vec();
+        data.extend_from_slice(&self.seed);
+        data.extend_from_slice(&(self.columns as u64).to_le_bytes());
+        data.extend_from_slice(&(self.weight as u64).to_le_bytes());
+        blake2b::<8>(&data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use aes_gcm::aead::OsRng;
+
+    use super::*;
+    use crate::types::{OkvsKey, OkvsValue};
+
+    #[test]
+    fn test_sparse_okvs() {
+        let mut pairs: Vec<Pair<OkvsKey, OkvsValue<8>>> = (0..10000u64)
+            .map(|i| (OkvsKey(i.to_le_bytes()), OkvsValue((i * 5).to_le_bytes())))
+            .collect();
+        let okvs = SparseOkvs::new(pairs.len()).with_seed([4; 16]);
+        assert_eq!(okvs.columns(), 13002);
+
+        let encoding = okvs.encode_ref(&pairs).unwrap();
+        let random = okvs.encode_with_rng(&pairs, &mut OsRng).unwrap();
+        assert_ne!(encoding, random);
+        for (k, v) in &pairs {
+            assert_eq!(okvs.decode(&encoding, k).unwrap(), *v);
+            assert_eq!(okvs.decode(&random, k).unwrap(), *v);
+        }
+        assert!(okvs.decode(&encoding[..100].to_vec(), &pairs[0].0).is_err());
+
+        pairs.push((pairs[7].0.clone(), OkvsValue([1; 8])));
+        assert!(matches!(
+            okvs.encode_ref(&pairs),
+            Err(Error::SingularCore {
+                index: 10000,
+                rows: 2
+            })
+        ));
+        assert!(SparseOkvs::with_params(10, 0, 0.3).is_err());
+    }
+
+    #[test]
+    fn test_core() {
+        // Too few columns to peel completely, so a large core is left
+        let pairs: Vec<Pair<OkvsKey, OkvsValue<8>>> = (0..2000u64)
+            .map(|i| (OkvsKey(i.to_le_bytes()), OkvsValue(i.to_le_bytes())))
+            .collect();
+        let okvs = SparseOkvs::with_params(pairs.len(), 3, 0.15).unwrap();
+        let encoding = okvs.encode_ref(&pairs).unwrap();
+        for (k, v) in &pairs {
+            assert_eq!(okvs.decode(&encoding, k).unwrap(), *v);
+        }
+    }
+}
@@ -0,0 +1,215 @@
+use std::sync::Arc;
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream, ToSocketAddrs};
+use tokio::sync::Mutex;
+
+use crate::emm::{QueryResponse, QueryToken};
+use crate::error::{Error, Result};
+use crate::server::EmmServer;
+use crate::utils::{to_u32, to_usize};
+
+/// Request kinds of the TCP protocol. Every message is a frame of a length
+/// (u32 LE) and a body; requests start with their kind, responses with a
+/// status byte followed by a [`QueryResponse`] or an error message.
+const LEN_REQUEST: u8 = 0;
+const QUERY_REQUEST: u8 = 1;
+const STATUS_OK: u8 = 0;
+const STATUS_ERR: u8 = 1;
+/// Largest frame either side accepts, so a peer can't make the other
+/// allocate arbitrary amounts.
+pub(crate) const MAX_FRAME: usize = 1 << 26;
+
+/// Serves `server` to every connection `listener` accepts, each on its own
+/// task, until accepting fails.
+pub async fn serve<S, const OKVS_V_SIZE: usize>(listener: TcpListener, server: Arc<S>) -> Result<()>
+where
+    S: EmmServer<OKVS_V_SIZE> + 'static,
+{
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let server = server.clone();
+        tokio::spawn(async move {
+            // A broken connection only ends its own task
+            let _ = handle(stream, &*server).await;
+        });
+    }
+}
+
+/// Answers the requests of one connection until the peer closes it.
+async fn handle<S, const OKVS_V_SIZE: usize>(mut stream: TcpStream, server: &S) -> Result<()>
+where
+    S: EmmServer<OKVS_V_SIZE>,
+{
+    while let Some(request) = read_frame(&mut stream).await? {
+        let mut frame = vec![];
+        match answer_request(server, &request)
+            .await
+            .and_then(|response| response.to_bytes())
+        {
+            Ok(response) => {
+                frame.push(STATUS_OK);
+                frame.extend(response);
+            }
+            Err(e) => {
+                frame.push(STATUS_ERR);
+                frame.extend(e.to_string().into_bytes());
+            }
+        }
+        write_frame(&mut stream, &frame).await?;
+    }
+    Ok(())
+}
+
+/// Answers a request body, shared with the HTTP transport.
+pub(crate) async fn answer_request<S, const OKVS_V_SIZE: usize>(
+    server: &S,
+    request: &[u8],
+) -> Result<QueryResponse<OKVS_V_SIZE>>
+where
+    S: EmmServer<OKVS_V_SIZE>,
+{
+    match request {
+        [LEN_REQUEST, token @ ..] => server.answer_len(QueryToken::from_bytes(token)?).await,
+        [QUERY_REQUEST, rest @ ..] if rest.len() >= 8 => {
+            let (v_len, token) = rest.split_at(8);
+            let v_len = to_usize(u64::from_le_bytes(v_len.try_into().unwrap()))?;
+            if v_len.saturating_mul(OKVS_V_SIZE) > MAX_FRAME {
+                return Err(Error::Message(format!("{v_len} values requested")));
+            }
+            server
+                .answer_query(QueryToken::from_bytes(token)?, v_len)
+                .await
+        }
+        _ => Err(Error::Message("unknown request".into())),
+    }
+}
+
+/// An [`EmmServer`] reached over TCP, speaking the protocol of [`serve`].
+/// Requests on one connection are answered in order.
+pub struct RemoteEmmServer<const OKVS_V_SIZE: usize> {
+    stream: Mutex<TcpStream>,
+}
+
+impl<const OKVS_V_SIZE: usize> RemoteEmmServer<OKVS_V_SIZE> {
+    pub async fn connect(addr: impl ToSocketAddrs) -> Result<Self> {
+        Ok(Self {
+            stream: Mutex::new(TcpStream::connect(addr).await?),
+        })
+    }
+
+    async fn request(&self, request: &[u8]) -> Result<QueryResponse<OKVS_V_SIZE>> {
+        let mut stream = self.stream.lock().await;
+        write_frame(&mut *stream, request).await?;
+        let response = read_frame(&mut *stream)
+            .await?
+            .ok_or_else(|| Error::Message("connection closed".into()))?;
+        match response.split_first() {
+            Some((&STATUS_OK, response)) => QueryResponse::from_bytes(response),
+            Some((&STATUS_ERR, message)) => Err(Error::Message(
+                String::from_utf8_lossy(message).into_owned(),
+            )),
+            _ => Err(Error::Message("unknown response status".into())),
+        }
+    }
+}
+
+impl<const OKVS_V_SIZE: usize> EmmServer<OKVS_V_SIZE> for RemoteEmmServer<OKVS_V_SIZE> {
+    async fn answer_len(&self, token: QueryToken) -> Result<QueryResponse<OKVS_V_SIZE>> {
+        self.request(&len_request(&token)).await
+    }
+
+    async fn answer_query(
+        &self,
+        token: QueryToken,
+        v_len: usize,
+    ) -> Result<QueryResponse<OKVS_V_SIZE>> {
+        self.request(&query_request(&token, v_len)).await
+    }
+}
+
+pub(crate) fn len_request(token: &QueryToken) -> Vec<u8> {
+    let mut request = vec![LEN_REQUEST];
+    request.extend(token.to_bytes());
+    request
+}
+
+pub(crate) fn query_request(token: &QueryToken, v_len: usize) -> Vec<u8> {
+    let mut request = vec![QUERY_REQUEST];
+    request.extend_from_slice(&(v_len as u64).to_le_bytes());
+    request.extend(token.to_bytes());
+    request
+}
+
+/// None when the peer closed the connection between frames.
+async fn read_frame<R: AsyncRead + Unpin>(reader: &mut R) -> Result<Option<Vec<u8>>> {
+    let mut len = [0u8; 4];
+    match reader.read_exact(&mut len).await {
+        Ok(_) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.into()),
+    }
+    let len = u32::from_le_bytes(len) as usize;
+    if len > MAX_FRAME {
+        return Err(Error::Message(format!("frame of {len} bytes")));
+    }
+    let mut frame = vec![0u8; len];
+    reader.read_exact(&mut frame).await?;
+    Ok(Some(frame))
+}
+
+async fn write_frame<W: AsyncWrite + Unpin>(writer: &mut W, frame: &[u8]) -> Result<()> {
+    writer
+        .write_all(&to_u32(frame.len())?.to_le_bytes())
+        .await?;
+    writer.write_all(frame).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emm::{ClientState, EmmPair, VhEmm, VhEmmClient, VhEmmServer};
+    use crate::okvs::RbOkvs;
+    use crate::server::query;
+
+    #[tokio::test]
+    async fn test_tcp() {
+        let mut pairs: Vec<EmmPair<u64, Vec<u64>>> = vec![];
+        for i in 0..100 {
+            pairs.push((i, vec![i, i + 1]));
+        }
+        let rb_okvs = RbOkvs::new(pairs.len() * 3);
+
+        // 68 = 60 + <u64 as EmmV>::len()
+        let rb_mm = VhEmm::<RbOkvs, 8, 68>::new(rb_okvs.clone());
+        let client_state = ClientState::new_random();
+        let emm = rb_mm.setup(pairs, &client_state).unwrap();
+        let server = Arc::new(VhEmmServer::<RbOkvs, 8, 68>::new(rb_okvs, emm));
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(serve::<_, 68>(listener, server.clone()));
+
+        let client = VhEmmClient::new(client_state, *server.fingerprint());
+        let remote = RemoteEmmServer::<68>::connect(addr).await.unwrap();
+        for i in [0u64, 42, 99] {
+            let values: Vec<u64> = query(&client, &remote, &i).await.unwrap();
+            assert_eq!(values.len(), 2);
+            assert_eq!(values[0], i);
+
+            let local: Vec<u64> = query(&client, &*server, &i).await.unwrap();
+            assert_eq!(local[1], values[1]);
+        }
+        assert!(matches!(
+            query::<_, _, _, _, u64, 68>(&client, &remote, &1000u64).await,
+            Err(Error::NotFound)
+        ));
+
+        // The server reports malformed requests and keeps the connection
+        let bad = remote.request(&[QUERY_REQUEST]).await;
+        assert!(matches!(bad, Err(Error::Message(m)) if m.contains("unknown request")));
+        let values: Vec<u64> = query(&client, &remote, &7u64).await.unwrap();
+        assert_eq!(values[0], 7);
+    }
+}
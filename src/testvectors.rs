@@ -0,0 +1,190 @@
+//! Deterministic fixtures for checking other implementations against this
+//! one.
+//!
+//! A [`TestVector`] derives its keys and values from a seed, and records
+//! what the encoder computes for them: the start position and band of every
+//! row, and the final encoding. Byte strings are lowercase hex in JSON.
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::{Error, Result};
+use crate::okvs::RbOkvs;
+use crate::types::{Okvs, OkvsKey, OkvsValue};
+use crate::utils::{blake2b, from_hex, to_hex};
+
+/// Bytes of the keys and values of a vector.
+pub const KEY_SIZE: usize = 8;
+pub const VALUE_SIZE: usize = 16;
+/// Leading byte of [`TestVector::to_bytes`].
+pub const VECTOR_VERSION: u8 = 1;
+const SEED_DOMAIN: &[u8] = b"rb-okvs/testvectors";
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TestVector {
+    pub seed:        u64,
+    pub kv_count:    usize,
+    pub columns:     usize,
+    pub band_width:  usize,
+    pub fingerprint: String,
+    pub rows:        Vec<Row>,
+    pub encoding:    Vec<String>,
+}
+
+/// A key-value pair, with the outputs of `hash_to_index` and `hash_to_band`
+/// for its key.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Row {
+    pub key:   String,
+    pub value: String,
+    pub start: usize,
+    /// The band as `band_width.div_ceil(8)` little-endian bytes, bit `i`
+    /// being the coefficient of column `start + i`.
+    pub band:  String,
+}
+
+/// The vector of `kv_count` pairs derived from `seed`, encoded with
+/// [`RbOkvs::new`].
+pub fn generate(seed: u64, kv_count: usize) -> Result<TestVector> {
+    let okvs = RbOkvs::new(kv_count);
+    let pairs: Vec<(OkvsKey<KEY_SIZE>, OkvsValue<VALUE_SIZE>)> = (0..kv_count)
+        .map(|i| {
+            (
+                OkvsKey(derive(seed, b"key", i)),
+                OkvsValue(derive(seed, b"value", i)),
+            )
+        })
+        .collect();
+    let encoding = okvs.encode_ref(&pairs)?;
+
+    let band_bytes = okvs.band_width().div_ceil(8);
+    let rows = pairs
+        .iter()
+        .map(|(k, v)| {
+            let (start, band) = okvs.locate(k);
+            let band = band.to_le_bytes();
+            Row {
+                key: to_hex(&k.0),
+                value: to_hex(&v.0),
+                start,
+                band: to_hex(&band[..band_bytes]),
+            }
+        })
+        .collect();
+    Ok(TestVector {
+        seed,
+        kv_count,
+        columns: okvs.columns(),
+        band_width: okvs.band_width(),
+        fingerprint: to_hex(&okvs.fingerprint()),
+        rows,
+        encoding: encoding.iter().map(|v| to_hex(&v.0)).collect(),
+    })
+}
+
+impl TestVector {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap()
+    }
+
+    pub fn from_json(json: &str) -> Result<Self> {
+        serde_json::from_str(json).map_err(|e| Error::Message(e.to_string()))
+    }
+
+    /// `version || seed || kv_count || columns || band_width` (u64 LE), then
+    /// `key || value || start (u64 LE) || band` per row and the encoding.
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        let mut bytes = vec![VECTOR_VERSION];
+        for n in [
+            self.seed,
+            self.kv_count as u64,
+            self.columns as u64,
+            self.band_width as u64,
+        ] {
+            bytes.extend_from_slice(&n.to_le_bytes());
+        }
+        for row in &self.rows {
+            bytes.extend(from_hex(&row.key)?);
+            bytes.extend(from_hex(&row.value)?);
+            bytes.extend_from_slice(&(row.start as u64).to_le_bytes());
+            bytes.extend(from_hex(&row.band)?);
+        }
+        for v in &self.encoding {
+            bytes.extend(from_hex(v)?);
+        }
+        Ok(bytes)
+    }
+
+    /// Checks every encoding row decodes to its value, the property a
+    /// decoder under test has to reproduce.
+    pub fn verify(&self) -> Result<()> {
+        let encoding = self
+            .encoding
+            .iter()
+            .map(|v| Ok(OkvsValue::<VALUE_SIZE>(fixed(v)?)))
+            .collect::<Result<Vec<_>>>()?;
+        let okvs = RbOkvs::new(self.kv_count);
+        for (i, row) in self.rows.iter().enumerate() {
+            let key = OkvsKey::<KEY_SIZE>(fixed(&row.key)?);
+            if okvs.decode(&encoding, &key)?.0 != fixed::<VALUE_SIZE>(&row.value)? {
+                return Err(Error::Decode(i));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn derive<const N: usize>(seed: u64, label: &[u8], i: usize) -> [u8; N] {
+    blake2b(
+        &[
+            SEED_DOMAIN,
+            label,
+            &seed.to_le_bytes(),
+            &(i as u64).to_le_bytes(),
+        ]
+        .concat(),
+    )
+}
+
+fn fixed<const N: usize>(s: &str) -> Result<[u8; N]> {
+    from_hex(s)?
+        .try_into()
+        .map_err(|_| Error::Message(format!("{s} is not {N} bytes")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_vectors() {
+        let vector = generate(7, 300).unwrap();
+        assert_eq!(vector, generate(7, 300).unwrap());
+        assert_ne!(vector.rows, generate(8, 300).unwrap().rows);
+        assert_eq!(vector.columns, 330);
+        assert_eq!(vector.rows[0].band.len(), 2 * 16);
+        vector.verify().unwrap();
+
+        let parsed = TestVector::from_json(&vector.to_json()).unwrap();
+        assert_eq!(parsed, vector);
+
+        let rows = 300 * (KEY_SIZE + VALUE_SIZE + 8 + 16);
+        assert_eq!(
+            vector.to_bytes().unwrap().len(),
+            33 + rows + 330 * VALUE_SIZE
+        );
+
+        let mut tampered = vector.clone();
+        tampered.encoding[150] = to_hex(&[0xff; VALUE_SIZE]);
+        assert!(tampered.verify().is_err());
+    }
+
+    /// Starts, bands and the encoding are fixed bytes, the same on hosts of
+    /// either byte order.
+    #[test]
+    fn test_golden() {
+        let golden = TestVector::from_json(include_str!("../testdata/vector-7-40.json")).unwrap();
+        assert_eq!(generate(7, 40).unwrap(), golden);
+        golden.verify().unwrap();
+        assert_eq!(TestVector::from_json(&golden.to_json()).unwrap(), golden);
+    }
+}
@@ -0,0 +1,103 @@
+//! Instrumentation behind the `tracing` feature: `debug` spans around the
+//! phases of encoding and decoding, and events carrying their counters.
+//! Without the feature the macros only borrow their field values, so
+//! counters kept for them don't warn.
+
+/// Enters a span until the end of the enclosing block.
+macro_rules! trace_span {
+    ($name:literal $(, $field:ident = $value:expr)* $(,)?) => {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::debug_span!($name $(, $field = $value)*).entered();
+        #[cfg(not(feature = "tracing"))]
+        let _ = ($(&$value,)*);
+    };
+}
+
+/// Emits an event with the given fields.
+macro_rules! trace_event {
+    ($message:literal $(, $field:ident = $value:expr)* $(,)?) => {
+        #[cfg(feature = "tracing")]
+        tracing::debug!($($field = $value,)* $message);
+        #[cfg(not(feature = "tracing"))]
+        let _ = ($(&$value,)*);
+    };
+}
+
+pub(crate) use {trace_event, trace_span};
+
+#[cfg(all(test, feature = "tracing"))]
+mod tests {
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::Mutex;
+
+    use tracing::span::{Attributes, Id, Record};
+    use tracing::{Event, Metadata, Subscriber};
+
+    use crate::okvs::RbOkvs;
+    use crate::types::{Okvs, OkvsKey, OkvsValue, Pair};
+
+    /// Records the names of spans and events.
+    #[derive(Default)]
+    struct Recorder {
+        next:  AtomicU64,
+        names: Mutex<Vec<&'static str>>,
+    }
+
+    impl Subscriber for Recorder {
+        fn enabled(&self, _: &Metadata<'_>) -> bool {
+            true
+        }
+
+        fn new_span(&self, span: &Attributes<'_>) -> Id {
+            self.names.lock().unwrap().push(span.metadata().name());
+            Id::from_u64(self.next.fetch_add(1, Ordering::Relaxed) + 1)
+        }
+
+        fn record(&self, _: &Id, _: &Record<'_>) {}
+
+        fn record_follows_from(&self, _: &Id, _: &Id) {}
+
+        fn event(&self, event: &Event<'_>) {
+            let mut message = None;
+            event.record(
+                &mut |field: &tracing::field::Field, value: &dyn std::fmt::Debug| {
+                    if field.name() == "message" {
+                        message = Some(format!("{value:?}"));
+                    }
+                },
+            );
+            if message.as_deref() == Some("eliminated") {
+                self.names.lock().unwrap().push("eliminated");
+            }
+        }
+
+        fn enter(&self, _: &Id) {}
+
+        fn exit(&self, _: &Id) {}
+    }
+
+    #[test]
+    fn test_encode_spans() {
+        let recorder = std::sync::Arc::new(Recorder::default());
+        let pairs: Vec<Pair<OkvsKey, OkvsValue<1>>> = (0..100u64)
+            .map(|i| (OkvsKey(i.to_le_bytes()), OkvsValue([i as u8])))
+            .collect();
+        tracing::subscriber::with_default(recorder.clone(), || {
+            RbOkvs::new(pairs.len()).encode_ref(&pairs).unwrap();
+        });
+
+        let names = recorder.names.lock().unwrap();
+        let expected = [
+            "encode",
+            "hash_index",
+            "sort",
+            "hash_band",
+            "eliminate",
+            "eliminated",
+            "back_substitute",
+        ];
+        for name in expected {
+            assert!(names.contains(&name), "{name} missing from {names:?}");
+        }
+    }
+}
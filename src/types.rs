@@ -1,30 +1,350 @@
-use sp_core::U256;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::mpsc::SyncSender;
+use std::sync::Arc;
 
-use crate::error::Result;
+use aes_gcm::aead::rand_core::RngCore;
+
+use crate::band::Band;
+use crate::error::{Error, Result};
+use crate::executor::Executor;
+use crate::okvs::Fingerprint;
+use crate::trace::trace_span;
 use crate::utils::*;
 
+/// Domain tags of the two key hashes, so neither output says anything about
+/// the other.
+const INDEX_DOMAIN: &[u8] = b"rb-okvs/index";
+const BAND_DOMAIN: &[u8] = b"rb-okvs/band";
+
 pub type Encoding<T> = Vec<T>;
+/// Per-instance salt of the key hashes, see [`RbOkvsBuilder::seed`](crate::okvs::RbOkvsBuilder::seed).
+pub type Seed = [u8; 16];
 pub type Pair<K, V> = (K, V);
 
+/// Multimap key. Tokens are derived from `to_bytes`, which must be injective
+/// over the keys of one multimap.
 pub trait EmmK {
     fn to_bytes(&self) -> Vec<u8>;
 }
 
+macro_rules! impl_emm_k_int {
+    ($($t:ty),*) => {$(
+        impl EmmK for $t {
+            fn to_bytes(&self) -> Vec<u8> {
+                self.to_le_bytes().into()
+            }
+        }
+    )*};
+}
+
+impl_emm_k_int!(u8, u16, u32, u64, u128, i8, i16, i32, i64, i128);
+
+impl EmmK for str {
+    fn to_bytes(&self) -> Vec<u8> {
+        self.as_bytes().into()
+    }
+}
+
+impl EmmK for String {
+    fn to_bytes(&self) -> Vec<u8> {
+        self.as_bytes().into()
+    }
+}
+
+impl EmmK for [u8] {
+    fn to_bytes(&self) -> Vec<u8> {
+        self.into()
+    }
+}
+
+impl EmmK for Vec<u8> {
+    fn to_bytes(&self) -> Vec<u8> {
+        self.clone()
+    }
+}
+
+/// Fixed-size ids such as UUIDs.
+impl<const N: usize> EmmK for [u8; N] {
+    fn to_bytes(&self) -> Vec<u8> {
+        self.into()
+    }
+}
+
+impl<T: EmmK + ?Sized> EmmK for &T {
+    fn to_bytes(&self) -> Vec<u8> {
+        (**self).to_bytes()
+    }
+}
+
+/// Composite keys length-prefix every component but the last, so that
+/// `("ab", "c")` and `("a", "bc")` differ.
+macro_rules! impl_emm_k_tuple {
+    ($($t:ident),*; $last:ident) => {
+        impl<$($t: EmmK,)* $last: EmmK> EmmK for ($($t,)* $last,) {
+            #[allow(non_snake_case)]
+            fn to_bytes(&self) -> Vec<u8> {
+                let ($($t,)* $last,) = self;
+                let mut bytes = vec![];
+                $(
+                    let b = $t.to_bytes();
+                    bytes.extend_from_slice(&(b.len() as u64).to_le_bytes());
+                    bytes.extend_from_slice(&b);
+                )*
+                bytes.extend_from_slice(&$last.to_bytes());
+                bytes
+            }
+        }
+    };
+}
+
+impl_emm_k_tuple!(A; B);
+impl_emm_k_tuple!(A, B; C);
+impl_emm_k_tuple!(A, B, C; D);
+
+/// Multimap value of a fixed size: `encode` returns `len()` bytes, which
+/// `decode` is given back. With the `derive` feature, `#[derive(EmmV)]`
+/// implements it for structs of such values.
 pub trait EmmV {
     fn len() -> usize;
     fn encode(&self) -> Vec<u8>;
     fn decode(b: &[u8]) -> Self;
 }
 
+#[cfg(feature = "derive")]
+pub use rb_okvs_derive::EmmV;
+
+macro_rules! impl_emm_v_int {
+    ($($t:ty),*) => {$(
+        impl EmmV for $t {
+            fn len() -> usize {
+                std::mem::size_of::<$t>()
+            }
+
+            fn encode(&self) -> Vec<u8> {
+                self.to_le_bytes().into()
+            }
+
+            fn decode(b: &[u8]) -> Self {
+                <$t>::from_le_bytes(b.try_into().unwrap())
+            }
+        }
+    )*};
+}
+
+impl_emm_v_int!(u8, u16, u32, u64, u128, i8, i16, i32, i64, i128);
+
+impl<const N: usize> EmmV for [u8; N] {
+    fn len() -> usize {
+        N
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        self.to_vec()
+    }
+
+    fn decode(b: &[u8]) -> Self {
+        b.try_into().unwrap()
+    }
+}
+
 /// Oblivious Key-Value Stores
 pub trait Okvs {
     fn encode<K: OkvsK, V: OkvsV>(&self, input: Vec<Pair<K, V>>) -> Result<Encoding<V>>;
-    fn decode<V: OkvsV>(&self, encoding: &Encoding<V>, key: &impl OkvsK) -> V;
+    /// Like `encode`, but borrows `input` instead of consuming it.
+    fn encode_ref<K: OkvsK, V: OkvsV>(&self, input: &[Pair<K, V>]) -> Result<Encoding<V>>;
+    /// Like `encode_ref`, with the columns the keys leave free drawn from
+    /// `rng`, so the encoding is uniform over all encodings of `input`.
+    ///
+    /// `encode` leaves free columns zero, at positions that depend on the
+    /// keys, so its encodings are not oblivious: they tell key sets apart
+    /// even under random values. Use this wherever the encoding is shown to
+    /// someone who must not learn the keys.
+    fn encode_with_rng<K, V, R>(&self, input: &[Pair<K, V>], rng: &mut R) -> Result<Encoding<V>>
+    where
+        K: OkvsK,
+        V: RandomV,
+        R: RngCore + ?Sized;
+    /// Encodes the entries of a map such as `&HashMap<K, V>` or
+    /// `&BTreeMap<K, V>`. Entries are taken in order of their key bytes, so
+    /// the encoding does not depend on iteration order and is reproducible
+    /// under a fixed seed.
+    fn encode_map<'a, K, V, M>(&self, map: M) -> Result<Encoding<V>>
+    where
+        K: OkvsK + 'a,
+        V: OkvsV + 'a,
+        M: IntoIterator<Item = (&'a K, &'a V)>,
+    {
+        let mut pairs: Vec<Pair<&K, V>> = map.into_iter().map(|(k, v)| (k, v.clone())).collect();
+        pairs.sort_by_cached_key(|(k, _)| k.to_bytes());
+        self.encode(pairs)
+    }
+    /// Like `encode_ref`, but writes the encoding to `out`, replacing its
+    /// contents, so a buffer reused across encodes is allocated only once.
+    fn encode_into<K: OkvsK, V: OkvsV>(
+        &self,
+        input: &[Pair<K, V>],
+        out: &mut Vec<V>,
+    ) -> Result<()> {
+        *out = self.encode_ref(input)?;
+        Ok(())
+    }
+    /// The value `key` decodes to. Fails with [`Error::EncodingTooShort`]
+    /// when `encoding` has fewer values than this instance's encodings.
+    fn decode<V: OkvsV>(&self, encoding: &Encoding<V>, key: &impl OkvsK) -> Result<V>;
+    /// Digest of everything that shapes the encodings of this instance. A
+    /// peer holding an encoding compares it before decoding, since another
+    /// instance decodes it to garbage without failing.
+    fn fingerprint(&self) -> Fingerprint;
+
+    /// Decodes `keys[i]` into `out[i]`, for callers that reuse `out`.
+    fn decode_batch_into<K, V>(
+        &self,
+        encoding: &Encoding<V>,
+        keys: &[K],
+        out: &mut [V],
+    ) -> Result<()>
+    where
+        K: OkvsK,
+        V: OkvsV,
+    {
+        if keys.len() != out.len() {
+            return Err(Error::Config(format!(
+                "{} keys for {} outputs",
+                keys.len(),
+                out.len()
+            )));
+        }
+        for (key, v) in keys.iter().zip(out) {
+            *v = self.decode(encoding, key)?;
+        }
+        Ok(())
+    }
+
+    /// Decodes every key in `keys` on `executor`, results in key order.
+    fn decode_batch<K, V, E>(
+        &self,
+        executor: &E,
+        encoding: &Encoding<V>,
+        keys: &[K],
+    ) -> Result<Vec<V>>
+    where
+        Self: Sync,
+        K: OkvsK + Sync,
+        V: OkvsV + Send + Sync,
+        E: Executor,
+    {
+        trace_span!("decode_batch", keys = keys.len());
+        executor
+            .map(keys, |key| self.decode(encoding, key))
+            .into_iter()
+            .collect()
+    }
+}
+
+/// An [`Okvs`] chosen at runtime, for fixed key and value types. The
+/// generic methods of [`Okvs`] keep it from being a trait object, so this
+/// erases the scheme behind one instead.
+pub struct DynOkvs<K, V> {
+    inner: Arc<dyn ErasedOkvs<K, V> + Send + Sync>,
+}
+
+impl<K: OkvsK, V: OkvsV> DynOkvs<K, V> {
+    pub fn new<T: Okvs + Send + Sync + 'static>(okvs: T) -> Self {
+        Self {
+            inner: Arc::new(okvs),
+        }
+    }
+
+    pub fn encode(&self, input: &[Pair<K, V>]) -> Result<Encoding<V>> {
+        self.inner.encode_ref(input)
+    }
+
+    /// See [`Okvs::encode_with_rng`].
+    pub fn encode_with_rng(
+        &self,
+        input: &[Pair<K, V>],
+        rng: &mut dyn RngCore,
+    ) -> Result<Encoding<V>>
+    where
+        V: RandomV,
+    {
+        self.inner.encode_with_rng(input, rng)
+    }
+
+    pub fn decode(&self, encoding: &Encoding<V>, key: &K) -> Result<V> {
+        self.inner.decode(encoding, key)
+    }
+}
+
+impl<K, V> Clone for DynOkvs<K, V> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+/// The methods of [`Okvs`] at fixed key and value types, so they can be
+/// called through a vtable.
+trait ErasedOkvs<K, V> {
+    fn encode_ref(&self, input: &[Pair<K, V>]) -> Result<Encoding<V>>;
+    fn encode_with_rng(&self, input: &[Pair<K, V>], rng: &mut dyn RngCore) -> Result<Encoding<V>>
+    where
+        V: RandomV;
+    fn decode(&self, encoding: &Encoding<V>, key: &K) -> Result<V>;
+}
+
+impl<T: Okvs, K: OkvsK, V: OkvsV> ErasedOkvs<K, V> for T {
+    fn encode_ref(&self, input: &[Pair<K, V>]) -> Result<Encoding<V>> {
+        Okvs::encode_ref(self, input)
+    }
+
+    fn encode_with_rng(&self, input: &[Pair<K, V>], rng: &mut dyn RngCore) -> Result<Encoding<V>>
+    where
+        V: RandomV,
+    {
+        Okvs::encode_with_rng(self, input, rng)
+    }
+
+    fn decode(&self, encoding: &Encoding<V>, key: &K) -> Result<V> {
+        Okvs::decode(self, encoding, key)
+    }
+}
+
+/// Receives encoding columns as they become final. Blocking in `put` applies
+/// back-pressure to the encoder.
+pub trait EncodingSink<V> {
+    fn put(&mut self, column: usize, value: &V) -> Result<()>;
+}
+
+/// Bounded channels let a consumer thread serialize columns while the encoder
+/// is still back-substituting.
+impl<V: Clone> EncodingSink<V> for SyncSender<(usize, V)> {
+    fn put(&mut self, column: usize, value: &V) -> Result<()> {
+        self.send((column, value.clone()))
+            .map_err(|_| Error::SinkClosed)
+    }
+}
+
+impl<V: Clone> EncodingSink<V> for Vec<(usize, V)> {
+    fn put(&mut self, column: usize, value: &V) -> Result<()> {
+        self.push((column, value.clone()));
+        Ok(())
+    }
+}
+
+/// Maps a key to a band start position. Implement it on a key type to plug in
+/// a different reduction.
+pub trait HashToIndex {
+    /// hash1(seed, key) -> [0, range)
+    fn hash_to_index(&self, seed: &Seed, range: usize) -> usize;
 }
 
-pub trait OkvsK {
-    fn hash_to_index(&self, range: usize) -> usize;
-    fn hash_to_band(&self, band_width: usize) -> U256;
+pub trait OkvsK: HashToIndex {
+    /// `band_width` must be in `[1, 64 * W]`; bits from `band_width` up are
+    /// zero.
+    fn hash_to_band<const W: usize>(&self, seed: &Seed, band_width: usize) -> Band<W>;
     fn to_bytes(&self) -> Vec<u8>;
 }
 
@@ -35,21 +355,32 @@ pub trait OkvsV: Clone {
     fn in_place_xor(&mut self, other: &Self);
 }
 
-#[derive(Clone)]
+/// Values that can be drawn uniformly at random, to mask an encoding.
+pub trait RandomV: OkvsV {
+    fn random<R: RngCore + ?Sized>(rng: &mut R) -> Self;
+}
+
+/// Ordered and hashable, to key a `HashMap` or `BTreeMap` given to
+/// [`Okvs::encode_map`].
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub struct OkvsKey<const N: usize = 8>(pub [u8; N]);
 
-impl<const N: usize> OkvsK for OkvsKey<N> {
-    /// hash1(key) -> [0, range)
-    fn hash_to_index(&self, range: usize) -> usize {
-        let v = blake2b::<8>(&self.to_bytes());
-        usize::from_le_bytes(v) % range
+impl<const N: usize> HashToIndex for OkvsKey<N> {
+    fn hash_to_index(&self, seed: &Seed, range: usize) -> usize {
+        let v = blake2b::<8>(&[INDEX_DOMAIN, seed, &self.0].concat());
+        reduce(u64::from_le_bytes(v), range)
     }
+}
 
-    /// hash2(key) -> {0, 1}^band_width
-    fn hash_to_band(&self, band_width: usize) -> U256 {
-        let mut v = hash(&self.0, band_width / 8);
+impl<const N: usize> OkvsK for OkvsKey<N> {
+    /// hash2(seed, key) -> {0, 1}^band_width
+    fn hash_to_band<const W: usize>(&self, seed: &Seed, band_width: usize) -> Band<W> {
+        let mut v = hash(
+            &[BAND_DOMAIN, seed, &self.0].concat(),
+            band_width.div_ceil(8),
+        );
         v[0] |= 1;
-        U256::from_little_endian(&v)
+        Band::from_le_bits(&v, band_width)
     }
 
     fn to_bytes(&self) -> Vec<u8> {
@@ -57,9 +388,87 @@ impl<const N: usize> OkvsK for OkvsKey<N> {
     }
 }
 
+impl<K: HashToIndex + ?Sized> HashToIndex for &K {
+    fn hash_to_index(&self, seed: &Seed, range: usize) -> usize {
+        (**self).hash_to_index(seed, range)
+    }
+}
+
+/// Lets the encoders take pairs of borrowed keys.
+impl<K: OkvsK + ?Sized> OkvsK for &K {
+    fn hash_to_band<const W: usize>(&self, seed: &Seed, band_width: usize) -> Band<W> {
+        (**self).hash_to_band(seed, band_width)
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        (**self).to_bytes()
+    }
+}
+
 #[derive(Clone, Debug, PartialEq, Eq)]
+#[repr(transparent)]
 pub struct OkvsValue<const N: usize>(pub [u8; N]);
 
+impl<const N: usize> OkvsValue<N> {
+    /// Uniform random bytes, see [`RandomV`].
+    pub fn random<R: RngCore + ?Sized>(rng: &mut R) -> Self {
+        <Self as RandomV>::random(rng)
+    }
+}
+
+/// Conversions from arrays, slices and lowercase hex, the format of
+/// `Display`.
+macro_rules! impl_bytes {
+    ($($t:ident),*) => {$(
+        impl<const N: usize> From<[u8; N]> for $t<N> {
+            fn from(bytes: [u8; N]) -> Self {
+                Self(bytes)
+            }
+        }
+
+        impl<const N: usize> TryFrom<&[u8]> for $t<N> {
+            type Error = Error;
+
+            fn try_from(bytes: &[u8]) -> Result<Self> {
+                bytes.try_into().map(Self).map_err(|_| Error::ValueSizeMismatch {
+                    expected: N,
+                    actual: bytes.len(),
+                })
+            }
+        }
+
+        impl<const N: usize> AsRef<[u8]> for $t<N> {
+            fn as_ref(&self) -> &[u8] {
+                &self.0
+            }
+        }
+
+        impl<const N: usize> fmt::Display for $t<N> {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str(&to_hex(&self.0))
+            }
+        }
+
+        impl<const N: usize> FromStr for $t<N> {
+            type Err = Error;
+
+            fn from_str(s: &str) -> Result<Self> {
+                Self::try_from(from_hex(s)?.as_slice())
+            }
+        }
+    )*};
+}
+
+impl_bytes!(OkvsKey, OkvsValue);
+
+impl<const N: usize> RandomV for OkvsValue<N> {
+    fn random<R: RngCore + ?Sized>(rng: &mut R) -> Self {
+        let mut v = [0u8; N];
+        rng.fill_bytes(&mut v);
+        Self(v)
+    }
+}
+
 impl<const N: usize> OkvsV for OkvsValue<N> {
     fn default() -> Self {
         Self([0u8; N])
@@ -75,16 +484,128 @@ impl<const N: usize> OkvsV for OkvsValue<N> {
     }
 
     fn xor(&self, other: &Self) -> Self {
-        let mut result = [0u8; N];
-        for (i, item) in self.0.iter().enumerate() {
-            result[i] = item ^ other.0[i];
-        }
-        Self(result)
+        let mut result = self.clone();
+        result.in_place_xor(other);
+        result
     }
 
+    /// XORs 8 bytes at a time, the tail byte by byte. Words are loaded and
+    /// stored in the same native order, so the bytes match on any host.
     fn in_place_xor(&mut self, other: &Self) {
-        for i in 0..self.0.len() {
-            self.0[i] ^= other.0[i];
+        let mut lhs = self.0.chunks_exact_mut(8);
+        let mut rhs = other.0.chunks_exact(8);
+        for (a, b) in (&mut lhs).zip(&mut rhs) {
+            let word = u64::from_ne_bytes((&*a).try_into().unwrap())
+                ^ u64::from_ne_bytes(b.try_into().unwrap());
+            a.copy_from_slice(&word.to_ne_bytes());
+        }
+        for (a, b) in lhs.into_remainder().iter_mut().zip(rhs.remainder()) {
+            *a ^= b;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_emm_k() {
+        assert_eq!(7u32.to_bytes(), vec![7, 0, 0, 0]);
+        assert_eq!("ab".to_bytes(), b"ab".to_vec());
+        assert_eq!(String::from("ab").to_bytes(), "ab".to_bytes());
+        assert_eq!([1u8; 16].to_bytes(), vec![1u8; 16]);
+
+        assert_ne!(("ab", "c").to_bytes(), ("a", "bc").to_bytes());
+        assert_eq!(("a", 1u8).to_bytes(), vec![1, 0, 0, 0, 0, 0, 0, 0, b'a', 1]);
+    }
+
+    #[cfg(feature = "derive")]
+    #[test]
+    fn test_derive_emm_v() {
+        #[derive(EmmV, Debug, PartialEq)]
+        struct Record {
+            id:    u64,
+            tag:   [u8; 3],
+            flags: u8,
+        }
+
+        #[derive(EmmV, Debug, PartialEq)]
+        struct Versioned(u16, Record);
+
+        let value = Versioned(
+            2,
+            Record {
+                id: 7,
+                tag: *b"abc",
+                flags: 1,
+            },
+        );
+        assert_eq!(<Versioned as EmmV>::len(), 2 + 8 + 3 + 1);
+        let bytes = value.encode();
+        assert_eq!(
+            bytes,
+            [&[2, 0][..], &7u64.to_le_bytes(), b"abc", &[1]].concat()
+        );
+        assert_eq!(Versioned::decode(&bytes), value);
+    }
+
+    #[test]
+    fn test_conversions() {
+        let key = OkvsKey::from([0xab, 1, 2, 3]);
+        assert_eq!(key.to_string(), "ab010203");
+        assert_eq!("AB010203".parse::<OkvsKey<4>>().unwrap(), key);
+        assert_eq!(OkvsKey::<4>::try_from(&[0xab, 1, 2, 3][..]).unwrap(), key);
+        assert!(matches!(
+            OkvsKey::<4>::try_from(&[1, 2][..]),
+            Err(Error::ValueSizeMismatch {
+                expected: 4,
+                actual: 2,
+            })
+        ));
+        assert!("ab01".parse::<OkvsValue<4>>().is_err());
+        assert!("zz010203".parse::<OkvsValue<4>>().is_err());
+
+        let value = OkvsValue::<16>::random(&mut aes_gcm::aead::OsRng);
+        assert_eq!(value.to_string().parse::<OkvsValue<16>>().unwrap(), value);
+        assert_eq!(value.as_ref(), &value.0);
+    }
+
+    #[test]
+    fn test_dyn_okvs() {
+        use crate::okvs::RbOkvs;
+        use crate::sharded::ShardedOkvs;
+
+        let pairs: Vec<Pair<OkvsKey, OkvsValue<8>>> = (0..1000u64)
+            .map(|i| (OkvsKey(i.to_le_bytes()), OkvsValue((i + 5).to_le_bytes())))
+            .collect();
+        let schemes: Vec<DynOkvs<OkvsKey, OkvsValue<8>>> = vec![
+            DynOkvs::new(RbOkvs::new(pairs.len())),
+            DynOkvs::new(ShardedOkvs::new(pairs.len(), 4).unwrap()),
+        ];
+        for okvs in &schemes {
+            let encoding = okvs
+                .encode_with_rng(&pairs, &mut aes_gcm::aead::OsRng)
+                .unwrap();
+            for (k, v) in &pairs {
+                assert_eq!(okvs.decode(&encoding, k).unwrap(), *v);
+            }
         }
     }
+
+    #[test]
+    fn test_okvs_value_xor() {
+        let a = OkvsValue::<13>(core::array::from_fn(|i| i as u8));
+        let b = OkvsValue::<13>([0xff; 13]);
+
+        let c = a.xor(&b);
+        for i in 0..13 {
+            assert_eq!(c.0[i], !(i as u8));
+        }
+
+        let mut d = c.clone();
+        d.in_place_xor(&b);
+        assert_eq!(d, a);
+        assert!(a.xor(&a).is_zero());
+    }
 }